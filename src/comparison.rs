@@ -1,11 +1,20 @@
 //! File comparison functions for the tbcompare tool.
 
-use std::collections::HashSet;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::path::{Path, PathBuf};
-use std::fs;
+use std::fs::{self, File};
+use std::io::Read;
+use std::fmt;
+use std::time::SystemTime;
+use std::str::FromStr;
 use std::process::Command;
-use log::info;
+use log::{info, warn};
 use anyhow::{Context, Result};
+use regex::Regex;
+use similar::{ChangeTag, TextDiff};
+use crate::file_utils::{fnv1a, is_gzip_path, is_same_file, process_file, process_file_unsorted, read_list_lines, ProcessOptions, ProcessedFile};
+use crate::ordered_diff::diff_ordered;
 
 /// Represents the differences between two files
 #[derive(Debug, Clone)]
@@ -14,6 +23,718 @@ pub struct FileDifferences {
     pub only_in_first: Vec<String>,
     /// Lines that exist only in the second file
     pub only_in_second: Vec<String>,
+    /// Number of lines present in both files
+    /// (distinct lines in the set-based comparison, unchanged lines in the ordered comparison)
+    pub common_count: usize,
+    /// Stable identifier of the file pair, see [`pair_id`]
+    pub pair_id: String,
+    /// Summed line weights of each part, as computed with [`CompareOptions::line_weights`]
+    pub weights: DiffWeights,
+    /// Lines of each file dropped at the truncation marker, see [`ProcessOptions::truncate_at`]
+    pub truncated_lines: (usize, usize),
+    /// Lines in both files that only differ in trailing whitespace, sorted by line
+    /// (only with [`ProcessOptions::track_trailing_whitespace`] in the set-based comparison)
+    pub whitespace_only_diffs: Vec<WhitespaceOnlyDiff>,
+    /// Number of lines compared in each file (after the header and any filtered lines, duplicates included)
+    pub line_counts: (usize, usize),
+    /// Numbers of identical lines before the first change and after the last one
+    /// (only in the ordered comparison)
+    pub common_edges: Option<(usize, usize)>,
+}
+
+/// A line present in both files, but with different trailing whitespace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WhitespaceOnlyDiff {
+    /// The line as it appears in the first file, without its trailing whitespace
+    pub line: String,
+    /// Trailing whitespace of the line in the first file
+    pub trailing1: String,
+    /// Trailing whitespace of the line in the second file
+    pub trailing2: String,
+}
+
+impl FileDifferences {
+    /// Computes the similarity of the two files' line sets with the given metric
+    /// Lines count with their weight (1 each unless weighting rules were given).
+    /// 
+    /// # Returns
+    /// 
+    /// A value between 0.0 (nothing in common) and 1.0 (identical line sets)
+    pub fn similarity(&self, metric: SimilarityMetric) -> f64 {
+        let common = self.weights.common;
+        let first = self.weights.common + self.weights.only_in_first;
+        let second = self.weights.common + self.weights.only_in_second;
+        let denominator = match metric {
+            SimilarityMetric::Jaccard => first + second - common,
+            SimilarityMetric::Overlap => first.min(second),
+            SimilarityMetric::Dice => (first + second) / 2.0,
+        };
+        if denominator == 0.0 {
+            return if first == second { 1.0 } else { 0.0 };
+        }
+        common / denominator
+    }
+}
+
+/// Differing lines of a pair with near-identical removed/added lines merged into modifications
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LineModifications {
+    /// (old line, new line) pairs, in the order of the old lines
+    pub modified: Vec<(String, String)>,
+    /// Lines of the first file with no close counterpart
+    pub removed: Vec<String>,
+    /// Lines of the second file with no close counterpart
+    pub added: Vec<String>,
+}
+
+/// Maximum number of (removed, added) line combinations [`merge_modifications`] scores; above it,
+/// the lines are left unmerged rather than stalling on a pair with thousands of differing lines
+const MERGE_MAX_CANDIDATE_PAIRS: usize = 100_000;
+
+/// Pairs each line only in the first file with its closest line only in the second file
+/// 
+/// Line similarity is the character-level diff ratio (0.0 to 1.0). Candidate pairs are matched
+/// greedily, most similar first, and each line is used at most once. This is a presentation step
+/// for the set-based comparison: it doesn't change what counts as a difference. Lines whose lengths
+/// alone rule out the threshold aren't scored, and when there are more than 100,000 combinations of
+/// removed and added lines, every line is kept as removed or added.
+///
+/// # Arguments
+///
+/// * `only_in_first` - Lines only in the first file
+/// * `only_in_second` - Lines only in the second file
+/// * `threshold` - Minimum similarity for two lines to be shown as a modification
+///
+/// # Returns
+///
+/// The modifications and the remaining removed and added lines
+pub fn merge_modifications(only_in_first: &[String], only_in_second: &[String], threshold: f64) -> LineModifications {
+    if only_in_first.len().saturating_mul(only_in_second.len()) > MERGE_MAX_CANDIDATE_PAIRS {
+        info!("Too many differing lines ({} removed, {} added) to merge modifications", only_in_first.len(), only_in_second.len());
+        return LineModifications {
+            removed: only_in_first.to_vec(),
+            added: only_in_second.to_vec(),
+            ..LineModifications::default()
+        };
+    }
+    
+    let char_counts2: Vec<usize> = only_in_second.iter().map(|line| line.chars().count()).collect();
+    let mut candidates = Vec::new();
+    for (index1, line1) in only_in_first.iter().enumerate() {
+        let count1 = line1.chars().count();
+        for (index2, line2) in only_in_second.iter().enumerate() {
+            // The ratio is 2 * matching chars / total chars, so it can't exceed what the shorter line allows
+            let count2 = char_counts2[index2];
+            if count1 + count2 > 0 && 2.0 * count1.min(count2) as f64 / ((count1 + count2) as f64) < threshold {
+                continue;
+            }
+            let ratio = f64::from(TextDiff::from_chars(line1.as_str(), line2.as_str()).ratio());
+            if ratio >= threshold {
+                candidates.push((ratio, index1, index2));
+            }
+        }
+    }
+    // Most similar first; ties keep the line order
+    candidates.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| (a.1, a.2).cmp(&(b.1, b.2))));
+    
+    let mut matches: Vec<Option<usize>> = vec![None; only_in_first.len()];
+    let mut used = vec![false; only_in_second.len()];
+    for (_, index1, index2) in candidates {
+        if matches[index1].is_none() && !used[index2] {
+            matches[index1] = Some(index2);
+            used[index2] = true;
+        }
+    }
+    
+    let mut modifications = LineModifications::default();
+    for (line1, matched) in only_in_first.iter().zip(matches) {
+        match matched {
+            Some(index2) => modifications.modified.push((line1.clone(), only_in_second[index2].clone())),
+            None => modifications.removed.push(line1.clone()),
+        }
+    }
+    modifications.added = only_in_second.iter().zip(used)
+        .filter(|(_, used)| !used)
+        .map(|(line2, _)| line2.clone())
+        .collect();
+    modifications
+}
+
+/// A field whose value differs between two JSON records with the same key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonFieldChange {
+    /// Name of the top-level field
+    pub field: String,
+    /// JSON value in the first file (`None` if the field is missing)
+    pub old: Option<String>,
+    /// JSON value in the second file (`None` if the field is missing)
+    pub new: Option<String>,
+}
+
+/// A JSON record present in both files with different contents
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonRecordChange {
+    /// Value of the key field (strings without their quotes)
+    pub key: String,
+    /// Diverging top-level fields, sorted by name
+    pub fields: Vec<JsonFieldChange>,
+}
+
+/// Differing JSON lines grouped into records by a key field
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct JsonRecordDiff {
+    /// Records with the same key on both sides, in the order of the first file's lines
+    pub changed: Vec<JsonRecordChange>,
+    /// Lines of the first file with no record of the same key in the second
+    pub removed: Vec<String>,
+    /// Lines of the second file with no record of the same key in the first
+    pub added: Vec<String>,
+}
+
+/// Returns a JSON line's object and the value of its key field rendered as text
+fn json_record(line: &str, key_field: &str) -> Option<(serde_json::Map<String, serde_json::Value>, String)> {
+    let serde_json::Value::Object(object) = serde_json::from_str(line).ok()? else {
+        return None;
+    };
+    let key = match object.get(key_field)? {
+        serde_json::Value::String(key) => key.clone(),
+        key => key.to_string(),
+    };
+    Some((object, key))
+}
+
+/// Matches the differing lines of two JSON-lines files into records by a key field
+/// 
+/// Like [`merge_modifications`], this is a presentation step for the set-based comparison. Lines
+/// that aren't JSON objects, lack the key field or repeat a key already matched stay removed or added.
+///
+/// # Arguments
+///
+/// * `only_in_first` - Lines only in the first file
+/// * `only_in_second` - Lines only in the second file
+/// * `key_field` - Top-level field identifying a record across files
+///
+/// # Returns
+///
+/// The changed records with their diverging fields, and the remaining removed and added lines
+pub fn diff_json_records(only_in_first: &[String], only_in_second: &[String], key_field: &str) -> JsonRecordDiff {
+    let records2: Vec<_> = only_in_second.iter().map(|line| json_record(line, key_field)).collect();
+    let mut index_by_key = HashMap::new();
+    for (index2, record) in records2.iter().enumerate() {
+        if let Some((object2, key)) = record {
+            index_by_key.entry(key.as_str()).or_insert((index2, object2));
+        }
+    }
+    
+    let mut record_diff = JsonRecordDiff::default();
+    let mut used = vec![false; only_in_second.len()];
+    for line1 in only_in_first {
+        let matched = json_record(line1, key_field)
+            .and_then(|(object1, key)| index_by_key.get(key.as_str()).map(|&(index2, object2)| (object1, key, index2, object2)))
+            .filter(|&(_, _, index2, _)| !used[index2]);
+        let Some((object1, key, index2, object2)) = matched else {
+            record_diff.removed.push(line1.clone());
+            continue;
+        };
+        used[index2] = true;
+        
+        let field_names: BTreeSet<&String> = object1.keys().chain(object2.keys()).collect();
+        let fields = field_names.into_iter()
+            .filter(|&name| object1.get(name) != object2.get(name))
+            .map(|name| JsonFieldChange {
+                field: name.clone(),
+                old: object1.get(name).map(|value| value.to_string()),
+                new: object2.get(name).map(|value| value.to_string()),
+            })
+            .collect();
+        record_diff.changed.push(JsonRecordChange { key, fields });
+    }
+    record_diff.added = only_in_second.iter().zip(used)
+        .filter(|(_, used)| !used)
+        .map(|(line2, _)| line2.clone())
+        .collect();
+    record_diff
+}
+
+/// Unit of the intra-line diff of a modified line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DiffGranularity {
+    /// Individual characters
+    #[default]
+    Char,
+    /// Words and the whitespace between them
+    Word,
+}
+
+impl FromStr for DiffGranularity {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "char" => Ok(DiffGranularity::Char),
+            "word" => Ok(DiffGranularity::Word),
+            _ => anyhow::bail!("Unknown diff granularity: {} (expected char or word)", s),
+        }
+    }
+}
+
+impl fmt::Display for DiffGranularity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DiffGranularity::Char => "char",
+            DiffGranularity::Word => "word",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Renders the changes from one line to another inline, as `[-removed-]` and `{+added+}` spans
+/// 
+/// Consecutive removed or added units are grouped into a single span, removals before additions.
+/// 
+/// # Arguments
+/// 
+/// * `old` - The line in the first file
+/// * `new` - The line in the second file
+/// * `granularity` - Whether to diff characters or words
+/// 
+/// # Returns
+/// 
+/// The line with its unchanged text as is and its changed spans marked
+pub fn inline_diff(old: &str, new: &str, granularity: DiffGranularity) -> String {
+    let diff = match granularity {
+        DiffGranularity::Char => TextDiff::from_chars(old, new),
+        DiffGranularity::Word => TextDiff::from_words(old, new),
+    };
+    
+    let mut rendered = String::with_capacity(old.len() + new.len());
+    let mut removed = String::new();
+    let mut added = String::new();
+    let flush = |rendered: &mut String, removed: &mut String, added: &mut String| {
+        if !removed.is_empty() {
+            rendered.push_str(&format!("[-{}-]", removed));
+            removed.clear();
+        }
+        if !added.is_empty() {
+            rendered.push_str(&format!("{{+{}+}}", added));
+            added.clear();
+        }
+    };
+    for change in diff.iter_all_changes() {
+        match change.tag() {
+            ChangeTag::Delete => removed.push_str(change.value()),
+            ChangeTag::Insert => added.push_str(change.value()),
+            ChangeTag::Equal => {
+                flush(&mut rendered, &mut removed, &mut added);
+                rendered.push_str(change.value());
+            }
+        }
+    }
+    flush(&mut rendered, &mut removed, &mut added);
+    rendered
+}
+
+/// Formats a path as `<parent dir name>/<file name>`, independently of the platform separator
+fn normalized_short_path(path: &Path) -> String {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    match path.parent().and_then(|parent| parent.file_name()) {
+        Some(parent_name) => format!("{}/{}", parent_name.to_string_lossy(), file_name),
+        None => file_name.into_owned(),
+    }
+}
+
+/// Computes a stable identifier for a file pair, for cross-referencing pairs across runs
+/// 
+/// The ID is the 64-bit FNV-1a hash of both normalized short paths (`<parent dir>/<file name>`),
+/// so it doesn't depend on the pair's position in a report nor on where the directories are mounted.
+/// 
+/// # Arguments
+/// 
+/// * `file1_path` - Path to the first file
+/// * `file2_path` - Path to the second file
+/// 
+/// # Returns
+/// 
+/// The ID as 16 lowercase hexadecimal digits
+pub fn pair_id<P: AsRef<Path>>(file1_path: P, file2_path: P) -> String {
+    let key = format!("{}\n{}", normalized_short_path(file1_path.as_ref()), normalized_short_path(file2_path.as_ref()));
+    format!("{:016x}", fnv1a(key.as_bytes()))
+}
+
+/// Returns the path of a file named after a pair in `output_dir`: `<file1 stem>__<file2 stem>.<pair id>.<extension>`
+/// 
+/// The [`pair_id`] keeps same-named files of different directories (tree, candidate or manifest pairs) apart.
+/// 
+/// # Arguments
+/// 
+/// * `file1_path` - Path to the first file
+/// * `file2_path` - Path to the second file
+/// * `output_dir` - Directory the file is written to
+/// * `extension` - Extension of the file, without the leading dot
+/// 
+/// # Returns
+/// 
+/// The path of the file in `output_dir`
+pub fn pair_output_path(file1_path: &Path, file2_path: &Path, output_dir: &Path, extension: &str) -> PathBuf {
+    let stem1 = file1_path.file_stem().unwrap_or_default().to_string_lossy();
+    let stem2 = file2_path.file_stem().unwrap_or_default().to_string_lossy();
+    output_dir.join(format!("{}__{}.{}.{}", stem1, stem2, pair_id(file1_path, file2_path), extension))
+}
+
+/// Formula used to turn two line sets A and B into a similarity score
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SimilarityMetric {
+    /// |A ∩ B| / |A ∪ B|
+    #[default]
+    Jaccard,
+    /// |A ∩ B| / min(|A|, |B|)
+    Overlap,
+    /// 2 |A ∩ B| / (|A| + |B|)
+    Dice,
+}
+
+impl FromStr for SimilarityMetric {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "jaccard" => Ok(SimilarityMetric::Jaccard),
+            "overlap" => Ok(SimilarityMetric::Overlap),
+            "dice" => Ok(SimilarityMetric::Dice),
+            _ => anyhow::bail!("Unknown similarity metric: {} (expected jaccard, overlap or dice)", s),
+        }
+    }
+}
+
+impl fmt::Display for SimilarityMetric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SimilarityMetric::Jaccard => "jaccard",
+            SimilarityMetric::Overlap => "overlap",
+            SimilarityMetric::Dice => "dice",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Summed line weights of the lines only in each file and of the lines in both
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DiffWeights {
+    /// Total weight of the lines only in the first file
+    pub only_in_first: f64,
+    /// Total weight of the lines only in the second file
+    pub only_in_second: f64,
+    /// Total weight of the lines present in both files
+    pub common: f64,
+}
+
+impl DiffWeights {
+    /// Returns the total weight of the differing lines
+    pub fn differing(&self) -> f64 {
+        self.only_in_first + self.only_in_second
+    }
+}
+
+/// Rules giving lines more or less importance in the similarity score and differing line counts
+/// Each line takes the weight of the first rule whose regex matches it, or 1 if none matches.
+#[derive(Debug, Clone, Default)]
+pub struct LineWeights {
+    rules: Vec<(Regex, f64)>,
+}
+
+impl LineWeights {
+    /// Builds weighting rules from (regex, weight) pairs, tried in order
+    pub fn new(rules: Vec<(Regex, f64)>) -> Self {
+        LineWeights { rules }
+    }
+    
+    /// Loads weighting rules from a file with one `<weight> <regex>` rule per line
+    /// Empty lines are ignored; the file's encoding is detected like any input file
+    /// 
+    /// # Arguments
+    /// 
+    /// * `file_path` - Path to the weights file
+    /// 
+    /// # Returns
+    /// 
+    /// A Result containing either the rules or an error naming the first invalid rule
+    pub fn load<P: AsRef<Path>>(file_path: P) -> Result<Self> {
+        let file_path = file_path.as_ref();
+        let mut rules = Vec::new();
+        for line in read_list_lines(file_path)? {
+            let (weight, pattern) = line.split_once(char::is_whitespace)
+                .with_context(|| format!("Invalid weight rule in {}: expected \"<weight> <regex>\", got {:?}",
+                                         file_path.display(), line))?;
+            let weight: f64 = weight.parse()
+                .ok()
+                .filter(|weight: &f64| weight.is_finite() && *weight >= 0.0)
+                .with_context(|| format!("Invalid weight {:?} in {}: expected a non-negative number",
+                                         weight, file_path.display()))?;
+            let regex = Regex::new(pattern.trim_start())
+                .with_context(|| format!("Invalid regex {:?} in {}", pattern.trim_start(), file_path.display()))?;
+            rules.push((regex, weight));
+        }
+        Ok(Self::new(rules))
+    }
+    
+    /// Returns the weight of a line
+    pub fn weight(&self, line: &str) -> f64 {
+        self.rules.iter()
+            .find(|(regex, _)| regex.is_match(line))
+            .map_or(1.0, |(_, weight)| *weight)
+    }
+    
+    /// Returns the total weight of some lines
+    fn total<'a, I: IntoIterator<Item = &'a str>>(&self, lines: I) -> f64 {
+        // Folding from +0.0 (`sum()` starts at -0.0), so an empty total doesn't print as "-0.00"
+        lines.into_iter().fold(0.0, |total, line| total + self.weight(line))
+    }
+}
+
+/// Default of [`CompareOptions::external_diff_min_bytes`]: spawning `diff` costs more than comparing smaller files in memory
+pub const DEFAULT_EXTERNAL_DIFF_MIN_BYTES: u64 = 64 * 1024;
+
+/// Options controlling how a pair of files is compared
+#[derive(Debug, Clone)]
+pub struct CompareOptions {
+    /// Options applied when reading and normalizing each file
+    pub process: ProcessOptions,
+    /// Pairs whose files are both smaller than this many bytes skip the external
+    /// `diff`/`fc.exe` check and are compared in memory directly (0 = always run it,
+    /// default [`DEFAULT_EXTERNAL_DIFF_MIN_BYTES`])
+    pub external_diff_min_bytes: u64,
+    /// Compare lines in file order (positional diff) instead of as sets
+    pub ordered: bool,
+    /// Field both files are stably sorted by before an ordered comparison
+    pub sort_key: Option<SortKey>,
+    /// Weights of the lines in the similarity score and differing line counts
+    pub line_weights: LineWeights,
+    /// Program used for the quick identity check instead of `diff` (`fc.exe` on Windows)
+    pub diff_command: Option<String>,
+    /// Compare the raw bytes of both files before anything else, treating byte-identical files as
+    /// identical without decoding them or running the external check
+    pub byte_identity: bool,
+    /// Header lines skipped in the second file of a pair when they differ from the first file's
+    /// (`None` skips `process.header_lines` in both)
+    pub second_header_lines: Option<usize>,
+    /// Command the second file of a pair is piped through before decoding, in place of `process.transform_cmd`
+    /// (which then only applies to the first file); `None` leaves the second file's bytes as they are
+    pub second_transform_cmd: Option<String>,
+}
+
+impl Default for CompareOptions {
+    fn default() -> Self {
+        CompareOptions {
+            process: ProcessOptions::default(),
+            external_diff_min_bytes: DEFAULT_EXTERNAL_DIFF_MIN_BYTES,
+            ordered: false,
+            sort_key: None,
+            line_weights: LineWeights::default(),
+            diff_command: None,
+            byte_identity: false,
+            second_header_lines: None,
+            second_transform_cmd: None,
+        }
+    }
+}
+
+impl CompareOptions {
+    /// Returns the options applied to the second file of a pair
+    pub fn for_second_file(&self) -> Cow<'_, CompareOptions> {
+        if self.second_header_lines.is_none() && self.second_transform_cmd == self.process.transform_cmd {
+            return Cow::Borrowed(self);
+        }
+        Cow::Owned(CompareOptions {
+            process: ProcessOptions {
+                header_lines: self.second_header_lines.or(self.process.header_lines),
+                transform_cmd: self.second_transform_cmd.clone(),
+                ..self.process.clone()
+            },
+            second_header_lines: None,
+            ..self.clone()
+        })
+    }
+    
+    /// Returns true if the raw bytes of both files are compared as they are (no transform command, or the
+    /// same on both sides, and the same header lines skipped), so byte-identical files are identical
+    fn compares_raw_bytes(&self) -> bool {
+        self.process.transform_cmd == self.second_transform_cmd
+            && self.second_header_lines.is_none_or(|header_lines| Some(header_lines) == self.process.header_lines)
+    }
+    
+    /// Returns true if identical files may be detected without processing their lines: the raw bytes are
+    /// compared as they are, and no rule usage is counted (files skipped unread would count no rule hits)
+    fn may_skip_processing(&self) -> bool {
+        self.compares_raw_bytes() && self.process.rule_usage.is_none()
+    }
+}
+
+/// Lines of several files that are not present in all of them, with the files containing each
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LineOrigins {
+    /// Each line missing from at least one file, with the 1-based indices of the files containing it,
+    /// sorted by line
+    pub lines: Vec<(String, Vec<usize>)>,
+    /// Number of distinct lines present in every file
+    pub common_count: usize,
+}
+
+impl LineOrigins {
+    /// Formats the indices of the files containing a line compactly, e.g. `[1,3]`
+    pub fn format_origins(origins: &[usize]) -> String {
+        let indices: Vec<_> = origins.iter().map(usize::to_string).collect();
+        format!("[{}]", indices.join(","))
+    }
+}
+
+/// Compares the line sets of any number of files, tagging each line with the files it appears in
+/// 
+/// This generalizes `only_in_first`/`only_in_second` of [`FileDifferences`] to N inputs.
+/// Lines are reported with their text before normalization, as found in the first file containing them.
+///
+/// # Arguments
+///
+/// * `file_paths` - Paths of the files to compare
+/// * `options` - Options applied when reading each file
+///
+/// # Returns
+///
+/// A Result containing the lines that aren't present in every file
+pub fn compare_many<P: AsRef<Path>>(file_paths: &[P], options: &CompareOptions) -> Result<LineOrigins> {
+    let mut presence: BTreeMap<String, (String, Vec<usize>)> = BTreeMap::new();
+    for (index, file_path) in file_paths.iter().enumerate() {
+        let processed = process_file_unsorted(file_path.as_ref(), &options.process)?;
+        for line in &processed.lines {
+            let (_, origins) = presence.entry(line.clone())
+                .or_insert_with(|| (processed.original(line).to_string(), Vec::new()));
+            // Duplicated lines are listed once per file
+            if origins.last() != Some(&(index + 1)) {
+                origins.push(index + 1);
+            }
+        }
+    }
+    
+    let mut line_origins = LineOrigins::default();
+    for (_, (original, origins)) in presence {
+        if origins.len() == file_paths.len() {
+            line_origins.common_count += 1;
+        } else {
+            line_origins.lines.push((original, origins));
+        }
+    }
+    Ok(line_origins)
+}
+
+/// Field used to order lines before an ordered comparison
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SortKey {
+    /// 1-based index of the field
+    pub field: usize,
+    /// Delimiter separating the fields of a line
+    pub delimiter: String,
+}
+
+impl SortKey {
+    /// Extracts the key field from a line; lines with too few fields have an empty key
+    pub fn extract<'a>(&self, line: &'a str) -> &'a str {
+        line.split(self.delimiter.as_str())
+            .nth(self.field.saturating_sub(1))
+            .unwrap_or("")
+    }
+}
+
+/// Number of records of one bucket (value of the bucket field) in both files of a pair
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BucketCount {
+    /// Value of the bucket field shared by the records
+    pub bucket: String,
+    /// Records of the bucket in the first file
+    pub count1: usize,
+    /// Records of the bucket in the second file
+    pub count2: usize,
+}
+
+impl BucketCount {
+    /// Returns how many more records the second file has in the bucket (negative if it has fewer)
+    pub fn delta(&self) -> i64 {
+        self.count2 as i64 - self.count1 as i64
+    }
+}
+
+/// Counts the records of both files per value of a field, without comparing the records themselves
+/// 
+/// The counts are taken on the processed lines (after header skipping and normalization; duplicates count
+/// once per occurrence), so a report can show which segments grew or shrank without any line content.
+/// 
+/// # Arguments
+/// 
+/// * `file1_path` - Path to the first file
+/// * `file2_path` - Path to the second file
+/// * `options` - Comparison options applied when reading each file
+/// * `bucket_field` - Field whose value buckets the records (lines with too few fields are in the empty bucket)
+/// 
+/// # Returns
+/// 
+/// A Result containing the counts of every bucket present in either file, sorted by bucket
+pub fn compare_bucket_counts<P: AsRef<Path>>(file1_path: P, file2_path: P, options: &CompareOptions, bucket_field: &SortKey) -> Result<Vec<BucketCount>> {
+    let file1_path = file1_path.as_ref();
+    let file2_path = file2_path.as_ref();
+    let processed1 = process_file(file1_path, &options.process)
+        .with_context(|| format!("Failed to read and process file: {}", file1_path.display()))?;
+    let processed2 = process_file(file2_path, &options.for_second_file().process)
+        .with_context(|| format!("Failed to read and process file: {}", file2_path.display()))?;
+    
+    let mut counts: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+    for line in &processed1.lines {
+        counts.entry(bucket_field.extract(line)).or_default().0 += 1;
+    }
+    for line in &processed2.lines {
+        counts.entry(bucket_field.extract(line)).or_default().1 += 1;
+    }
+    Ok(counts.into_iter()
+        .map(|(bucket, (count1, count2))| BucketCount { bucket: bucket.to_string(), count1, count2 })
+        .collect())
+}
+
+/// Fields of a line masked when it is shown, so a report can be shared without their values
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnRedaction {
+    /// 1-based indices of the masked fields
+    pub columns: Vec<usize>,
+    /// Delimiter separating the fields of a line
+    pub delimiter: String,
+}
+
+impl ColumnRedaction {
+    /// Text replacing the value of a masked field
+    pub const MASK: &'static str = "***";
+    
+    /// Returns a line with its masked fields replaced by [`ColumnRedaction::MASK`]; missing fields are ignored
+    pub fn redact<'a>(&self, line: &'a str) -> Cow<'a, str> {
+        if self.columns.is_empty() {
+            return Cow::Borrowed(line);
+        }
+        let fields: Vec<&str> = line.split(self.delimiter.as_str())
+            .enumerate()
+            .map(|(index, field)| if self.columns.contains(&(index + 1)) { Self::MASK } else { field })
+            .collect();
+        Cow::Owned(fields.join(&self.delimiter))
+    }
+    
+    /// Masks every line of the differences that can be shown: the lines only in either file and the
+    /// whitespace-only lines. Counts, weights and similarity keep the values computed on the full lines.
+    pub fn redact_differences(&self, mut differences: FileDifferences) -> FileDifferences {
+        if self.columns.is_empty() {
+            return differences;
+        }
+        for line in differences.only_in_first.iter_mut().chain(&mut differences.only_in_second) {
+            *line = self.redact(line).into_owned();
+        }
+        for whitespace_diff in &mut differences.whitespace_only_diffs {
+            whitespace_diff.line = self.redact(&whitespace_diff.line).into_owned();
+        }
+        differences
+    }
 }
 
 /// Compares two files using system commands for efficiency
@@ -27,6 +748,47 @@ pub struct FileDifferences {
 /// 
 /// A Result containing either the differences or an error
 pub fn compare_files<P: AsRef<Path>>(file1_path: P, file2_path: P) -> Result<Option<FileDifferences>> {
+    compare_files_with_options(file1_path, file2_path, &CompareOptions::default())
+}
+
+/// Compares two files like [`compare_files`], applying the given options
+/// 
+/// # Arguments
+/// 
+/// * `file1_path` - Path to the first file
+/// * `file2_path` - Path to the second file
+/// * `options` - Comparison options to apply
+/// 
+/// # Returns
+/// 
+/// A Result containing either the differences or an error
+pub fn compare_files_with_options<P: AsRef<Path>>(file1_path: P, file2_path: P, options: &CompareOptions) -> Result<Option<FileDifferences>> {
+    compare_files_with_stats(file1_path, file2_path, options).map(|(differences, _)| differences)
+}
+
+/// Resources used by the comparison of a file pair
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ComparisonStats {
+    /// Estimated peak memory, in bytes, of the pair's processed lines and line sets
+    /// (0 when the external check found the files identical without reading them)
+    pub memory_estimate: u64,
+    /// Number of lines compared in each file, see [`FileDifferences::line_counts`]
+    /// (`None` when the external check found the files identical without reading them)
+    pub line_counts: Option<(usize, usize)>,
+}
+
+/// Compares two files like [`compare_files_with_options`], also returning the resources used
+/// 
+/// # Arguments
+/// 
+/// * `file1_path` - Path to the first file
+/// * `file2_path` - Path to the second file
+/// * `options` - Comparison options to apply
+/// 
+/// # Returns
+/// 
+/// A Result containing either the differences and the comparison's stats, or an error
+pub fn compare_files_with_stats<P: AsRef<Path>>(file1_path: P, file2_path: P, options: &CompareOptions) -> Result<(Option<FileDifferences>, ComparisonStats)> {
     let file1_path = file1_path.as_ref();
     let file2_path = file2_path.as_ref();
     
@@ -39,76 +801,69 @@ pub fn compare_files<P: AsRef<Path>>(file1_path: P, file2_path: P) -> Result<Opt
         anyhow::bail!("File {} does not exist", file2_path.display());
     }
     
-    // Try using system commands for comparison first (more efficient for large files)
-    // On Windows, use fc.exe; on Unix-like systems, use diff
-    #[cfg(windows)]
-    {
-        // Use fc.exe on Windows
-        let output = Command::new("fc.exe")
-            .arg("/L")  // Compare as text files
-            .arg(file1_path)
-            .arg(file2_path)
-            .output()
-            .context("Failed to execute fc.exe command")?;
-            
-        // fc.exe returns 0 if files are identical, 1 if different, 2 if error
-        match output.status.code() {
-            Some(0) => {
-                // Files are identical
-                info!("{} and {} are identical", file1_path.display(), file2_path.display());
-                return Ok(None);
-            }
-            Some(1) => {
-                // Files are different, fall through to detailed comparison
-            }
-            _ => {
-                // Error occurred, fall through to detailed comparison
-                info!("fc.exe failed, falling back to detailed comparison");
-            }
-        }
+    // Reading both files side by side stops at the first differing chunk and costs no process;
+    // differing bytes (e.g. another encoding) still go through the decoding comparison below
+    if options.byte_identity && options.may_skip_processing() && same_bytes(file1_path, file2_path).unwrap_or(false) {
+        info!("{} and {} are byte-identical", file1_path.display(), file2_path.display());
+        return Ok((None, ComparisonStats::default()));
     }
     
-    #[cfg(not(windows))]
-    {
-        // Use diff on Unix-like systems
-        let output = Command::new("diff")
-            .arg("-q")  // Quiet mode - just report if files differ
-            .arg(file1_path)
-            .arg(file2_path)
-            .output()
-            .context("Failed to execute diff command")?;
-            
-        if output.status.success() {
-            // Files are identical (diff returned 0)
+    // Spawning a process costs more than comparing small files in memory; a file of unknown size counts as large
+    let size = |path: &Path| file_size(path).unwrap_or(u64::MAX);
+    let use_external_diff = options.may_skip_processing() && (options.external_diff_min_bytes == 0
+        || size(file1_path).max(size(file2_path)) >= options.external_diff_min_bytes);
+    
+    if use_external_diff {
+        // Try using system commands for comparison first (more efficient for large files)
+        // On Windows, use fc.exe; on Unix-like systems, use diff
+        if external_diff_identical(file1_path, file2_path, options.diff_command.as_deref()) {
             info!("{} and {} are identical", file1_path.display(), file2_path.display());
-            return Ok(None);
-        } else if !output.stderr.is_empty() {
-            // Error occurred, fall through to detailed comparison
-            info!("diff failed, falling back to detailed comparison");
+            return Ok((None, ComparisonStats::default()));
         }
-        // If diff succeeded but files are different, we fall through to detailed comparison
     }
     
     // If system commands couldn't determine identity or we need detailed differences,
     // fall back to our detailed comparison implementation
+    if options.ordered {
+        return compare_ordered(file1_path, file2_path, options);
+    }
     
     // Read and process files
-    let lines1 = super::file_utils::read_and_process_file(file1_path)
+    let processed1 = process_file(file1_path, &options.process)
         .with_context(|| format!("Failed to read and process file: {}", file1_path.display()))?;
-    let lines2 = super::file_utils::read_and_process_file(file2_path)
+    let processed2 = process_file(file2_path, &options.for_second_file().process)
         .with_context(|| format!("Failed to read and process file: {}", file2_path.display()))?;
     
+    // Both sides are sorted, so identical contents can be detected without building the sets
+    // (unless trailing whitespace must still be checked)
+    let lines_memory = processed_memory(&processed1) + processed_memory(&processed2);
+    let line_counts = (processed1.lines.len(), processed2.lines.len());
+    if processed1.lines == processed2.lines && !options.process.track_trailing_whitespace {
+        info!("{} and {} have no differences", file1_path.display(), file2_path.display());
+        return Ok((None, ComparisonStats { memory_estimate: lines_memory, line_counts: Some(line_counts) }));
+    }
+    
     // Convert to sets for comparison
-    let set1: HashSet<_> = lines1.into_iter().collect();
-    let set2: HashSet<_> = lines2.into_iter().collect();
+    let set1: HashSet<&str> = processed1.lines.iter().map(String::as_str).collect();
+    let set2: HashSet<&str> = processed2.lines.iter().map(String::as_str).collect();
+    let stats = ComparisonStats {
+        memory_estimate: lines_memory + set_memory(set1.len()) + set_memory(set2.len()),
+        line_counts: Some(line_counts),
+    };
     
-    // Find differences
-    let only_in_first: Vec<_> = set1.difference(&set2).cloned().collect();
-    let only_in_second: Vec<_> = set2.difference(&set1).cloned().collect();
+    // Find differences, reporting lines as they appeared before normalization
+    let only_in_first: Vec<_> = set1.difference(&set2)
+        .map(|line| processed1.original(line).to_string())
+        .collect();
+    let only_in_second: Vec<_> = set2.difference(&set1)
+        .map(|line| processed2.original(line).to_string())
+        .collect();
     
-    if only_in_first.is_empty() && only_in_second.is_empty() {
+    let whitespace_only_diffs = trailing_whitespace_diffs(&processed1, &processed2, |line| set1.contains(line) && set2.contains(line));
+    
+    if only_in_first.is_empty() && only_in_second.is_empty() && whitespace_only_diffs.is_empty() {
         info!("{} and {} have no differences", file1_path.display(), file2_path.display());
-        Ok(None)
+        Ok((None, stats))
     } else {
         info!("{} and {} have differences", file1_path.display(), file2_path.display());
         if !only_in_first.is_empty() {
@@ -123,128 +878,1953 @@ pub fn compare_files<P: AsRef<Path>>(file1_path: P, file2_path: P) -> Result<Opt
                 info!("  {}", line);
             }
         }
-        Ok(Some(FileDifferences {
+        let common: Vec<_> = set1.intersection(&set2)
+            .map(|line| processed1.original(line))
+            .collect();
+        let weights = DiffWeights {
+            only_in_first: options.line_weights.total(only_in_first.iter().map(String::as_str)),
+            only_in_second: options.line_weights.total(only_in_second.iter().map(String::as_str)),
+            common: options.line_weights.total(common.iter().copied()),
+        };
+        Ok((Some(FileDifferences {
             only_in_first,
             only_in_second,
-        }))
+            common_count: common.len(),
+            pair_id: pair_id(file1_path, file2_path),
+            weights,
+            truncated_lines: (processed1.truncated_lines, processed2.truncated_lines),
+            whitespace_only_diffs,
+            line_counts,
+            common_edges: None,
+        }), stats))
     }
 }
 
-/// Generates file name pairs based on the actual files in the directories
-/// Files are matched based on the pattern SC_aaaaaaaa_yyyymmdd_tttN_AXX_Z where
-/// aaaaaaaa, yyyymmdd, and AXX must be the same, but tttN (version) may differ.
-///
-/// # Arguments
-///
-/// * `dir1_path` - Path to the first directory
-/// * `dir2_path` - Path to the second directory
-///
-/// # Returns
-///
-/// A vector of tuples containing file path pairs
-pub fn generate_file_pairs<P: AsRef<Path>>(dir1_path: P, dir2_path: P) -> Result<Vec<(PathBuf, PathBuf)>> {
-    let dir1_path = dir1_path.as_ref();
-    let dir2_path = dir2_path.as_ref();
+/// Finds the lines in both files whose trailing whitespace differs
+fn trailing_whitespace_diffs(processed1: &ProcessedFile, processed2: &ProcessedFile, in_both: impl Fn(&str) -> bool) -> Vec<WhitespaceOnlyDiff> {
+    let lines: BTreeSet<&String> = processed1.trailing_whitespace.keys().chain(processed2.trailing_whitespace.keys()).collect();
+    lines.into_iter()
+        .filter(|line| in_both(line))
+        .filter_map(|line| {
+            let trailing1 = processed1.trailing_whitespace.get(line).map_or("", String::as_str);
+            let trailing2 = processed2.trailing_whitespace.get(line).map_or("", String::as_str);
+            (trailing1 != trailing2).then(|| WhitespaceOnlyDiff {
+                line: processed1.original(line).to_string(),
+                trailing1: trailing1.to_string(),
+                trailing2: trailing2.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Estimates the memory held by a processed file: its lines and the original text of normalized lines
+fn processed_memory(processed: &ProcessedFile) -> u64 {
+    let string_size = std::mem::size_of::<String>();
+    let lines: usize = processed.lines.iter().map(|line| line.len() + string_size).sum();
+    let originals: usize = processed.originals.iter()
+        .map(|(normalized, original)| normalized.len() + original.len() + 2 * string_size)
+        .sum();
+    (lines + originals) as u64
+}
+
+/// Estimates the memory of a hash set of `len` borrowed lines (entries plus one control byte per
+/// bucket, with buckets at most 7/8 full)
+fn set_memory(len: usize) -> u64 {
+    let buckets = (len * 8 / 7).next_power_of_two();
+    (buckets * (std::mem::size_of::<&str>() + 1)) as u64
+}
+
+/// Processes a file for an ordered comparison, stably sorting it by the key field if one is set
+fn process_file_ordered(file_path: &Path, options: &CompareOptions) -> Result<ProcessedFile> {
+    let mut processed = process_file_unsorted(file_path, &options.process)
+        .with_context(|| format!("Failed to read and process file: {}", file_path.display()))?;
     
-    // Read files from both directories
-    let files1: Vec<_> = fs::read_dir(dir1_path)
-        .with_context(|| format!("Failed to read directory: {}", dir1_path.display()))?
-        .filter_map(|entry| entry.ok())
-        .filter(|entry| entry.path().is_file())
-        .map(|entry| entry.path())
-        .collect();
-        
-    let files2: Vec<_> = fs::read_dir(dir2_path)
-        .with_context(|| format!("Failed to read directory: {}", dir2_path.display()))?
+    // A stable sort neutralizes incidental ordering differences between records with the same key
+    // while keeping the relative order of lines within each key
+    if let Some(sort_key) = &options.sort_key {
+        processed.lines.sort_by(|a, b| sort_key.extract(a).cmp(sort_key.extract(b)));
+    }
+    Ok(processed)
+}
+
+/// Writes the lines of a file exactly as they are compared, one per line
+/// 
+/// The lines are processed like in [`compare_files_with_options`] (header skipped, lines normalized,
+/// then sorted, or kept in file order for an ordered comparison), which helps diagnosing unexpected differences.
+/// 
+/// # Arguments
+/// 
+/// * `file_path` - Path to the file to process
+/// * `options` - Comparison options
+/// * `output_path` - Path of the file the processed lines are written to
+/// 
+/// # Returns
+/// 
+/// A Result indicating success or failure
+pub fn write_processed_lines<P: AsRef<Path>>(file_path: P, options: &CompareOptions, output_path: &Path) -> Result<()> {
+    let file_path = file_path.as_ref();
+    let processed = if options.ordered {
+        process_file_ordered(file_path, options)?
+    } else {
+        process_file(file_path, &options.process)
+            .with_context(|| format!("Failed to read and process file: {}", file_path.display()))?
+    };
+    
+    let mut content = String::new();
+    for line in &processed.lines {
+        content.push_str(line);
+        content.push('\n');
+    }
+    fs::write(output_path, content)
+        .with_context(|| format!("Failed to write processed lines: {}", output_path.display()))
+}
+
+/// Checks whether two files hold the same lines in a different order
+/// 
+/// The set-based comparison ignores line order, so this tells apart identical files from files
+/// whose lines were only reordered. Lines are processed like in [`compare_files_with_options`], but not sorted.
+/// 
+/// # Arguments
+/// 
+/// * `file1_path` - Path to the first file
+/// * `file2_path` - Path to the second file
+/// * `options` - Comparison options
+/// 
+/// # Returns
+/// 
+/// True if both files have the same lines (with the same multiplicities) in a different order
+pub fn is_reordered<P: AsRef<Path>>(file1_path: P, file2_path: P, options: &CompareOptions) -> Result<bool> {
+    let file1_path = file1_path.as_ref();
+    let file2_path = file2_path.as_ref();
+    
+    let mut lines1 = process_file_unsorted(file1_path, &options.process)
+        .with_context(|| format!("Failed to read and process file: {}", file1_path.display()))?
+        .lines;
+    let mut lines2 = process_file_unsorted(file2_path, &options.for_second_file().process)
+        .with_context(|| format!("Failed to read and process file: {}", file2_path.display()))?
+        .lines;
+    if lines1 == lines2 {
+        return Ok(false);
+    }
+    
+    lines1.sort_unstable();
+    lines2.sort_unstable();
+    Ok(lines1 == lines2)
+}
+
+/// Compares the processed lines of two files in file order (optionally sorted by a key field first)
+fn compare_ordered(file1_path: &Path, file2_path: &Path, options: &CompareOptions) -> Result<(Option<FileDifferences>, ComparisonStats)> {
+    let processed1 = process_file_ordered(file1_path, options)?;
+    let processed2 = process_file_ordered(file2_path, &options.for_second_file())?;
+    let line_counts = (processed1.lines.len(), processed2.lines.len());
+    let stats = ComparisonStats {
+        memory_estimate: processed_memory(&processed1) + processed_memory(&processed2),
+        line_counts: Some(line_counts),
+    };
+    
+    let diff = diff_ordered(&processed1.lines, &processed2.lines);
+    if diff.is_empty() {
+        info!("{} and {} have no differences", file1_path.display(), file2_path.display());
+        return Ok((None, stats));
+    }
+    
+    info!("{} and {} have {} removed and {} added lines", file1_path.display(), file2_path.display(),
+          diff.removed.len(), diff.added.len());
+    let only_in_first: Vec<_> = diff.removed.iter().map(|line| processed1.original(line).to_string()).collect();
+    let only_in_second: Vec<_> = diff.added.iter().map(|line| processed2.original(line).to_string()).collect();
+    
+    // Unchanged lines are the lines of the first file that weren't removed
+    let weight_of = |lines: &[String]| options.line_weights.total(lines.iter().map(String::as_str));
+    let total1 = options.line_weights.total(processed1.lines.iter().map(|line| processed1.original(line)));
+    let weights = DiffWeights {
+        only_in_first: weight_of(&only_in_first),
+        only_in_second: weight_of(&only_in_second),
+        common: total1 - weight_of(&only_in_first),
+    };
+    
+    Ok((Some(FileDifferences {
+        only_in_first,
+        only_in_second,
+        common_count: diff.unchanged,
+        pair_id: pair_id(file1_path, file2_path),
+        weights,
+        truncated_lines: (processed1.truncated_lines, processed2.truncated_lines),
+        whitespace_only_diffs: Vec::new(),
+        line_counts,
+        common_edges: Some((diff.common_prefix, diff.common_suffix)),
+    }), stats))
+}
+
+/// Command used for the quick identity check when none is configured
+#[cfg(windows)]
+const DEFAULT_DIFF_COMMAND: &str = "fc.exe";
+#[cfg(not(windows))]
+const DEFAULT_DIFF_COMMAND: &str = "diff";
+
+/// Runs the external quick identity check (`<command> /L` on Windows, `<command> -q` elsewhere)
+/// 
+/// Only an exit status of 0 counts as identical. Differing files, command errors and a command
+/// that can't be run all fall back to the detailed comparison.
+fn external_diff_identical(file1_path: &Path, file2_path: &Path, program: Option<&str>) -> bool {
+    let program = program.unwrap_or(DEFAULT_DIFF_COMMAND);
+    let mut command = Command::new(program);
+    if cfg!(windows) {
+        command.arg("/L");  // Compare as text files
+    } else {
+        command.arg("-q");  // Quiet mode - just report if files differ
+    }
+    let output = match command.arg(file1_path).arg(file2_path).output() {
+        Ok(output) => output,
+        Err(e) => {
+            info!("Failed to execute {}: {}, falling back to detailed comparison", program, e);
+            return false;
+        }
+    };
+    
+    // fc.exe and diff both return 0 if files are identical, 1 if different, 2 on error
+    match output.status.code() {
+        Some(0) => true,
+        Some(1) => false,
+        _ => {
+            info!("{} failed, falling back to detailed comparison", program);
+            false
+        }
+    }
+}
+
+/// Returns the size of a file in bytes, or `None` if it can't be determined
+pub fn file_size(path: &Path) -> Option<u64> {
+    fs::metadata(path).map(|metadata| metadata.len()).ok()
+}
+
+/// Fields of a file name following the pattern SC_aaaaaaaa_yyyymmdd_tttN_AXX_Z
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedFileName {
+    /// Identifier field (aaaaaaaa)
+    pub account: String,
+    /// Date field (yyyymmdd)
+    pub date: String,
+    /// Version field (tttN); may span several underscore-separated parts
+    pub version: String,
+    /// Report type field (AXX)
+    pub report_type: String,
+}
+
+impl ParsedFileName {
+    /// Returns the key used to match files across directories (aaaaaaaa_yyyymmdd_AXX)
+    pub fn match_key(&self) -> String {
+        format!("{}_{}_{}", self.account, self.date, self.report_type)
+    }
+    
+    /// Returns the numeric part of the version field, e.g. 19 for `019N`
+    pub fn version_number(&self) -> Option<u64> {
+        let digits: String = self.version.chars().take_while(|c| c.is_ascii_digit()).collect();
+        digits.parse().ok()
+    }
+}
+
+/// Strategy used to match the files of two directories into pairs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchBy {
+    /// Same aaaaaaaa_yyyymmdd_AXX key of the naming pattern (or of the key regex)
+    #[default]
+    Pattern,
+    /// Same file name
+    ExactName,
+    /// Same index once both directories' files are sorted by name
+    Position,
+}
+
+impl FromStr for MatchBy {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "pattern" => Ok(MatchBy::Pattern),
+            "exact-name" => Ok(MatchBy::ExactName),
+            "position" => Ok(MatchBy::Position),
+            _ => anyhow::bail!("Unknown pairing strategy: {} (expected pattern, exact-name or position)", s),
+        }
+    }
+}
+
+impl fmt::Display for MatchBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            MatchBy::Pattern => "pattern",
+            MatchBy::ExactName => "exact-name",
+            MatchBy::Position => "position",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Rewrite of the digits of a file name field, so differently padded names build the same match key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldNormalization {
+    /// Left-pad the first run of digits with zeros to this width (`A5` becomes `A05` with 2)
+    ZeroPad(usize),
+    /// Strip the leading zeros of the first run of digits, keeping at least one digit (`A05` becomes `A5`)
+    StripZeros,
+}
+
+impl FieldNormalization {
+    /// Applies the normalization to a field; fields without digits are returned unchanged
+    pub fn apply<'a>(&self, field: &'a str) -> Cow<'a, str> {
+        let Some(start) = field.find(|c: char| c.is_ascii_digit()) else {
+            return Cow::Borrowed(field);
+        };
+        let end = field[start..].find(|c: char| !c.is_ascii_digit()).map_or(field.len(), |end| start + end);
+        let digits = &field[start..end];
+        let digits = match *self {
+            FieldNormalization::ZeroPad(width) => format!("{:0>width$}", digits),
+            FieldNormalization::StripZeros => {
+                let stripped = digits.trim_start_matches('0');
+                if stripped.is_empty() { "0".to_string() } else { stripped.to_string() }
+            }
+        };
+        Cow::Owned(format!("{}{}{}", &field[..start], digits, &field[end..]))
+    }
+}
+
+/// Normalization of one underscore-separated field of the file name, parsed from `INDEX:pad=WIDTH`
+/// or `INDEX:strip-zeros` (indices as in [`PairingOptions::key_fields`], e.g. `-2:pad=2` for AXX)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyFieldNormalization {
+    /// 0-based index of the field; negative indices count from the end
+    pub field: isize,
+    /// Normalization applied to the field
+    pub normalization: FieldNormalization,
+}
+
+impl FromStr for KeyFieldNormalization {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        let (field, rule) = s.split_once(':')
+            .with_context(|| format!("Invalid field normalization: {} (expected INDEX:pad=WIDTH or INDEX:strip-zeros)", s))?;
+        let field: isize = field.trim().parse().with_context(|| format!("Invalid field index: {}", field))?;
+        let normalization = match rule.trim().to_ascii_lowercase().as_str() {
+            "strip-zeros" => FieldNormalization::StripZeros,
+            rule => {
+                let width = rule.strip_prefix("pad=")
+                    .with_context(|| format!("Unknown field normalization: {} (expected pad=WIDTH or strip-zeros)", rule))?;
+                FieldNormalization::ZeroPad(width.parse().with_context(|| format!("Invalid padding width: {}", width))?)
+            }
+        };
+        Ok(KeyFieldNormalization { field, normalization })
+    }
+}
+
+/// Options controlling how files are matched into pairs
+#[derive(Debug, Clone, Default)]
+pub struct PairingOptions {
+    /// Match file names case-insensitively, so `sc_..._z` pairs with `SC_..._Z`
+    pub case_insensitive: bool,
+    /// Regex with named capture groups matched against the file stem; when set, the match key
+    /// is built from the named captures (joined by `_`) instead of the underscore-split fields.
+    /// Build it with [`anchored_regex`] so it must match the whole stem, with `(?i)` when `case_insensitive` is set.
+    pub key_regex: Option<Regex>,
+    /// Only files whose match key is in this set take part in pairing (`None` keeps every file).
+    /// Keys are compared as computed, i.e. lowercased when `case_insensitive` is set.
+    pub keys: Option<HashSet<String>>,
+    /// Pairs where either file is smaller than this many bytes on disk are excluded
+    pub min_file_size: Option<u64>,
+    /// Pairs where either file is larger than this many bytes on disk are excluded
+    pub max_file_size: Option<u64>,
+    /// How the files of two directories are matched (not used when pairing versions within a directory)
+    pub match_by: MatchBy,
+    /// Regex the last field of the file name must match instead of being `Z` (e.g. `Z\d*` to also
+    /// accept `Z1`, `Z2`); like `Z`, the field isn't part of the match key. Build it with [`anchored_regex`]
+    /// so it must match the whole field.
+    /// With `case_insensitive` the field is lowercased first, so the regex should accept lowercase.
+    pub suffix_pattern: Option<Regex>,
+    /// Pairs where neither file was modified after this time are excluded (`None` keeps every pair)
+    pub modified_since: Option<SystemTime>,
+    /// Underscore-separated fields of the file name that make up the match key, in key order, instead of
+    /// aaaaaaaa, yyyymmdd and AXX. Indices are 0-based (`SC` is field 0); negative indices count from the
+    /// end (-1 is the last field). Names must still follow the pattern.
+    pub key_fields: Option<Vec<isize>>,
+    /// Separator joining the `key_fields` (`None` joins them with `_`)
+    pub key_separator: Option<String>,
+    /// Normalizations applied to fields of the file name before the match key is built from them,
+    /// on both sides (not used with `key_regex`)
+    pub key_normalizations: Vec<KeyFieldNormalization>,
+}
+
+impl PairingOptions {
+    /// Builds the match key of a file, or `None` if the file doesn't take part in pairing
+    fn match_key(&self, file_path: &Path) -> Option<String> {
+        self.unfiltered_match_key(file_path).filter(|key| self.is_selected(key))
+    }
+    
+    /// Returns the key a file is paired by (whether or not it is among the selected keys),
+    /// or `None` if its name doesn't yield one
+    pub fn key_for(&self, file_path: &Path) -> Option<String> {
+        self.unfiltered_match_key(file_path)
+    }
+    
+    /// Builds the match key of a file, whether or not it is among the selected keys
+    fn unfiltered_match_key(&self, file_path: &Path) -> Option<String> {
+        let key = match &self.key_regex {
+            _ if self.match_by == MatchBy::ExactName => {
+                let name = file_path.file_name()?.to_string_lossy();
+                if self.case_insensitive { name.to_lowercase() } else { name.into_owned() }
+            }
+            Some(key_regex) => {
+                let stem = logical_stem(file_path)?;
+                let captures = key_regex.captures(stem)?;
+                let key = key_regex.capture_names()
+                    .flatten()
+                    .map(|name| captures.name(name).map_or("", |m| m.as_str()))
+                    .collect::<Vec<_>>()
+                    .join("_");
+                if self.case_insensitive { key.to_lowercase() } else { key }
+            }
+            None => {
+                let parsed = parse_filename_with_options(file_path, self)?;
+                self.pattern_key(file_path, &parsed)?
+            }
+        };
+        Some(key)
+    }
+    
+    /// Builds the key of a file whose name follows the pattern, from `key_fields` when set
+    /// Returns `None` if a selected field is out of range.
+    fn pattern_key(&self, file_path: &Path, parsed: &ParsedFileName) -> Option<String> {
+        if self.key_fields.is_none() && self.key_normalizations.is_empty() {
+            return Some(parsed.match_key());
+        }
+        let stem = logical_stem(file_path)?;
+        let stem = if self.case_insensitive { Cow::Owned(stem.to_lowercase()) } else { Cow::Borrowed(stem) };
+        let mut fields: Vec<Cow<str>> = stem.split('_').map(Cow::Borrowed).collect();
+        let position = |len: usize, index: isize| if index < 0 { len.checked_sub(index.unsigned_abs()) } else { Some(index as usize) };
+        for normalization in &self.key_normalizations {
+            if let Some(field) = position(fields.len(), normalization.field).and_then(|position| fields.get_mut(position)) {
+                *field = Cow::Owned(normalization.normalization.apply(field).into_owned());
+            }
+        }
+        // Same fields as ParsedFileName::match_key: aaaaaaaa, yyyymmdd and AXX
+        let key_fields = self.key_fields.as_deref().unwrap_or(&[1, 2, -2]);
+        let selected = key_fields.iter()
+            .map(|&index| fields.get(position(fields.len(), index)?).map(Cow::as_ref))
+            .collect::<Option<Vec<_>>>()?;
+        Some(selected.join(self.key_separator.as_deref().unwrap_or("_")))
+    }
+    
+    /// Returns true if files with the given match key take part in pairing
+    fn is_selected(&self, key: &str) -> bool {
+        self.keys.as_ref().is_none_or(|keys| keys.contains(key))
+    }
+    
+    /// Returns true if both files of a pair are within the size range, logging the pair otherwise
+    /// Files whose size can't be read are kept, so the comparison reports the problem.
+    fn is_size_in_range(&self, file1_path: &Path, file2_path: &Path) -> bool {
+        if self.min_file_size.is_none() && self.max_file_size.is_none() {
+            return true;
+        }
+        let in_range = |file_path: &Path| fs::metadata(file_path).map_or(true, |metadata| {
+            let size = metadata.len();
+            self.min_file_size.is_none_or(|min| size >= min) && self.max_file_size.is_none_or(|max| size <= max)
+        });
+        if in_range(file1_path) && in_range(file2_path) {
+            return true;
+        }
+        info!("{} 和 {} 的文件大小超出范围，已排除", file1_path.display(), file2_path.display());
+        false
+    }
+    
+    /// Returns true if either file of a pair was modified after `modified_since`, logging the pair otherwise
+    /// Files whose modification time can't be read count as modified.
+    fn is_modified_since(&self, file1_path: &Path, file2_path: &Path) -> bool {
+        let Some(since) = self.modified_since else {
+            return true;
+        };
+        let modified = |file_path: &Path| fs::metadata(file_path)
+            .and_then(|metadata| metadata.modified())
+            .map_or(true, |modified| modified > since);
+        if modified(file1_path) || modified(file2_path) {
+            return true;
+        }
+        info!("{} 和 {} 自上次运行以来未修改，已跳过", file1_path.display(), file2_path.display());
+        false
+    }
+    
+    /// Returns true if a pair passes both the size range and the modification time filters
+    fn is_included(&self, file1_path: &Path, file2_path: &Path) -> bool {
+        self.is_size_in_range(file1_path, file2_path) && self.is_modified_since(file1_path, file2_path)
+    }
+    
+    /// Returns why a pair whose counterpart exists was excluded
+    fn exclusion(&self, file1_path: &Path, file2_path: PathBuf) -> PairingOutcome {
+        if self.is_modified_since(file1_path, &file2_path) {
+            PairingOutcome::SizeOutOfRange(file2_path)
+        } else {
+            PairingOutcome::Unchanged(file2_path)
+        }
+    }
+}
+
+/// Compiles a regex that only matches a whole string, as `^(?:pattern)$`
+/// 
+/// Unlike checking the bounds of a match, this also accepts strings matched by a later alternative,
+/// e.g. `Z1` for `Z|Z1`.
+/// 
+/// # Arguments
+/// 
+/// * `pattern` - Regex to anchor
+/// 
+/// # Returns
+/// 
+/// A Result containing either the anchored regex or the error of an invalid pattern
+pub fn anchored_regex(pattern: &str) -> std::result::Result<Regex, regex::Error> {
+    Regex::new(&format!("^(?:{})$", pattern))
+}
+
+/// Returns the file stem used for pairing, ignoring a `.gz` suffix so that
+/// `SC_..._Z.txt.gz` pairs with `SC_..._Z.txt`
+fn logical_stem(file_path: &Path) -> Option<&str> {
+    let file_path = if is_gzip_path(file_path) {
+        Path::new(file_path.file_stem()?)
+    } else {
+        file_path
+    };
+    file_path.file_stem()?.to_str()
+}
+
+/// Parses a file name following the pattern SC_aaaaaaaa_yyyymmdd_tttN_AXX_Z[.ext][.gz]
+/// 
+/// # Arguments
+/// 
+/// * `file_path` - Path of the file whose name is parsed
+/// 
+/// # Returns
+/// 
+/// The parsed fields, or `None` if the file name doesn't match the pattern
+pub fn parse_filename<P: AsRef<Path>>(file_path: P) -> Option<ParsedFileName> {
+    parse_filename_with_options(file_path, &PairingOptions::default())
+}
+
+/// Parses a file name like [`parse_filename`], applying the given pairing options
+/// 
+/// # Arguments
+/// 
+/// * `file_path` - Path of the file whose name is parsed
+/// * `options` - Pairing options to apply
+/// 
+/// # Returns
+/// 
+/// The parsed fields, or `None` if the file name doesn't match the pattern
+pub fn parse_filename_with_options<P: AsRef<Path>>(file_path: P, options: &PairingOptions) -> Option<ParsedFileName> {
+    parse_filename_checked(file_path.as_ref(), options).ok()
+}
+
+/// Reason why a file name doesn't follow the SC_aaaaaaaa_yyyymmdd_tttN_AXX_Z pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NameMismatch {
+    /// The file name isn't valid UTF-8
+    NotUtf8,
+    /// The stem has fewer than the six underscore-separated fields of the pattern
+    TooFewFields(usize),
+    /// The first field isn't `SC`
+    WrongPrefix(String),
+    /// The last field isn't `Z` (or doesn't match the suffix pattern)
+    WrongSuffix(String),
+}
+
+/// Parses a file name like [`parse_filename_with_options`], returning why it doesn't match the pattern
+fn parse_filename_checked(file_path: &Path, options: &PairingOptions) -> std::result::Result<ParsedFileName, NameMismatch> {
+    let stem = logical_stem(file_path).ok_or(NameMismatch::NotUtf8)?;
+    // Lowercase before parsing so both the pattern checks and the match key ignore case
+    let stem = if options.case_insensitive {
+        stem.to_lowercase()
+    } else {
+        stem.to_string()
+    };
+    let (prefix, suffix) = if options.case_insensitive { ("sc", "z") } else { ("SC", "Z") };
+    let parts: Vec<&str> = stem.split('_').collect();
+    
+    // Check if the file name matches the expected pattern
+    if parts.len() < 6 {
+        return Err(NameMismatch::TooFewFields(parts.len()));
+    }
+    if parts[0] != prefix {
+        return Err(NameMismatch::WrongPrefix(parts[0].to_string()));
+    }
+    let last = parts[parts.len() - 1];
+    let suffix_matches = match &options.suffix_pattern {
+        Some(pattern) => pattern.is_match(last),
+        None => last == suffix,
+    };
+    if !suffix_matches {
+        return Err(NameMismatch::WrongSuffix(last.to_string()));
+    }
+    
+    Ok(ParsedFileName {
+        account: parts[1].to_string(),
+        date: parts[2].to_string(),
+        version: parts[3..parts.len() - 2].join("_"),
+        report_type: parts[parts.len() - 2].to_string(),
+    })
+}
+
+/// Lists the regular files directly inside a directory
+pub(crate) fn list_files(dir_path: &Path) -> Result<Vec<PathBuf>> {
+    Ok(fs::read_dir(dir_path)
+        .with_context(|| format!("Failed to read directory: {}", dir_path.display()))?
         .filter_map(|entry| entry.ok())
         .filter(|entry| entry.path().is_file())
         .map(|entry| entry.path())
+        .collect())
+}
+
+/// Lists the files of a directory that take part in position pairing, sorted by path
+/// 
+/// Hidden files (`.DS_Store`, editor swap and lock files) and `~` backups are left out, since
+/// they would shift every file after them onto the wrong partner.
+fn list_position_files(dir_path: &Path) -> Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = list_files(dir_path)?.into_iter()
+        .filter(|path| path.file_name().and_then(|name| name.to_str())
+            .is_some_and(|name| !name.starts_with('.') && !name.ends_with('~')))
         .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Generates pairs of consecutive versions of the same file within a single directory
+/// Files are grouped by their aaaaaaaa_yyyymmdd_AXX key and sorted by the tttN version;
+/// each version is paired with the one immediately before it.
+///
+/// # Arguments
+///
+/// * `dir_path` - Path to the directory
+/// * `options` - Pairing options to apply
+///
+/// # Returns
+///
+/// A vector of (previous version, next version) path pairs
+pub fn generate_version_pairs<P: AsRef<Path>>(dir_path: P, options: &PairingOptions) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let dir_path = dir_path.as_ref();
+    
+    let mut groups: HashMap<String, Vec<(ParsedFileName, PathBuf)>> = HashMap::new();
+    for file_path in list_files(dir_path)? {
+        if let Some(parsed) = parse_filename_with_options(&file_path, options)
+            && let Some(key) = options.pattern_key(&file_path, &parsed)
+            && options.is_selected(&key) {
+            groups.entry(key).or_default().push((parsed, file_path));
+        }
+    }
+    
+    // Sort keys so the output order doesn't depend on hashing
+    let mut keys: Vec<_> = groups.keys().cloned().collect();
+    keys.sort();
+    
+    let mut file_pairs = Vec::new();
+    for key in keys {
+        let mut versions = groups.remove(&key).unwrap_or_default();
+        versions.sort_by(|(a, _), (b, _)| {
+            a.version_number().cmp(&b.version_number()).then_with(|| a.version.cmp(&b.version))
+        });
+        for window in versions.windows(2) {
+            if options.is_included(&window[0].1, &window[1].1) {
+                file_pairs.push((window[0].1.clone(), window[1].1.clone()));
+            }
+        }
+    }
+    
+    info!("生成了 {} 个版本文件对", file_pairs.len());
+    Ok(file_pairs)
+}
+
+/// Generates file name pairs based on the actual files in the directories
+/// Files are matched based on the pattern SC_aaaaaaaa_yyyymmdd_tttN_AXX_Z where
+/// aaaaaaaa, yyyymmdd, and AXX must be the same, but tttN (version) may differ.
+///
+/// # Arguments
+///
+/// * `dir1_path` - Path to the first directory
+/// * `dir2_path` - Path to the second directory
+///
+/// # Returns
+///
+/// A vector of tuples containing file path pairs
+pub fn generate_file_pairs<P: AsRef<Path>>(dir1_path: P, dir2_path: P) -> Result<Vec<(PathBuf, PathBuf)>> {
+    generate_file_pairs_with_options(dir1_path, dir2_path, &PairingOptions::default())
+}
+
+/// Generates file name pairs like [`generate_file_pairs`], applying the given pairing options
+///
+/// # Arguments
+///
+/// * `dir1_path` - Path to the first directory
+/// * `dir2_path` - Path to the second directory
+/// * `options` - Pairing options to apply
+///
+/// # Returns
+///
+/// A vector of tuples containing file path pairs
+pub fn generate_file_pairs_with_options<P: AsRef<Path>>(dir1_path: P, dir2_path: P, options: &PairingOptions) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let dir1_path = dir1_path.as_ref();
+    let dir2_path = dir2_path.as_ref();
     
     let mut file_pairs = Vec::new();
     
+    if options.match_by == MatchBy::Position {
+        let files1 = list_position_files(dir1_path)?;
+        let files2 = list_position_files(dir2_path)?;
+        if files1.len() != files2.len() {
+            warn!("{} 中有 {} 个文件，{} 中有 {} 个文件，多出的文件不参与比较",
+                  dir1_path.display(), files1.len(), dir2_path.display(), files2.len());
+        }
+        for (file1_path, file2_path) in files1.iter().zip(&files2) {
+            if options.is_included(file1_path, file2_path) {
+                file_pairs.push((file1_path.clone(), file2_path.clone()));
+            }
+        }
+        info!("按位置生成了 {} 个文件对", file_pairs.len());
+        return Ok(file_pairs);
+    }
+    
+    // Read files from both directories
+    let files1 = list_files(dir1_path)?;
+    let files2 = list_files(dir2_path)?;
+    
     // Create a hash map for files in dir2 for O(1) lookup
-    let mut dir2_map = std::collections::HashMap::new();
+    let mut dir2_map = HashMap::new();
     
     // Populate the hash map with files from dir2
     for file2_path in &files2 {
-        if let Some(file2_stem) = file2_path.file_stem().and_then(|n| n.to_str()) {
-            let parts2: Vec<&str> = file2_stem.split('_').collect();
-            
-            // Check if the file name matches the expected pattern
-            if parts2.len() >= 6 && parts2[0] == "SC" && parts2[parts2.len()-1] == "Z" {
-                // Extract the parts that must match: aaaaaaaa, yyyymmdd, AXX
-                let key2 = format!("{}_{}_{}", parts2[1], parts2[2], parts2[parts2.len()-2]);
-                
-                // Store the file path in the hash map
-                dir2_map.insert(key2, file2_path.clone());
-            }
+        // Only files matching the expected pattern take part in pairing
+        if let Some(key2) = options.match_key(file2_path) {
+            dir2_map.insert(key2, file2_path.clone());
+        }
+    }
+    
+    // For each file in dir1, find the corresponding file in dir2 using the hash map
+    for file1_path in &files1 {
+        // Look up the matching file in dir2 using the hash map
+        if let Some(key1) = options.match_key(file1_path)
+            && let Some(file2_path) = dir2_map.get(&key1)
+            && options.is_included(file1_path, file2_path) {
+            file_pairs.push((file1_path.clone(), file2_path.clone()));
+        }
+    }
+    
+    info!("生成了 {} 个文件对", file_pairs.len());
+    Ok(file_pairs)
+}
+
+/// Checks whether two directories look like the same directory or a copy of it, from a sample of their pairs
+/// 
+/// Up to `samples` pairs, spread evenly over the list, are checked without any normalization: the directories
+/// look duplicated when every sampled pair is the same file or two byte-identical files. This is a cheap guard
+/// against comparing a directory with itself, where the whole run would find nothing.
+/// 
+/// # Arguments
+/// 
+/// * `pairs` - File pairs of the comparison
+/// * `samples` - Maximum number of pairs checked (0 disables the check)
+/// 
+/// # Returns
+/// 
+/// A Result containing whether every sampled pair is identical, or an error if a sampled file can't be read
+pub fn looks_duplicated(pairs: &[(PathBuf, PathBuf)], samples: usize) -> Result<bool> {
+    let samples = samples.min(pairs.len());
+    if samples == 0 {
+        return Ok(false);
+    }
+    for sample in 0..samples {
+        let (file1_path, file2_path) = &pairs[sample * pairs.len() / samples];
+        if !is_same_file(file1_path, file2_path)? && !same_bytes(file1_path, file2_path)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Compares the raw bytes of two files, stopping at the first difference
+fn same_bytes(file1_path: &Path, file2_path: &Path) -> Result<bool> {
+    let open = |path: &Path| File::open(path).with_context(|| format!("Failed to open file: {}", path.display()));
+    let (mut file1, mut file2) = (open(file1_path)?, open(file2_path)?);
+    if file1.metadata()?.len() != file2.metadata()?.len() {
+        return Ok(false);
+    }
+    
+    let (mut buffer1, mut buffer2) = (vec![0; 64 * 1024], vec![0; 64 * 1024]);
+    loop {
+        let read = file1.read(&mut buffer1)?;
+        if read == 0 {
+            return Ok(true);
+        }
+        // Both files have the same length, so the second one has at least as many bytes left
+        file2.read_exact(&mut buffer2[..read])?;
+        if buffer1[..read] != buffer2[..read] {
+            return Ok(false);
+        }
+    }
+}
+
+/// Outcome of pairing a single file of the first directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PairingOutcome {
+    /// The file is paired with this file of the second directory
+    Matched(PathBuf),
+    /// The file name doesn't follow the naming pattern
+    UnparsedName(NameMismatch),
+    /// The key regex doesn't match the file stem
+    KeyRegexMismatch,
+    /// The match key isn't in the list of selected keys
+    KeyNotSelected,
+    /// No file of the second directory has the same match key
+    NoCounterpart,
+    /// The second directory has fewer files, so none sits at the file's position
+    NoFileAtPosition(usize),
+    /// The counterpart exists, but either file is outside the size range
+    SizeOutOfRange(PathBuf),
+    /// The counterpart exists, but neither file was modified since the modification time filter
+    Unchanged(PathBuf),
+}
+
+/// Explanation of how a file of the first directory was (or wasn't) paired
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PairingExplanation {
+    /// Path of the explained file
+    pub file1_path: PathBuf,
+    /// Fields parsed from the file name, or `None` if it doesn't follow the naming pattern
+    pub parsed: Option<ParsedFileName>,
+    /// Key the file is matched by (even if it isn't among the selected keys), or `None` if none
+    /// could be computed or the files are matched by position
+    pub match_key: Option<String>,
+    /// Whether and why the file was paired
+    pub outcome: PairingOutcome,
+}
+
+/// Explains how a file of the first directory is paired with the files of the second directory
+/// 
+/// The pairs are generated exactly as in [`generate_file_pairs_with_options`], so the outcome always
+/// agrees with a real run; when the file isn't paired, the reason is worked out afterwards.
+///
+/// # Arguments
+///
+/// * `dir1_path` - Path to the first directory
+/// * `dir2_path` - Path to the second directory
+/// * `file_name` - Name of the file in the first directory
+/// * `options` - Pairing options to apply
+///
+/// # Returns
+///
+/// A Result containing either the explanation or an error (e.g. if the file isn't in the first directory)
+pub fn explain_pairing<P: AsRef<Path>>(dir1_path: P, dir2_path: P, file_name: &str, options: &PairingOptions) -> Result<PairingExplanation> {
+    let dir1_path = dir1_path.as_ref();
+    let dir2_path = dir2_path.as_ref();
+    let file1_path = dir1_path.join(file_name);
+    if !file1_path.is_file() {
+        anyhow::bail!("File not found in directory {}: {}", dir1_path.display(), file_name);
+    }
+    
+    let parsed = parse_filename_checked(&file1_path, options);
+    let match_key = match options.match_by {
+        MatchBy::Position => None,
+        _ => options.unfiltered_match_key(&file1_path),
+    };
+    let pairs = generate_file_pairs_with_options(dir1_path, dir2_path, options)?;
+    let paired = pairs.into_iter().find(|(paired1, _)| *paired1 == file1_path).map(|(_, file2_path)| file2_path);
+    
+    let outcome = match (paired, &match_key) {
+        (Some(file2_path), _) => PairingOutcome::Matched(file2_path),
+        (None, _) if options.match_by == MatchBy::Position => {
+            let files1 = list_position_files(dir1_path)?;
+            let files2 = list_position_files(dir2_path)?;
+            let position = files1.iter().position(|path| *path == file1_path).unwrap_or_default();
+            match files2.get(position) {
+                Some(file2_path) => options.exclusion(&file1_path, file2_path.clone()),
+                None => PairingOutcome::NoFileAtPosition(position + 1),
+            }
+        }
+        (None, Some(key)) if !options.is_selected(key) => PairingOutcome::KeyNotSelected,
+        (None, Some(key)) => {
+            let counterpart = list_files(dir2_path)?.into_iter()
+                .find(|file2_path| options.match_key(file2_path).as_ref() == Some(key));
+            match counterpart {
+                Some(file2_path) => options.exclusion(&file1_path, file2_path),
+                None => PairingOutcome::NoCounterpart,
+            }
+        }
+        (None, None) if options.key_regex.is_some() && options.match_by == MatchBy::Pattern => PairingOutcome::KeyRegexMismatch,
+        (None, None) => match &parsed {
+            Err(mismatch) => PairingOutcome::UnparsedName(mismatch.clone()),
+            Ok(_) => PairingOutcome::NoCounterpart,
+        },
+    };
+    
+    Ok(PairingExplanation { file1_path, parsed: parsed.ok(), match_key, outcome })
+}
+
+/// A file present under the same name in both directories that isn't paired because its name doesn't fit the pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnpairedCommonFile {
+    /// Name of the file in both directories
+    pub file_name: String,
+    /// Why the name doesn't follow the built-in pattern (`None` when the key regex doesn't match it)
+    pub mismatch: Option<NameMismatch>,
+}
+
+/// Lists the files present under the same name in both directories whose name doesn't yield a match key
+/// 
+/// Such files are never paired by [`generate_file_pairs_with_options`] and would otherwise go unnoticed.
+/// Names are compared case-insensitively when `case_insensitive` is set; files excluded by the selected
+/// keys, size or modification time filters aren't listed, as their names do fit the pattern.
+///
+/// # Arguments
+///
+/// * `dir1_path` - Path to the first directory
+/// * `dir2_path` - Path to the second directory
+/// * `options` - Pairing options to apply
+///
+/// # Returns
+///
+/// A Result containing either the files, sorted by name, or an error if a directory can't be read
+pub fn find_unpaired_common_files<P: AsRef<Path>>(dir1_path: P, dir2_path: P, options: &PairingOptions) -> Result<Vec<UnpairedCommonFile>> {
+    let name_of = |file_path: &Path| {
+        let name = file_path.file_name().unwrap_or_default().to_string_lossy();
+        if options.case_insensitive { name.to_lowercase() } else { name.into_owned() }
+    };
+    let names2: HashSet<String> = list_files(dir2_path.as_ref())?.iter().map(|file_path| name_of(file_path)).collect();
+    
+    let mut files1 = list_files(dir1_path.as_ref())?;
+    files1.sort();
+    Ok(files1.iter()
+        .filter(|file1_path| names2.contains(&name_of(file1_path)) && options.unfiltered_match_key(file1_path).is_none())
+        .map(|file1_path| UnpairedCommonFile {
+            file_name: file1_path.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+            mismatch: match options.key_regex {
+                Some(_) => None,
+                None => parse_filename_checked(file1_path, options).err(),
+            },
+        })
+        .collect())
+}
+
+/// Coverage of an expected list of match keys by the files of two directories
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyCoverage {
+    /// Expected keys that produced a pair, sorted
+    pub paired: Vec<String>,
+    /// Expected keys with a file in both directories that weren't paired (excluded by a size or time filter), sorted
+    pub unpaired: Vec<String>,
+    /// Expected keys with a file in the second directory only, sorted
+    pub missing_from_first: Vec<String>,
+    /// Expected keys with a file in the first directory only, sorted
+    pub missing_from_second: Vec<String>,
+    /// Expected keys with a file in neither directory, sorted
+    pub missing_from_both: Vec<String>,
+}
+
+impl KeyCoverage {
+    /// Returns the number of expected keys that didn't produce a pair
+    pub fn not_paired_count(&self) -> usize {
+        self.unpaired.len() + self.missing_from_first.len() + self.missing_from_second.len() + self.missing_from_both.len()
+    }
+    
+    /// Returns true if every expected key produced a pair
+    pub fn is_complete(&self) -> bool {
+        self.not_paired_count() == 0
+    }
+}
+
+/// Checks which of the expected match keys produced a pair, and where the others are missing
+/// 
+/// Unlike the unpaired files, which only cover the files that are present, this tells whether every
+/// expected file was received. A key is present in a directory when one of its files yields that key,
+/// whether or not the key is among the selected keys. Keys are compared as computed, i.e. lowercased
+/// when `case_insensitive` is set.
+///
+/// # Arguments
+///
+/// * `dir1_path` - Path to the first directory
+/// * `dir2_paths` - Paths to the second directory and any candidate directories; a key is present on
+///   the second side when a file of any of them yields it
+/// * `pairs` - File pairs generated for the directories
+/// * `expected_keys` - Match keys that should be present
+/// * `options` - Pairing options the pairs were generated with
+///
+/// # Returns
+///
+/// A Result containing the coverage of the expected keys, or an error if a directory can't be read
+pub fn check_key_coverage<P: AsRef<Path>>(dir1_path: P, dir2_paths: &[P], pairs: &[(PathBuf, PathBuf)],
+                                          expected_keys: &HashSet<String>, options: &PairingOptions) -> Result<KeyCoverage> {
+    let keys_in = |dir_path: &Path| -> Result<HashSet<String>> {
+        Ok(list_files(dir_path)?.iter().filter_map(|file_path| options.key_for(file_path)).collect())
+    };
+    let keys1 = keys_in(dir1_path.as_ref())?;
+    let mut keys2 = HashSet::new();
+    for dir2_path in dir2_paths {
+        keys2.extend(keys_in(dir2_path.as_ref())?);
+    }
+    let paired: HashSet<String> = pairs.iter().filter_map(|(file1_path, _)| options.key_for(file1_path)).collect();
+    
+    let mut expected_keys: Vec<&String> = expected_keys.iter().collect();
+    expected_keys.sort();
+    let mut coverage = KeyCoverage::default();
+    for key in expected_keys {
+        let keys = match (paired.contains(key), keys1.contains(key), keys2.contains(key)) {
+            (true, _, _) => &mut coverage.paired,
+            (false, true, true) => &mut coverage.unpaired,
+            (false, false, true) => &mut coverage.missing_from_first,
+            (false, true, false) => &mut coverage.missing_from_second,
+            (false, false, false) => &mut coverage.missing_from_both,
+        };
+        keys.push(key.clone());
+    }
+    Ok(coverage)
+}
+
+/// Maximum number of file names examined per directory by [`suggest_pattern`]
+const SUGGEST_SAMPLE_FILES: usize = 500;
+
+/// Role of an underscore-separated file name field in a suggested pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldRole {
+    /// Same in every sampled name: matched literally as the first or last field, by its character
+    /// class elsewhere, and left out of the match key
+    Fixed,
+    /// Tells files apart: captured into the match key
+    Key,
+    /// Constant within each directory but different between directories, like the version of a run:
+    /// left out of the match key
+    Version,
+}
+
+/// One field of a suggested file name pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldSuggestion {
+    /// Role of the field
+    pub role: FieldRole,
+    /// Regex fragment matching the field
+    pub pattern: String,
+    /// Value of the field in the first sampled name
+    pub example: String,
+}
+
+/// Naming structure inferred from the file names of one or more directories
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatternSuggestion {
+    /// Number of file names examined, over all directories
+    pub sampled: usize,
+    /// Number of examined names with the most common number of fields, from which the pattern is inferred
+    pub matching: usize,
+    /// Whether every examined name already follows the built-in SC_aaaaaaaa_yyyymmdd_tttN_AXX_Z pattern
+    pub built_in_matches: bool,
+    /// Fields of the inferred pattern, in order
+    pub fields: Vec<FieldSuggestion>,
+    /// Key regex for the inferred pattern (see [`PairingOptions::key_regex`])
+    pub key_regex: String,
+}
+
+/// Returns the regex fragment matching every value of a field
+fn field_class(values: &[&str]) -> String {
+    let class = if values.iter().all(|value| value.chars().all(|c| c.is_ascii_digit())) {
+        r"\d"
+    } else if values.iter().all(|value| value.chars().all(|c| c.is_ascii_alphanumeric())) {
+        "[0-9A-Za-z]"
+    } else {
+        "[^_]"
+    };
+    let length = values[0].chars().count();
+    if values.iter().all(|value| value.chars().count() == length) {
+        format!("{}{{{}}}", class, length)
+    } else {
+        format!("{}+", class)
+    }
+}
+
+/// Suggests a file name pattern for directories whose files don't follow the built-in pattern
+/// 
+/// The names (up to a sample per directory) are split on `_`; the most common number of fields is kept.
+/// Fields with a single value are fixed, fields that are constant within each directory but differ
+/// between directories (such as the version of two runs) are versions, and the remaining fields,
+/// which tell the files of a directory apart, form the match key.
+///
+/// # Arguments
+///
+/// * `dir_paths` - Paths to the directories whose file names are examined, e.g. the two compared directories
+///
+/// # Returns
+///
+/// A Result containing either the suggestion or an error if the directories have no file names to examine
+pub fn suggest_pattern(dir_paths: &[PathBuf]) -> Result<PatternSuggestion> {
+    let mut files = Vec::new();
+    for dir_path in dir_paths {
+        let mut dir_files = list_files(dir_path)?;
+        dir_files.sort();
+        dir_files.truncate(SUGGEST_SAMPLE_FILES);
+        files.extend(dir_files.into_iter().map(|file_path| (dir_path, file_path)));
+    }
+    let stems: Vec<(&PathBuf, &str)> = files.iter()
+        .filter_map(|(dir_path, file_path)| Some((*dir_path, logical_stem(file_path)?)))
+        .collect();
+    if stems.is_empty() {
+        anyhow::bail!("No file names to examine in: {}",
+                      dir_paths.iter().map(|dir_path| dir_path.display().to_string()).collect::<Vec<_>>().join(", "));
+    }
+    let default_options = PairingOptions::default();
+    let built_in_matches = files.iter().all(|(_, file_path)| parse_filename_checked(file_path, &default_options).is_ok());
+    
+    // The most common field count wins; ties go to the longer structure
+    let mut field_counts: BTreeMap<usize, usize> = BTreeMap::new();
+    for (_, stem) in &stems {
+        *field_counts.entry(stem.split('_').count()).or_default() += 1;
+    }
+    let field_count = field_counts.iter().max_by_key(|&(&count, &names)| (names, count)).map_or(0, |(&count, _)| count);
+    let names: Vec<(&PathBuf, Vec<&str>)> = stems.iter()
+        .map(|(dir_path, stem)| (*dir_path, stem.split('_').collect::<Vec<_>>()))
+        .filter(|(_, fields)| fields.len() == field_count)
+        .collect();
+    
+    let mut fields: Vec<FieldSuggestion> = (0..field_count).map(|position| {
+        let values: Vec<&str> = names.iter().map(|(_, fields)| fields[position]).collect();
+        let fixed = values.iter().all(|value| *value == values[0]);
+        let mut value_by_dir: HashMap<&PathBuf, &str> = HashMap::new();
+        let varies_within_dir = names.iter()
+            .any(|(dir_path, fields)| *value_by_dir.entry(dir_path).or_insert(fields[position]) != fields[position]);
+        let role = match (fixed, varies_within_dir) {
+            (true, _) => FieldRole::Fixed,
+            (false, false) => FieldRole::Version,
+            (false, true) => FieldRole::Key,
+        };
+        let pattern = if fixed && (position == 0 || position + 1 == field_count) {
+            regex::escape(values[0])
+        } else {
+            field_class(&values)
+        };
+        FieldSuggestion { role, pattern, example: values[0].to_string() }
+    }).collect();
+    // Without varying fields (e.g. a single file), every inner field is kept in the key
+    if !fields.iter().any(|field| field.role == FieldRole::Key) {
+        let last = fields.len().saturating_sub(1);
+        for (position, field) in fields.iter_mut().enumerate() {
+            if field.role == FieldRole::Fixed && position != 0 && position != last {
+                field.role = FieldRole::Key;
+            }
+        }
+    }
+    
+    let parts: Vec<String> = fields.iter().enumerate()
+        .map(|(position, field)| match field.role {
+            FieldRole::Key => format!("(?P<f{}>{})", position + 1, field.pattern),
+            _ => field.pattern.clone(),
+        })
+        .collect();
+    let key_regex = format!("^{}$", parts.join("_"));
+    
+    Ok(PatternSuggestion { sampled: stems.len(), matching: names.len(), built_in_matches, fields, key_regex })
+}
+
+/// Generates, for each file in the first directory, the matching files of several candidate directories
+/// 
+/// Files are matched by key like [`generate_file_pairs_with_options`]; a file with no match in any
+/// candidate directory is left out.
+///
+/// # Arguments
+///
+/// * `dir1_path` - Path to the first directory
+/// * `dir2_paths` - Paths to the candidate directories, in order of preference for ties
+/// * `options` - Pairing options to apply
+///
+/// # Returns
+///
+/// A vector of (file, candidate counterparts) tuples, candidates in the order of `dir2_paths`
+pub fn generate_candidate_pairs<P: AsRef<Path>>(dir1_path: P, dir2_paths: &[PathBuf], options: &PairingOptions) -> Result<Vec<(PathBuf, Vec<PathBuf>)>> {
+    let dir1_path = dir1_path.as_ref();
+    
+    let mut candidate_pairs: Vec<(PathBuf, Vec<PathBuf>)> = Vec::new();
+    let mut index: HashMap<PathBuf, usize> = HashMap::new();
+    for dir2_path in dir2_paths {
+        for (file1_path, file2_path) in generate_file_pairs_with_options(dir1_path, dir2_path, options)? {
+            match index.get(&file1_path) {
+                Some(&position) => candidate_pairs[position].1.push(file2_path),
+                None => {
+                    index.insert(file1_path.clone(), candidate_pairs.len());
+                    candidate_pairs.push((file1_path, vec![file2_path]));
+                }
+            }
+        }
+    }
+    
+    Ok(candidate_pairs)
+}
+
+/// File pair listed in a manifest
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestPair {
+    /// Path of the first file
+    pub file1_path: PathBuf,
+    /// Path of the second file
+    pub file2_path: PathBuf,
+    /// Header lines skipped in the first and second file, overriding the global setting (`None` keeps it)
+    pub header_lines: Option<(usize, usize)>,
+}
+
+/// Loads a manifest listing the file pairs to compare, one `path1<TAB>path2[<TAB>skip1<TAB>skip2]` per line
+/// Relative paths are resolved against the first and second directory; `skip1` and `skip2` are the
+/// number of header lines skipped in each file. Empty lines are ignored; the manifest's encoding is
+/// detected like any input file.
+///
+/// # Arguments
+///
+/// * `manifest_path` - Path to the manifest
+/// * `dir1_path` - Directory the first paths are relative to
+/// * `dir2_path` - Directory the second paths are relative to
+///
+/// # Returns
+///
+/// A Result containing either the pairs in manifest order or an error naming the first malformed line
+pub fn load_manifest<P: AsRef<Path>>(manifest_path: P, dir1_path: &Path, dir2_path: &Path) -> Result<Vec<ManifestPair>> {
+    let manifest_path = manifest_path.as_ref();
+    let mut pairs = Vec::new();
+    for line in read_list_lines(manifest_path)? {
+        let fields: Vec<&str> = line.split('\t').map(str::trim).collect();
+        let (path1, path2, header_lines) = match fields[..] {
+            [path1, path2] => (path1, path2, None),
+            [path1, path2, skip1, skip2] => {
+                let parse_skip = |skip: &str| skip.parse::<usize>().with_context(|| format!(
+                    "Invalid header line count {:?} in {}: expected a non-negative integer, got line {:?}",
+                    skip, manifest_path.display(), line));
+                (path1, path2, Some((parse_skip(skip1)?, parse_skip(skip2)?)))
+            }
+            _ => anyhow::bail!("Invalid manifest line in {}: expected \"path1<TAB>path2[<TAB>skip1<TAB>skip2]\", got {:?}",
+                               manifest_path.display(), line),
+        };
+        if path1.is_empty() || path2.is_empty() {
+            anyhow::bail!("Invalid manifest line in {}: empty path in {:?}", manifest_path.display(), line);
+        }
+        pairs.push(ManifestPair {
+            file1_path: dir1_path.join(path1),
+            file2_path: dir2_path.join(path2),
+            header_lines,
+        });
+    }
+    Ok(pairs)
+}
+
+/// Counterpart picked for a file among several candidates
+#[derive(Debug, Clone, PartialEq)]
+pub struct BestMatch {
+    /// The most similar candidate
+    pub file2_path: PathBuf,
+    /// Similarity of the picked candidate, or `None` if no candidate could be compared
+    pub similarity: Option<f64>,
+    /// The other candidates, most similar first, with `None` for those that couldn't be compared
+    pub alternates: Vec<(PathBuf, Option<f64>)>,
+}
+
+/// Compares a file against each of its candidate counterparts and picks the most similar one
+/// 
+/// Identical candidates have a similarity of 1.0. Ties go to the earliest candidate, and
+/// candidates that fail to compare rank last.
+///
+/// # Arguments
+///
+/// * `file1_path` - Path to the file
+/// * `candidates` - Candidate counterparts (at least one)
+/// * `options` - Comparison options
+/// * `metric` - Similarity metric used to rank the candidates
+///
+/// # Returns
+///
+/// The picked candidate and the ranked alternates
+pub fn pick_best_match<P: AsRef<Path>>(file1_path: P, candidates: &[PathBuf], options: &CompareOptions, metric: SimilarityMetric) -> BestMatch {
+    let file1_path = file1_path.as_ref();
+    
+    let mut ranked: Vec<(PathBuf, Option<f64>)> = candidates.iter()
+        .map(|file2_path| {
+            let similarity = match compare_files_with_options(file1_path, file2_path, options) {
+                Ok(Some(diff)) => Some(diff.similarity(metric)),
+                Ok(None) => Some(1.0),
+                Err(e) => {
+                    info!("Failed to compare candidate {}: {:#}", file2_path.display(), e);
+                    None
+                }
+            };
+            (file2_path.clone(), similarity)
+        })
+        .collect();
+    // Stable sort, so ties keep the candidate order
+    ranked.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+    
+    let mut ranked = ranked.into_iter();
+    let (file2_path, similarity) = ranked.next().expect("pick_best_match needs at least one candidate");
+    BestMatch { file2_path, similarity, alternates: ranked.collect() }
+}
+
+/// Structural differences between two directory trees, as paths relative to each root
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TreeDiff {
+    /// Files that exist only in the first tree
+    pub only_in_first: Vec<PathBuf>,
+    /// Files that exist only in the second tree
+    pub only_in_second: Vec<PathBuf>,
+    /// Files that exist at the same relative path in both trees
+    pub in_both: Vec<PathBuf>,
+}
+
+impl TreeDiff {
+    /// Pairs the files present in both trees, for content comparison
+    ///
+    /// # Arguments
+    ///
+    /// * `dir1_path` - Root of the first tree
+    /// * `dir2_path` - Root of the second tree
+    ///
+    /// # Returns
+    ///
+    /// A vector of tuples containing file path pairs
+    pub fn file_pairs(&self, dir1_path: &Path, dir2_path: &Path) -> Vec<(PathBuf, PathBuf)> {
+        self.in_both.iter()
+            .map(|relative| (dir1_path.join(relative), dir2_path.join(relative)))
+            .collect()
+    }
+}
+
+/// Recursively lists the files under `root`, as sorted paths relative to it
+/// Symbolic links to directories are not followed.
+fn list_files_recursive(root: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(relative_dir) = pending.pop() {
+        let dir_path = root.join(&relative_dir);
+        for entry in fs::read_dir(&dir_path)
+            .with_context(|| format!("Failed to read directory: {}", dir_path.display()))? {
+            let entry = entry
+                .with_context(|| format!("Failed to read directory entry in: {}", dir_path.display()))?;
+            let relative = relative_dir.join(entry.file_name());
+            if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+                pending.push(relative);
+            } else if entry.path().is_file() {
+                files.push(relative);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Compares the structure of two directory trees by the relative paths of their files
+///
+/// # Arguments
+///
+/// * `dir1_path` - Root of the first tree
+/// * `dir2_path` - Root of the second tree
+///
+/// # Returns
+///
+/// The relative paths only in the first tree, only in the second tree and in both, each sorted
+pub fn diff_trees<P: AsRef<Path>>(dir1_path: P, dir2_path: P) -> Result<TreeDiff> {
+    let files1 = list_files_recursive(dir1_path.as_ref())?;
+    let files2 = list_files_recursive(dir2_path.as_ref())?;
+    
+    let set1: HashSet<&PathBuf> = files1.iter().collect();
+    let set2: HashSet<&PathBuf> = files2.iter().collect();
+    
+    let mut tree_diff = TreeDiff::default();
+    for relative in &files1 {
+        if set2.contains(relative) {
+            tree_diff.in_both.push(relative.clone());
+        } else {
+            tree_diff.only_in_first.push(relative.clone());
+        }
+    }
+    tree_diff.only_in_second = files2.iter()
+        .filter(|relative| !set1.contains(relative))
+        .cloned()
+        .collect();
+    
+    info!("目录结构比较: 仅在第一个目录 {} 个，仅在第二个目录 {} 个，共有 {} 个文件",
+          tree_diff.only_in_first.len(), tree_diff.only_in_second.len(), tree_diff.in_both.len());
+    Ok(tree_diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::sync::Arc;
+    use tempfile::Builder;
+    use crate::file_utils::RuleUsage;
+    use anyhow::Result;
+
+    #[test]
+    fn test_compare_files_identical() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file1_path = dir.path().join("file1.txt");
+        let file2_path = dir.path().join("file2.txt");
+
+        // Create test files with identical content (after skipping first line)
+        let content = "Header line\nLine 2\nLine 1\nLine 3\n";
+        fs::write(&file1_path, content)?;
+        fs::write(&file2_path, content)?;
+
+        let result = compare_files(&file1_path, &file2_path)?;
+        assert!(result.is_none()); // No differences expected
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare_files_reordered_in_memory() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file1_path = dir.path().join("file1.txt");
+        let file2_path = dir.path().join("file2.txt");
+
+        // Same lines in a different order, compared without the external diff fast path
+        fs::write(&file1_path, "Header line\nLine 2\nLine 1\nLine 3\n")?;
+        fs::write(&file2_path, "Other header\nLine 3\nLine 2\nLine 1\n")?;
+
+        let options = CompareOptions {
+            external_diff_min_bytes: u64::MAX,
+            ..CompareOptions::default()
+        };
+        assert!(compare_files_with_options(&file1_path, &file2_path, &options)?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare_files_different() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file1_path = dir.path().join("file1.txt");
+        let file2_path = dir.path().join("file2.txt");
+
+        // Create test files with different content (after skipping first line)
+        fs::write(&file1_path, "Header line\nLine 2\nLine 1\nLine 3\n")?;
+        fs::write(&file2_path, "Header line\nLine 2\nLine 4\nLine 3\n")?;
+
+        let result = compare_files(&file1_path, &file2_path)?;
+        assert!(result.is_some()); // Differences expected
+
+        let diff = result.unwrap();
+        assert_eq!(diff.only_in_first, vec!["Line 1"]);
+        assert_eq!(diff.only_in_second, vec!["Line 4"]);
+        assert_eq!(diff.common_count, 2);
+        
+        // A = {1, 2, 3}, B = {2, 3, 4}
+        assert_eq!(diff.similarity(SimilarityMetric::Jaccard), 0.5);
+        assert_eq!(diff.similarity(SimilarityMetric::Overlap), 2.0 / 3.0);
+        assert_eq!(diff.similarity(SimilarityMetric::Dice), 2.0 / 3.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare_files_with_stats() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file1_path = dir.path().join("file1.txt");
+        let file2_path = dir.path().join("file2.txt");
+        let file3_path = dir.path().join("file3.txt");
+        fs::write(&file1_path, "Header line\nLine 2\nLine 1\nLine 3\n")?;
+        fs::write(&file2_path, "Header line\nLine 2\nLine 4\nLine 3\n")?;
+        fs::write(&file3_path, "Header line\nLine 2\nLine 1\nLine 3\n")?;
+        
+        // Six 6-byte lines, plus the line sets
+        let options = CompareOptions { external_diff_min_bytes: u64::MAX, ..CompareOptions::default() };
+        let (diff, different) = compare_files_with_stats(&file1_path, &file2_path, &options)?;
+        assert!(diff.is_some());
+        let lines_memory = 6 * (6 + std::mem::size_of::<String>()) as u64;
+        assert!(different.memory_estimate > lines_memory);
+        
+        // Identical sorted lines are detected before the sets are built
+        let (diff, identical) = compare_files_with_stats(&file1_path, &file3_path, &options)?;
+        assert!(diff.is_none());
+        assert_eq!(identical.memory_estimate, lines_memory);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare_files_with_second_header_lines() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file1_path = dir.path().join("old.txt");
+        let file2_path = dir.path().join("new.txt");
+        fs::write(&file1_path, "ID|NAME\n1|a\n2|b\n")?;
+        fs::write(&file2_path, "Exported by system B\nID|NAME\n2|b\n1|a\n")?;
+        
+        // Skipping one header line on both sides leaves the new file's column header as a difference
+        let options = CompareOptions {
+            process: ProcessOptions { header_lines: Some(1), ..ProcessOptions::default() },
+            ..CompareOptions::default()
+        };
+        assert!(compare_files_with_options(&file1_path, &file2_path, &options)?.is_some());
+        
+        let options = CompareOptions { second_header_lines: Some(2), ..options };
+        assert!(compare_files_with_options(&file1_path, &file2_path, &options)?.is_none());
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare_files_fast_paths_with_second_header_lines() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file1_path = dir.path().join("file1.txt");
+        let file2_path = dir.path().join("file2.txt");
+        fs::write(&file1_path, "H1\nH2\nrow1\nrow2\n")?;
+        fs::write(&file2_path, "H1\nH2\nrow1\nrow2\n")?;
+        
+        // Byte-identical files still differ by the header line only skipped in the first one
+        let options = CompareOptions {
+            process: ProcessOptions { header_lines: Some(1), ..ProcessOptions::default() },
+            second_header_lines: Some(2),
+            ..CompareOptions::default()
+        };
+        assert!(compare_files_with_options(&file1_path, &file2_path, &options)?.is_some());
+        let external_diff = CompareOptions { external_diff_min_bytes: 0, ..options.clone() };
+        assert!(compare_files_with_options(&file1_path, &file2_path, &external_diff)?.is_some());
+        let byte_identity = CompareOptions { byte_identity: true, ..options };
+        assert!(compare_files_with_options(&file1_path, &file2_path, &byte_identity)?.is_some());
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare_files_byte_identity() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file1_path = dir.path().join("file1.txt");
+        let file2_path = dir.path().join("file2.txt");
+        let file3_path = dir.path().join("file3.txt");
+        fs::write(&file1_path, "Header line\nLine 1\nLine 2\n")?;
+        fs::write(&file2_path, "Header line\nLine 1\nLine 2\n")?;
+        fs::write(&file3_path, "Header line\nLine 2\nLine 1\n")?;
+        
+        // Byte-identical files are identical without being decoded, so no line counts are known
+        let options = CompareOptions { byte_identity: true, external_diff_min_bytes: u64::MAX, ..CompareOptions::default() };
+        let (diff, stats) = compare_files_with_stats(&file1_path, &file2_path, &options)?;
+        assert!(diff.is_none());
+        assert_eq!(stats.line_counts, None);
+        
+        // Differing bytes fall back to the line comparison
+        let (diff, stats) = compare_files_with_stats(&file1_path, &file3_path, &options)?;
+        assert!(diff.is_none());
+        assert_eq!(stats.line_counts, Some((2, 2)));
+        
+        // Counting rule usage reads identical files too, so their rule hits are counted
+        let rule_usage = Arc::new(RuleUsage::new());
+        let options = CompareOptions {
+            process: ProcessOptions { min_line_length: Some(7), rule_usage: Some(rule_usage.clone()), ..ProcessOptions::default() },
+            byte_identity: true,
+            ..CompareOptions::default()
+        };
+        let (_, stats) = compare_files_with_stats(&file1_path, &file2_path, &options)?;
+        assert_eq!(stats.line_counts, Some((0, 0)));
+        assert_eq!(rule_usage.counts(), vec![("--min-line-length 7".to_string(), 4)]);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare_bucket_counts() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file1_path = dir.path().join("file1.txt");
+        let file2_path = dir.path().join("file2.txt");
+        fs::write(&file1_path, "REGION|ID\nEU|1\nEU|2\nUS|3\n")?;
+        fs::write(&file2_path, "REGION|ID\nEU|1\nUS|3\nUS|4\nAPAC|5\n")?;
+        
+        let bucket_field = SortKey { field: 1, delimiter: "|".to_string() };
+        let counts = compare_bucket_counts(&file1_path, &file2_path, &CompareOptions::default(), &bucket_field)?;
+        let counts: Vec<_> = counts.iter().map(|count| (count.bucket.as_str(), count.count1, count.count2, count.delta())).collect();
+        assert_eq!(counts, vec![("APAC", 0, 1, 1), ("EU", 2, 1, -1), ("US", 1, 2, 1)]);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_column_redaction() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file1_path = dir.path().join("file1.txt");
+        let file2_path = dir.path().join("file2.txt");
+        fs::write(&file1_path, "ID|NAME|AMOUNT\n1|Alice|10\n2|Bob|20\n")?;
+        fs::write(&file2_path, "ID|NAME|AMOUNT\n1|Alicia|10\n2|Bob|20\n")?;
+        
+        // The masked column still takes part in the comparison, only the shown lines hide it
+        let diff = compare_files(&file1_path, &file2_path)?.expect("the names differ");
+        assert_eq!(diff.only_in_first, vec!["1|Alice|10"]);
+        assert_eq!(diff.only_in_second, vec!["1|Alicia|10"]);
+        let redaction = ColumnRedaction { columns: vec![2, 5], delimiter: "|".to_string() };
+        assert_eq!(redaction.redact(&diff.only_in_first[0]), "1|***|10");
+        assert_eq!(redaction.redact(&diff.only_in_second[0]), "1|***|10");
+        
+        // Masking the differences keeps their similarity
+        let similarity = diff.similarity(SimilarityMetric::Jaccard);
+        let redacted = redaction.redact_differences(diff);
+        assert_eq!((redacted.only_in_first, redacted.only_in_second), (vec!["1|***|10".to_string()], vec!["1|***|10".to_string()]));
+        assert_eq!(redacted.weights.common, 1.0);
+        assert!(similarity < 1.0);
+        
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn test_compare_files_with_second_transform_cmd() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file1_path = dir.path().join("plain.txt");
+        let file2_path = dir.path().join("obfuscated.txt");
+        let file3_path = dir.path().join("copy.txt");
+        fs::write(&file1_path, "Header line\nline a\nline b\n")?;
+        fs::write(&file2_path, "Urnqre yvar\nyvar n\nyvar o\n")?;
+        fs::write(&file3_path, "Header line\nline a\nline b\n")?;
+        
+        // Only the second side is decoded, even by the external diff and byte-identity fast paths
+        let options = CompareOptions {
+            second_transform_cmd: Some("tr A-Za-z N-ZA-Mn-za-m".to_string()),
+            byte_identity: true,
+            external_diff_min_bytes: 0,
+            ..CompareOptions::default()
+        };
+        assert!(compare_files_with_options(&file1_path, &file2_path, &options)?.is_none());
+        assert!(compare_files_with_options(&file1_path, &file3_path, &options)?.is_some());
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare_files_line_counts() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file1_path = dir.path().join("file1.txt");
+        let file2_path = dir.path().join("file2.txt");
+        let file3_path = dir.path().join("file3.txt");
+        fs::write(&file1_path, "Header line\nLine 1\nLine 2\n")?;
+        fs::write(&file2_path, "Header line\nLine 1\nLine 2\nLine 2\n")?;
+        fs::write(&file3_path, "Header line\nLine 1\nLine 3\nLine 3\n")?;
+        
+        // A duplicated line leaves the line sets equal, but not the counts
+        let options = CompareOptions { external_diff_min_bytes: u64::MAX, ..CompareOptions::default() };
+        let (diff, stats) = compare_files_with_stats(&file1_path, &file2_path, &options)?;
+        assert!(diff.is_none());
+        assert_eq!(stats.line_counts, Some((2, 3)));
+        
+        let diff = compare_files_with_options(&file1_path, &file3_path, &options)?.unwrap();
+        assert_eq!(diff.line_counts, (2, 3));
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare_files_line_weights() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file1_path = dir.path().join("file1.txt");
+        let file2_path = dir.path().join("file2.txt");
+        let weights_path = dir.path().join("weights.txt");
+
+        fs::write(&file1_path, "Header line\nINFO a\nERR b\nINFO c\n")?;
+        fs::write(&file2_path, "Header line\nINFO a\nERR d\nINFO e\n")?;
+        fs::write(&weights_path, "10 ^ERR\n0.5   ^INFO \n")?;
+
+        let options = CompareOptions {
+            line_weights: LineWeights::load(&weights_path)?,
+            ..CompareOptions::default()
+        };
+        let diff = compare_files_with_options(&file1_path, &file2_path, &options)?.unwrap();
+        assert_eq!(diff.weights, DiffWeights { only_in_first: 10.5, only_in_second: 10.5, common: 0.5 });
+        assert_eq!(diff.weights.differing(), 21.0);
+        // Jaccard: 0.5 / (11 + 11 - 0.5)
+        assert_eq!(diff.similarity(SimilarityMetric::Jaccard), 0.5 / 21.5);
+
+        fs::write(&weights_path, "heavy ^ERR\n")?;
+        assert!(LineWeights::load(&weights_path).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pair_id_is_stable() {
+        let id = pair_id(Path::new("/mnt/x/a/f1.txt"), Path::new("/mnt/x/b/f2.txt"));
+        assert_eq!(id.len(), 16);
+        // Only the parent directory names and file names take part in the ID
+        assert_eq!(id, pair_id(Path::new("/other/a/f1.txt"), Path::new("b/f2.txt")));
+        assert_ne!(id, pair_id(Path::new("a/f2.txt"), Path::new("b/f1.txt")));
+        
+        // Same-named files of different directories get different output files
+        let output_dir = Path::new("out");
+        assert_eq!(pair_output_path(Path::new("a/f1.txt"), Path::new("b/f2.txt"), output_dir, "txt"),
+                   output_dir.join(format!("f1__f2.{}.txt", id)));
+        assert_ne!(pair_output_path(Path::new("a/f1.txt"), Path::new("b/f2.txt"), output_dir, "txt"),
+                   pair_output_path(Path::new("c/f1.txt"), Path::new("d/f2.txt"), output_dir, "txt"));
+    }
+
+    #[test]
+    fn test_compare_files_strip_trailing_timestamp() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file1_path = dir.path().join("file1.txt");
+        let file2_path = dir.path().join("file2.txt");
+        
+        fs::write(&file1_path, "Header line\nLine 1 2024-01-01 10:00:00\nLine 2 2024-01-01 10:00:01\nLine 3\n")?;
+        fs::write(&file2_path, "Header line\nLine 1 2024-02-03 11:22:33\nLine 2 2024-02-03 11:22:34\nLine 3\n")?;
+        
+        let options = CompareOptions {
+            process: ProcessOptions {
+                strip_trailing_timestamp: Some("%Y-%m-%d %H:%M:%S".to_string()),
+                ..ProcessOptions::default()
+            },
+            ..CompareOptions::default()
+        };
+        assert!(compare_files_with_options(&file1_path, &file2_path, &options)?.is_none());
+        
+        // Differing lines are reported with their original timestamp
+        fs::write(&file2_path, "Header line\nLine 1 2024-02-03 11:22:33\nLine 4 2024-02-03 11:22:34\nLine 3\n")?;
+        let diff = compare_files_with_options(&file1_path, &file2_path, &options)?.unwrap();
+        assert_eq!(diff.only_in_first, vec!["Line 2 2024-01-01 10:00:01"]);
+        assert_eq!(diff.only_in_second, vec!["Line 4 2024-02-03 11:22:34"]);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_processed_lines() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file_path = dir.path().join("file.txt");
+        let output_path = dir.path().join("file.processed.txt");
+        fs::write(&file_path, "Header line\n  Line 2\nLine 1\n")?;
+
+        write_processed_lines(&file_path, &CompareOptions::default(), &output_path)?;
+        assert_eq!(fs::read_to_string(&output_path)?, "Line 1\nLine 2\n");
+
+        let options = CompareOptions { ordered: true, ..CompareOptions::default() };
+        write_processed_lines(&file_path, &options, &output_path)?;
+        assert_eq!(fs::read_to_string(&output_path)?, "Line 2\nLine 1\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_reordered() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file1_path = dir.path().join("file1.txt");
+        let file2_path = dir.path().join("file2.txt");
+        let file3_path = dir.path().join("file3.txt");
+        fs::write(&file1_path, "Header line\nLine 1\nLine 2\n")?;
+        fs::write(&file2_path, "Header line\nLine 2\nLine 1\n")?;
+        fs::write(&file3_path, "Other header\nLine 1\nLine 2\n")?;
+
+        let options = CompareOptions::default();
+        assert!(is_reordered(&file1_path, &file2_path, &options)?);
+        assert!(!is_reordered(&file1_path, &file3_path, &options)?);
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_compare_files_diff_command_stub() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file1_path = dir.path().join("file1.txt");
+        let file2_path = dir.path().join("file2.txt");
+        fs::write(&file1_path, "Header line\nLine 1\n")?;
+        fs::write(&file2_path, "Header line\nLine 2\n")?;
+        
+        // A command reporting "identical" is trusted, but not run for small files by default
+        let options = CompareOptions { diff_command: Some("true".to_string()), ..CompareOptions::default() };
+        assert!(compare_files_with_options(&file1_path, &file2_path, &options)?.is_some());
+        let options = CompareOptions { external_diff_min_bytes: 0, ..options };
+        assert!(compare_files_with_options(&file1_path, &file2_path, &options)?.is_none());
+        
+        // A failing command, or one that can't be run, falls back to the detailed comparison
+        let stub_path = dir.path().join("failing-diff");
+        fs::write(&stub_path, "#!/bin/sh\necho 'diff: broken' >&2\nexit 2\n")?;
+        fs::set_permissions(&stub_path, fs::Permissions::from_mode(0o755))?;
+        for program in [stub_path, dir.path().join("missing-diff")] {
+            let options = CompareOptions {
+                diff_command: Some(program.display().to_string()),
+                ..CompareOptions::default()
+            };
+            let diff = compare_files_with_options(&file1_path, &file2_path, &options)?.unwrap();
+            assert_eq!(diff.only_in_first, vec!["Line 1"]);
+        }
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare_many() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let paths: Vec<_> = ["Header\nA\nB\nC\n", "Header\nA\nC\nA\n", "Header\nA\nB\nD\n"].iter()
+            .enumerate()
+            .map(|(index, content)| {
+                let path = dir.path().join(format!("file{}.txt", index + 1));
+                fs::write(&path, content).map(|_| path)
+            })
+            .collect::<std::io::Result<_>>()?;
+        
+        let origins = compare_many(&paths, &CompareOptions::default())?;
+        assert_eq!(origins.common_count, 1);
+        assert_eq!(origins.lines, vec![
+            ("B".to_string(), vec![1, 3]),
+            ("C".to_string(), vec![1, 2]),
+            ("D".to_string(), vec![3]),
+        ]);
+        assert_eq!(LineOrigins::format_origins(&origins.lines[0].1), "[1,3]");
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_modifications() {
+        let lines = |items: &[&str]| items.iter().map(|item| item.to_string()).collect::<Vec<_>>();
+        let only_in_first = lines(&["price=100 USD", "obsolete entry", "name=alpha"]);
+        let only_in_second = lines(&["name=alpah", "price=105 USD", "brand new"]);
+        
+        let modifications = merge_modifications(&only_in_first, &only_in_second, 0.8);
+        assert_eq!(modifications.modified, vec![
+            ("price=100 USD".to_string(), "price=105 USD".to_string()),
+            ("name=alpha".to_string(), "name=alpah".to_string()),
+        ]);
+        assert_eq!(modifications.removed, lines(&["obsolete entry"]));
+        assert_eq!(modifications.added, lines(&["brand new"]));
+        
+        // Above every line similarity, nothing is merged
+        assert!(merge_modifications(&only_in_first, &only_in_second, 1.0).modified.is_empty());
+        
+        // Too many combinations to score: the lines are left as they are
+        let many1: Vec<_> = (0..400).map(|index| format!("line {}", index)).collect();
+        let many2: Vec<_> = (0..400).map(|index| format!("line {}!", index)).collect();
+        let modifications = merge_modifications(&many1, &many2, 0.5);
+        assert!(modifications.modified.is_empty());
+        assert_eq!((modifications.removed, modifications.added), (many1, many2));
+    }
+
+    #[test]
+    fn test_find_unpaired_common_files() -> Result<()> {
+        let dir1 = Builder::new().prefix("tbcompare_test1").tempdir()?;
+        let dir2 = Builder::new().prefix("tbcompare_test2").tempdir()?;
+        for name in ["SC_13260000_20190820_019N_A05_Z.txt", "summary.txt", "SC_1_20190820_019N_A05_Y.txt", "only_here.txt"] {
+            fs::write(dir1.path().join(name), "Header\n")?;
         }
-    }
-    
-    // For each file in dir1, find the corresponding file in dir2 using the hash map
-    for file1_path in &files1 {
-        if let Some(file1_stem) = file1_path.file_stem().and_then(|n| n.to_str()) {
-            let parts1: Vec<&str> = file1_stem.split('_').collect();
-            
-            // Check if the file name matches the expected pattern
-            if parts1.len() >= 6 && parts1[0] == "SC" && parts1[parts1.len()-1] == "Z" {
-                // Extract the parts that must match: aaaaaaaa, yyyymmdd, AXX
-                let key1 = format!("{}_{}_{}", parts1[1], parts1[2], parts1[parts1.len()-2]);
-                
-                // Look up the matching file in dir2 using the hash map
-                if let Some(file2_path) = dir2_map.get(&key1) {
-                    file_pairs.push((file1_path.clone(), file2_path.clone()));
-                }
-            }
+        for name in ["SC_13260000_20190820_019N_A05_Z.txt", "summary.txt", "SC_1_20190820_019N_A05_Y.txt"] {
+            fs::write(dir2.path().join(name), "Header\n")?;
         }
+        
+        // The pairable file and the file in one directory only aren't listed
+        let unpaired = find_unpaired_common_files(dir1.path(), dir2.path(), &PairingOptions::default())?;
+        assert_eq!(unpaired, vec![
+            UnpairedCommonFile { file_name: "SC_1_20190820_019N_A05_Y.txt".to_string(), mismatch: Some(NameMismatch::WrongSuffix("Y".to_string())) },
+            UnpairedCommonFile { file_name: "summary.txt".to_string(), mismatch: Some(NameMismatch::TooFewFields(1)) },
+        ]);
+        
+        Ok(())
     }
-    
-    info!("生成了 {} 个文件对", file_pairs.len());
-    Ok(file_pairs)
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::fs;
-    use tempfile::Builder;
-    use anyhow::Result;
 
     #[test]
-    fn test_compare_files_identical() -> Result<()> {
-        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
-        let file1_path = dir.path().join("file1.txt");
-        let file2_path = dir.path().join("file2.txt");
+    fn test_check_key_coverage() -> Result<()> {
+        let dir1 = Builder::new().prefix("tbcompare_test1").tempdir()?;
+        let dir2 = Builder::new().prefix("tbcompare_test2").tempdir()?;
+        fs::write(dir1.path().join("SC_11111111_20240101_001N_A01_Z.txt"), "Header\n")?;
+        fs::write(dir2.path().join("SC_11111111_20240101_002N_A01_Z.txt"), "Header\n")?;
+        fs::write(dir1.path().join("SC_22222222_20240101_001N_A02_Z.txt"), "Header\n")?;
+        fs::write(dir2.path().join("SC_33333333_20240101_001N_A03_Z.txt"), "Header\n")?;
+        
+        let options = PairingOptions::default();
+        let pairs = generate_file_pairs_with_options(dir1.path(), dir2.path(), &options)?;
+        let expected: HashSet<String> = ["11111111_20240101_A01", "22222222_20240101_A02", "33333333_20240101_A03", "44444444_20240101_A04"]
+            .into_iter().map(String::from).collect();
+        let coverage = check_key_coverage(dir1.path(), &[dir2.path()], &pairs, &expected, &options)?;
+        assert_eq!(coverage, KeyCoverage {
+            paired: vec!["11111111_20240101_A01".to_string()],
+            unpaired: Vec::new(),
+            missing_from_first: vec!["33333333_20240101_A03".to_string()],
+            missing_from_second: vec!["22222222_20240101_A02".to_string()],
+            missing_from_both: vec!["44444444_20240101_A04".to_string()],
+        });
+        assert!(!coverage.is_complete());
+        
+        // A key whose pair was filtered out is present on both sides without being paired
+        let coverage = check_key_coverage(dir1.path(), &[dir2.path()], &[], &expected, &options)?;
+        assert_eq!(coverage.unpaired, vec!["11111111_20240101_A01"]);
+        
+        // Candidate directories count as the second side
+        let candidate_dir = Builder::new().prefix("tbcompare_test3").tempdir()?;
+        fs::write(candidate_dir.path().join("SC_22222222_20240101_002N_A02_Z.txt"), "Header\n")?;
+        let coverage = check_key_coverage(dir1.path(), &[dir2.path(), candidate_dir.path()], &pairs, &expected, &options)?;
+        assert_eq!(coverage.unpaired, vec!["22222222_20240101_A02"]);
+        assert!(coverage.missing_from_second.is_empty());
+        
+        Ok(())
+    }
 
-        // Create test files with identical content (after skipping first line)
-        let content = "Header line\nLine 2\nLine 1\nLine 3\n";
-        fs::write(&file1_path, content)?;
-        fs::write(&file2_path, content)?;
+    #[test]
+    fn test_suggest_pattern() -> Result<()> {
+        let dir1 = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let dir2 = Builder::new().prefix("tbcompare_test").tempdir()?;
+        for name in ["RPT-12_20240101_v1_EU.csv", "RPT-34_20240102_v1_EU.csv", "notes.txt"] {
+            fs::write(dir1.path().join(name), "Header\n")?;
+        }
+        for name in ["RPT-12_20240101_v2_EU.csv", "RPT-34_20240102_v2_EU.csv"] {
+            fs::write(dir2.path().join(name), "Header\n")?;
+        }
+        
+        let suggestion = suggest_pattern(&[dir1.path().to_path_buf(), dir2.path().to_path_buf()])?;
+        assert_eq!((suggestion.sampled, suggestion.matching, suggestion.built_in_matches), (5, 4, false));
+        let roles: Vec<_> = suggestion.fields.iter().map(|field| field.role.clone()).collect();
+        assert_eq!(roles, vec![FieldRole::Key, FieldRole::Key, FieldRole::Version, FieldRole::Fixed]);
+        assert_eq!(suggestion.key_regex, r"^(?P<f1>[^_]{6})_(?P<f2>\d{8})_[0-9A-Za-z]{2}_EU$");
+        
+        // The suggested regex pairs the names it was inferred from
+        let options = PairingOptions { key_regex: Some(Regex::new(&suggestion.key_regex)?), ..PairingOptions::default() };
+        let pairs = generate_file_pairs_with_options(dir1.path(), dir2.path(), &options)?;
+        assert_eq!(pairs.len(), 2);
+        
+        Ok(())
+    }
 
-        let result = compare_files(&file1_path, &file2_path)?;
-        assert!(result.is_none()); // No differences expected
+    #[test]
+    fn test_diff_json_records() {
+        let lines = |items: &[&str]| items.iter().map(|item| item.to_string()).collect::<Vec<_>>();
+        let only_in_first = lines(&[r#"{"id":"a","price":100,"unit":"USD"}"#, r#"{"id":"b","price":1}"#, "not json"]);
+        let only_in_second = lines(&[r#"{"id":"a","price":105,"tax":true,"unit":"USD"}"#, r#"{"id":"c","price":1}"#]);
+        
+        let record_diff = diff_json_records(&only_in_first, &only_in_second, "id");
+        assert_eq!(record_diff.changed, vec![JsonRecordChange {
+            key: "a".to_string(),
+            fields: vec![
+                JsonFieldChange { field: "price".to_string(), old: Some("100".to_string()), new: Some("105".to_string()) },
+                JsonFieldChange { field: "tax".to_string(), old: None, new: Some("true".to_string()) },
+            ],
+        }]);
+        assert_eq!(record_diff.removed, lines(&[r#"{"id":"b","price":1}"#, "not json"]));
+        assert_eq!(record_diff.added, lines(&[r#"{"id":"c","price":1}"#]));
+    }
 
-        Ok(())
+    #[test]
+    fn test_inline_diff() {
+        assert_eq!(inline_diff("price=100 USD", "price=105 USD", DiffGranularity::Char), "price=10[-0-]{+5+} USD");
+        assert_eq!(inline_diff("status ok at noon", "status failed at noon", DiffGranularity::Word),
+                   "status [-ok-]{+failed+} at noon");
+        assert_eq!(inline_diff("same", "same", DiffGranularity::Word), "same");
+        assert_eq!("WORD".parse::<DiffGranularity>().unwrap(), DiffGranularity::Word);
     }
 
     #[test]
-    fn test_compare_files_different() -> Result<()> {
+    fn test_compare_files_trailing_whitespace() -> Result<()> {
         let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
         let file1_path = dir.path().join("file1.txt");
         let file2_path = dir.path().join("file2.txt");
-
-        // Create test files with different content (after skipping first line)
-        fs::write(&file1_path, "Header line\nLine 2\nLine 1\nLine 3\n")?;
-        fs::write(&file2_path, "Header line\nLine 2\nLine 4\nLine 3\n")?;
-
-        let result = compare_files(&file1_path, &file2_path)?;
-        assert!(result.is_some()); // Differences expected
-
-        let diff = result.unwrap();
-        assert_eq!(diff.only_in_first, vec!["Line 1"]);
-        assert_eq!(diff.only_in_second, vec!["Line 4"]);
-
+        fs::write(&file1_path, "Header\nsame\npadded  \ntab\n")?;
+        fs::write(&file2_path, "Header\nsame\npadded\ntab\t\n")?;
+        
+        // Trimmed, the files are identical
+        let options = CompareOptions { external_diff_min_bytes: u64::MAX, ..CompareOptions::default() };
+        assert!(compare_files_with_options(&file1_path, &file2_path, &options)?.is_none());
+        
+        let options = CompareOptions {
+            process: ProcessOptions { track_trailing_whitespace: true, ..ProcessOptions::default() },
+            ..options
+        };
+        let diff = compare_files_with_options(&file1_path, &file2_path, &options)?.expect("whitespace differences");
+        assert!(diff.only_in_first.is_empty() && diff.only_in_second.is_empty());
+        assert_eq!(diff.whitespace_only_diffs, vec![
+            WhitespaceOnlyDiff { line: "padded".to_string(), trailing1: "  ".to_string(), trailing2: String::new() },
+            WhitespaceOnlyDiff { line: "tab".to_string(), trailing1: String::new(), trailing2: "\t".to_string() },
+        ]);
+        
         Ok(())
     }
 
@@ -296,5 +2876,448 @@ mod tests {
 
         Ok(())
     }
-}
 
+    #[test]
+    fn test_pick_best_match() -> Result<()> {
+        let dir1 = Builder::new().prefix("tbcompare_test1").tempdir()?;
+        let mirror1 = Builder::new().prefix("tbcompare_test2").tempdir()?;
+        let mirror2 = Builder::new().prefix("tbcompare_test3").tempdir()?;
+        
+        let file1_path = dir1.path().join("SC_13260000_20190820_019N_A05_Z.txt");
+        let far_path = mirror1.path().join("SC_13260000_20190820_020N_A05_Z.txt");
+        let close_path = mirror2.path().join("SC_13260000_20190820_021N_A05_Z.txt");
+        fs::write(&file1_path, "Header\nLine 1\nLine 2\nLine 3\n")?;
+        fs::write(&far_path, "Header\nLine 1\nLine 4\nLine 5\n")?;
+        fs::write(&close_path, "Header\nLine 1\nLine 2\nLine 4\n")?;
+        // Only in the first mirror
+        fs::write(dir1.path().join("SC_13260000_20190820_019N_A06_Z.txt"), "Header\n")?;
+        fs::write(mirror1.path().join("SC_13260000_20190820_020N_A06_Z.txt"), "Header\n")?;
+        
+        let mirrors = vec![mirror1.path().to_path_buf(), mirror2.path().to_path_buf()];
+        let mut candidate_pairs = generate_candidate_pairs(dir1.path(), &mirrors, &PairingOptions::default())?;
+        candidate_pairs.sort();
+        assert_eq!(candidate_pairs.len(), 2);
+        assert_eq!(candidate_pairs[0], (file1_path.clone(), vec![far_path.clone(), close_path.clone()]));
+        assert_eq!(candidate_pairs[1].1.len(), 1);
+        
+        let best = pick_best_match(&file1_path, &candidate_pairs[0].1, &CompareOptions::default(), SimilarityMetric::Jaccard);
+        assert_eq!(best.file2_path, close_path);
+        assert_eq!(best.similarity, Some(0.5));
+        assert_eq!(best.alternates, vec![(far_path, Some(0.2))]);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_file_pairs_size_range() -> Result<()> {
+        let dir1 = Builder::new().prefix("tbcompare_test1").tempdir()?;
+        let dir2 = Builder::new().prefix("tbcompare_test2").tempdir()?;
+        
+        for (name, content1, content2) in [("A01", "Header\n", ""), ("A02", "Header\n", "Header\n"), ("A03", "Header\n", "Header\nLine 1\n")] {
+            fs::write(dir1.path().join(format!("SC_13260000_20190820_019N_{}_Z.txt", name)), content1)?;
+            fs::write(dir2.path().join(format!("SC_13260000_20190820_020N_{}_Z.txt", name)), content2)?;
+        }
+        
+        // Zero-byte and larger files exclude their whole pair
+        let options = PairingOptions { min_file_size: Some(1), max_file_size: Some(7), ..PairingOptions::default() };
+        let pairs = generate_file_pairs_with_options(dir1.path(), dir2.path(), &options)?;
+        assert_eq!(pairs, vec![(dir1.path().join("SC_13260000_20190820_019N_A02_Z.txt"),
+                                dir2.path().join("SC_13260000_20190820_020N_A02_Z.txt"))]);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_file_pairs_modified_since() -> Result<()> {
+        let dir1 = Builder::new().prefix("tbcompare_test1").tempdir()?;
+        let dir2 = Builder::new().prefix("tbcompare_test2").tempdir()?;
+        let since = SystemTime::now() - std::time::Duration::from_secs(3600);
+        let before = since - std::time::Duration::from_secs(60);
+        
+        for name in ["A01", "A02", "A03"] {
+            fs::write(dir1.path().join(format!("SC_13260000_20190820_019N_{}_Z.txt", name)), "Header\n")?;
+            fs::write(dir2.path().join(format!("SC_13260000_20190820_020N_{}_Z.txt", name)), "Header\n")?;
+        }
+        // A01 is unchanged on both sides; a change on either side keeps A02 and A03
+        for (dir, name) in [(&dir1, "019N_A01"), (&dir2, "020N_A01"), (&dir1, "019N_A02"), (&dir2, "020N_A03")] {
+            fs::File::options().write(true).open(dir.path().join(format!("SC_13260000_20190820_{}_Z.txt", name)))?
+                .set_modified(before)?;
+        }
+        
+        let options = PairingOptions { modified_since: Some(since), ..PairingOptions::default() };
+        let pairs = generate_file_pairs_with_options(dir1.path(), dir2.path(), &options)?;
+        assert_eq!(pairs.len(), 2);
+        let explanation = explain_pairing(dir1.path(), dir2.path(), "SC_13260000_20190820_019N_A01_Z.txt", &options)?;
+        assert_eq!(explanation.outcome, PairingOutcome::Unchanged(dir2.path().join("SC_13260000_20190820_020N_A01_Z.txt")));
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_file_pairs_key_normalizations() -> Result<()> {
+        let dir1 = Builder::new().prefix("tbcompare_test1").tempdir()?;
+        let dir2 = Builder::new().prefix("tbcompare_test2").tempdir()?;
+        fs::write(dir1.path().join("SC_13260000_20190820_019N_A5_Z.txt"), "Header\n")?;
+        fs::write(dir2.path().join("SC_13260000_20190820_19N_A05_Z.txt"), "Header\n")?;
+        
+        // A5 and A05 only pair once the report type is padded (or stripped) on both sides
+        assert!(generate_file_pairs(dir1.path(), dir2.path())?.is_empty());
+        for rule in ["-2:pad=2", "4:strip-zeros"] {
+            let options = PairingOptions { key_normalizations: vec![rule.parse()?], ..PairingOptions::default() };
+            assert_eq!(generate_file_pairs_with_options(dir1.path(), dir2.path(), &options)?.len(), 1);
+        }
+        
+        assert_eq!(FieldNormalization::ZeroPad(3).apply("A5x"), "A005x");
+        assert_eq!(FieldNormalization::StripZeros.apply("000N"), "0N");
+        assert_eq!(FieldNormalization::StripZeros.apply("Z"), "Z");
+        assert!("-2:pad".parse::<KeyFieldNormalization>().is_err());
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_file_pairs_key_fields() -> Result<()> {
+        let dir1 = Builder::new().prefix("tbcompare_test1").tempdir()?;
+        let dir2 = Builder::new().prefix("tbcompare_test2").tempdir()?;
+        fs::write(dir1.path().join("SC_13260000_20190820_019N_A05_Z.txt"), "Header\n")?;
+        fs::write(dir2.path().join("SC_13260000_20190821_020N_A05_Z.txt"), "Header\n")?;
+        
+        // The dates differ, so only a key without the date pairs the files
+        assert!(generate_file_pairs(dir1.path(), dir2.path())?.is_empty());
+        let options = PairingOptions { key_fields: Some(vec![-2, 1]), key_separator: Some("/".to_string()), ..PairingOptions::default() };
+        assert_eq!(generate_file_pairs_with_options(dir1.path(), dir2.path(), &options)?.len(), 1);
+        assert_eq!(options.match_key(&dir1.path().join("SC_13260000_20190820_019N_A05_Z.txt")).as_deref(), Some("A05/13260000"));
+        
+        // A field past the end of the name gives no key
+        let options = PairingOptions { key_fields: Some(vec![1, 9]), ..PairingOptions::default() };
+        assert!(generate_file_pairs_with_options(dir1.path(), dir2.path(), &options)?.is_empty());
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_file_pairs_match_by() -> Result<()> {
+        let dir1 = Builder::new().prefix("tbcompare_test1").tempdir()?;
+        let dir2 = Builder::new().prefix("tbcompare_test2").tempdir()?;
+        for name in ["b.txt", "a.txt", "c.txt"] {
+            fs::write(dir1.path().join(name), "Content")?;
+        }
+        for name in ["x2.txt", "a.txt", "x1.txt"] {
+            fs::write(dir2.path().join(name), "Content")?;
+        }
+        
+        let options = PairingOptions { match_by: MatchBy::ExactName, ..PairingOptions::default() };
+        let pairs = generate_file_pairs_with_options(dir1.path(), dir2.path(), &options)?;
+        assert_eq!(pairs, vec![(dir1.path().join("a.txt"), dir2.path().join("a.txt"))]);
+        
+        let options = PairingOptions { match_by: "position".parse()?, ..PairingOptions::default() };
+        let pairs = generate_file_pairs_with_options(dir1.path(), dir2.path(), &options)?;
+        assert_eq!(pairs, vec![
+            (dir1.path().join("a.txt"), dir2.path().join("a.txt")),
+            (dir1.path().join("b.txt"), dir2.path().join("x1.txt")),
+            (dir1.path().join("c.txt"), dir2.path().join("x2.txt")),
+        ]);
+        
+        // Hidden files and backups don't shift the positions
+        fs::write(dir2.path().join(".DS_Store"), "Content")?;
+        fs::write(dir2.path().join("a.txt~"), "Content")?;
+        assert_eq!(generate_file_pairs_with_options(dir1.path(), dir2.path(), &options)?, pairs);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_explain_pairing() -> Result<()> {
+        let dir1 = Builder::new().prefix("tbcompare_test1").tempdir()?;
+        let dir2 = Builder::new().prefix("tbcompare_test2").tempdir()?;
+        for name in ["SC_13260000_20190820_019N_A05_Z.txt", "SC_13260000_20190820_019N_A06_Z.txt", "SC_13260000_20190820_A05_Z.txt", "XX_13260000_20190820_019N_A05_Z.txt"] {
+            fs::write(dir1.path().join(name), "Content")?;
+        }
+        fs::write(dir2.path().join("SC_13260000_20190820_020N_A05_Z.txt"), "Content")?;
+        
+        let options = PairingOptions::default();
+        let explanation = explain_pairing(dir1.path(), dir2.path(), "SC_13260000_20190820_019N_A05_Z.txt", &options)?;
+        assert_eq!(explanation.parsed.map(|parsed| parsed.version), Some("019N".to_string()));
+        assert_eq!(explanation.match_key.as_deref(), Some("13260000_20190820_A05"));
+        assert_eq!(explanation.outcome, PairingOutcome::Matched(dir2.path().join("SC_13260000_20190820_020N_A05_Z.txt")));
+        
+        let explain = |file_name: &str, options: &PairingOptions| -> Result<PairingOutcome> {
+            Ok(explain_pairing(dir1.path(), dir2.path(), file_name, options)?.outcome)
+        };
+        assert_eq!(explain("SC_13260000_20190820_019N_A06_Z.txt", &options)?, PairingOutcome::NoCounterpart);
+        assert_eq!(explain("SC_13260000_20190820_A05_Z.txt", &options)?, PairingOutcome::UnparsedName(NameMismatch::TooFewFields(5)));
+        assert_eq!(explain("XX_13260000_20190820_019N_A05_Z.txt", &options)?,
+                   PairingOutcome::UnparsedName(NameMismatch::WrongPrefix("XX".to_string())));
+        
+        let keys = PairingOptions { keys: Some(HashSet::from(["other".to_string()])), ..PairingOptions::default() };
+        assert_eq!(explain("SC_13260000_20190820_019N_A05_Z.txt", &keys)?, PairingOutcome::KeyNotSelected);
+        
+        let size = PairingOptions { min_file_size: Some(100), ..PairingOptions::default() };
+        assert_eq!(explain("SC_13260000_20190820_019N_A05_Z.txt", &size)?,
+                   PairingOutcome::SizeOutOfRange(dir2.path().join("SC_13260000_20190820_020N_A05_Z.txt")));
+        
+        let position = PairingOptions { match_by: MatchBy::Position, ..PairingOptions::default() };
+        assert_eq!(explain("SC_13260000_20190820_019N_A06_Z.txt", &position)?, PairingOutcome::NoFileAtPosition(2));
+        
+        assert!(explain("missing.txt", &options).is_err());
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_file_pairs_suffix_pattern() -> Result<()> {
+        let dir1 = Builder::new().prefix("tbcompare_test1").tempdir()?;
+        let dir2 = Builder::new().prefix("tbcompare_test2").tempdir()?;
+        
+        let file1_a05 = dir1.path().join("SC_13260000_20190820_019N_A05_Z.txt");
+        let file1_a06 = dir1.path().join("SC_13260000_20190820_019N_A06_Z1.txt");
+        let file2_a05 = dir2.path().join("SC_13260000_20190820_020N_A05_Z2.txt");
+        let file2_a06 = dir2.path().join("SC_13260000_20190820_020N_A06_Z.txt");
+        for file_path in [&file1_a05, &file1_a06, &file2_a05, &file2_a06] {
+            fs::write(file_path, "Content")?;
+        }
+        fs::write(dir2.path().join("SC_13260000_20190820_020N_A06_ZX.txt"), "Content")?;
+        
+        // Only the exact `Z` suffix is accepted by default
+        assert!(generate_file_pairs(dir1.path(), dir2.path())?.is_empty());
+        
+        let options = PairingOptions { suffix_pattern: Some(anchored_regex(r"Z\d*")?), ..PairingOptions::default() };
+        let mut pairs = generate_file_pairs_with_options(dir1.path(), dir2.path(), &options)?;
+        pairs.sort();
+        assert_eq!(pairs, vec![(file1_a05.clone(), file2_a05), (file1_a06, file2_a06)]);
+        assert_eq!(parse_filename_with_options(&file1_a05, &options).map(|parsed| parsed.match_key()),
+                   Some("13260000_20190820_A05".to_string()));
+        
+        // Every alternative may match the whole field, not only the leftmost one
+        let options = PairingOptions { suffix_pattern: Some(anchored_regex("Z|Z1")?), ..PairingOptions::default() };
+        assert!(parse_filename_with_options("SC_13260000_20190820_019N_A06_Z1.txt", &options).is_some());
+        assert!(parse_filename_with_options("SC_13260000_20190820_020N_A05_Z2.txt", &options).is_none());
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_manifest_with_header_lines() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let manifest_path = dir.path().join("manifest.tsv");
+        fs::write(&manifest_path, "one.txt\tone.txt\n\nthree.txt\t/abs/three.txt\t1\t3\n")?;
+        
+        let pairs = load_manifest(&manifest_path, Path::new("d1"), Path::new("d2"))?;
+        assert_eq!(pairs, vec![
+            ManifestPair { file1_path: PathBuf::from("d1/one.txt"), file2_path: PathBuf::from("d2/one.txt"), header_lines: None },
+            ManifestPair { file1_path: PathBuf::from("d1/three.txt"), file2_path: PathBuf::from("/abs/three.txt"), header_lines: Some((1, 3)) },
+        ]);
+        
+        // Per-pair header counts apply to each side of the comparison
+        fs::write(dir.path().join("one.txt"), "Header\nLine A\nLine B\n")?;
+        fs::write(dir.path().join("three.txt"), "Generated\nby\nsystem B\nLine B\nLine A\n")?;
+        let options = CompareOptions {
+            process: ProcessOptions { header_lines: Some(1), ..ProcessOptions::default() },
+            second_header_lines: Some(3),
+            ..CompareOptions::default()
+        };
+        assert!(compare_files_with_options(dir.path().join("one.txt"), dir.path().join("three.txt"), &options)?.is_none());
+        
+        for malformed in ["a.txt\n", "a.txt\tb.txt\t1\n", "a.txt\tb.txt\t1\t-2\n", "a.txt\tb.txt\tx\t2\n"] {
+            fs::write(&manifest_path, malformed)?;
+            let error = load_manifest(&manifest_path, Path::new("d1"), Path::new("d2")).unwrap_err().to_string();
+            assert!(error.contains("manifest.tsv"), "{}", error);
+        }
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_looks_duplicated() -> Result<()> {
+        let dir1 = Builder::new().prefix("tbcompare_test1").tempdir()?;
+        let dir2 = Builder::new().prefix("tbcompare_test2").tempdir()?;
+        let mut pairs = Vec::new();
+        for (index, content) in ["a\n", "b\n", "c\n", "d\n"].into_iter().enumerate() {
+            let name = format!("file{}.txt", index);
+            fs::write(dir1.path().join(&name), content)?;
+            fs::write(dir2.path().join(&name), content)?;
+            pairs.push((dir1.path().join(&name), dir2.path().join(&name)));
+        }
+        
+        // A copied directory, and a directory compared with itself
+        assert!(looks_duplicated(&pairs, 3)?);
+        let self_pairs: Vec<_> = pairs.iter().map(|(file1_path, _)| (file1_path.clone(), file1_path.clone())).collect();
+        assert!(looks_duplicated(&self_pairs, 3)?);
+        
+        // One differing sampled pair is enough to proceed; a disabled check never reports duplicates
+        fs::write(&pairs[2].1, "C\n")?;
+        assert!(!looks_duplicated(&pairs, 4)?);
+        assert!(!looks_duplicated(&pairs, 0)?);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_file_pairs_case_insensitive() -> Result<()> {
+        let dir1 = Builder::new().prefix("tbcompare_test1").tempdir()?;
+        let dir2 = Builder::new().prefix("tbcompare_test2").tempdir()?;
+        
+        let file1_path = dir1.path().join("SC_13260000_20190820_019N_A05_Z.txt");
+        let file2_path = dir2.path().join("sc_13260000_20190820_020n_a05_z.txt");
+        fs::write(&file1_path, "Content 1")?;
+        fs::write(&file2_path, "Content 2")?;
+        
+        // Case-sensitive matching is the default
+        assert!(generate_file_pairs(dir1.path(), dir2.path())?.is_empty());
+        
+        let options = PairingOptions { case_insensitive: true, ..PairingOptions::default() };
+        let pairs = generate_file_pairs_with_options(dir1.path(), dir2.path(), &options)?;
+        assert_eq!(pairs, vec![(file1_path, file2_path)]);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_file_pairs_key_regex() -> Result<()> {
+        let dir1 = Builder::new().prefix("tbcompare_test1").tempdir()?;
+        let dir2 = Builder::new().prefix("tbcompare_test2").tempdir()?;
+        
+        // The version part has a varying number of fields
+        let file1_path = dir1.path().join("SC_13260000_20190820_019N_A05_Z.txt");
+        let file2_path = dir2.path().join("SC_13260000_20190820_020N_EXTRA_A05_Z.txt");
+        fs::write(&file1_path, "Content 1")?;
+        fs::write(&file2_path, "Content 2")?;
+        fs::write(dir2.path().join("unrelated.txt"), "Content 3")?;
+        // Only part of the stem matches, so the anchored regex skips it
+        fs::write(dir2.path().join("OLD_SC_13260000_20190820_018N_A05_Z.txt"), "Content 4")?;
+        
+        let options = PairingOptions {
+            key_regex: Some(anchored_regex(r"SC_(?P<acct>\d+)_(?P<date>\d{8})_.+_(?P<type>A\d+)_Z")?),
+            ..PairingOptions::default()
+        };
+        let pairs = generate_file_pairs_with_options(dir1.path(), dir2.path(), &options)?;
+        assert_eq!(pairs, vec![(file1_path.clone(), file2_path.clone())]);
+        
+        // A case-insensitive key regex pairs names differing in case, under the same lowercased key
+        let file3_path = dir2.path().join("sc_13260000_20190820_021n_a05_z.txt");
+        fs::rename(&file2_path, &file3_path)?;
+        let options = PairingOptions {
+            case_insensitive: true,
+            key_regex: Some(anchored_regex(r"(?i)SC_(?P<acct>\d+)_(?P<date>\d{8})_.+_(?P<type>A\d+)_Z")?),
+            ..PairingOptions::default()
+        };
+        let pairs = generate_file_pairs_with_options(dir1.path(), dir2.path(), &options)?;
+        assert_eq!(pairs, vec![(file1_path, file3_path)]);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_trees() -> Result<()> {
+        let dir1 = Builder::new().prefix("tbcompare_test1").tempdir()?;
+        let dir2 = Builder::new().prefix("tbcompare_test2").tempdir()?;
+        fs::create_dir_all(dir1.path().join("sub/deep"))?;
+        fs::create_dir_all(dir2.path().join("sub/deep"))?;
+        fs::write(dir1.path().join("common.txt"), "1")?;
+        fs::write(dir2.path().join("common.txt"), "2")?;
+        fs::write(dir1.path().join("sub/deep/nested.txt"), "1")?;
+        fs::write(dir2.path().join("sub/deep/nested.txt"), "2")?;
+        fs::write(dir1.path().join("sub/first.txt"), "1")?;
+        fs::write(dir2.path().join("second.txt"), "2")?;
+        
+        let tree_diff = diff_trees(dir1.path(), dir2.path())?;
+        assert_eq!(tree_diff.only_in_first, vec![PathBuf::from("sub/first.txt")]);
+        assert_eq!(tree_diff.only_in_second, vec![PathBuf::from("second.txt")]);
+        assert_eq!(tree_diff.in_both, vec![PathBuf::from("common.txt"), PathBuf::from("sub/deep/nested.txt")]);
+        
+        let pairs = tree_diff.file_pairs(dir1.path(), dir2.path());
+        assert_eq!(pairs[1], (dir1.path().join("sub/deep/nested.txt"), dir2.path().join("sub/deep/nested.txt")));
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_file_pairs_keys_filter() -> Result<()> {
+        let dir1 = Builder::new().prefix("tbcompare_test1").tempdir()?;
+        let dir2 = Builder::new().prefix("tbcompare_test2").tempdir()?;
+        
+        for (account, version) in [("11111111", "019N"), ("22222222", "020N")] {
+            fs::write(dir1.path().join(format!("SC_{}_20190820_{}_A05_Z.txt", account, version)), "Content 1")?;
+            fs::write(dir2.path().join(format!("SC_{}_20190820_{}_A05_Z.txt", account, version)), "Content 2")?;
+        }
+        
+        let options = PairingOptions {
+            keys: Some(HashSet::from(["22222222_20190820_A05".to_string()])),
+            ..PairingOptions::default()
+        };
+        let pairs = generate_file_pairs_with_options(dir1.path(), dir2.path(), &options)?;
+        assert_eq!(pairs, vec![(dir1.path().join("SC_22222222_20190820_020N_A05_Z.txt"),
+                                dir2.path().join("SC_22222222_20190820_020N_A05_Z.txt"))]);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_file_pairs_gzip() -> Result<()> {
+        let dir1 = Builder::new().prefix("tbcompare_test1").tempdir()?;
+        let dir2 = Builder::new().prefix("tbcompare_test2").tempdir()?;
+        
+        let file1_path = dir1.path().join("SC_13260000_20190820_019N_A05_Z.txt.gz");
+        let file2_path = dir2.path().join("SC_13260000_20190820_020N_A05_Z.txt");
+        let mut encoder = flate2::write::GzEncoder::new(fs::File::create(&file1_path)?, flate2::Compression::default());
+        std::io::Write::write_all(&mut encoder, b"Header line\nLine 2\nLine 1\n")?;
+        encoder.finish()?;
+        fs::write(&file2_path, "Other header\nLine 1\nLine 2\n")?;
+        
+        let pairs = generate_file_pairs(dir1.path(), dir2.path())?;
+        assert_eq!(pairs, vec![(file1_path.clone(), file2_path.clone())]);
+        assert!(compare_files(&file1_path, &file2_path)?.is_none());
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_version_pairs() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        
+        let v9_path = dir.path().join("SC_13260000_20190820_9N_A05_Z.txt");
+        let v10_path = dir.path().join("SC_13260000_20190820_010N_A05_Z.txt");
+        let v11_path = dir.path().join("SC_13260000_20190820_011N_A05_Z.txt");
+        let other_path = dir.path().join("SC_13260000_20190820_001N_A01_Z.txt"); // Only version of its key
+        for path in [&v9_path, &v10_path, &v11_path, &other_path] {
+            fs::write(path, "Content")?;
+        }
+        
+        let pairs = generate_version_pairs(dir.path(), &PairingOptions::default())?;
+        
+        // Versions are ordered numerically, so 9N precedes 010N
+        assert_eq!(pairs, vec![(v9_path, v10_path.clone()), (v10_path, v11_path)]);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare_files_ordered_with_sort_key() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file1_path = dir.path().join("file1.txt");
+        let file2_path = dir.path().join("file2.txt");
+        
+        fs::write(&file1_path, "Header line\n2|b\n1|a\n1|c\n")?;
+        fs::write(&file2_path, "Header line\n1|a\n1|c\n2|b\n")?;
+        
+        // In file order, "2|b" moved to the end
+        let mut options = CompareOptions {
+            ordered: true,
+            ..CompareOptions::default()
+        };
+        let diff = compare_files_with_options(&file1_path, &file2_path, &options)?.unwrap();
+        assert_eq!(diff.only_in_first, vec!["2|b"]);
+        assert_eq!(diff.only_in_second, vec!["2|b"]);
+        
+        // Sorting both files by the first field removes the incidental ordering difference
+        options.sort_key = Some(SortKey { field: 1, delimiter: "|".to_string() });
+        assert!(compare_files_with_options(&file1_path, &file2_path, &options)?.is_none());
+        
+        // Lines within the same key keep their relative order
+        fs::write(&file2_path, "Header line\n1|c\n1|a\n2|b\n")?;
+        let diff = compare_files_with_options(&file1_path, &file2_path, &options)?.unwrap();
+        assert_eq!(diff.common_edges, Some((0, 1)));
+        
+        Ok(())
+    }
+}
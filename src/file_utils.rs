@@ -1,17 +1,41 @@
 //! File utility functions for the tbcompare tool.
 
 use std::fs::File;
-use std::io::{Read, BufReader, BufRead, Write};
-use std::path::Path;
+use std::io::{Read, BufReader, BufRead, Cursor, Seek, SeekFrom, Write};
+use std::fmt::{self, Write as _};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::fs;
 use encoding_rs_io::DecodeReaderBytesBuilder;
 use encoding_rs::Encoding;
+use flate2::read::MultiGzDecoder;
 use anyhow::{Context, Result};
-use std::process::Command;
+use std::process::{Command, Stdio};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::str::FromStr;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use log::{info, warn};
+use regex::Regex;
+use crate::comparison::parse_filename;
+use sha2::Digest;
+use chrono::NaiveDate;
+use chrono::format::{Parsed, StrftimeItems};
 
 /// Maximum file size that can be loaded into memory (100MB)
 const MAX_MEMORY_FILE_SIZE: u64 = 100 * 1024 * 1024;
 
+/// Number of leading lines examined when detecting the header length
+const AUTO_HEADER_SAMPLE_LINES: usize = 50;
+
+/// Number of consecutive lines with the same delimiter counts that mark the start of the data
+const AUTO_HEADER_STABLE_LINES: usize = 4;
+
+/// Delimiters whose per-line counts make up a line's shape for header detection
+const AUTO_HEADER_DELIMITERS: [char; 4] = ['\t', ',', '|', ';'];
+
 /// Detects the encoding of a file
 /// 
 /// # Arguments
@@ -22,18 +46,96 @@ const MAX_MEMORY_FILE_SIZE: u64 = 100 * 1024 * 1024;
 /// 
 /// A Result containing either the detected encoding or an error
 pub fn detect_encoding<P: AsRef<Path>>(file_path: P) -> Result<&'static Encoding> {
-    let file = File::open(&file_path)
-        .with_context(|| format!("Failed to open file: {}", file_path.as_ref().display()))?;
-    let mut reader = BufReader::new(file);
-    let mut buffer = [0; 1024];
-    let bytes_read = reader.read(&mut buffer)
-        .with_context(|| format!("Failed to read file: {}", file_path.as_ref().display()))?;
+    let file_path = file_path.as_ref();
+    let reader = open_input(file_path)?;
+    let mut buffer = Vec::with_capacity(1024);
+    reader.take(1024).read_to_end(&mut buffer)
+        .with_context(|| format!("Failed to read file: {}", file_path.display()))?;
     
+    Ok(guess_encoding(&buffer))
+}
+
+/// Guesses the encoding of content from its first 1024 bytes
+fn guess_encoding(content: &[u8]) -> &'static Encoding {
+    let sample = &content[..content.len().min(1024)];
     let mut encoding_detector = chardetng::EncodingDetector::new();
-    encoding_detector.feed(&buffer[..bytes_read], bytes_read < 1024);
-    let encoding = encoding_detector.guess(None, true);
+    encoding_detector.feed(sample, sample.len() < 1024);
+    encoding_detector.guess(None, true)
+}
+
+/// Checks that a whole file is in the expected encoding
+/// The whole content must decode without errors in the expected encoding, and be detected as that
+/// encoding (many byte sequences are valid in several encodings). Content that is all ASCII conforms to
+/// any ASCII-compatible expected encoding, since it decodes the same way in it. Unlike [`detect_encoding`],
+/// this reads the whole file, so a body in another encoding after an ASCII header is caught.
+/// 
+/// # Arguments
+/// 
+/// * `file_path` - Path to the file to check
+/// * `expected` - Encoding the file must be in
+/// 
+/// # Returns
+/// 
+/// A Result containing either `None` if the file conforms, or its detected encoding
+/// (detected from the content read up to the first byte that doesn't decode)
+pub fn check_encoding<P: AsRef<Path>>(file_path: P, expected: &'static Encoding) -> Result<Option<&'static Encoding>> {
+    let file_path = file_path.as_ref();
+    let mut reader = open_input(file_path)?;
+    let mut decoder = expected.new_decoder_without_bom_handling();
+    let mut detector = chardetng::EncodingDetector::new();
+    let mut input = vec![0; 64 * 1024];
+    let mut output = vec![0; decoder.max_utf8_buffer_length(input.len()).unwrap_or(4 * input.len())];
+    let mut is_ascii = true;
+    loop {
+        let read = reader.read(&mut input)
+            .with_context(|| format!("Failed to read file: {}", file_path.display()))?;
+        let last = read == 0;
+        let mut chunk = &input[..read];
+        is_ascii &= chunk.is_ascii();
+        detector.feed(chunk, last);
+        loop {
+            let (result, consumed, _, had_errors) = decoder.decode_to_utf8(chunk, &mut output, last);
+            if had_errors {
+                return Ok(Some(detector.guess(None, true)));
+            }
+            chunk = &chunk[consumed..];
+            if result == encoding_rs::CoderResult::InputEmpty {
+                break;
+            }
+        }
+        if last {
+            break;
+        }
+    }
     
-    Ok(encoding)
+    let detected = detector.guess(None, true);
+    let conforms = detected == expected || (is_ascii && expected.is_ascii_compatible());
+    Ok((!conforms).then_some(detected))
+}
+
+/// Returns true if the file is gzip-compressed, judging by its `.gz` extension
+pub fn is_gzip_path(file_path: &Path) -> bool {
+    file_path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("gz"))
+}
+
+/// Opens an input file for reading, transparently decompressing it if it is gzip-compressed
+pub(crate) fn open_input(file_path: &Path) -> Result<Box<dyn Read + Send>> {
+    let file = File::open(file_path)
+        .with_context(|| format!("Failed to open file: {}", file_path.display()))?;
+    if is_gzip_path(file_path) {
+        Ok(Box::new(MultiGzDecoder::new(BufReader::new(file))))
+    } else {
+        Ok(Box::new(file))
+    }
+}
+
+/// Reads the whole content of an input file, decompressing it if it is gzip-compressed
+pub(crate) fn read_input(file_path: &Path) -> Result<Vec<u8>> {
+    let mut content = Vec::new();
+    open_input(file_path)?
+        .read_to_end(&mut content)
+        .with_context(|| format!("Failed to read file: {}", file_path.display()))?;
+    Ok(content)
 }
 
 /// Checks if a file is too large to be loaded into memory
@@ -51,215 +153,1534 @@ fn is_file_too_large<P: AsRef<Path>>(file_path: P) -> Result<bool> {
     Ok(metadata.len() > MAX_MEMORY_FILE_SIZE)
 }
 
-/// Reads and processes a file, skipping the first line and sorting the rest
-/// For large files, uses external sorting to avoid memory issues
+/// Lines of a file after reading and normalization
+#[derive(Debug, Clone, Default)]
+pub struct ProcessedFile {
+    /// Lines after the header, trimmed, normalized and (unless read in file order) sorted
+    pub lines: Vec<String>,
+    /// Original text of lines changed by normalization, keyed by their normalized form
+    pub originals: HashMap<String, String>,
+    /// Number of lines dropped from the truncation marker on (marker line included)
+    pub truncated_lines: usize,
+    /// Trailing whitespace of normalized lines whose original ends with some, keyed by their normalized form
+    /// (only recorded with [`ProcessOptions::track_trailing_whitespace`])
+    pub trailing_whitespace: HashMap<String, String>,
+}
+
+impl ProcessedFile {
+    /// Returns the original text of a normalized line
+    pub fn original<'a>(&'a self, line: &'a str) -> &'a str {
+        self.originals.get(line).map(String::as_str).unwrap_or(line)
+    }
+}
+
+/// Checks whether two paths refer to the same file
+/// Paths are considered the same when they canonicalize to the same path or, on Unix,
+/// share the same device and inode (hardlinks)
 /// 
 /// # Arguments
 /// 
-/// * `file_path` - Path to the file to read and process
+/// * `file1_path` - Path to the first file
+/// * `file2_path` - Path to the second file
 /// 
 /// # Returns
 /// 
-/// A Result containing either a sorted vector of lines or an error
-pub fn read_and_process_file<P: AsRef<Path>>(file_path: P) -> Result<Vec<String>> {
-    let file_path = file_path.as_ref();
-    
-    // Check if file is too large for memory
-    if let Ok(too_large) = is_file_too_large(file_path) {
-        if too_large {
-            // For large files, use external sorting directly
-            return external_sort_large_file(file_path);
-        }
-    }
-    
-    let encoding = detect_encoding(file_path)
-        .with_context(|| format!("Failed to detect encoding for file: {}", file_path.display()))?;
-    
-    let file = File::open(file_path)
-        .with_context(|| format!("Failed to open file: {}", file_path.display()))?;
-    let decoder = DecodeReaderBytesBuilder::new()
-        .encoding(Some(encoding))
-        .build(file);
-    let reader = BufReader::new(decoder);
+/// A Result containing either whether both paths refer to the same file or an error
+pub fn is_same_file<P: AsRef<Path>>(file1_path: P, file2_path: P) -> Result<bool> {
+    let file1_path = file1_path.as_ref();
+    let file2_path = file2_path.as_ref();
     
-    let mut lines = Vec::new();
-    let mut first_line_skipped = false;
-    
-    for (index, line_result) in reader.lines().enumerate() {
-        let line = line_result
-            .with_context(|| format!("Failed to read line {} from file: {}", index, file_path.display()))?;
-        if !first_line_skipped {
-            first_line_skipped = true;
-            continue;
-        }
-        lines.push(line.trim().to_string());
+    let canonical1 = fs::canonicalize(file1_path)
+        .with_context(|| format!("Failed to canonicalize path: {}", file1_path.display()))?;
+    let canonical2 = fs::canonicalize(file2_path)
+        .with_context(|| format!("Failed to canonicalize path: {}", file2_path.display()))?;
+    if canonical1 == canonical2 {
+        return Ok(true);
     }
     
-    // For large files (many lines), use external sorting
-    if lines.len() > 100_000 {
-        external_sort(&mut lines)
-            .with_context(|| format!("Failed to externally sort file: {}", file_path.display()))?;
-    } else {
-        lines.sort();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        
+        let metadata1 = fs::metadata(file1_path)
+            .with_context(|| format!("Failed to get metadata for file: {}", file1_path.display()))?;
+        let metadata2 = fs::metadata(file2_path)
+            .with_context(|| format!("Failed to get metadata for file: {}", file2_path.display()))?;
+        Ok(metadata1.dev() == metadata2.dev() && metadata1.ino() == metadata2.ino())
     }
     
-    Ok(lines)
+    #[cfg(not(unix))]
+    Ok(false)
 }
 
-/// Sorts lines using Rust's unstable sort algorithm
-/// This is faster than stable sort and more reliable across platforms than external sorting
-fn internal_sort(lines: &mut Vec<String>) -> Result<()> {
-    lines.sort_unstable();
-    Ok(())
+/// Signs that one file of a pair was cut off while being written (e.g. by an interrupted delivery)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TruncationSigns {
+    /// The file (1 or 2) that doesn't end with a newline while the other one does
+    pub missing_final_newline: Option<usize>,
+    /// Size of the larger file divided by the smaller one's, when above the allowed ratio
+    /// (infinite when the smaller file is empty)
+    pub size_ratio: Option<f64>,
 }
 
-/// External sorting implementation for large files
-/// Uses the system's sort command for efficiency
-fn external_sort(lines: &mut Vec<String>) -> Result<()> {
-    // Create a temporary file
-    let mut temp_file = tempfile::NamedTempFile::new()
-        .context("Failed to create temporary file for external sorting")?;
-    
-    // Write lines to temporary file
-    for line in lines.iter() {
-        writeln!(temp_file, "{}", line)
-            .context("Failed to write to temporary file")?;
-    }
-    
-    // Flush the file to ensure all data is written
-    temp_file.flush()
-        .context("Failed to flush temporary file")?;
+/// Checks whether either file of a pair looks truncated, from file sizes and final bytes only
+/// 
+/// The final newline is only checked on uncompressed, non-empty files. Both checks are heuristics:
+/// they flag a pair for inspection rather than prove a truncation.
+/// 
+/// # Arguments
+/// 
+/// * `file1_path` - Path to the first file
+/// * `file2_path` - Path to the second file
+/// * `max_size_ratio` - Largest ratio between the two file sizes that isn't suspicious
+/// 
+/// # Returns
+/// 
+/// A Result containing the signs found, or `None` if there are none
+pub fn check_truncation<P: AsRef<Path>>(file1_path: P, file2_path: P, max_size_ratio: f64) -> Result<Option<TruncationSigns>> {
+    let file1_path = file1_path.as_ref();
+    let file2_path = file2_path.as_ref();
     
-    // Get the path of the temporary file
-    let temp_path = temp_file.path();
+    let size = |file_path: &Path| fs::metadata(file_path)
+        .map(|metadata| metadata.len())
+        .with_context(|| format!("Failed to get metadata for file: {}", file_path.display()));
+    let (size1, size2) = (size(file1_path)?, size(file2_path)?);
+    let (smaller, larger) = (size1.min(size2), size1.max(size2));
+    let ratio = match smaller {
+        0 if larger > 0 => f64::INFINITY,
+        0 => 1.0,
+        _ => larger as f64 / smaller as f64,
+    };
     
-    // Use system sort command
-    let output = if cfg!(windows) {
-        // On Windows, we'll use internal sorting instead of external command
-        // which can be unreliable
-        return internal_sort(lines);
-    } else {
-        Command::new("sort")
-            .arg(temp_path)
-            .output()
-            .context("Failed to execute Unix sort command")?
+    let missing_final_newline = match (ends_with_newline(file1_path, size1)?, ends_with_newline(file2_path, size2)?) {
+        (Some(false), Some(true)) => Some(1),
+        (Some(true), Some(false)) => Some(2),
+        _ => None,
     };
+    let signs = TruncationSigns { missing_final_newline, size_ratio: (ratio > max_size_ratio).then_some(ratio) };
+    Ok((signs.missing_final_newline.is_some() || signs.size_ratio.is_some()).then_some(signs))
+}
+
+/// Returns whether a file's last byte is a newline, or `None` for empty and gzip-compressed files
+fn ends_with_newline(file_path: &Path, size: u64) -> Result<Option<bool>> {
+    if size == 0 || is_gzip_path(file_path) {
+        return Ok(None);
+    }
+    let mut file = File::open(file_path)
+        .with_context(|| format!("Failed to open file: {}", file_path.display()))?;
+    let mut last = [0; 1];
+    file.seek(SeekFrom::End(-1))
+        .and_then(|_| file.read_exact(&mut last))
+        .with_context(|| format!("Failed to read file: {}", file_path.display()))?;
+    Ok(Some(last[0] == b'\n'))
+}
+
+/// Hash algorithm used to fingerprint file contents
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HashAlgorithm {
+    /// SHA-256
+    #[default]
+    Sha256,
+    /// SHA-512
+    Sha512,
+    /// SHA-1, for matching checksums recorded by older tooling
+    Sha1,
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = anyhow::Error;
     
-    // Check if the sort command was successful
-    if !output.status.success() {
-        anyhow::bail!("External sort command failed: {}", 
-                      String::from_utf8_lossy(&output.stderr));
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            "sha512" => Ok(HashAlgorithm::Sha512),
+            "sha1" => Ok(HashAlgorithm::Sha1),
+            _ => anyhow::bail!("Unknown hash algorithm: {} (expected sha256, sha512 or sha1)", s),
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha512 => "sha512",
+            HashAlgorithm::Sha1 => "sha1",
+        };
+        f.write_str(name)
     }
+}
+
+/// Computes the hash of a file's raw bytes (compressed bytes for gzip files), as lowercase hex
+/// 
+/// # Arguments
+/// 
+/// * `file_path` - Path to the file to hash
+/// * `algorithm` - Hash algorithm to use
+/// 
+/// # Returns
+/// 
+/// A Result containing either the hex digest or an error
+pub fn content_hash<P: AsRef<Path>>(file_path: P, algorithm: HashAlgorithm) -> Result<String> {
+    let file_path = file_path.as_ref();
+    let file = File::open(file_path)
+        .with_context(|| format!("Failed to open file: {}", file_path.display()))?;
     
-    // Read sorted lines back
-    let sorted_content = String::from_utf8(output.stdout)
-        .context("Failed to parse sorted output as UTF-8")?;
+    let digest = match algorithm {
+        HashAlgorithm::Sha256 => hash_reader::<sha2::Sha256>(file),
+        HashAlgorithm::Sha512 => hash_reader::<sha2::Sha512>(file),
+        HashAlgorithm::Sha1 => hash_reader::<sha1::Sha1>(file),
+    }.with_context(|| format!("Failed to read file: {}", file_path.display()))?;
     
-    lines.clear();
-    for line in sorted_content.lines() {
-        lines.push(line.to_string());
+    let mut hex = String::with_capacity(digest.len() * 2);
+    for byte in digest {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    Ok(hex)
+}
+
+/// Feeds a reader through a hasher in fixed-size chunks and returns the digest
+fn hash_reader<D: Digest>(mut reader: impl Read) -> std::io::Result<Vec<u8>> {
+    let mut hasher = D::new();
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
     }
+    Ok(hasher.finalize().to_vec())
+}
+
+/// Column of fixed-width records, in characters from the start of the untrimmed line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedColumn {
+    /// Position of the column's first character (1-based)
+    pub start: usize,
+    /// Number of characters in the column
+    pub width: usize,
+}
+
+impl FromStr for FixedColumn {
+    type Err = anyhow::Error;
     
-    Ok(())
+    fn from_str(s: &str) -> Result<Self> {
+        let (start, width) = s.split_once(':')
+            .with_context(|| format!("Invalid column: {} (expected START:WIDTH)", s))?;
+        let start: usize = start.trim().parse().with_context(|| format!("Invalid column start: {}", start))?;
+        let width: usize = width.trim().parse().with_context(|| format!("Invalid column width: {}", width))?;
+        if start == 0 || width == 0 {
+            anyhow::bail!("Invalid column: {} (START is 1-based and WIDTH must be positive)", s);
+        }
+        Ok(FixedColumn { start, width })
+    }
 }
 
-/// External sorting implementation for large files that cannot fit in memory
-/// Uses the system's sort command directly on the input file
-fn external_sort_large_file<P: AsRef<Path>>(file_path: P) -> Result<Vec<String>> {
-    let file_path = file_path.as_ref();
+/// Record terminator used instead of newlines, e.g. `\x1e` for the ASCII record separator
+/// Parsed from text where `\xHH` (ASCII only), `\t`, `\r`, `\n`, `\0` and `\\` are unescaped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordSeparator(pub String);
+
+impl FromStr for RecordSeparator {
+    type Err = anyhow::Error;
     
-    // Detect encoding
+    fn from_str(s: &str) -> Result<Self> {
+        let mut separator = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                separator.push(c);
+                continue;
+            }
+            let unescaped = match chars.next() {
+                Some('t') => '\t',
+                Some('r') => '\r',
+                Some('n') => '\n',
+                Some('0') => '\0',
+                Some('\\') => '\\',
+                Some('x') => {
+                    let hex: String = chars.by_ref().take(2).collect();
+                    u8::from_str_radix(&hex, 16).ok()
+                        .filter(|byte| hex.len() == 2 && byte.is_ascii())
+                        .map(char::from)
+                        .with_context(|| format!("Invalid record separator: {} (\\x must be followed by an ASCII byte in hex)", s))?
+                }
+                _ => anyhow::bail!("Invalid record separator: {} (unknown escape)", s),
+            };
+            separator.push(unescaped);
+        }
+        if separator.is_empty() {
+            anyhow::bail!("Invalid record separator: the separator can't be empty");
+        }
+        Ok(RecordSeparator(separator))
+    }
+}
+
+/// Options controlling how a file's lines are read and normalized before comparison
+#[derive(Debug, Clone, Default)]
+pub struct ProcessOptions {
+    /// Shell command that each file's decoded content is piped through.
+    /// The command's stdout replaces the file content for the rest of the pipeline.
+    pub preprocess_cmd: Option<String>,
+    /// Shell command that each file's raw bytes (decompressed if gzipped) are piped through before decoding,
+    /// e.g. to decrypt or base64-decode them; the command's stdout is decoded in place of the file content
+    pub transform_cmd: Option<String>,
+    /// chrono strftime format of a volatile timestamp stripped from the end of each line
+    pub strip_trailing_timestamp: Option<String>,
+    /// Sort lines by their leading number instead of lexicographically.
+    /// The set-based comparison result is the same either way; only the line order changes.
+    pub numeric_sort: bool,
+    /// Locale whose collation orders the sorted lines (e.g. `zh_CN.UTF-8`) instead of byte-wise order.
+    /// Every file is then sorted by the system sort command, since the in-memory sort is byte-wise;
+    /// like `numeric_sort`, only the line order changes.
+    pub collation: Option<String>,
+    /// Lines (compared after trimming) that are dropped from every file before comparison
+    pub exclude_lines: HashSet<String>,
+    /// Only the first N lines after the header are read and compared (`None` reads the whole file)
+    pub head: Option<usize>,
+    /// Renamed values canonicalized to their new names before comparison
+    pub equivalence_map: Option<Arc<EquivalenceMap>>,
+    /// Expand tabs to spaces with tab stops every N characters before comparison
+    pub expand_tabs: Option<NonZeroUsize>,
+    /// Number of header lines skipped at the top of each file (`None` skips the usual single header line)
+    pub header_lines: Option<usize>,
+    /// When `header_lines` isn't set, detect each file's header length from where its lines settle into
+    /// a consistent delimiter count, falling back to a single header line when detection is inconclusive
+    pub auto_header: bool,
+    /// Compare lines case-insensitively (lines are lowercased after the other normalizations)
+    pub ignore_case: bool,
+    /// Lines (after trimming) matching any of these regexes are dropped before comparison
+    pub ignore_patterns: Vec<Regex>,
+    /// Program used to sort large files instead of `sort`
+    pub sort_command: Option<String>,
+    /// Only the lines of this section are compared: those after its `[NAME]` marker line,
+    /// up to the next marker line (markers themselves are not compared)
+    pub section: Option<String>,
+    /// Reuse the encoding detected for the first file of each directory instead of detecting it per file
+    pub encoding_cache: Option<Arc<EncodingCache>>,
+    /// Content from the first body line (after trimming) matching this regex on is not compared
+    pub truncate_at: Option<Regex>,
+    /// Encodings pinned for individual files; listed files skip detection and the encoding cache
+    pub encoding_map: Option<Arc<EncodingMap>>,
+    /// Thousands separator removed from numbers (`1,234.56` becomes `1234.56`) before comparison
+    pub thousands_separator: Option<char>,
+    /// Lines shorter than this many characters (after trimming) are dropped before comparison
+    pub min_line_length: Option<usize>,
+    /// Each line is split on this delimiter and its fields sorted before comparison,
+    /// so lines holding the same fields in a different order (`a|b|c` and `c|a|b`) are equal
+    pub sort_fields: Option<String>,
+    /// Each line is split on this delimiter and its empty (or blank) fields are dropped before comparison, so an
+    /// empty field and an omitted one are equal (`a||c` and `a|c`, `a|b|` and `a|b`); applied before `sort_fields`
+    pub drop_empty_fields: Option<String>,
+    /// Each line is parsed as a JSON value and rewritten in canonical form (sorted object keys, no
+    /// insignificant whitespace); lines that aren't valid JSON are compared as is.
+    /// JSON-lines files have no header, so no line is skipped unless `header_lines` is set.
+    pub json_lines: bool,
+    /// Fixed-width columns holding numbers compared by value: a column's number is rewritten without
+    /// leading zeros or padding (`00042` and `   42` are equal); columns that aren't numeric are kept as is
+    pub numeric_columns: Vec<FixedColumn>,
+    /// Record the trailing whitespace of each line, so lines equal once trimmed but with different trailing
+    /// whitespace can be reported separately (the lines are still compared trimmed)
+    pub track_trailing_whitespace: bool,
+    /// The first line (after trimming) matching this regex is the header: it and every line before it are
+    /// skipped. Takes precedence over `header_lines` and `auto_header`.
+    pub header_regex: Option<Regex>,
+    /// With `header_regex`, fail when no line matches instead of comparing every line
+    pub require_header: bool,
+    /// Each line is reduced to its first field on this delimiter (after the other normalizations), so only
+    /// the record keys (e.g. IDs) are compared; the keys are also what differences report, not the full lines
+    pub key_only: Option<String>,
+    /// Records end with this separator instead of a newline (newlines are then part of the records)
+    pub record_separator: Option<RecordSeparator>,
+    /// Records are this many characters long (after decoding) instead of ending with a newline;
+    /// takes precedence over `record_separator`
+    pub record_length: Option<NonZeroUsize>,
+    /// Only compare the lines (after every normalization) whose content hash is a multiple of N, about one
+    /// line in N; the selection depends on the content alone, so both files keep the same logical lines
+    pub sample_rate: Option<NonZeroUsize>,
+    /// Counts the lines each of the rules above drops or changes, so stale rules can be found after a run
+    pub rule_usage: Option<Arc<RuleUsage>>,
+}
+
+/// Reads the trimmed, non-empty lines of a list file, detecting its encoding like any input file
+pub(crate) fn read_list_lines(file_path: &Path) -> Result<Vec<String>> {
     let encoding = detect_encoding(file_path)
-        .with_context(|| format!("Failed to detect encoding for large file: {}", file_path.display()))?;
-    
-    // Create a temporary file for decoded content (without header)
-    let mut temp_decoded_file = tempfile::NamedTempFile::new()
-        .context("Failed to create temporary file for decoded content")?;
-    
-    // Open and decode the original file
+        .with_context(|| format!("Failed to detect encoding for file: {}", file_path.display()))?;
     let file = File::open(file_path)
-        .with_context(|| format!("Failed to open large file: {}", file_path.display()))?;
+        .with_context(|| format!("Failed to open file: {}", file_path.display()))?;
     let decoder = DecodeReaderBytesBuilder::new()
         .encoding(Some(encoding))
         .build(file);
-    let reader = BufReader::new(decoder);
     
-    // Skip the first line (header) and write the rest to temp file
-    let mut first_line_skipped = false;
-    let mut lines = Vec::new();
-    for (index, line_result) in reader.lines().enumerate() {
+    let mut entries = Vec::new();
+    for line_result in BufReader::new(decoder).lines() {
         let line = line_result
-            .with_context(|| format!("Failed to read line {} from large file: {}", index, file_path.display()))?;
-        if !first_line_skipped {
-            first_line_skipped = true;
-            continue;
+            .with_context(|| format!("Failed to read file: {}", file_path.display()))?;
+        let line = line.trim();
+        if !line.is_empty() {
+            entries.push(line.to_string());
         }
-        lines.push(line.trim().to_string());
     }
-    
-    // Instead of using external sort command on Windows, use internal sorting
-    if cfg!(windows) {
-        lines.sort();
-        return Ok(lines);
+    Ok(entries)
+}
+
+/// Loads a list of lines from a file, one entry per line
+/// Entries are trimmed and empty lines are ignored; the file's encoding is detected like any input file
+/// 
+/// # Arguments
+/// 
+/// * `file_path` - Path to the list file
+/// 
+/// # Returns
+/// 
+/// A Result containing either the set of entries or an error
+pub fn load_line_set<P: AsRef<Path>>(file_path: P) -> Result<HashSet<String>> {
+    Ok(read_list_lines(file_path.as_ref())?.into_iter().collect())
+}
+
+/// Mappings from old values to the new values they were renamed to
+/// 
+/// Lines are canonicalized by replacing every whole token (a run of letters, digits and `_`)
+/// equal to an old value with its new value, so renamed-but-equivalent lines compare equal.
+/// Each rule counts how many tokens it replaced, so its use can be audited after a run.
+#[derive(Debug, Default)]
+pub struct EquivalenceMap {
+    /// (old value, new value) rules, in file order
+    rules: Vec<(String, String)>,
+    /// Index into `rules` by old value
+    index: HashMap<String, usize>,
+    /// Number of tokens replaced by each rule
+    hits: Vec<AtomicUsize>,
+}
+
+impl EquivalenceMap {
+    /// Builds a map from (old value, new value) pairs; a later rule for the same old value wins
+    pub fn new<I: IntoIterator<Item = (String, String)>>(rules: I) -> Self {
+        let mut map = EquivalenceMap::default();
+        for (old, new) in rules {
+            match map.index.get(&old) {
+                Some(&position) => map.rules[position].1 = new,
+                None => {
+                    map.index.insert(old.clone(), map.rules.len());
+                    map.rules.push((old, new));
+                    map.hits.push(AtomicUsize::new(0));
+                }
+            }
+        }
+        map
     }
     
-    // Write lines to temporary file for Unix systems
-    for line in lines.iter() {
-        writeln!(temp_decoded_file, "{}", line)
-            .context("Failed to write to temporary decoded file")?;
+    /// Loads a map file with one `<old> <new>` pair of whitespace-separated values per line
+    /// Empty lines are ignored; the file's encoding is detected like any input file
+    /// 
+    /// # Arguments
+    /// 
+    /// * `file_path` - Path to the map file
+    /// 
+    /// # Returns
+    /// 
+    /// A Result containing either the map or an error naming the first malformed line
+    pub fn load<P: AsRef<Path>>(file_path: P) -> Result<Self> {
+        let file_path = file_path.as_ref();
+        let mut rules = Vec::new();
+        for line in read_list_lines(file_path)? {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [old, new] = fields[..] else {
+                anyhow::bail!("Invalid equivalence rule in {}: expected \"<old> <new>\", got {:?}",
+                              file_path.display(), line);
+            };
+            rules.push((old.to_string(), new.to_string()));
+        }
+        Ok(Self::new(rules))
     }
     
-    // Flush the file to ensure all data is written
-    temp_decoded_file.flush()
-        .context("Failed to flush temporary decoded file")?;
-    
-    // Get the path of the temporary file
-    let temp_path = temp_decoded_file.path();
+    /// Returns each rule with the number of tokens it has replaced so far
+    pub fn applied_counts(&self) -> Vec<(&str, &str, usize)> {
+        self.rules.iter()
+            .zip(&self.hits)
+            .map(|((old, new), hits)| (old.as_str(), new.as_str(), hits.load(AtomicOrdering::Relaxed)))
+            .collect()
+    }
     
-    // Use system sort command
-    let output = Command::new("sort")
-        .arg(temp_path)
-        .output()
-        .context("Failed to execute Unix sort command on large file")?;
+    /// Replaces the mapped tokens of a line with their new values
+    fn canonicalize<'a>(&self, line: &'a str) -> Cow<'a, str> {
+        if self.rules.is_empty() {
+            return Cow::Borrowed(line);
+        }
+        
+        let is_token_char = |c: char| c.is_alphanumeric() || c == '_';
+        let mut result = String::new();
+        let mut copied_up_to = 0;
+        let mut token_start = None;
+        for (position, c) in line.char_indices().chain(std::iter::once((line.len(), ' '))) {
+            match (token_start, is_token_char(c)) {
+                (None, true) => token_start = Some(position),
+                (Some(start), false) => {
+                    token_start = None;
+                    if let Some(&rule) = self.index.get(&line[start..position]) {
+                        self.hits[rule].fetch_add(1, AtomicOrdering::Relaxed);
+                        result.push_str(&line[copied_up_to..start]);
+                        result.push_str(&self.rules[rule].1);
+                        copied_up_to = position;
+                    }
+                }
+                _ => {}
+            }
+        }
+        
+        if copied_up_to == 0 {
+            return Cow::Borrowed(line);
+        }
+        result.push_str(&line[copied_up_to..]);
+        Cow::Owned(result)
+    }
+}
+
+/// Number of lines each configured ignore/normalization rule affected over a run
+/// 
+/// Counts are added once per processed file: the lines a rule dropped (ignore patterns, excluded lines,
+/// minimum length, truncation) or changed (the normalizations). Every rule configured for a processed file
+/// is listed, so a rule that never fires shows up with zero lines. A dropped line only counts for the first
+/// rule that drops it; equivalence rules are audited by [`EquivalenceMap`] instead. Files read again
+/// (e.g. to find duplicated lines) are counted again. The usage is safe to share between threads.
+#[derive(Debug, Default)]
+pub struct RuleUsage {
+    counts: Mutex<RuleCounts>,
+}
+
+/// Lines affected per rule, in the order the rules were first seen
+#[derive(Debug, Default)]
+struct RuleCounts {
+    rules: Vec<(String, usize)>,
+    /// Index into `rules` by rule
+    index: HashMap<String, usize>,
+}
+
+impl RuleUsage {
+    /// Creates an empty usage
+    pub fn new() -> Self {
+        RuleUsage::default()
+    }
     
-    // Check if the sort command was successful
-    if !output.status.success() {
-        anyhow::bail!("External sort command failed for large file: {}", 
-                      String::from_utf8_lossy(&output.stderr));
+    /// Returns each rule seen so far with the number of lines it has affected
+    pub fn counts(&self) -> Vec<(String, usize)> {
+        self.lock().rules.clone()
     }
     
-    // Read sorted lines back
-    let sorted_content = String::from_utf8(output.stdout)
-        .context("Failed to parse sorted output as UTF-8 for large file")?;
+    /// Adds the lines affected by the rules of `options` while processing one file
+    fn add(&self, options: &ProcessOptions, hits: &FileRuleHits) {
+        let mut counts = self.lock();
+        for (rule, lines) in hits.by_rule(options) {
+            match counts.index.get(&rule) {
+                Some(&position) => counts.rules[position].1 += lines,
+                None => {
+                    let position = counts.rules.len();
+                    counts.index.insert(rule.clone(), position);
+                    counts.rules.push((rule, lines));
+                }
+            }
+        }
+    }
     
-    // Convert to Vec<String>
-    let lines: Vec<String> = sorted_content
-        .lines()
-        .map(|line| line.to_string())
-        .collect();
+    fn lock(&self) -> std::sync::MutexGuard<'_, RuleCounts> {
+        // The counts are always left consistent, so a poisoned lock can still be used
+        self.counts.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Lines affected by each rule while processing one file
+#[derive(Debug, Default)]
+struct FileRuleHits<'a> {
+    /// Indexed like `ProcessOptions::ignore_patterns`
+    ignore_patterns: Vec<usize>,
+    exclude_lines: HashMap<&'a str, usize>,
+    min_line_length: usize,
+    truncate_at: usize,
+    expand_tabs: usize,
+    numeric_columns: usize,
+    thousands_separator: usize,
+    trailing_timestamp: usize,
+    json_lines: usize,
+    empty_fields: usize,
+    sort_fields: usize,
+    ignore_case: usize,
+}
+
+impl<'a> FileRuleHits<'a> {
+    fn new(options: &ProcessOptions) -> Self {
+        FileRuleHits {
+            ignore_patterns: vec![0; options.ignore_patterns.len()],
+            ..FileRuleHits::default()
+        }
+    }
     
-    Ok(lines)
+    /// Names each rule configured in `options` (as it is set on the command line or in a rules file)
+    /// with the lines it affected
+    fn by_rule(&self, options: &ProcessOptions) -> Vec<(String, usize)> {
+        let mut rules = Vec::new();
+        for (pattern, lines) in options.ignore_patterns.iter().zip(&self.ignore_patterns) {
+            rules.push((format!("ignore_pattern = {}", pattern), *lines));
+        }
+        let mut exclude_lines: Vec<&String> = options.exclude_lines.iter().collect();
+        exclude_lines.sort();
+        for line in exclude_lines {
+            let lines = self.exclude_lines.get(line.as_str()).copied().unwrap_or_default();
+            rules.push((format!("--exclude-lines {:?}", line), lines));
+        }
+        if let Some(min_length) = options.min_line_length {
+            rules.push((format!("--min-line-length {}", min_length), self.min_line_length));
+        }
+        if let Some(marker) = &options.truncate_at {
+            rules.push((format!("--truncate-at {}", marker), self.truncate_at));
+        }
+        if let Some(tab_width) = options.expand_tabs {
+            rules.push((format!("--expand-tabs {}", tab_width), self.expand_tabs));
+        }
+        if !options.numeric_columns.is_empty() {
+            let columns: Vec<String> = options.numeric_columns.iter()
+                .map(|column| format!("--numeric-column {}:{}", column.start, column.width))
+                .collect();
+            rules.push((columns.join(" "), self.numeric_columns));
+        }
+        if let Some(separator) = options.thousands_separator {
+            rules.push((format!("--strip-thousands-sep {:?}", separator), self.thousands_separator));
+        }
+        if let Some(format) = &options.strip_trailing_timestamp {
+            rules.push((format!("--strip-trailing-timestamp {}", format), self.trailing_timestamp));
+        }
+        if options.json_lines {
+            rules.push(("--json-lines".to_string(), self.json_lines));
+        }
+        if let Some(delimiter) = &options.drop_empty_fields {
+            rules.push((format!("--drop-empty-fields {:?}", delimiter), self.empty_fields));
+        }
+        if let Some(delimiter) = &options.sort_fields {
+            rules.push((format!("--sort-fields {:?}", delimiter), self.sort_fields));
+        }
+        if options.ignore_case {
+            rules.push(("ignore_case = true".to_string(), self.ignore_case));
+        }
+        rules
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::fs;
-    use tempfile::Builder;
-    use anyhow::Result;
+/// Encodings detected per directory, for runs where every file of a directory shares one encoding
+/// 
+/// One file of a directory determines the encoding used for all its other files, so a directory
+/// that mixes encodings is decoded incorrectly; only use it when that can't happen. Call
+/// [`EncodingCache::seed`] before reading files in parallel, otherwise the file that decides is
+/// whichever thread gets there first. The cache is safe to share between threads.
+#[derive(Debug, Default)]
+pub struct EncodingCache {
+    by_dir: Mutex<HashMap<PathBuf, &'static Encoding>>,
+}
 
-    #[test]
-    fn test_detect_encoding_utf8() -> Result<()> {
-        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
-        let file_path = dir.path().join("test_utf8.txt");
-        
-        // Create a UTF-8 encoded file
-        fs::write(&file_path, "Test content with UTF-8 encoding\n")?;
-        
-        let encoding = detect_encoding(&file_path)?;
-        assert_eq!(encoding, encoding_rs::UTF_8);
-        
-        Ok(())
+impl EncodingCache {
+    /// Creates an empty cache
+    pub fn new() -> Self {
+        EncodingCache::default()
+    }
+    
+    /// Detects the encoding of each directory from its first file in sorted path order
+    /// 
+    /// Files that can't be read are passed over, so their error surfaces when they are processed.
+    pub fn seed<P: AsRef<Path>>(&self, files: impl IntoIterator<Item = P>) {
+        let mut files: Vec<PathBuf> = files.into_iter().map(|file| file.as_ref().to_path_buf()).collect();
+        files.sort();
+        let mut by_dir = self.lock();
+        for file in files {
+            let dir = file.parent().unwrap_or(Path::new("")).to_path_buf();
+            if !by_dir.contains_key(&dir) && let Ok(encoding) = detect_encoding(&file) {
+                by_dir.insert(dir, encoding);
+            }
+        }
+    }
+    
+    /// Returns the encoding of the file's directory, detecting it from this file on first use
+    fn encoding_for(&self, file_path: &Path) -> Result<&'static Encoding> {
+        let dir = file_path.parent().unwrap_or(Path::new("")).to_path_buf();
+        if let Some(encoding) = self.lock().get(&dir) {
+            return Ok(encoding);
+        }
+        // Detection runs outside the lock; when threads race on a directory, the first result wins
+        let encoding = detect_encoding(file_path)?;
+        Ok(self.lock().entry(dir).or_insert(encoding))
+    }
+    
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<PathBuf, &'static Encoding>> {
+        // The map is always left consistent, so a poisoned lock can still be used
+        self.by_dir.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Encodings pinned for individual files, overriding detection
+/// 
+/// Entries are keyed by file name, or by match key (`aaaaaaaa_yyyymmdd_AXX` of the default
+/// naming pattern) to pin both sides of a pair at once. Files not listed are detected as usual.
+#[derive(Debug, Default)]
+pub struct EncodingMap {
+    /// Encoding by file name or match key
+    entries: HashMap<String, &'static Encoding>,
+}
+
+impl EncodingMap {
+    /// Loads a map file with one `<file name or match key> <encoding label>` entry per line
+    /// The label is the last whitespace-separated value, so file names may contain spaces.
+    /// Empty lines are ignored; the file's encoding is detected like any input file
+    /// 
+    /// # Arguments
+    /// 
+    /// * `file_path` - Path to the map file
+    /// 
+    /// # Returns
+    /// 
+    /// A Result containing either the map or an error naming the first malformed entry or unknown label
+    pub fn load<P: AsRef<Path>>(file_path: P) -> Result<Self> {
+        let file_path = file_path.as_ref();
+        let mut entries = HashMap::new();
+        for line in read_list_lines(file_path)? {
+            let Some((name, label)) = line.rsplit_once(char::is_whitespace) else {
+                anyhow::bail!("Invalid encoding entry in {}: expected \"<file name> <encoding>\", got {:?}",
+                              file_path.display(), line);
+            };
+            let name = name.trim();
+            let encoding = Encoding::for_label(label.as_bytes())
+                .with_context(|| format!("Unknown encoding {:?} for {} in {}", label, name, file_path.display()))?;
+            entries.insert(name.to_string(), encoding);
+        }
+        Ok(EncodingMap { entries })
+    }
+    
+    /// Returns the pinned encoding of a file, looked up by file name then by match key
+    fn encoding_for(&self, file_path: &Path) -> Option<&'static Encoding> {
+        let by_name = file_path.file_name()
+            .and_then(|name| self.entries.get(name.to_string_lossy().as_ref()));
+        by_name.or_else(|| self.entries.get(&parse_filename(file_path)?.match_key())).copied()
+    }
+}
+
+/// Reads and processes a file, skipping the first line and sorting the rest
+/// For large files, uses external sorting to avoid memory issues
+/// 
+/// # Arguments
+/// 
+/// * `file_path` - Path to the file to read and process
+/// 
+/// # Returns
+/// 
+/// A Result containing either a sorted vector of lines or an error
+pub fn read_and_process_file<P: AsRef<Path>>(file_path: P) -> Result<Vec<String>> {
+    read_and_process_file_with_options(file_path, &ProcessOptions::default())
+}
+
+/// Reads and processes a file like [`read_and_process_file`], applying the given options
+/// 
+/// # Arguments
+/// 
+/// * `file_path` - Path to the file to read and process
+/// * `options` - Processing options to apply
+/// 
+/// # Returns
+/// 
+/// A Result containing either a sorted vector of lines or an error
+pub fn read_and_process_file_with_options<P: AsRef<Path>>(file_path: P, options: &ProcessOptions) -> Result<Vec<String>> {
+    Ok(process_file(file_path, options)?.lines)
+}
+
+/// Reads and processes a file, keeping track of the original text of normalized lines
+/// 
+/// # Arguments
+/// 
+/// * `file_path` - Path to the file to read and process
+/// * `options` - Processing options to apply
+/// 
+/// # Returns
+/// 
+/// A Result containing either the processed file or an error
+pub fn process_file<P: AsRef<Path>>(file_path: P, options: &ProcessOptions) -> Result<ProcessedFile> {
+    let file_path = file_path.as_ref();
+    
+    let mut processed = process_file_unsorted(file_path, options)?;
+    
+    // The sort command works on newline-terminated lines, so records holding newlines are sorted in memory
+    let splits_records = options.record_separator.is_some() || options.record_length.is_some();
+    if splits_records && processed.lines.iter().any(|line| line.contains('\n')) {
+        internal_sort(&mut processed.lines, options.numeric_sort)?;
+    } else if let Ok(true) = is_file_too_large(file_path) {
+        // For large files, use external sorting directly
+        processed.lines = external_sort_large_file(file_path, processed.lines, options)?;
+    } else if processed.lines.len() > 100_000 || options.collation.is_some() {
+        // For large files (many lines), use external sorting; it is also the only locale-aware sort
+        external_sort(&mut processed.lines, options)
+            .with_context(|| format!("Failed to externally sort file: {}", file_path.display()))?;
+    } else if options.numeric_sort {
+        processed.lines.sort_by(|a, b| compare_numeric(a, b));
+    } else {
+        processed.lines.sort();
+    }
+    
+    Ok(processed)
+}
+
+/// Reads and processes a file like [`process_file`], but keeps the lines in file order
+/// 
+/// # Arguments
+/// 
+/// * `file_path` - Path to the file to read and process
+/// * `options` - Processing options to apply (sorting options are ignored)
+/// 
+/// # Returns
+/// 
+/// A Result containing either the processed file or an error
+pub fn process_file_unsorted<P: AsRef<Path>>(file_path: P, options: &ProcessOptions) -> Result<ProcessedFile> {
+    let file_path = file_path.as_ref();
+    // The transformed bytes stand in for the file's own, so their encoding is detected on them
+    let transformed = match &options.transform_cmd {
+        Some(cmd) => Some(pipe_through_command(cmd, read_input(file_path)?)
+            .with_context(|| format!("Transform command failed for file: {}", file_path.display()))?),
+        None => None,
+    };
+    let pinned = options.encoding_map.as_ref().and_then(|encoding_map| encoding_map.encoding_for(file_path));
+    let encoding = match (pinned, &transformed, &options.encoding_cache) {
+        (Some(encoding), _, _) => Ok(encoding),
+        (None, Some(content), _) => Ok(guess_encoding(content)),
+        (None, None, Some(encoding_cache)) => encoding_cache.encoding_for(file_path),
+        (None, None, None) => detect_encoding(file_path),
+    }.with_context(|| format!("Failed to detect encoding for file: {}", file_path.display()))?;
+    
+    let input: Box<dyn Read + Send> = match transformed {
+        Some(content) => Box::new(Cursor::new(content)),
+        None => open_input(file_path)?,
+    };
+    let decoder = DecodeReaderBytesBuilder::new()
+        .encoding(Some(encoding))
+        .build(input);
+    let mut reader = BufReader::new(decoder);
+    
+    if let Some(cmd) = &options.preprocess_cmd {
+        let mut content = String::new();
+        reader.read_to_string(&mut content)
+            .with_context(|| format!("Failed to read file: {}", file_path.display()))?;
+        let output = run_preprocess_command(cmd, &content)
+            .with_context(|| format!("Preprocess command failed for file: {}", file_path.display()))?;
+        return collect_body_lines(records(output.as_bytes(), options), file_path, options);
+    }
+    
+    collect_body_lines(records(reader, options), file_path, options)
+}
+
+/// Splits decoded content into records: lines, or records with a custom separator or a fixed length
+fn records<'a, R: BufRead + 'a>(reader: R, options: &ProcessOptions) -> Box<dyn Iterator<Item = std::io::Result<String>> + 'a> {
+    match (options.record_length, &options.record_separator) {
+        (Some(length), _) => Box::new(fixed_length_records(reader, length.get())),
+        (None, Some(RecordSeparator(separator))) => Box::new(separated_records(reader, separator.as_bytes().to_vec())),
+        (None, None) => Box::new(reader.lines()),
+    }
+}
+
+/// Splits content on a separator, which is dropped like the newline by `BufRead::lines`
+fn separated_records<R: BufRead>(mut reader: R, separator: Vec<u8>) -> impl Iterator<Item = std::io::Result<String>> {
+    let last_byte = separator.last().copied().unwrap_or(b'\n');
+    std::iter::from_fn(move || {
+        let mut record = Vec::new();
+        loop {
+            match reader.read_until(last_byte, &mut record) {
+                // End of input; a final record without separator is still a record
+                Ok(0) => return (!record.is_empty()).then(|| utf8_record(record)),
+                Ok(_) if record.ends_with(&separator) => {
+                    record.truncate(record.len() - separator.len());
+                    return Some(utf8_record(record));
+                }
+                Ok(_) => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    })
+}
+
+/// Splits content into records of `length` characters (the last one may be shorter)
+fn fixed_length_records<R: BufRead>(mut reader: R, length: usize) -> impl Iterator<Item = std::io::Result<String>> {
+    std::iter::from_fn(move || {
+        let mut record = Vec::new();
+        let mut chars = 0;
+        loop {
+            let buffer = match reader.fill_buf() {
+                Ok(buffer) => buffer,
+                Err(e) => return Some(Err(e)),
+            };
+            if buffer.is_empty() {
+                return (!record.is_empty()).then(|| utf8_record(record));
+            }
+            // The content is UTF-8, so every byte that isn't a continuation byte starts a character
+            let mut used = 0;
+            for &byte in buffer {
+                let starts_char = byte & 0xC0 != 0x80;
+                if starts_char && chars == length {
+                    break;
+                }
+                chars += usize::from(starts_char);
+                record.push(byte);
+                used += 1;
+            }
+            let complete = used < buffer.len();
+            reader.consume(used);
+            if complete {
+                return Some(utf8_record(record));
+            }
+        }
+    })
+}
+
+/// Converts a record of decoded (so UTF-8) content to a string
+fn utf8_record(record: Vec<u8>) -> std::io::Result<String> {
+    String::from_utf8(record).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+/// Finds the lines that appear more than once within a single file
+/// 
+/// The set-based comparison ignores duplicates, so this reads the file separately (with the same
+/// processing options) to surface duplicated records. Lines are reported with their original text.
+/// 
+/// # Arguments
+/// 
+/// * `file_path` - Path to the file to check
+/// * `options` - Processing options to apply
+/// 
+/// # Returns
+/// 
+/// A Result containing either the duplicated lines with their number of occurrences, sorted by line, or an error
+pub fn find_duplicate_lines<P: AsRef<Path>>(file_path: P, options: &ProcessOptions) -> Result<Vec<(String, usize)>> {
+    let processed = process_file_unsorted(file_path, options)?;
+    
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for line in &processed.lines {
+        *counts.entry(line.as_str()).or_default() += 1;
+    }
+    let mut duplicates: Vec<(String, usize)> = counts.into_iter()
+        .filter(|(_, count)| *count > 1)
+        .map(|(line, count)| (processed.original(line).to_string(), count))
+        .collect();
+    duplicates.sort();
+    Ok(duplicates)
+}
+
+/// Skips the first line (header), then trims and normalizes the remaining lines
+fn collect_body_lines<I>(mut lines: I, file_path: &Path, options: &ProcessOptions) -> Result<ProcessedFile>
+where
+    I: Iterator<Item = std::io::Result<String>>,
+{
+    let timestamp_width = match &options.strip_trailing_timestamp {
+        Some(format) => Some(timestamp_format_width(format)?),
+        None => None,
+    };
+    
+    // The leading lines are read ahead to detect the header length; they are processed like the rest below
+    // (a header regex takes precedence, so no lines are read ahead for the detection then)
+    let mut sample = Vec::new();
+    if options.auto_header && options.header_lines.is_none() && options.header_regex.is_none() {
+        for (index, line_result) in lines.by_ref().take(AUTO_HEADER_SAMPLE_LINES).enumerate() {
+            sample.push(line_result
+                .with_context(|| format!("Failed to read line {} from file: {}", index, file_path.display()))?);
+        }
+    }
+    let mut detected_header_lines = if sample.is_empty() { None } else { detect_header_lines(&sample) };
+    match detected_header_lines {
+        Some(count) => info!("Detected {} header line(s) in {}", count, file_path.display()),
+        None if !sample.is_empty() => info!("Header length of {} is inconclusive, skipping 1 line", file_path.display()),
+        None => {}
+    }
+    // The preamble is read ahead up to the header line (the whole file if there is none)
+    if let Some(header_regex) = &options.header_regex {
+        let mut header_found = false;
+        for (index, line_result) in lines.by_ref().enumerate() {
+            let line = line_result
+                .with_context(|| format!("Failed to read line {} from file: {}", index, file_path.display()))?;
+            header_found = header_regex.is_match(line.trim());
+            sample.push(line);
+            if header_found {
+                break;
+            }
+        }
+        if !header_found && options.require_header {
+            anyhow::bail!("No header line matching {} in file: {}", header_regex, file_path.display());
+        }
+        if !header_found {
+            info!("No header line matching {} in {}, comparing every line", header_regex, file_path.display());
+        }
+        detected_header_lines = Some(if header_found { sample.len() } else { 0 });
+    }
+    let lines = sample.into_iter().map(Ok).chain(lines);
+    
+    // Body lines start after the header, so `head` body lines end there too; stop reading at that point
+    let default_header_lines = if options.json_lines { 0 } else { 1 };
+    let header_lines = match options.header_regex {
+        Some(_) => detected_header_lines.unwrap_or_default(),
+        None => options.header_lines.or(detected_header_lines).unwrap_or(default_header_lines),
+    };
+    let max_lines = options.head.map_or(usize::MAX, |head| head.saturating_add(header_lines));
+    
+    let mut processed = ProcessedFile::default();
+    let mut rule_hits = FileRuleHits::new(options);
+    let mut in_section = false;
+    for (index, line_result) in lines.take(max_lines).enumerate() {
+        let raw_line = line_result
+            .with_context(|| format!("Failed to read line {} from file: {}", index, file_path.display()))?;
+        if index < header_lines {
+            continue;
+        }
+        let line = raw_line.trim();
+        // Lines past the marker are still read, only to count them
+        if processed.truncated_lines > 0
+            || options.truncate_at.as_ref().is_some_and(|marker| marker.is_match(line)) {
+            processed.truncated_lines += 1;
+            continue;
+        }
+        if let Some(section) = &options.section {
+            if let Some(name) = section_marker(line) {
+                in_section = name == section;
+                continue;
+            }
+            if !in_section {
+                continue;
+            }
+        }
+        if let Some(excluded) = options.exclude_lines.get(line) {
+            *rule_hits.exclude_lines.entry(excluded.as_str()).or_default() += 1;
+            continue;
+        }
+        if options.min_line_length.is_some_and(|min_length| line.chars().count() < min_length) {
+            rule_hits.min_line_length += 1;
+            continue;
+        }
+        if let Some(pattern) = options.ignore_patterns.iter().position(|pattern| pattern.is_match(line)) {
+            rule_hits.ignore_patterns[pattern] += 1;
+            continue;
+        }
+        
+        // Tab stops and fixed-width columns are relative to the start of the untrimmed line
+        let expand = options.expand_tabs.filter(|_| line.contains('\t'));
+        let expanded = if expand.is_some() || !options.numeric_columns.is_empty() {
+            let mut untrimmed = Cow::Borrowed(raw_line.as_str());
+            if let Some(tab_width) = expand {
+                rule_hits.expand_tabs += 1;
+                untrimmed = Cow::Owned(expand_tabs(&raw_line, tab_width));
+            }
+            if !options.numeric_columns.is_empty() {
+                let numbers_normalized = normalize_numeric_columns(&untrimmed, &options.numeric_columns);
+                if numbers_normalized != untrimmed {
+                    rule_hits.numeric_columns += 1;
+                }
+                untrimmed = Cow::Owned(numbers_normalized);
+            }
+            Cow::Owned(untrimmed.trim().to_string())
+        } else {
+            Cow::Borrowed(line)
+        };
+        let canonical = match &options.equivalence_map {
+            Some(equivalence_map) => equivalence_map.canonicalize(&expanded),
+            None => Cow::Borrowed(expanded.as_ref()),
+        };
+        let without_separators = match options.thousands_separator {
+            Some(separator) if canonical.contains(separator) => Cow::Owned(strip_thousands_separators(&canonical, separator)),
+            _ => Cow::Borrowed(canonical.as_ref()),
+        };
+        if without_separators != canonical {
+            rule_hits.thousands_separator += 1;
+        }
+        let mut normalized = without_separators.as_ref();
+        if let (Some(format), Some(width)) = (&options.strip_trailing_timestamp, timestamp_width) {
+            let stripped = strip_trailing_timestamp(normalized, format, width);
+            if stripped.len() != normalized.len() {
+                rule_hits.trailing_timestamp += 1;
+            }
+            normalized = stripped;
+        }
+        let json_canonical;
+        if options.json_lines && let Some(canonical) = canonical_json(normalized) {
+            if canonical != normalized {
+                rule_hits.json_lines += 1;
+            }
+            json_canonical = canonical;
+            normalized = &json_canonical;
+        }
+        let without_empty_fields;
+        if let Some(delimiter) = options.drop_empty_fields.as_deref().filter(|delimiter| normalized.contains(*delimiter)) {
+            without_empty_fields = drop_empty_fields(normalized, delimiter);
+            if without_empty_fields != normalized {
+                rule_hits.empty_fields += 1;
+            }
+            normalized = &without_empty_fields;
+        }
+        let fields_sorted;
+        if let Some(delimiter) = options.sort_fields.as_deref().filter(|delimiter| normalized.contains(*delimiter)) {
+            fields_sorted = sort_fields(normalized, delimiter);
+            if fields_sorted != normalized {
+                rule_hits.sort_fields += 1;
+            }
+            normalized = &fields_sorted;
+        }
+        let lowercased;
+        if options.ignore_case {
+            lowercased = normalized.to_lowercase();
+            if lowercased != normalized {
+                rule_hits.ignore_case += 1;
+            }
+            normalized = &lowercased;
+        }
+        if let Some(delimiter) = &options.key_only {
+            normalized = normalized.split(delimiter.as_str()).next().unwrap_or_default().trim();
+        }
+        
+        if options.sample_rate.is_some_and(|rate| !is_sampled(normalized, rate)) {
+            continue;
+        }
+        
+        if normalized != line && options.key_only.is_none() {
+            processed.originals.entry(normalized.to_string()).or_insert_with(|| line.to_string());
+        }
+        if options.track_trailing_whitespace {
+            let trailing = &raw_line[raw_line.trim_end().len()..];
+            if !trailing.is_empty() {
+                processed.trailing_whitespace.entry(normalized.to_string()).or_insert_with(|| trailing.to_string());
+            }
+        }
+        processed.lines.push(normalized.to_string());
+    }
+    if let Some(rule_usage) = &options.rule_usage {
+        rule_hits.truncate_at = processed.truncated_lines;
+        rule_usage.add(options, &rule_hits);
+    }
+    Ok(processed)
+}
+
+/// Computes the 64-bit FNV-1a hash of some bytes
+/// Unlike the standard library's hasher, it is stable across runs, versions and platforms.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+    
+    bytes.iter().fold(FNV_OFFSET_BASIS, |hash, &byte| (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME))
+}
+
+/// Whether a line is kept when sampling one line in `rate`, by the line's FNV-1a hash
+/// The hash is stable across runs and platforms, so sampled runs can be compared with each other.
+fn is_sampled(line: &str, rate: NonZeroUsize) -> bool {
+    fnv1a(line.as_bytes()).is_multiple_of(rate.get() as u64)
+}
+
+/// Detects the number of header lines from where the lines' delimiter counts become consistent
+/// The data starts at the first line followed by enough lines with the same non-zero delimiter counts.
+/// Returns `None` when no such run is found, or when it starts at the first line, since a column
+/// header shaped like the data can't be told apart from it.
+fn detect_header_lines(lines: &[String]) -> Option<usize> {
+    let shapes: Vec<[usize; AUTO_HEADER_DELIMITERS.len()]> = lines.iter()
+        .map(|line| AUTO_HEADER_DELIMITERS.map(|delimiter| line.matches(delimiter).count()))
+        .collect();
+    let start = shapes.windows(AUTO_HEADER_STABLE_LINES)
+        .position(|window| window[0].iter().any(|&count| count > 0) && window.iter().all(|shape| *shape == window[0]))?;
+    (start > 0).then_some(start)
+}
+
+/// Removes thousands separators from the numbers of a line
+/// A separator is only removed between a digit and a group of exactly three digits,
+/// so other uses of the character (e.g. `a,b` or `1,5`) are kept.
+fn strip_thousands_separators(line: &str, separator: char) -> String {
+    let chars: Vec<char> = line.chars().collect();
+    let mut stripped = String::with_capacity(line.len());
+    for (index, &c) in chars.iter().enumerate() {
+        let is_group_separator = c == separator
+            && index > 0
+            && chars[index - 1].is_ascii_digit()
+            && chars.get(index + 1..index + 4).is_some_and(|group| group.iter().all(char::is_ascii_digit))
+            && !chars.get(index + 4).is_some_and(char::is_ascii_digit);
+        if !is_group_separator {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
+
+/// Splits a line on a delimiter and rejoins its fields without the empty (or blank) ones
+fn drop_empty_fields(line: &str, delimiter: &str) -> String {
+    let fields: Vec<&str> = line.split(delimiter).filter(|field| !field.trim().is_empty()).collect();
+    fields.join(delimiter)
+}
+
+/// Splits a line on a delimiter and rejoins its fields in sorted order
+fn sort_fields(line: &str, delimiter: &str) -> String {
+    let mut fields: Vec<&str> = line.split(delimiter).collect();
+    fields.sort_unstable();
+    fields.join(delimiter)
+}
+
+/// Rewrites a JSON line with sorted object keys and no insignificant whitespace (`None` if it isn't JSON)
+fn canonical_json(line: &str) -> Option<String> {
+    // serde_json's maps are ordered by key, so serializing a parsed value sorts every object's keys
+    serde_json::from_str::<serde_json::Value>(line).ok().map(|value| value.to_string())
+}
+
+/// Rewrites the number in each fixed-width column without padding, right-aligned so later columns keep their positions
+fn normalize_numeric_columns(line: &str, columns: &[FixedColumn]) -> String {
+    let mut chars: Vec<char> = line.chars().collect();
+    for column in columns {
+        let start = (column.start - 1).min(chars.len());
+        let end = (start + column.width).min(chars.len());
+        let value: String = chars[start..end].iter().collect();
+        if let Some(number) = canonical_number(value.trim()) {
+            let aligned = format!("{:>width$}", number, width = end - start);
+            // A number that gets longer (`.5` becomes `0.5`) would overflow into the next column
+            if aligned.chars().count() == end - start {
+                chars.splice(start..end, aligned.chars());
+            }
+        }
+    }
+    chars.into_iter().collect()
+}
+
+/// Writes a decimal number without leading zeros, trailing fractional zeros or a negative zero sign
+/// (`None` if the value isn't a plain decimal number)
+fn canonical_number(value: &str) -> Option<String> {
+    let unsigned = value.strip_prefix('-').unwrap_or(value);
+    let (int_part, frac_part) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+    let is_number = unsigned.chars().any(|c| c.is_ascii_digit())
+        && int_part.chars().chain(frac_part.chars()).all(|c| c.is_ascii_digit());
+    if !is_number {
+        return None;
+    }
+    let (negative, int_part, frac_part) = leading_number(value);
+    let sign = if negative { "-" } else { "" };
+    let int_part = if int_part.is_empty() { "0" } else { int_part };
+    Some(match frac_part {
+        "" => format!("{}{}", sign, int_part),
+        _ => format!("{}{}.{}", sign, int_part, frac_part),
+    })
+}
+
+/// Returns the name of a section marker line such as `[SECTION_A]`
+fn section_marker(line: &str) -> Option<&str> {
+    line.strip_prefix('[')?.strip_suffix(']').filter(|name| !name.is_empty())
+}
+
+/// Replaces each tab with spaces up to the next tab stop (every `tab_width` characters)
+fn expand_tabs(line: &str, tab_width: NonZeroUsize) -> String {
+    let mut expanded = String::with_capacity(line.len());
+    let mut column = 0;
+    for c in line.chars() {
+        if c == '\t' {
+            let spaces = tab_width.get() - column % tab_width.get();
+            expanded.extend(std::iter::repeat_n(' ', spaces));
+            column += spaces;
+        } else {
+            expanded.push(c);
+            column += 1;
+        }
+    }
+    expanded
+}
+
+/// Returns the width of timestamps rendered with a chrono strftime `format`
+/// 
+/// Numeric fields are zero-padded by default, so formats made of them have a fixed width.
+/// 
+/// # Returns
+/// 
+/// A Result containing either the width in bytes or an error if the format is invalid
+pub fn timestamp_format_width(format: &str) -> Result<usize> {
+    let reference = NaiveDate::from_ymd_opt(2000, 1, 1)
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .context("Failed to build reference timestamp")?;
+    let mut rendered = String::new();
+    write!(rendered, "{}", reference.format(format))
+        .map_err(|_| anyhow::anyhow!("Invalid timestamp format: {}", format))?;
+    Ok(rendered.len())
+}
+
+/// Strips a trailing timestamp matching `format` (and the whitespace before it) from `line`
+/// Lines without a matching trailing timestamp are returned unchanged
+fn strip_trailing_timestamp<'a>(line: &'a str, format: &str, width: usize) -> &'a str {
+    if line.len() < width || !line.is_char_boundary(line.len() - width) {
+        return line;
+    }
+    let (rest, suffix) = line.split_at(line.len() - width);
+    let mut parsed = Parsed::new();
+    match chrono::format::parse(&mut parsed, suffix, StrftimeItems::new(format)) {
+        Ok(()) => rest.trim_end(),
+        Err(_) => line,
+    }
+}
+
+/// Pipes `input` through `cmd` using the platform shell and returns the command's stdout
+fn run_preprocess_command(cmd: &str, input: &str) -> Result<String> {
+    let output = pipe_through_command(cmd, input.as_bytes().to_vec())?;
+    String::from_utf8(output)
+        .context("Failed to parse preprocess command output as UTF-8")
+}
+
+/// Pipes raw bytes through `cmd` using the platform shell and returns the command's stdout
+fn pipe_through_command(cmd: &str, input: Vec<u8>) -> Result<Vec<u8>> {
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.arg("/C").arg(cmd);
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(cmd);
+        command
+    };
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to spawn command: {}", cmd))?;
+    
+    // Feed stdin from a separate thread so a command producing large output can't deadlock us
+    let mut stdin = child.stdin.take().context("Failed to open command stdin")?;
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    
+    let output = child.wait_with_output()
+        .with_context(|| format!("Failed to wait for command: {}", cmd))?;
+    // A command that doesn't read all of its input closes the pipe early; that's not an error
+    let _ = writer.join();
+    
+    if !output.status.success() {
+        anyhow::bail!("Command `{}` exited with {}: {}",
+                      cmd, output.status, String::from_utf8_lossy(&output.stderr).trim());
+    }
+    
+    Ok(output.stdout)
+}
+
+/// Sorts lines using Rust's unstable sort algorithm
+/// This is faster than stable sort and more reliable across platforms than external sorting
+fn internal_sort(lines: &mut [String], numeric: bool) -> Result<()> {
+    if numeric {
+        lines.sort_unstable_by(|a, b| compare_numeric(a, b));
+    } else {
+        lines.sort_unstable();
+    }
+    Ok(())
+}
+
+/// Compares two lines by their leading number, like `sort -n` in the C locale
+/// 
+/// The leading number is an optional `-`, digits and an optional fractional part; lines
+/// without one count as zero. Lines with equal numbers are ordered byte-wise.
+pub fn compare_numeric(a: &str, b: &str) -> Ordering {
+    let (negative_a, int_a, frac_a) = leading_number(a);
+    let (negative_b, int_b, frac_b) = leading_number(b);
+    
+    let magnitude = int_a.len().cmp(&int_b.len())
+        .then_with(|| int_a.cmp(int_b))
+        .then_with(|| frac_a.cmp(frac_b));
+    let numeric = match (negative_a, negative_b) {
+        (false, false) => magnitude,
+        (true, true) => magnitude.reverse(),
+        (true, false) => Ordering::Less,
+        (false, true) => Ordering::Greater,
+    };
+    numeric.then_with(|| a.cmp(b))
+}
+
+/// Splits the leading number of a line into its sign, integer digits without leading zeros
+/// and fractional digits without trailing zeros. Zero is never negative.
+fn leading_number(line: &str) -> (bool, &str, &str) {
+    let line = line.trim_start();
+    let (negative, rest) = match line.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let int_end = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+    let int_part = rest[..int_end].trim_start_matches('0');
+    let frac_part = match rest[int_end..].strip_prefix('.') {
+        Some(frac) => {
+            let frac_end = frac.find(|c: char| !c.is_ascii_digit()).unwrap_or(frac.len());
+            frac[..frac_end].trim_end_matches('0')
+        }
+        None => "",
+    };
+    let is_zero = int_part.is_empty() && frac_part.is_empty();
+    (negative && !is_zero, int_part, frac_part)
+}
+
+/// Builds the system sort command, pinned to the C locale so its ordering matches Rust's byte-wise sort
+/// (or to the collation locale when one is set)
+fn sort_command(options: &ProcessOptions) -> Command {
+    let mut command = Command::new(options.sort_command.as_deref().unwrap_or("sort"));
+    command.env("LC_ALL", options.collation.as_deref().unwrap_or("C"));
+    if options.numeric_sort {
+        command.arg("-n");
+    }
+    command
+}
+
+/// Checks that a locale is installed, as the system sort silently falls back to byte-wise order otherwise
+/// 
+/// Names are matched like `locale -a` lists them, ignoring case and dashes (`zh_CN.UTF-8` matches `zh_CN.utf8`).
+/// 
+/// # Arguments
+/// 
+/// * `locale` - Locale name, such as `zh_CN.UTF-8`
+/// 
+/// # Returns
+/// 
+/// A Result that is an error if the locale isn't installed or the installed locales can't be listed
+pub fn check_collation(locale: &str) -> Result<()> {
+    let output = Command::new("locale")
+        .arg("-a")
+        .output()
+        .context("Failed to list installed locales with `locale -a`")?;
+    let normalize = |name: &str| name.trim().to_ascii_lowercase().replace('-', "");
+    let wanted = normalize(locale);
+    if String::from_utf8_lossy(&output.stdout).lines().any(|installed| normalize(installed) == wanted) {
+        Ok(())
+    } else {
+        anyhow::bail!("Locale is not installed: {}", locale)
+    }
+}
+
+/// External sorting implementation for large files
+/// Uses the system's sort command for efficiency
+fn external_sort(lines: &mut Vec<String>, options: &ProcessOptions) -> Result<()> {
+    // Create a temporary file
+    let mut temp_file = tempfile::NamedTempFile::new()
+        .context("Failed to create temporary file for external sorting")?;
+    
+    // Write lines to temporary file
+    for line in lines.iter() {
+        writeln!(temp_file, "{}", line)
+            .context("Failed to write to temporary file")?;
+    }
+    
+    // Flush the file to ensure all data is written
+    temp_file.flush()
+        .context("Failed to flush temporary file")?;
+    
+    // Get the path of the temporary file
+    let temp_path = temp_file.path();
+    
+    // Use system sort command
+    let output = if cfg!(windows) {
+        // On Windows, we'll use internal sorting instead of external command
+        // which can be unreliable
+        return internal_sort(lines, options.numeric_sort);
+    } else {
+        sort_command(options)
+            .arg(temp_path)
+            .output()
+            .context("Failed to execute Unix sort command")?
+    };
+    
+    // Check if the sort command was successful
+    if !output.status.success() {
+        anyhow::bail!("External sort command failed: {}", 
+                      String::from_utf8_lossy(&output.stderr));
+    }
+    
+    // Read sorted lines back
+    *lines = sorted_output_to_lines(&output.stdout);
+    
+    Ok(())
+}
+
+/// Splits the output of the system sort command into lines
+/// Invalid UTF-8 is replaced with U+FFFD (with a warning) instead of aborting the comparison
+fn sorted_output_to_lines(output: &[u8]) -> Vec<String> {
+    let sorted_content = String::from_utf8_lossy(output);
+    if let Cow::Owned(_) = sorted_content {
+        warn!("Sorted output contained invalid UTF-8; invalid bytes were replaced with U+FFFD");
+    }
+    sorted_content.lines().map(|line| line.to_string()).collect()
+}
+
+/// External sorting implementation for large files that cannot fit in memory
+/// Uses the system's sort command on the decoded lines of the input file
+fn external_sort_large_file(file_path: &Path, mut lines: Vec<String>, options: &ProcessOptions) -> Result<Vec<String>> {
+    // Create a temporary file for decoded content (without header)
+    let mut temp_decoded_file = tempfile::NamedTempFile::new()
+        .context("Failed to create temporary file for decoded content")?;
+    
+    // Instead of using external sort command on Windows, use internal sorting
+    if cfg!(windows) {
+        internal_sort(&mut lines, options.numeric_sort)?;
+        return Ok(lines);
+    }
+    
+    // Write lines to temporary file for Unix systems
+    for line in lines.iter() {
+        writeln!(temp_decoded_file, "{}", line)
+            .context("Failed to write to temporary decoded file")?;
+    }
+    
+    // Flush the file to ensure all data is written
+    temp_decoded_file.flush()
+        .context("Failed to flush temporary decoded file")?;
+    
+    // Get the path of the temporary file
+    let temp_path = temp_decoded_file.path();
+    
+    // Use system sort command
+    let output = sort_command(options)
+        .arg(temp_path)
+        .output()
+        .with_context(|| format!("Failed to execute Unix sort command on large file: {}", file_path.display()))?;
+    
+    // Check if the sort command was successful
+    if !output.status.success() {
+        anyhow::bail!("External sort command failed for large file {}: {}", 
+                      file_path.display(), String::from_utf8_lossy(&output.stderr));
+    }
+    
+    // Read sorted lines back
+    Ok(sorted_output_to_lines(&output.stdout))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::Builder;
+    use anyhow::Result;
+
+    #[test]
+    fn test_detect_encoding_utf8() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file_path = dir.path().join("test_utf8.txt");
+        
+        // Create a UTF-8 encoded file
+        fs::write(&file_path, "Test content with UTF-8 encoding\n")?;
+        
+        let encoding = detect_encoding(&file_path)?;
+        assert_eq!(encoding, encoding_rs::UTF_8);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_encoding() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let gbk_path = dir.path().join("gbk.txt");
+        let utf8_path = dir.path().join("utf8.txt");
+        let ascii_path = dir.path().join("ascii.txt");
+        let (gbk, _, _) = encoding_rs::GBK.encode("表头\n中文内容，用于检测编码\n");
+        fs::write(&gbk_path, &gbk)?;
+        fs::write(&utf8_path, "表头\n中文内容，用于检测编码\n")?;
+        fs::write(&ascii_path, "Header\nASCII only\n")?;
+        
+        assert_eq!(check_encoding(&gbk_path, encoding_rs::GBK)?, None);
+        assert_eq!(check_encoding(&utf8_path, encoding_rs::GBK)?, Some(encoding_rs::UTF_8));
+        // ASCII content is valid GBK
+        assert_eq!(check_encoding(&ascii_path, encoding_rs::GBK)?, None);
+        
+        // The whole file is checked, not only a sample of its first bytes
+        let mixed_path = dir.path().join("mixed.txt");
+        let mut mixed = "Header, ASCII only\n".repeat(100).into_bytes();
+        mixed.extend_from_slice(&gbk);
+        fs::write(&mixed_path, mixed)?;
+        assert!(check_encoding(&mixed_path, encoding_rs::UTF_8)?.is_some());
+        
+        Ok(())
     }
 
     #[test]
@@ -278,4 +1699,596 @@ mod tests {
         
         Ok(())
     }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn test_read_and_process_file_with_preprocess_cmd() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file_path = dir.path().join("test_file.txt");
+        fs::write(&file_path, "Header line\nline b\nline a\n")?;
+        
+        let options = ProcessOptions {
+            preprocess_cmd: Some("tr a-z A-Z".to_string()),
+            ..ProcessOptions::default()
+        };
+        let lines = read_and_process_file_with_options(&file_path, &options)?;
+        assert_eq!(lines, vec!["LINE A", "LINE B"]);
+        
+        // A failing command is reported as an error for this file
+        let options = ProcessOptions {
+            preprocess_cmd: Some("exit 3".to_string()),
+            ..ProcessOptions::default()
+        };
+        assert!(read_and_process_file_with_options(&file_path, &options).is_err());
+        
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn test_read_and_process_file_with_transform_cmd() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file_path = dir.path().join("test_file.txt");
+        // ROT13-obfuscated content
+        fs::write(&file_path, "Urnqre yvar\nyvar o\nyvar n\n")?;
+        
+        let options = ProcessOptions {
+            transform_cmd: Some("tr A-Za-z N-ZA-Mn-za-m".to_string()),
+            ..ProcessOptions::default()
+        };
+        let lines = read_and_process_file_with_options(&file_path, &options)?;
+        assert_eq!(lines, vec!["line a", "line b"]);
+        
+        // A failing command is reported as an error for this file
+        let options = ProcessOptions {
+            transform_cmd: Some("echo bad key >&2; exit 1".to_string()),
+            ..ProcessOptions::default()
+        };
+        let error = read_and_process_file_with_options(&file_path, &options).unwrap_err();
+        assert!(format!("{:#}", error).contains("bad key"));
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_sorted_output_to_lines_lossy() {
+        let output = b"Line 1\nLine \xff2\nLine 3\n";
+        let lines = sorted_output_to_lines(output);
+        assert_eq!(lines, vec!["Line 1", "Line \u{FFFD}2", "Line 3"]);
+    }
+
+    #[test]
+    fn test_compare_numeric() {
+        let mut lines = vec!["10 b", "9", "-2", "abc", "10 a", "0.5", "-10", "007"];
+        lines.sort_by(|a, b| compare_numeric(a, b));
+        assert_eq!(lines, vec!["-10", "-2", "abc", "0.5", "007", "9", "10 a", "10 b"]);
+    }
+
+    #[test]
+    fn test_read_and_process_file_with_exclude_lines() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file_path = dir.path().join("test_file.txt");
+        let exclusions_path = dir.path().join("exclusions.txt");
+        fs::write(&file_path, "Header line\nLine 1\n--- footer ---\nLine 2\n  Header line  \n")?;
+        fs::write(&exclusions_path, "--- footer ---\n\nHeader line\n")?;
+        
+        let options = ProcessOptions {
+            exclude_lines: load_line_set(&exclusions_path)?,
+            ..ProcessOptions::default()
+        };
+        let lines = read_and_process_file_with_options(&file_path, &options)?;
+        assert_eq!(lines, vec!["Line 1", "Line 2"]);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_and_process_file_with_head() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file_path = dir.path().join("test_file.txt");
+        fs::write(&file_path, "Header line\nLine 3\nLine 1\nLine 2\n")?;
+        
+        let options = ProcessOptions { head: Some(2), ..ProcessOptions::default() };
+        let lines = read_and_process_file_with_options(&file_path, &options)?;
+        assert_eq!(lines, vec!["Line 1", "Line 3"]);
+        
+        let options = ProcessOptions { head: Some(0), ..ProcessOptions::default() };
+        assert!(read_and_process_file_with_options(&file_path, &options)?.is_empty());
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_and_process_file_with_equivalence_map() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file_path = dir.path().join("test_file.txt");
+        let map_path = dir.path().join("equivalences.txt");
+        fs::write(&file_path, "Header line\n1\tCODE_A\tx\n2\tCODE_AB\tCODE_A\n")?;
+        fs::write(&map_path, "CODE_A CODE_B\n\nOLD NEW\n")?;
+        
+        let equivalence_map = Arc::new(EquivalenceMap::load(&map_path)?);
+        let options = ProcessOptions {
+            equivalence_map: Some(Arc::clone(&equivalence_map)),
+            ..ProcessOptions::default()
+        };
+        let processed = process_file(&file_path, &options)?;
+        // Only whole tokens are replaced, and the original lines are kept for the report
+        assert_eq!(processed.lines, vec!["1\tCODE_B\tx", "2\tCODE_AB\tCODE_B"]);
+        assert_eq!(processed.original("1\tCODE_B\tx"), "1\tCODE_A\tx");
+        assert_eq!(equivalence_map.applied_counts(), vec![("CODE_A", "CODE_B", 2), ("OLD", "NEW", 0)]);
+        
+        fs::write(&map_path, "CODE_A\n")?;
+        assert!(EquivalenceMap::load(&map_path).is_err());
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_and_process_file_with_expand_tabs() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let tabs_path = dir.path().join("tabs.txt");
+        let spaces_path = dir.path().join("spaces.txt");
+        fs::write(&tabs_path, "Header line\nid\tname\tvalue\n\tindented\titem\n")?;
+        fs::write(&spaces_path, "Header line\nid  name    value\n    indented    item\n")?;
+        
+        let options = ProcessOptions { expand_tabs: NonZeroUsize::new(4), ..ProcessOptions::default() };
+        let tabs = process_file(&tabs_path, &options)?;
+        assert_eq!(tabs.lines, process_file(&spaces_path, &options)?.lines);
+        // The report shows the line as it was written
+        assert_eq!(tabs.original("id  name    value"), "id\tname\tvalue");
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_and_process_file_with_header_lines_and_ignore_rules() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file_path = dir.path().join("test_file.txt");
+        fs::write(&file_path, "Title\nColumns\nUnits\nLine B\nDEBUG noise\nline a\n")?;
+        
+        let options = ProcessOptions {
+            header_lines: Some(3),
+            ignore_case: true,
+            ignore_patterns: vec![Regex::new("^DEBUG")?],
+            ..ProcessOptions::default()
+        };
+        let processed = process_file(&file_path, &options)?;
+        assert_eq!(processed.lines, vec!["line a", "line b"]);
+        assert_eq!(processed.original("line b"), "Line B");
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_sample_rate() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file1_path = dir.path().join("file1.txt");
+        let file2_path = dir.path().join("file2.txt");
+        let lines: Vec<String> = (0..1000).map(|i| format!("record {}", i)).collect();
+        fs::write(&file1_path, format!("Header\n{}\n", lines.join("\n")))?;
+        // Same records in another order, with different trailing whitespace
+        fs::write(&file2_path, format!("Header\n{}\n", lines.iter().rev().map(|line| format!("{}  ", line)).collect::<Vec<_>>().join("\n")))?;
+        
+        let options = ProcessOptions {
+            sample_rate: NonZeroUsize::new(10),
+            ..ProcessOptions::default()
+        };
+        let sampled1 = process_file(&file1_path, &options)?;
+        let sampled2 = process_file(&file2_path, &options)?;
+        // The same lines are picked from both files, roughly one in ten
+        assert_eq!(sampled1.lines, sampled2.lines);
+        assert!((50..200).contains(&sampled1.lines.len()), "{} lines sampled", sampled1.lines.len());
+        
+        let all = process_file(&file1_path, &ProcessOptions { sample_rate: NonZeroUsize::new(1), ..ProcessOptions::default() })?;
+        assert_eq!(all.lines.len(), 1000);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_fnv1a() {
+        // Reference values of the 64-bit FNV-1a hash
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn test_rule_usage() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file1_path = dir.path().join("file1.txt");
+        let file2_path = dir.path().join("file2.txt");
+        fs::write(&file1_path, "Header\nLine B\nDEBUG noise\nINFO DEBUG\nline a\n")?;
+        fs::write(&file2_path, "Header\nline c\n")?;
+        
+        let rule_usage = Arc::new(RuleUsage::new());
+        let options = ProcessOptions {
+            ignore_case: true,
+            ignore_patterns: vec![Regex::new("^DEBUG")?, Regex::new("DEBUG")?, Regex::new("^TRACE")?],
+            rule_usage: Some(rule_usage.clone()),
+            ..ProcessOptions::default()
+        };
+        process_file(&file1_path, &options)?;
+        process_file(&file2_path, &options)?;
+        
+        // Counts add up over both files; a line dropped by the first pattern doesn't count for the second
+        assert_eq!(rule_usage.counts(), vec![
+            ("ignore_pattern = ^DEBUG".to_string(), 1),
+            ("ignore_pattern = DEBUG".to_string(), 1),
+            ("ignore_pattern = ^TRACE".to_string(), 0),
+            ("ignore_case = true".to_string(), 1),
+        ]);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_and_process_file_with_auto_header() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file1_path = dir.path().join("file1.txt");
+        let file2_path = dir.path().join("file2.txt");
+        let file3_path = dir.path().join("file3.txt");
+        fs::write(&file1_path, "Daily report\n1|a|x\n2|b|y\n3|c|z\n4|d|w\n")?;
+        fs::write(&file2_path, "Daily report\nGenerated by system B\nat 08:00\n3|c|z\n1|a|x\n2|b|y\n4|d|w\n")?;
+        fs::write(&file3_path, "id|name|value\n1|a|x\n2|b|y\n3|c|z\n")?;
+        
+        let options = ProcessOptions { auto_header: true, ..ProcessOptions::default() };
+        let processed1 = process_file(&file1_path, &options)?;
+        assert_eq!(processed1.lines, process_file(&file2_path, &options)?.lines);
+        assert_eq!(processed1.lines.len(), 4);
+        
+        // A header shaped like the data is inconclusive, so the default single line is skipped
+        assert_eq!(process_file(&file3_path, &options)?.lines, vec!["1|a|x", "2|b|y", "3|c|z"]);
+        
+        // An explicit header length takes precedence
+        let options = ProcessOptions { header_lines: Some(0), ..options };
+        assert_eq!(process_file(&file1_path, &options)?.lines.len(), 5);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_and_process_file_with_section() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file_path = dir.path().join("test_file.txt");
+        fs::write(&file_path, "Header\nLine 0\n[SECTION_A]\nLine 2\nLine 1\n[SECTION_B]\nLine 3\n[SECTION_A]\nLine 4\n")?;
+        
+        let options = ProcessOptions { section: Some("SECTION_A".to_string()), ..ProcessOptions::default() };
+        let processed = process_file(&file_path, &options)?;
+        assert_eq!(processed.lines, vec!["Line 1", "Line 2", "Line 4"]);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_and_process_file_with_encoding_cache() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let utf8_path = dir.path().join("utf8.txt");
+        let gbk_path = dir.path().join("gbk.txt");
+        fs::write(&utf8_path, "Header\n中文内容\n")?;
+        let (gbk, _, _) = encoding_rs::GBK.encode("表头\n中文内容\n");
+        fs::write(&gbk_path, gbk)?;
+        
+        // Per-file detection decodes both files correctly
+        assert_eq!(process_file(&gbk_path, &ProcessOptions::default())?.lines, vec!["中文内容"]);
+        
+        // With the cache, the directory's first file decides the encoding of the others
+        let options = ProcessOptions { encoding_cache: Some(Arc::new(EncodingCache::new())), ..ProcessOptions::default() };
+        assert_eq!(process_file(&utf8_path, &options)?.lines, vec!["中文内容"]);
+        assert_ne!(process_file(&gbk_path, &options)?.lines, vec!["中文内容"]);
+        
+        // Seeded, the first file in sorted order decides, whichever file is read first
+        let options = ProcessOptions { encoding_cache: Some(Arc::new(EncodingCache::new())), ..ProcessOptions::default() };
+        options.encoding_cache.as_ref().unwrap().seed([&utf8_path, &gbk_path]);
+        assert_ne!(process_file(&utf8_path, &options)?.lines, vec!["中文内容"]);
+        assert_eq!(process_file(&gbk_path, &options)?.lines, vec!["中文内容"]);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_and_process_file_with_truncate_at() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file_path = dir.path().join("test_file.txt");
+        fs::write(&file_path, "Header\nLine 2\nLine 1\n=== END ===\nGenerated at 12:00\n=== END ===\n")?;
+        
+        let options = ProcessOptions { truncate_at: Some(Regex::new("^=== END ===$")?), ..ProcessOptions::default() };
+        let processed = process_file(&file_path, &options)?;
+        assert_eq!(processed.lines, vec!["Line 1", "Line 2"]);
+        assert_eq!(processed.truncated_lines, 3);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_and_process_file_with_encoding_map() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file_path = dir.path().join("SC_13260000_20190820_019N_A05_Z.txt");
+        let (sjis, _, _) = encoding_rs::SHIFT_JIS.encode("ヘッダ\nデータ\n");
+        fs::write(&file_path, sjis)?;
+        
+        for key in ["SC_13260000_20190820_019N_A05_Z.txt", "13260000_20190820_A05"] {
+            let map_path = dir.path().join("encodings.txt");
+            fs::write(&map_path, format!("{} shift_jis\n", key))?;
+            let options = ProcessOptions { encoding_map: Some(Arc::new(EncodingMap::load(&map_path)?)), ..ProcessOptions::default() };
+            assert_eq!(process_file(&file_path, &options)?.lines, vec!["データ"]);
+        }
+        
+        let map_path = dir.path().join("invalid.txt");
+        fs::write(&map_path, "odd file.txt klingon\n")?;
+        let error = EncodingMap::load(&map_path).unwrap_err().to_string();
+        assert!(error.contains("klingon") && error.contains("odd file.txt"));
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_and_process_file_with_thousands_separator() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file1_path = dir.path().join("file1.txt");
+        let file2_path = dir.path().join("file2.txt");
+        fs::write(&file1_path, "Header\ntotal 1,234,567.89\nratio 1,5 a,b\n")?;
+        fs::write(&file2_path, "Header\ntotal 1234567.89\nratio 1,5 a,b\n")?;
+        
+        let options = ProcessOptions { thousands_separator: Some(','), ..ProcessOptions::default() };
+        let processed1 = process_file(&file1_path, &options)?;
+        let processed2 = process_file(&file2_path, &options)?;
+        assert_eq!(processed1.lines, processed2.lines);
+        assert_eq!(processed1.original("total 1234567.89"), "total 1,234,567.89");
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_and_process_file_with_sorted_fields() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file1_path = dir.path().join("file1.txt");
+        let file2_path = dir.path().join("file2.txt");
+        fs::write(&file1_path, "Header\na|b|c\n1|2\n")?;
+        fs::write(&file2_path, "Header\nc|b|a\n2|1\n")?;
+        
+        let options = ProcessOptions { sort_fields: Some("|".to_string()), ..ProcessOptions::default() };
+        let processed1 = process_file(&file1_path, &options)?;
+        let processed2 = process_file(&file2_path, &options)?;
+        assert_eq!(processed1.lines, processed2.lines);
+        assert_eq!(processed2.original("a|b|c"), "c|b|a");
+        
+        // Without the option the reordered lines differ
+        assert_ne!(process_file(&file1_path, &ProcessOptions::default())?.lines,
+                   process_file(&file2_path, &ProcessOptions::default())?.lines);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_and_process_file_dropping_empty_fields() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file1_path = dir.path().join("file1.txt");
+        let file2_path = dir.path().join("file2.txt");
+        // An empty middle field on one side, then a missing trailing field on the other
+        fs::write(&file1_path, "Header\na||c\nx|y\n")?;
+        fs::write(&file2_path, "Header\na|c\nx|y| \n")?;
+        
+        let options = ProcessOptions { drop_empty_fields: Some("|".to_string()), ..ProcessOptions::default() };
+        let processed1 = process_file(&file1_path, &options)?;
+        let processed2 = process_file(&file2_path, &options)?;
+        assert_eq!(processed1.lines, vec!["a|c", "x|y"]);
+        assert_eq!(processed1.lines, processed2.lines);
+        assert_eq!(processed1.original("a|c"), "a||c");
+        
+        // Without the option the empty fields make the lines differ
+        assert_ne!(process_file(&file1_path, &ProcessOptions::default())?.lines,
+                   process_file(&file2_path, &ProcessOptions::default())?.lines);
+        
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_collation() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file_path = dir.path().join("file.txt");
+        fs::write(&file_path, "Header\nb\nB\na\nA\n")?;
+        
+        // The C locale is always installed and sorts like the in-memory path
+        check_collation("C")?;
+        assert!(check_collation("xx_NOT_A_LOCALE").is_err());
+        let options = ProcessOptions { collation: Some("C".to_string()), ..ProcessOptions::default() };
+        assert_eq!(process_file(&file_path, &options)?.lines, process_file(&file_path, &ProcessOptions::default())?.lines);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_numeric_columns() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file1_path = dir.path().join("file1.txt");
+        let file2_path = dir.path().join("file2.txt");
+        fs::write(&file1_path, "Header\nAB00042X 1.50\nCD00007Y -0.0\n")?;
+        fs::write(&file2_path, "Header\nAB   42X  1.5\nCD    7Y    0\n")?;
+        
+        let columns = vec!["3:5".parse::<FixedColumn>()?, "9:5".parse::<FixedColumn>()?];
+        let options = ProcessOptions { numeric_columns: columns, ..ProcessOptions::default() };
+        let processed1 = process_file(&file1_path, &options)?;
+        assert_eq!(processed1.lines, process_file(&file2_path, &options)?.lines);
+        // The report shows the lines as they were
+        assert_eq!(processed1.original("AB   42X  1.5"), "AB00042X 1.50");
+        assert!("0:5".parse::<FixedColumn>().is_err());
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_only() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file_path = dir.path().join("file.txt");
+        fs::write(&file_path, "ID|NAME\n2|b\n1|a\n3\n")?;
+        
+        let options = ProcessOptions { key_only: Some("|".to_string()), ..ProcessOptions::default() };
+        let processed = process_file(&file_path, &options)?;
+        assert_eq!(processed.lines, vec!["1", "2", "3"]);
+        assert_eq!(processed.original("2"), "2");
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_record_separator() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file_path = dir.path().join("file.txt");
+        fs::write(&file_path, "ID|NAME\x1e2|b\nc\x1e1|a\x1e")?;
+        
+        let separator: RecordSeparator = "\\x1e".parse()?;
+        assert_eq!(separator, RecordSeparator("\x1e".to_string()));
+        assert!("\\xff".parse::<RecordSeparator>().is_err());
+        let options = ProcessOptions { record_separator: Some(separator), ..ProcessOptions::default() };
+        assert_eq!(process_file(&file_path, &options)?.lines, vec!["1|a", "2|b\nc"]);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_record_length() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file_path = dir.path().join("file.txt");
+        fs::write(&file_path, "HEAD1002中b001中a00")?;
+        
+        // Lengths are in characters, and the last record may be short
+        let options = ProcessOptions { record_length: NonZeroUsize::new(5), ..ProcessOptions::default() };
+        assert_eq!(process_file(&file_path, &options)?.lines, vec!["00", "001中a", "002中b"]);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_header_regex() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file_path = dir.path().join("file.txt");
+        fs::write(&file_path, "# exported 2024-01-01\n\nID|NAME\n1|a\nID|NAME\n")?;
+        
+        // Only the first matching line is the header
+        let options = ProcessOptions { header_regex: Some(Regex::new("^ID\\|")?), ..ProcessOptions::default() };
+        assert_eq!(process_file(&file_path, &options)?.lines, vec!["1|a", "ID|NAME"]);
+        
+        // Without a match, every line is compared unless the header is required
+        let options = ProcessOptions { header_regex: Some(Regex::new("^CODE")?), ..options };
+        assert_eq!(process_file(&file_path, &options)?.lines.len(), 5);
+        let options = ProcessOptions { require_header: true, ..options };
+        assert!(process_file(&file_path, &options).is_err());
+        
+        // The header regex takes precedence over the header detection, without losing any line
+        let options = ProcessOptions { header_regex: Some(Regex::new("^ID\\|")?), auto_header: true, ..ProcessOptions::default() };
+        assert_eq!(process_file(&file_path, &options)?.lines, vec!["1|a", "ID|NAME"]);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_lines() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file1_path = dir.path().join("file1.jsonl");
+        let file2_path = dir.path().join("file2.jsonl");
+        fs::write(&file1_path, "{\"id\": 1, \"name\": \"a\"}\n{\"id\":2,\"tags\":{\"y\":1,\"x\":2}}\nnot json\n")?;
+        fs::write(&file2_path, "{\"name\":\"a\",\"id\":1}\n{\"tags\":{\"x\":2,\"y\":1},\"id\":2}\nnot json\n")?;
+        
+        // No header line is skipped and key order is ignored, including in nested objects
+        let options = ProcessOptions { json_lines: true, ..ProcessOptions::default() };
+        let processed1 = process_file(&file1_path, &options)?;
+        assert_eq!(processed1.lines, vec!["not json", r#"{"id":1,"name":"a"}"#, r#"{"id":2,"tags":{"x":2,"y":1}}"#]);
+        assert_eq!(processed1.lines, process_file(&file2_path, &options)?.lines);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_duplicate_lines() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file_path = dir.path().join("test_file.txt");
+        fs::write(&file_path, "Header\nRecord B\nRecord A\n  Record B\nRecord C\nRecord B\nRecord A\n")?;
+        
+        let duplicates = find_duplicate_lines(&file_path, &ProcessOptions::default())?;
+        assert_eq!(duplicates, vec![("Record A".to_string(), 2), ("Record B".to_string(), 3)]);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_and_process_file_with_min_line_length() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file_path = dir.path().join("test_file.txt");
+        fs::write(&file_path, "Header\nRecord 1\n--\n  ab  \nRecord 2\nabc\n")?;
+        
+        let options = ProcessOptions { min_line_length: Some(3), ..ProcessOptions::default() };
+        assert_eq!(process_file(&file_path, &options)?.lines, vec!["Record 1", "Record 2", "abc"]);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_and_process_gzip_file() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file_path = dir.path().join("test_file.txt.gz");
+        let mut encoder = flate2::write::GzEncoder::new(File::create(&file_path)?, flate2::Compression::default());
+        encoder.write_all(b"Header line\nLine 2\nLine 1\n")?;
+        encoder.finish()?;
+        
+        let lines = read_and_process_file(&file_path)?;
+        assert_eq!(lines, vec!["Line 1", "Line 2"]);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_truncation() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let complete_path = dir.path().join("complete.txt");
+        let cut_path = dir.path().join("cut.txt");
+        let copy_path = dir.path().join("copy.txt");
+        fs::write(&complete_path, "Header\nline 1\nline 2\nline 3\n")?;
+        fs::write(&cut_path, "Header\nli")?;
+        fs::write(&copy_path, "Header\nline 1\nline 2\nline 4\n")?;
+        
+        let signs = check_truncation(&complete_path, &cut_path, 2.0)?.expect("the second file is cut off");
+        assert_eq!(signs.missing_final_newline, Some(2));
+        assert!(signs.size_ratio.is_some_and(|ratio| ratio > 2.0));
+        // Within the size ratio, only the missing newline is flagged
+        let signs = check_truncation(&cut_path, &complete_path, 5.0)?.expect("the first file is cut off");
+        assert_eq!(signs, TruncationSigns { missing_final_newline: Some(1), size_ratio: None });
+        
+        assert_eq!(check_truncation(&complete_path, &copy_path, 2.0)?, None);
+        
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_is_same_file_hardlink() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file_path = dir.path().join("file.txt");
+        let link_path = dir.path().join("link.txt");
+        let copy_path = dir.path().join("copy.txt");
+        fs::write(&file_path, "Header line\nLine 1\n")?;
+        fs::hard_link(&file_path, &link_path)?;
+        fs::copy(&file_path, &copy_path)?;
+        
+        assert!(is_same_file(&file_path, &link_path)?);
+        assert!(is_same_file(&file_path, &dir.path().join("./file.txt"))?);
+        assert!(!is_same_file(&file_path, &copy_path)?);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_content_hash() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file_path = dir.path().join("file.txt");
+        fs::write(&file_path, "abc")?;
+        
+        assert_eq!(content_hash(&file_path, HashAlgorithm::default())?,
+                   "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(content_hash(&file_path, "SHA1".parse()?)?, "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert!("md4".parse::<HashAlgorithm>().is_err());
+        
+        Ok(())
+    }
 }
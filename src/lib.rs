@@ -7,6 +7,22 @@
 
 pub mod file_utils;
 pub mod comparison;
+pub mod ordered_diff;
+pub mod type_rules;
+pub mod report_merge;
+pub mod summary;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+#[cfg(feature = "publish")]
+pub mod publish;
 
-pub use file_utils::{detect_encoding, read_and_process_file};
-pub use comparison::{compare_files, generate_file_pairs};
\ No newline at end of file
+pub use file_utils::{check_collation, check_encoding, check_truncation, content_hash, detect_encoding, find_duplicate_lines, is_gzip_path, is_same_file, load_line_set, process_file, process_file_unsorted, read_and_process_file, read_and_process_file_with_options, timestamp_format_width, EncodingCache, EncodingMap, EquivalenceMap, FixedColumn, HashAlgorithm, ProcessOptions, ProcessedFile, RecordSeparator, RuleUsage, TruncationSigns};
+pub use comparison::{anchored_regex, check_key_coverage, compare_bucket_counts, compare_files, compare_files_with_options, compare_files_with_stats, compare_many, diff_json_records, diff_trees, explain_pairing, file_size, find_unpaired_common_files, generate_candidate_pairs, generate_file_pairs, generate_file_pairs_with_options, generate_version_pairs, inline_diff, is_reordered, load_manifest, looks_duplicated, merge_modifications, pair_id, pair_output_path, parse_filename, parse_filename_with_options, pick_best_match, suggest_pattern, write_processed_lines, BestMatch, BucketCount, ColumnRedaction, CompareOptions, ComparisonStats, DEFAULT_EXTERNAL_DIFF_MIN_BYTES, DiffGranularity, DiffWeights, FieldNormalization, FieldRole, FieldSuggestion, FileDifferences, JsonFieldChange, JsonRecordChange, JsonRecordDiff, KeyCoverage, KeyFieldNormalization, LineModifications, LineOrigins, LineWeights, ManifestPair, MatchBy, NameMismatch, PairingExplanation, PairingOptions, PairingOutcome, ParsedFileName, PatternSuggestion, SimilarityMetric, SortKey, TreeDiff, UnpairedCommonFile, WhitespaceOnlyDiff};
+pub use ordered_diff::{conflict_merge, diff_ordered, unified_patch, write_conflict_file, write_patch_file, OrderedDiff};
+pub use type_rules::TypeRules;
+pub use report_merge::{merge_pair_streams, MergedPairs, StreamedPair};
+pub use summary::{summarize_directories, Summary, SummaryOptions};
+#[cfg(feature = "sqlite")]
+pub use sqlite::{write_run, PairRecord, RunRecord};
+#[cfg(feature = "publish")]
+pub use publish::Publisher;
\ No newline at end of file
@@ -1,200 +1,2820 @@
-use clap::Parser;
-use std::path::PathBuf;
-use std::fs::File;
-use std::io::Write;
-use tbcompare::{compare_files, generate_file_pairs};
-use log::{info, error};
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::num::NonZeroUsize;
+use std::process::ExitCode;
+use std::time::{Duration, Instant, SystemTime};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::mpsc::{self, SyncSender};
+use std::thread::{self, JoinHandle};
+use tbcompare::{anchored_regex, check_collation, compare_bucket_counts, check_encoding, check_truncation, compare_files_with_stats, content_hash, diff_json_records, diff_trees, check_key_coverage, explain_pairing, file_size, find_duplicate_lines, find_unpaired_common_files, generate_candidate_pairs, generate_file_pairs_with_options, generate_version_pairs, inline_diff, is_reordered, is_same_file, looks_duplicated, merge_modifications, merge_pair_streams, load_line_set, load_manifest, pair_id, pair_output_path, parse_filename, parse_filename_with_options, pick_best_match, suggest_pattern, timestamp_format_width, write_conflict_file, write_patch_file, write_processed_lines, BestMatch, BucketCount, ColumnRedaction, CompareOptions, DEFAULT_EXTERNAL_DIFF_MIN_BYTES, DiffGranularity, EncodingCache, EncodingMap, EquivalenceMap, FieldRole, FileDifferences, FixedColumn, HashAlgorithm, KeyCoverage, KeyFieldNormalization, LineModifications, LineWeights, MatchBy, MergedPairs, NameMismatch, PairingExplanation, PairingOptions, PairingOutcome, PatternSuggestion, ProcessOptions, RecordSeparator, RuleUsage, SimilarityMetric, SortKey, TruncationSigns, TypeRules};
+use log::{info, warn, error};
+use encoding_rs::Encoding;
 use indicatif::{ProgressBar, ProgressStyle};
 use rayon::prelude::*;
 use anyhow::{Context, Result};
-use chrono::Local;
+use chrono::{DateTime, Local};
+use regex::Regex;
+use serde_json::json;
+use messages::{Lang, Messages};
+#[cfg(feature = "sqlite")]
+use tbcompare::{write_run, PairRecord, RunRecord};
+#[cfg(feature = "publish")]
+use tbcompare::Publisher;
+
+mod messages;
+
+/// Number of NDJSON records that may be queued before workers wait for the writer
+const NDJSON_CHANNEL_CAPACITY: usize = 64;
+
+/// Set when `--stream-ndjson -` writes the records to stdout, so console messages go to stderr instead
+static CONSOLE_ON_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Prints a console message of a comparison run, on stdout unless it carries the NDJSON records
+macro_rules! console {
+    ($($arg:tt)*) => {
+        if CONSOLE_ON_STDERR.load(AtomicOrdering::Relaxed) { eprintln!($($arg)*) } else { println!($($arg)*) }
+    };
+}
+
+/// Maximum width of the `+`/`-` bars of the --stat output
+const STAT_BAR_WIDTH: usize = 40;
+
+/// Width of the report's `=` rules when neither --report-width nor a terminal width is available
+const DEFAULT_REPORT_WIDTH: usize = 80;
+
+/// Number of pairs listed in the --profile memory section
+const PROFILE_TOP_PAIRS: usize = 10;
+
+/// Order of the per-pair sections in the generated report
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ReportOrder {
+    /// Keep the order in which pairs were generated
+    Processed,
+    /// Errors first, then differing pairs by number of differing lines (largest first)
+    ErrorsFirst,
+    /// Differing pairs by number of differing lines (largest first), then errors
+    DiffsBySizeDesc,
+    /// Sort by the first file's path
+    ByName,
+}
+
+/// Output format of the merge-reports subcommand
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum MergeFormat {
+    /// A text report like the one of a comparison run
+    Text,
+    /// A JSON document with the summary totals and the merged pair records
+    Json,
+}
+
+/// Severity of a run's outcome, encoded in the exit code with --minor-threshold
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Severity {
+    /// No differences (other than accepted ones)
+    Identical,
+    /// Fewer differing lines than the minor threshold
+    Minor,
+    /// At least as many differing lines as the minor threshold, or files in the wrong encoding
+    Major,
+    /// Pairs failed to compare or weren't compared, so the drift isn't known
+    Error,
+}
+
+impl Severity {
+    /// Exit code of the severity; 1 and 2 are left to other failures and usage errors
+    fn exit_code(self) -> u8 {
+        match self {
+            Severity::Identical => 0,
+            Severity::Minor => 3,
+            Severity::Major => 4,
+            Severity::Error => 5,
+        }
+    }
+}
+
+/// Comparison result of a single file pair
+struct PairResult {
+    file1_path: PathBuf,
+    file2_path: PathBuf,
+    result: anyhow::Result<Option<FileDifferences>>,
+    /// Both paths refer to the same file (e.g. hardlinks), so the comparison was skipped
+    same_file: bool,
+    /// The deadline was reached before the pair was dispatched, so it wasn't compared
+    not_compared: bool,
+    /// The files hold the same lines in a different order (only checked with --detect-reorder)
+    reordered: bool,
+    /// Hashes of both files' raw bytes (only computed with --content-hash)
+    content_hashes: Option<(String, String)>,
+    /// Files of the pair not in the --require-encoding encoding, with their detected encoding
+    encoding_mismatches: Vec<(PathBuf, &'static Encoding)>,
+    /// Estimated peak memory of the comparison in bytes (0 when the files weren't read)
+    memory_estimate: u64,
+    /// Number of lines compared in each file (`None` when the files weren't read)
+    line_counts: Option<(usize, usize)>,
+    /// Signs that either file was cut off (only checked with --check-truncation)
+    truncation: Option<TruncationSigns>,
+}
+
+/// Periodic progress lines on stderr, used instead of the progress bar outside a terminal (e.g. in CI logs)
+struct ProgressLog {
+    started: Instant,
+    total: usize,
+    /// Minimum time between two lines
+    interval: Duration,
+    /// Also log after every this many completed pairs, whatever the interval
+    every_pairs: Option<NonZeroUsize>,
+    completed: AtomicUsize,
+    /// When the last line was written
+    last_logged: Mutex<Instant>,
+    messages: &'static Messages,
+}
+
+impl ProgressLog {
+    fn new(total: usize, interval: Duration, every_pairs: Option<NonZeroUsize>, messages: &'static Messages) -> Self {
+        let started = Instant::now();
+        ProgressLog {
+            started, total, interval, every_pairs,
+            completed: AtomicUsize::new(0),
+            last_logged: Mutex::new(started),
+            messages,
+        }
+    }
+    
+    /// Counts a completed pair, writing a line when one is due
+    fn pair_done(&self) {
+        let completed = self.completed.fetch_add(1, AtomicOrdering::Relaxed) + 1;
+        let every_pairs_due = self.every_pairs.is_some_and(|every_pairs| completed.is_multiple_of(every_pairs.get()));
+        let mut last_logged = self.last_logged.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if every_pairs_due || last_logged.elapsed() >= self.interval {
+            *last_logged = Instant::now();
+            self.log(completed);
+        }
+    }
+    
+    /// Writes the final line
+    fn finish(&self) {
+        self.log(self.completed.load(AtomicOrdering::Relaxed));
+    }
+    
+    fn log(&self, completed: usize) {
+        let percent = if self.total == 0 { 100.0 } else { completed as f64 * 100.0 / self.total as f64 };
+        eprintln!("{}", (self.messages.progress)(completed, self.total, percent, self.started.elapsed().as_secs_f64()));
+    }
+}
+
+/// Reorders the collected results according to the requested report order
+fn order_results(results: &mut [PairResult], order: ReportOrder) {
+    // Number of differing lines; errors and identical pairs count as zero
+    let diff_size = |pair: &PairResult| match &pair.result {
+        Ok(Some(diff)) => diff.only_in_first.len() + diff.only_in_second.len(),
+        _ => 0,
+    };
+    match order {
+        ReportOrder::Processed => {}
+        ReportOrder::ErrorsFirst => {
+            results.sort_by_key(|pair| (pair.result.is_ok(), std::cmp::Reverse(diff_size(pair))));
+        }
+        ReportOrder::DiffsBySizeDesc => {
+            results.sort_by_key(|pair| (pair.result.is_err(), std::cmp::Reverse(diff_size(pair))));
+        }
+        ReportOrder::ByName => {
+            results.sort_by(|a, b| a.file1_path.cmp(&b.file1_path).then_with(|| a.file2_path.cmp(&b.file2_path)));
+        }
+    }
+}
+
+/// Describes the version change between two files, e.g. `019N → 020N`
+fn version_transition(file1_path: &Path, file2_path: &Path) -> String {
+    let version = |path: &Path| parse_filename(path).map(|parsed| parsed.version).unwrap_or_default();
+    format!("{} → {}", version(file1_path), version(file2_path))
+}
+
+/// Returns the numeric distance between the tttN versions of two files, if both have one
+fn version_gap(file1_path: &Path, file2_path: &Path) -> Option<u64> {
+    let version = |path: &Path| parse_filename(path).and_then(|parsed| parsed.version_number());
+    Some(version(file1_path)?.abs_diff(version(file2_path)?))
+}
+
+/// Whether a pair is on the --accept-pairs list, by the match key of its first file or the short path of either file
+fn is_accepted_pair(accepted_pairs: &HashSet<String>, file1_path: &Path, file2_path: &Path, pairing_options: &PairingOptions) -> bool {
+    if accepted_pairs.is_empty() {
+        return false;
+    }
+    pairing_options.key_for(file1_path).is_some_and(|key| accepted_pairs.contains(&key))
+        || [file1_path, file2_path].into_iter().any(|path| accepted_pairs.contains(&short_path(path).display().to_string()))
+}
+
+/// Shortens a path to `<parent dir name>/<file name>` for display in the report
+fn short_path(path: &Path) -> PathBuf {
+    // 从路径中提取父目录名和文件名
+    let file_name = path.file_name().unwrap_or_else(|| std::ffi::OsStr::new(""));
+    let parent_name = path.parent()
+        .and_then(|p| p.file_name())
+        .unwrap_or_else(|| std::ffi::OsStr::new(""));
+    Path::new(parent_name).join(file_name)
+}
+
+/// Formats a `git diff --stat` style summary of (name, added lines, removed lines) entries
+fn diffstat(entries: &[(String, usize, usize)], messages: &Messages) -> String {
+    let name_width = entries.iter().map(|(name, _, _)| name.chars().count()).max().unwrap_or(0);
+    let count_width = entries.iter().map(|(_, added, removed)| (added + removed).to_string().len()).max().unwrap_or(0);
+    let max_changes = entries.iter().map(|(_, added, removed)| added + removed).max().unwrap_or(0);
+    // Bars are scaled down to the fixed width only when needed; a non-zero count keeps at least one mark
+    let scale = |lines: usize| if max_changes <= STAT_BAR_WIDTH { lines } else { (lines * STAT_BAR_WIDTH).div_ceil(max_changes) };
+    
+    let mut stat = String::new();
+    for (name, added, removed) in entries {
+        let padding = name_width - name.chars().count();
+        stat.push_str(&format!(" {}{} | {:>count_width$} {}{}\n", name, " ".repeat(padding), added + removed,
+                               "+".repeat(scale(*added)), "-".repeat(scale(*removed))));
+    }
+    let total_added: usize = entries.iter().map(|(_, added, _)| added).sum();
+    let total_removed: usize = entries.iter().map(|(_, _, removed)| removed).sum();
+    stat.push_str(&format!("{}\n", (messages.stat_total)(entries.len(), total_added, total_removed)));
+    stat
+}
+
+/// Expands the `{name}` placeholders of a report file name template
+/// Placeholder values are sanitized so they can't introduce path separators or characters
+/// that are illegal in file names; the template's own text is kept as written.
+fn render_output_template(template: &str, values: &[(&str, &str)]) -> Result<String> {
+    let sanitize = |value: &str| value.replace(|c: char| c.is_control() || r#"<>:"/\|?*"#.contains(c), "_");
+    
+    let mut rendered = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let end = rest[start..].find('}')
+            .with_context(|| format!("输出文件名模板中的 {{ 未闭合: {}", template))?;
+        let name = &rest[start + 1..start + end];
+        let value = values.iter()
+            .find(|(placeholder, _)| *placeholder == name)
+            .map(|(_, value)| *value)
+            .with_context(|| {
+                let known: Vec<_> = values.iter().map(|(placeholder, _)| format!("{{{}}}", placeholder)).collect();
+                format!("输出文件名模板中的占位符 {{{}}} 未知（可用: {}）", name, known.join(", "))
+            })?;
+        rendered.push_str(&sanitize(value));
+        rest = &rest[start + end + 1..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+/// Describes the amount of data compared and the derived throughput
+fn throughput(bytes: u64, pairs: usize, elapsed: Duration, messages: &Messages) -> String {
+    let mebibytes = bytes as f64 / (1024.0 * 1024.0);
+    // Guard against a zero duration on trivially small runs
+    let seconds = elapsed.as_secs_f64().max(0.001);
+    (messages.throughput)(mebibytes, pairs, seconds, mebibytes / seconds, pairs as f64 / seconds)
+}
+
+/// Starts the --stream-ndjson record of a pair, identified by its pair ID, with its status
+fn stream_record(file1_path: &Path, file2_path: &Path, status: &str) -> serde_json::Value {
+    json!({
+        "id": pair_id(file1_path, file2_path),
+        "file1": file1_path.display().to_string(),
+        "file2": file2_path.display().to_string(),
+        "status": status,
+    })
+}
+
+/// Builds the SQLite row of a pair's result
+#[cfg(feature = "sqlite")]
+fn pair_record(pair: &PairResult, metric: SimilarityMetric) -> PairRecord {
+    let mut record = PairRecord {
+        pair_id: pair_id(&pair.file1_path, &pair.file2_path),
+        file1: pair.file1_path.display().to_string(),
+        file2: pair.file2_path.display().to_string(),
+        ..PairRecord::default()
+    };
+    match &pair.result {
+        _ if pair.not_compared => record.status = "not_compared",
+        _ if pair.same_file => (record.status, record.similarity) = ("same_file", Some(1.0)),
+        Ok(Some(diff)) => {
+            record.status = "different";
+            record.only_in_first = diff.only_in_first.len();
+            record.only_in_second = diff.only_in_second.len();
+            record.similarity = Some(diff.similarity(metric));
+        }
+        Ok(None) => (record.status, record.similarity) = ("identical", Some(1.0)),
+        Err(_) => record.status = "error",
+    }
+    record
+}
+
+/// Builds the message published for a completed pair, with the run's progress
+#[cfg(feature = "publish")]
+fn pair_message(pair: &PairResult, metric: SimilarityMetric, completed: usize, total: usize) -> String {
+    let (status, similarity) = match &pair.result {
+        _ if pair.not_compared => ("not_compared", None),
+        _ if pair.same_file => ("same_file", Some(1.0)),
+        Ok(Some(diff)) => ("different", Some(diff.similarity(metric))),
+        Ok(None) => ("identical", Some(1.0)),
+        Err(_) => ("error", None),
+    };
+    let mut message = json!({
+        "event": "pair",
+        "id": pair_id(&pair.file1_path, &pair.file2_path),
+        "file1": pair.file1_path.display().to_string(),
+        "file2": pair.file2_path.display().to_string(),
+        "status": status,
+        "similarity": similarity,
+        "completed": completed,
+        "total": total,
+    });
+    match &pair.result {
+        Ok(Some(diff)) => {
+            message["only_in_first"] = json!(diff.only_in_first.len());
+            message["only_in_second"] = json!(diff.only_in_second.len());
+        }
+        Err(e) => message["error"] = json!(format!("{:#}", e)),
+        Ok(None) => {}
+    }
+    message.to_string()
+}
+
+/// Describes why a file name doesn't follow the SC_aaaaaaaa_yyyymmdd_tttN_AXX_Z naming pattern
+fn name_mismatch_reason(mismatch: &NameMismatch, messages: &Messages) -> String {
+    match mismatch {
+        NameMismatch::NotUtf8 => messages.name_not_utf8.to_string(),
+        NameMismatch::TooFewFields(count) => (messages.name_too_few_fields)(*count),
+        NameMismatch::WrongPrefix(prefix) => (messages.name_wrong_prefix)(prefix),
+        NameMismatch::WrongSuffix(suffix) => (messages.name_wrong_suffix)(suffix),
+    }
+}
+
+/// Prints the explanation of how a file was (or wasn't) paired, for the explain-pairing subcommand
+fn print_pairing_explanation(explanation: &PairingExplanation, match_by: MatchBy, messages: &Messages) {
+    println!("{}: {}", messages.file, explanation.file1_path.display());
+    match &explanation.parsed {
+        Some(parsed) => println!("{}: aaaaaaaa={} yyyymmdd={} tttN={} AXX={}", messages.name_fields,
+                                 parsed.account, parsed.date, parsed.version, parsed.report_type),
+        None => println!("{}: {}", messages.name_fields, messages.name_pattern_mismatch),
+    }
+    println!("{}: {}", messages.match_by, match_by);
+    match &explanation.match_key {
+        Some(key) => println!("{}: {}", messages.match_key, key),
+        None if match_by == MatchBy::Position => println!("{}: {}", messages.match_key, messages.match_key_by_position),
+        None => println!("{}: {}", messages.match_key, messages.match_key_unavailable),
+    }
+    let result = match &explanation.outcome {
+        PairingOutcome::Matched(file2_path) => (messages.matched)(file2_path),
+        PairingOutcome::UnparsedName(mismatch) => (messages.unmatched)(&name_mismatch_reason(mismatch, messages)),
+        PairingOutcome::KeyRegexMismatch => (messages.unmatched)(messages.key_regex_mismatch),
+        PairingOutcome::KeyNotSelected => (messages.unmatched)(messages.key_not_selected),
+        PairingOutcome::NoCounterpart => (messages.unmatched)(messages.no_counterpart),
+        PairingOutcome::NoFileAtPosition(position) => (messages.unmatched)(&(messages.no_file_at_position)(*position)),
+        PairingOutcome::SizeOutOfRange(file2_path) => (messages.unmatched)(&(messages.size_out_of_range)(file2_path)),
+        PairingOutcome::Unchanged(file2_path) => (messages.unmatched)(&(messages.unchanged_since_last_run)(file2_path)),
+    };
+    println!("{}: {}", messages.result, result);
+}
+
+/// Prints the inferred file name structure and the suggested key regex
+fn print_pattern_suggestion(suggestion: &PatternSuggestion, messages: &Messages) {
+    println!("{}", (messages.suggestion_sampled)(suggestion.sampled, suggestion.matching, suggestion.fields.len()));
+    for (position, field) in suggestion.fields.iter().enumerate() {
+        let role = match field.role {
+            FieldRole::Fixed => messages.role_fixed,
+            FieldRole::Key => messages.role_key,
+            FieldRole::Version => messages.role_version,
+        };
+        println!("{}", (messages.suggestion_field)(position + 1, &field.pattern, &field.example, role));
+    }
+    if suggestion.built_in_matches {
+        println!("{}", messages.suggestion_built_in);
+    } else {
+        println!("{}: --key-regex '{}'", messages.suggestion, suggestion.key_regex);
+        println!("{}", messages.suggestion_hint);
+    }
+}
+
+/// Formats the bucket counts of every pair as a text report, flagging the buckets whose counts differ
+fn bucket_report(pairs: &[(PathBuf, PathBuf, Result<Vec<BucketCount>>)], bucket_field: usize, rule_width: usize, messages: &Messages) -> String {
+    let section_rule_width = (rule_width * 5 / 8).max(1);
+    let mut report = String::new();
+    report.push_str(&format!("{}\n", "=".repeat(rule_width)));
+    report.push_str(&format!("{:^rule_width$}\n", messages.bucket_report_title));
+    report.push_str(&format!("{:^rule_width$}\n", format!("{}: {}", messages.generated_at, Local::now().format("%Y-%m-%d %H:%M:%S"))));
+    report.push_str(&format!("{}\n\n", "=".repeat(rule_width)));
+    report.push_str(&format!("{}\n\n", (messages.bucket_field)(bucket_field)));
+    
+    let (mut changed_pairs, mut error_pairs) = (0, 0);
+    for (file1_path, file2_path, counts) in pairs {
+        report.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        report.push_str(&format!("ID: {}\n", pair_id(file1_path, file2_path)));
+        report.push_str(&format!("{}: {}\n", messages.file1, short_path(file1_path).display()));
+        report.push_str(&format!("{}: {}\n", messages.file2, short_path(file2_path).display()));
+        report.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        let counts = match counts {
+            Ok(counts) => counts,
+            Err(e) => {
+                error_pairs += 1;
+                report.push_str(&format!("  ✗ {}: {:#}\n\n", messages.comparison_failed, e));
+                continue;
+            }
+        };
+        if counts.iter().any(|count| count.delta() != 0) {
+            changed_pairs += 1;
+        }
+        let width = counts.iter().map(|count| count.bucket.chars().count()).chain([messages.bucket.chars().count()]).max().unwrap_or_default();
+        report.push_str(&format!("  {:<width$}  {:>10}  {:>10}  {:>10}\n", messages.bucket, messages.file1, messages.file2, messages.delta));
+        for count in counts {
+            let flag = if count.delta() != 0 { format!("  {}", messages.bucket_counts_differ) } else { String::new() };
+            report.push_str(&format!("  {:<width$}  {:>10}  {:>10}  {:>+10}{}\n", count.bucket, count.count1, count.count2, count.delta(), flag));
+        }
+        report.push('\n');
+    }
+    
+    report.push_str(&format!("{}\n", "=".repeat(rule_width)));
+    report.push_str(&format!("{}\n", messages.summary));
+    report.push_str(&format!("{}\n", "=".repeat(rule_width)));
+    report.push_str(&format!("  • {}: {}\n", messages.bucket_changed_pairs, changed_pairs));
+    report.push_str(&format!("  • {}: {}\n", messages.error_pairs, error_pairs));
+    report.push_str(&format!("{}\n", "=".repeat(rule_width)));
+    report
+}
+
+/// Formats the merged results of several runs as a text report
+fn merged_report(merged: &MergedPairs, streams: &[PathBuf], messages: &Messages) -> String {
+    let rule_width = DEFAULT_REPORT_WIDTH;
+    let section_rule_width = rule_width * 5 / 8;
+    let mut report = String::new();
+    report.push_str(&format!("{}\n", "=".repeat(rule_width)));
+    report.push_str(&format!("{:^rule_width$}\n", messages.merged_report_title));
+    report.push_str(&format!("{:^rule_width$}\n", format!("{}: {}", messages.generated_at, Local::now().format("%Y-%m-%d %H:%M:%S"))));
+    report.push_str(&format!("{}\n\n", "=".repeat(rule_width)));
+    
+    report.push_str(&format!("{}\n", messages.merged_sources));
+    for stream in streams {
+        report.push_str(&format!("  • {}\n", stream.display()));
+    }
+    report.push('\n');
+    
+    for (index, pair) in merged.pairs.iter().filter(|pair| pair.is_different()).enumerate() {
+        report.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        report.push_str(&format!("{}\n", (messages.diff_section)(index + 1)));
+        report.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        report.push_str(&format!("ID: {}\n", pair.id));
+        report.push_str(&format!("{}: {}\n", messages.file1, pair.file1));
+        report.push_str(&format!("{}: {}\n", messages.file2, pair.file2));
+        report.push_str(&format!("{}: {:.2}%\n", messages.similarity, pair.similarity.unwrap_or_default() * 100.0));
+        report.push_str(&format!("{}: {}\n\n", messages.merged_source, pair.source.display()));
+        for (file_path, lines) in [(&pair.file1, &pair.only_in_first), (&pair.file2, &pair.only_in_second)] {
+            if lines.is_empty() {
+                continue;
+            }
+            report.push_str(&format!("  ► {}:\n", (messages.only_in)(&short_path(Path::new(file_path)))));
+            for line in lines {
+                report.push_str(&format!("    • {}\n", line));
+            }
+            report.push('\n');
+        }
+    }
+    
+    for pair in merged.pairs.iter().filter(|pair| pair.status == "error") {
+        report.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        report.push_str(&format!("{}\n", messages.comparison_error));
+        report.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        report.push_str(&format!("ID: {}\n", pair.id));
+        report.push_str(&format!("{}: {}\n", messages.file1, pair.file1));
+        report.push_str(&format!("{}: {}\n", messages.file2, pair.file2));
+        report.push_str(&format!("{}: {}\n", messages.merged_source, pair.source.display()));
+        report.push_str(&format!("{}: {}\n\n", messages.error_message, pair.error.as_deref().unwrap_or_default()));
+    }
+    
+    let identical: Vec<_> = merged.pairs.iter().filter(|pair| matches!(pair.status.as_str(), "identical" | "same_file")).collect();
+    if !identical.is_empty() {
+        report.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        report.push_str(&format!("{}\n", messages.identical_section));
+        report.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        for pair in &identical {
+            report.push_str(&format!("  • [{}] {} ↔ {}\n", pair.id, pair.file1, pair.file2));
+        }
+        report.push('\n');
+    }
+    
+    report.push_str(&format!("{}\n", "=".repeat(rule_width)));
+    report.push_str(&format!("{}\n", messages.summary));
+    report.push_str(&format!("{}\n", "=".repeat(rule_width)));
+    report.push_str(&format!("  • {}: {}\n", messages.merged_sources, streams.len()));
+    report.push_str(&format!("  • {}: {}\n", messages.differing_pairs, merged.count("different")));
+    report.push_str(&format!("  • {}: {}\n", messages.identical_pairs, identical.len()));
+    report.push_str(&format!("  • {}: {}\n", messages.error_pairs, merged.count("error")));
+    report.push_str(&format!("  • {}: {}\n", messages.merged_duplicates, merged.duplicates));
+    report.push_str(&format!("{}\n", "=".repeat(rule_width)));
+    report
+}
+
+/// Formats the merged results of several runs as a JSON document
+fn merged_report_json(merged: &MergedPairs, streams: &[PathBuf]) -> String {
+    let pairs: Vec<_> = merged.pairs.iter()
+        .map(|pair| json!({
+            "id": pair.id,
+            "file1": pair.file1,
+            "file2": pair.file2,
+            "status": pair.status,
+            "only_in_first": pair.only_in_first,
+            "only_in_second": pair.only_in_second,
+            "similarity": pair.similarity,
+            "error": pair.error,
+            "source": pair.source.display().to_string(),
+        }))
+        .collect();
+    let report = json!({
+        "streams": streams.iter().map(|stream| stream.display().to_string()).collect::<Vec<_>>(),
+        "differing_pairs": merged.count("different"),
+        "identical_pairs": merged.count("identical") + merged.count("same_file"),
+        "error_pairs": merged.count("error"),
+        "duplicates": merged.duplicates,
+        "pairs": pairs,
+    });
+    format!("{}\n", report)
+}
+
+/// The expected keys that didn't produce a pair, grouped by where they are missing, with their labels
+fn key_coverage_groups<'a>(coverage: &'a KeyCoverage, messages: &Messages) -> [(&'static str, &'a [String]); 4] {
+    [
+        (messages.keys_missing_from_both, &coverage.missing_from_both),
+        (messages.keys_missing_from_first, &coverage.missing_from_first),
+        (messages.keys_missing_from_second, &coverage.missing_from_second),
+        (messages.keys_not_paired, &coverage.unpaired),
+    ]
+}
+
+/// Prints how many expected keys produced a pair, and the ones that didn't
+fn print_key_coverage(coverage: &KeyCoverage, messages: &Messages) {
+    println!("{}", (messages.key_coverage_notice)(coverage.paired.len(), coverage.paired.len() + coverage.not_paired_count()));
+    for (label, keys) in key_coverage_groups(coverage, messages) {
+        if !keys.is_empty() {
+            println!("  {} ({}): {}", label, keys.len(), keys.join(", "));
+        }
+    }
+}
+
+/// Loads a list of match keys, lowercased when file names are matched case-insensitively
+fn load_match_keys(path: &Path, case_insensitive: bool) -> Result<HashSet<String>> {
+    let keys = load_line_set(path)
+        .with_context(|| format!("无法读取匹配键列表文件: {:?}", path))?;
+    // Computed keys are lowercased when matching case-insensitively
+    Ok(if case_insensitive {
+        keys.into_iter().map(|key| key.to_lowercase()).collect()
+    } else {
+        keys
+    })
+}
+
+/// Writes a file that must not exist yet, so files of different pairs can't overwrite each other
+fn write_new_file(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    OpenOptions::new().write(true).create_new(true).open(path)?.write_all(content)
+}
+
+/// Reads when the last successful incremental run started, or `None` if no run was recorded yet
+fn read_last_run(state_path: &Path) -> Result<Option<SystemTime>> {
+    let content = match std::fs::read_to_string(state_path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("无法读取状态文件: {:?}", state_path)),
+    };
+    let last_run = DateTime::parse_from_rfc3339(content.trim())
+        .with_context(|| format!("状态文件中的时间无效: {:?}", state_path))?;
+    Ok(Some(last_run.into()))
+}
+
+/// Records when a successful incremental run started, as an RFC 3339 timestamp
+fn write_last_run(state_path: &Path, started_at: SystemTime) -> Result<()> {
+    std::fs::write(state_path, format!("{}\n", DateTime::<Local>::from(started_at).to_rfc3339()))
+        .with_context(|| format!("无法写入状态文件: {:?}", state_path))
+}
+
+/// Starts a thread writing NDJSON records received over a bounded channel to `path` (stdout for `-`),
+/// flushing after every record so consumers see each result immediately
+fn spawn_ndjson_writer(path: &Path) -> Result<(SyncSender<String>, JoinHandle<Result<()>>)> {
+    let output: Box<dyn Write + Send> = if path == Path::new("-") {
+        Box::new(std::io::stdout())
+    } else {
+        Box::new(File::create(path)
+            .with_context(|| format!("无法创建 NDJSON 输出文件: {:?}", path))?)
+    };
+    let path = path.to_path_buf();
+    let (sender, receiver) = mpsc::sync_channel::<String>(NDJSON_CHANNEL_CAPACITY);
+    let writer = thread::spawn(move || -> Result<()> {
+        let mut writer = BufWriter::new(output);
+        for record in receiver {
+            writeln!(writer, "{}", record)
+                .and_then(|_| writer.flush())
+                .with_context(|| format!("无法写入 NDJSON 输出文件: {:?}", path))?;
+        }
+        Ok(())
+    });
+    Ok((sender, writer))
+}
 
 /// Tool for comparing text files with specific naming conventions
 #[derive(Parser, Debug)]
-#[clap(author, version, about, long_about = None)]
+#[clap(author, version, about, long_about = None, subcommand_negates_reqs = true)]
 struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
+    
     /// First directory path
-    #[clap(value_name = "DIR1")]
-    dir1: PathBuf,
+    #[clap(value_name = "DIR1", required = true)]
+    dir1: Option<PathBuf>,
 
-    /// Second directory path
-    #[clap(value_name = "DIR2")]
-    dir2: PathBuf,
+    /// Second directory path (omitted with --previous-version)
+    #[clap(value_name = "DIR2", required_unless_present = "previous_version")]
+    dir2: Option<PathBuf>,
+    
+    /// Compare each file in DIR1 against the previous version of the same file in DIR1
+    #[clap(long, conflicts_with = "dir2")]
+    previous_version: bool,
+    
+    /// Compare even when DIR1 and DIR2 are the same directory, or the sampled pairs suggest they are copies
+    #[clap(long)]
+    force: bool,
+    
+    /// Number of pairs sampled before comparing to detect that DIR1 and DIR2 are copies of each other,
+    /// in which case the run stops unless --force is given (0 disables the check)
+    #[clap(long, value_name = "N", default_value_t = 5)]
+    duplicate_check_samples: usize,
     
     /// Number of parallel threads to use
     #[clap(short, long, default_value_t = 4)]
     threads: usize,
     
+    /// Minimum number of pairs a thread takes at once: pairs are split into chunks of at least this size
+    /// instead of being pulled one at a time, largest first, from a single queue. This bypasses the
+    /// size-ordered queue: each chunk is a contiguous run of the size-sorted list, so one thread gets the
+    /// largest pairs together. Only for many pairs of about the same size, where it lowers scheduling overhead.
+    #[clap(long, value_name = "N")]
+    par_chunk_size: Option<NonZeroUsize>,
+    
     /// Output report file path (optional)
     #[clap(short, long)]
     output: Option<PathBuf>,
+    
+    /// Report file name template with {dir1}, {dir2} (directory names), {date} (timestamp)
+    /// and {ext} (report extension) placeholders, e.g. '{dir1}_{dir2}_{date}.{ext}'
+    #[clap(long, value_name = "TEMPLATE", conflicts_with = "output")]
+    output_template: Option<String>,
+    
+    /// Shell command each file's decoded content is piped through before comparison
+    #[clap(long, value_name = "CMD")]
+    preprocess_cmd: Option<String>,
+    
+    /// Shell command the raw bytes of each DIR1 file are piped through before decoding (e.g. to decrypt or
+    /// base64-decode them); its output is compared in place of the file content
+    #[clap(long, value_name = "CMD")]
+    transform1_cmd: Option<String>,
+    
+    /// Shell command the raw bytes of each DIR2 file are piped through before decoding, like --transform1-cmd
+    #[clap(long, value_name = "CMD")]
+    transform2_cmd: Option<String>,
+    
+    /// Order of the per-pair sections in the report
+    #[clap(long, value_enum, default_value_t = ReportOrder::Processed)]
+    report_order: ReportOrder,
+    
+    /// Append each pair's section to the report as soon as the pair is compared (in completion order,
+    /// largest pairs first), then the overview, the other sections and the summary at the end,
+    /// so the report of a long run can be followed with `tail -f`
+    #[clap(long, conflicts_with = "report_order")]
+    tail_report: bool,
+    
+    /// Directory to write a unified-diff patch per differing pair (applicable with `patch file1 < patch`)
+    #[clap(long, value_name = "DIR")]
+    patch_dir: Option<PathBuf>,
+    
+    /// Directory to write a copy of each differing pair with Git-style conflict markers around every
+    /// differing region (<file1 stem>__<file2 stem>.<pair id>.conflict.txt), to resolve by hand in an editor
+    #[clap(long, value_name = "DIR")]
+    conflict_dir: Option<PathBuf>,
+    
+    /// Write the lines of each file exactly as they are compared (after header skipping, normalization
+    /// and sorting) to <file1 stem>__<file2 stem>.<pair id>.1.txt / .2.txt in this directory
+    #[clap(long, value_name = "DIR")]
+    dump_processed: Option<PathBuf>,
+    
+    /// Strip a trailing timestamp in this chrono format (e.g. '%Y-%m-%d %H:%M:%S') from each line before comparison
+    #[clap(long, value_name = "FORMAT")]
+    strip_trailing_timestamp: Option<String>,
+    
+    /// Sort lines by their leading number instead of lexicographically
+    #[clap(long)]
+    numeric_sort: bool,
+    
+    /// Sort lines in the collation order of this installed locale (e.g. zh_CN.UTF-8) instead of byte-wise;
+    /// every file is then sorted by the system sort command
+    #[clap(long, value_name = "LOCALE")]
+    collation: Option<String>,
+    
+    /// Write only the failed pairs (file1, file2, error; tab-separated) to this file
+    #[clap(long, value_name = "PATH")]
+    errors_out: Option<PathBuf>,
+    
+    /// Don't create the --errors-out file when no pair failed
+    #[clap(long, requires = "errors_out")]
+    errors_out_skip_empty: bool,
+    
+    /// Write the differing lines of every differing pair to this file for spreadsheet pivoting
+    /// (tab-separated: pair ID, file1, file2, line, present in first, present in second)
+    #[clap(long, value_name = "PATH")]
+    pivot_out: Option<PathBuf>,
+    
+    /// Similarity metric shown for differing pairs: jaccard, overlap or dice
+    #[clap(long, value_name = "METRIC", default_value_t = SimilarityMetric::Jaccard)]
+    similarity_metric: SimilarityMetric,
+    
+    /// Warn about pairs whose tttN version numbers differ by more than N
+    #[clap(long, value_name = "N")]
+    warn_version_gap: Option<u64>,
+    
+    /// Skip the external diff/fc.exe check when both files are smaller than this many bytes (0 = never skip)
+    #[clap(long, value_name = "BYTES", default_value_t = DEFAULT_EXTERNAL_DIFF_MIN_BYTES)]
+    external_diff_min_bytes: u64,
+    
+    /// Program used for the quick identity check instead of diff (fc.exe on Windows), called with the same arguments
+    #[clap(long, value_name = "PROGRAM")]
+    diff_command: Option<String>,
+    
+    /// Compare the raw bytes of both files first, treating byte-identical files as identical without decoding them
+    #[clap(long)]
+    byte_identity: bool,
+    
+    /// Program used to sort very large files instead of sort, called with the same arguments
+    #[clap(long, value_name = "PROGRAM")]
+    sort_command: Option<String>,
+    
+    /// File of lines (one per line, exact match after trimming) that never count as differences
+    #[clap(long, value_name = "FILE")]
+    exclude_lines: Option<PathBuf>,
+    
+    /// Skip pairs whose two paths refer to the same file (same canonical path or hardlinked inode)
+    #[clap(long)]
+    skip_same_file: bool,
+    
+    /// Stream each pair as one JSON object per line to this file (- for stdout, console messages then going
+    /// to stderr) as soon as it is compared, with its ID and status (different, identical, same_file or error)
+    #[clap(long, value_name = "PATH")]
+    stream_ndjson: Option<PathBuf>,
+    
+    /// Compare lines in file order (positional diff) instead of as unordered sets
+    #[clap(long)]
+    ordered: bool,
+    
+    /// With --ordered, stably sort both files by this 1-based field before the positional diff
+    #[clap(long, value_name = "N", requires = "ordered")]
+    sort_key_field: Option<NonZeroUsize>,
+    
+    /// Delimiter separating the fields of a line
+    #[clap(long, value_name = "DELIM", default_value = "\t")]
+    column_delimiter: String,
+    
+    /// Only compare the first field of each line (split on --column-delimiter), reporting the keys
+    /// present in only one file rather than the full records
+    #[clap(long, conflicts_with_all = ["json_key", "merge_modifications"])]
+    key_only: bool,
+    
+    /// Mask these 1-based fields (split on --column-delimiter) with *** in every line shown in the report,
+    /// --pivot-out and --stream-ndjson (differences and repeated lines); the comparison still uses the full values
+    #[clap(long, value_name = "COLUMNS", value_delimiter = ',', conflicts_with = "json_key")]
+    redact_columns: Option<Vec<NonZeroUsize>>,
+    
+    /// Only count the records of each file per value of this 1-based field (split on --column-delimiter),
+    /// reporting both counts side by side with their delta instead of comparing the lines
+    #[clap(long, value_name = "N")]
+    bucket_field: Option<NonZeroUsize>,
+    
+    /// Records end with this terminator instead of a newline, e.g. '\x1e' (\xHH, \t, \r, \n, \0 and \\
+    /// are unescaped); header lines, --head and the report then count records
+    #[clap(long, value_name = "SEP")]
+    record_separator: Option<RecordSeparator>,
+    
+    /// Split files into fixed-length records of N characters (after decoding) instead of lines
+    #[clap(long, value_name = "N", conflicts_with = "record_separator")]
+    record_length: Option<NonZeroUsize>,
+    
+    /// Match file names case-insensitively (sc_..._z pairs with SC_..._Z)
+    #[clap(long)]
+    case_insensitive_names: bool,
+    
+    /// Regex with named capture groups used to build the match key from the file stem, which it must match
+    /// as a whole (e.g. 'SC_(?P<acct>\d+)_(?P<date>\d{8})_.+_(?P<type>A\d+)_Z'); non-matching files are
+    /// skipped. Matched case-insensitively with --case-insensitive-names
+    #[clap(long, value_name = "REGEX", value_parser = anchored_regex, conflicts_with = "previous_version")]
+    key_regex: Option<Regex>,
+    
+    /// Build the match key from these underscore-separated fields of the file name, in this order, instead of
+    /// aaaaaaaa_yyyymmdd_AXX; 0-based (SC is field 0), negative indices count from the end (e.g. 1,-2)
+    #[clap(long, value_name = "INDICES", value_delimiter = ',', allow_hyphen_values = true, conflicts_with = "key_regex")]
+    key_fields: Option<Vec<isize>>,
+    
+    /// Separator joining the --key-fields in the match key (default `_`)
+    #[clap(long, value_name = "SEP", requires = "key_fields")]
+    key_separator: Option<String>,
+    
+    /// Normalize a field of the file names on both sides before building the match key, as INDEX:pad=WIDTH
+    /// (zero-pad its digits) or INDEX:strip-zeros, with indices as in --key-fields (e.g. -2:pad=2 pairs A5 with A05);
+    /// can be repeated
+    #[clap(long, value_name = "RULE", allow_hyphen_values = true, conflicts_with = "key_regex")]
+    normalize_key_field: Vec<KeyFieldNormalization>,
+    
+    /// Regex the last field of file names must match in full instead of being `Z`, e.g. 'Z\d*' to also pair
+    /// `..._Z1` and `..._Z2` files; the field is not part of the match key
+    /// (lowercased first with --case-insensitive-names, so use e.g. '(?i)Z\d*')
+    #[clap(long, value_name = "REGEX", value_parser = anchored_regex)]
+    suffix_pattern: Option<Regex>,
+    
+    /// Compare only the first N lines after the header of each file (for quick sampling of huge files)
+    #[clap(long, value_name = "N")]
+    head: Option<usize>,
+    
+    /// Compare only about one line in N of each file, picked by a hash of the processed line so both files
+    /// keep the same logical lines (a statistical check of huge files; the report is marked non-exhaustive)
+    #[clap(long, value_name = "N")]
+    sample_rate: Option<NonZeroUsize>,
+    
+    /// Exit with status 1 when more than N pairs differ (differences are still reported either way)
+    #[clap(long, value_name = "N")]
+    fail_threshold: Option<usize>,
+    
+    /// Exit with status 1 when more than N lines differ in total across all pairs
+    /// (each line counts with its --line-weights weight)
+    #[clap(long, value_name = "N")]
+    fail_line_threshold: Option<f64>,
+    
+    /// Encode the severity of the drift in the exit code instead of pass/fail: 0 without differences,
+    /// 3 for fewer than N differing lines in total (weighted like --fail-line-threshold), 4 for N or more
+    /// (or files failing --require-encoding), 5 when pairs failed to compare or weren't compared
+    #[clap(long, value_name = "N", conflicts_with_all = ["fail_threshold", "fail_line_threshold"])]
+    minor_threshold: Option<f64>,
+    
+    /// File of pairs whose differences are accepted, one match key or short path (`<dir>/<file name>`)
+    /// per line; they are still reported, but only other differing pairs fail the run (or count toward
+    /// --fail-threshold and --fail-line-threshold when set)
+    #[clap(long, value_name = "FILE")]
+    accept_pairs: Option<PathBuf>,
+    
+    /// File of renamed values, one "<old> <new>" pair per line; old values are replaced by
+    /// their new names in both files before comparison, and rule usage is listed in the report
+    #[clap(long, value_name = "FILE")]
+    equivalence_map: Option<PathBuf>,
+    
+    /// Count the lines each ignore/normalization rule dropped or changed over the run, and list the counts
+    /// in the report and on the console; rules that never matched are flagged. Every compared file is read
+    /// (the byte-identity and external diff shortcuts are off), so identical files count too
+    #[clap(long)]
+    report_rule_usage: bool,
+    
+    /// File of line weighting rules, one "<weight> <regex>" per line; a differing line counts with the
+    /// weight of the first matching rule (1 if none) in the similarity and --fail-line-threshold
+    #[clap(long, value_name = "FILE")]
+    line_weights: Option<PathBuf>,
+    
+    /// Expand tabs to spaces with tab stops every WIDTH characters before comparison
+    #[clap(long, value_name = "WIDTH")]
+    expand_tabs: Option<NonZeroUsize>,
+    
+    /// Rules file of per-report-type (AXX) option overrides, applied on top of the other options
+    #[clap(long, value_name = "FILE")]
+    type_rules: Option<PathBuf>,
+    
+    /// Stop starting new comparisons after this many seconds; pairs in progress finish and the
+    /// report covers the completed pairs, listing the rest as not compared
+    #[clap(long, value_name = "SECONDS")]
+    deadline: Option<u64>,
+    
+    /// Write periodic progress lines (pairs completed / total, elapsed time) to stderr instead of the
+    /// progress bar; this is the default when stderr isn't a terminal
+    #[clap(long)]
+    progress_log: bool,
+    
+    /// Seconds between two progress lines
+    #[clap(long, value_name = "SECONDS", default_value_t = 10)]
+    progress_interval: u64,
+    
+    /// Also write a progress line after every N completed pairs
+    #[clap(long, value_name = "N")]
+    progress_every: Option<NonZeroUsize>,
+    
+    /// For pairs with identical line sets, also check whether the line order differs and note it in the report
+    #[clap(long, conflicts_with = "ordered")]
+    detect_reorder: bool,
+    
+    /// List the pairs whose files have a different number of compared lines, even when their line sets
+    /// are equal or barely differ (catches truncated or duplicated records)
+    #[clap(long)]
+    flag_count_mismatch: bool,
+    
+    /// Warn about pairs where one file may have been cut off mid-write: it lacks the final newline the
+    /// other file has, or the files' sizes differ by more than --truncation-size-ratio
+    #[clap(long)]
+    check_truncation: bool,
+    
+    /// Ratio between the larger and the smaller file of a pair above which --check-truncation warns
+    #[clap(long, value_name = "RATIO", default_value_t = 2.0, requires = "check_truncation")]
+    truncation_size_ratio: f64,
+    
+    /// Print a `git diff --stat` style summary of the differing pairs (lines added/removed per pair)
+    #[clap(long)]
+    stat: bool,
+    
+    /// File listing the match keys (aaaaaaaa_yyyymmdd_AXX, one per line) to compare; other files are skipped
+    /// (not used by the tree subcommand, which pairs files by relative path)
+    #[clap(long, value_name = "FILE")]
+    keys_from: Option<PathBuf>,
+    
+    /// File listing the match keys (one per line) that should be present; after pairing, report which of them
+    /// produced a pair and which are missing from DIR1, DIR2 (with any --candidate-dir) or both (not used by subcommands)
+    #[clap(long, value_name = "FILE")]
+    expected_keys: Option<PathBuf>,
+    
+    /// Additional directory where the counterparts of DIR1 files may be (repeatable); each DIR1 file is
+    /// compared with the most similar same-key file across DIR2 and these directories
+    #[clap(long = "candidate-dir", value_name = "DIR", conflicts_with = "previous_version")]
+    candidate_dirs: Vec<PathBuf>,
+    
+    /// File listing the pairs to compare instead of pairing DIR1 and DIR2 by name, one
+    /// `path1<TAB>path2[<TAB>skip1<TAB>skip2]` per line; relative paths are resolved against DIR1 and DIR2,
+    /// and skip1/skip2 override the number of header lines skipped in each file of the pair
+    #[clap(long, value_name = "FILE", conflicts_with_all = ["previous_version", "candidate_dirs"])]
+    manifest: Option<PathBuf>,
+    
+    /// Width of the report's decorative rules, section rules being 5/8 of it
+    /// (default: the terminal width when stdout is a terminal, 80 otherwise)
+    #[clap(long, value_name = "COLUMNS")]
+    report_width: Option<NonZeroUsize>,
+    
+    /// Language of the report and console output (log lines and error messages stay in Chinese)
+    #[clap(long, value_enum, default_value_t = Lang::Zh)]
+    lang: Lang,
+    
+    /// Exclude pairs where either file is smaller than this many bytes (1 skips empty files)
+    /// (not used by the tree subcommand, which pairs files by relative path)
+    #[clap(long, value_name = "BYTES")]
+    min_file_size: Option<u64>,
+    
+    /// Exclude pairs where either file is larger than this many bytes
+    /// (not used by the tree subcommand, which pairs files by relative path)
+    #[clap(long, value_name = "BYTES")]
+    max_file_size: Option<u64>,
+    
+    /// Only compare pairs where either file was modified since the last successful incremental run, as recorded
+    /// in the state file (the first run compares every pair); the state file is updated when no pair fails
+    /// (not used by the tree subcommand or with --manifest)
+    #[clap(long)]
+    incremental: bool,
+    
+    /// With --incremental, compare every pair regardless of the state file, still updating it
+    #[clap(long, requires = "incremental")]
+    full: bool,
+    
+    /// State file recording when the last successful incremental run started
+    #[clap(long, value_name = "PATH", default_value = ".tbcompare_state")]
+    state_file: PathBuf,
+    
+    /// Record a hash of each compared file's raw bytes in the report and NDJSON output:
+    /// sha256 (default when no value is given), sha512 or sha1
+    #[clap(long, value_name = "ALGORITHM", num_args = 0..=1, default_missing_value = "sha256")]
+    content_hash: Option<HashAlgorithm>,
+    
+    /// In the report, show each line only in the first file next to its closest line only in the second
+    /// file as an `old → new` modification when their similarity is at least THRESHOLD (0.0-1.0)
+    #[clap(long, value_name = "THRESHOLD")]
+    merge_modifications: Option<f64>,
+    
+    /// Show each merged modification as a single line with its changed spans marked as `[-old-]{+new+}`,
+    /// diffing characters (char) or words (word)
+    #[clap(long, value_name = "UNIT", requires = "merge_modifications")]
+    inline_diff: Option<DiffGranularity>,
+    
+    /// Only compare the lines of this section: those between its `[NAME]` marker line and the next marker line
+    #[clap(long, value_name = "NAME")]
+    section: Option<String>,
+    
+    /// Assert that all files of a directory share one encoding: it is detected once per directory, from
+    /// its first file in sorted order, and reused instead of per file (directories mixing encodings are
+    /// then decoded incorrectly)
+    #[clap(long)]
+    uniform_encoding: bool,
+    
+    /// Only compare each file up to the first line (after the header, trimmed) matching this regex,
+    /// e.g. '^=== END ===$'; that line and everything after it are ignored
+    #[clap(long, value_name = "REGEX")]
+    truncate_at: Option<Regex>,
+    
+    /// File of `<file name or match key> <encoding label>` lines pinning the encoding of the listed files;
+    /// other files are detected as usual
+    #[clap(long, value_name = "FILE")]
+    encoding_map: Option<PathBuf>,
+    
+    /// Append the run and its per-pair results to this SQLite database, creating its tables if needed
+    /// (requires a build with the `sqlite` feature)
+    #[clap(long, value_name = "DB")]
+    sqlite_out: Option<PathBuf>,
+    
+    /// Publish each pair's result as it completes to the clients connected to this address, as
+    /// length-prefixed JSON messages: a TCP address such as 127.0.0.1:7878, or unix:<path> for a Unix socket
+    /// (requires a build with the `publish` feature)
+    #[clap(long, value_name = "ADDR")]
+    publish_socket: Option<String>,
+    
+    /// Remove this thousands separator from numbers before comparison, so `1,234.56` equals `1234.56`
+    /// (only between a digit and a group of three digits)
+    #[clap(long, value_name = "CHAR")]
+    strip_thousands_sep: Option<char>,
+    
+    /// Report the lines that appear more than once within each compared file
+    /// (the set-based comparison otherwise hides such duplicates)
+    #[clap(long)]
+    report_intra_file_dups: bool,
+    
+    /// How files of DIR1 and DIR2 are paired: pattern (same aaaaaaaa_yyyymmdd_AXX key), exact-name
+    /// (same file name) or position (Nth file of each directory sorted by name, hidden files and ~ backups left out)
+    #[clap(long, value_name = "STRATEGY", default_value_t = MatchBy::Pattern)]
+    match_by: MatchBy,
+    
+    /// Write each differing pair's details to <file1 stem>__<file2 stem>.<pair id>.txt in this directory instead
+    /// of the report, which then serves as the index and summary (existing files are not overwritten)
+    #[clap(long, value_name = "DIR")]
+    split_output_dir: Option<PathBuf>,
+    
+    /// Drop lines shorter than N characters (after trimming) before comparison, e.g. separator lines
+    #[clap(long, value_name = "N")]
+    min_line_length: Option<usize>,
+    
+    /// Split each line on this delimiter and sort its fields before comparison, so lines holding the
+    /// same fields in a different order (`a|b|c` and `c|a|b`) are equal; differing lines are reported as-is
+    #[clap(long, value_name = "DELIM")]
+    sort_fields: Option<String>,
+    
+    /// Split each line on this delimiter and drop its empty fields before comparison, so a field left empty
+    /// by one system and omitted by the other (`a||c` and `a|c`, `a|b|` and `a|b`) is equal; lines are reported as-is
+    #[clap(long, value_name = "DELIM")]
+    drop_empty_fields: Option<String>,
+    
+    /// Treat each line as a JSON value, ignoring key order and whitespace within it (no header line is skipped
+    /// by default)
+    #[clap(long)]
+    json_lines: bool,
+    
+    /// With --json-lines, match the differing records of each pair by this top-level field and report
+    /// the fields that diverge
+    #[clap(long, value_name = "FIELD", requires = "json_lines", conflicts_with = "merge_modifications")]
+    json_key: Option<String>,
+    
+    /// Compare the number in this column of fixed-width records by value, ignoring leading zeros and padding
+    /// (`00042` equals `   42`); START is the 1-based character position (repeatable)
+    #[clap(long = "numeric-column", value_name = "START:WIDTH")]
+    numeric_columns: Vec<FixedColumn>,
+    
+    /// Report the lines that only differ in trailing whitespace separately, as low-severity findings
+    /// (lines are still compared trimmed; not used with --ordered)
+    #[clap(long)]
+    report_trailing_whitespace: bool,
+    
+    /// Only report the differing pairs whose similarity (0.0-1.0, see --similarity-metric) is within
+    /// LOW..=HIGH; the others are only counted as "too different" or "near identical"
+    #[clap(long, num_args = 2, value_names = ["LOW", "HIGH"], allow_negative_numbers = true)]
+    similarity_range: Option<Vec<f64>>,
+    
+    /// Detect each file's header length from where its lines settle into a consistent delimiter count
+    /// (logged at info level), skipping the usual single line when detection is inconclusive;
+    /// header counts from --header-lines, --header-lines1/2, --type-rules or --manifest take precedence
+    #[clap(long)]
+    auto_header: bool,
+    
+    /// Number of header lines skipped at the top of every file, instead of the usual single line (none with --json-lines)
+    #[clap(long, value_name = "N")]
+    header_lines: Option<usize>,
+    
+    /// Header lines skipped in the files of the first directory (overrides --header-lines for that side;
+    /// without --header-lines, the other side skips the usual single line)
+    #[clap(long, value_name = "N")]
+    header_lines1: Option<usize>,
+    
+    /// Header lines skipped in the files of the second directory (overrides --header-lines for that side;
+    /// without --header-lines, the other side skips the usual single line)
+    #[clap(long, value_name = "N")]
+    header_lines2: Option<usize>,
+    
+    /// Treat the first line matching this regex as the header, skipping it and everything before it;
+    /// files without a matching line are compared in full unless --require-header is given
+    #[clap(long, value_name = "REGEX", conflicts_with_all = ["auto_header", "header_lines", "header_lines1", "header_lines2"])]
+    header_regex: Option<Regex>,
+    
+    /// Fail the files that have no line matching --header-regex instead of comparing all their lines
+    #[clap(long, requires = "header_regex")]
+    require_header: bool,
+    
+    /// Encoding every file must be in (e.g. GBK), checked over the whole file; other files are listed as encoding errors,
+    /// separately from content differences, and make the run exit with code 1 (ASCII-only files conform)
+    #[clap(long, value_name = "ENCODING")]
+    require_encoding: Option<String>,
+    
+    /// Estimate each pair's peak comparison memory (processed lines plus line sets, not a true RSS
+    /// measurement) and list the pairs with the highest estimates in the report
+    #[clap(long)]
+    profile: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Compare two directory trees recursively: report the relative paths only in one tree,
+    /// then compare the contents of the files present in both (options go before `tree`)
+    Tree {
+        /// Root of the first tree
+        #[clap(value_name = "DIR1")]
+        dir1: PathBuf,
+        
+        /// Root of the second tree
+        #[clap(value_name = "DIR2")]
+        dir2: PathBuf,
+    },
+    /// Explain how a file of DIR1 is paired: its parsed name fields, its match key, and its counterpart
+    /// in DIR2 or the reason it has none (pairing options go before `explain-pairing`)
+    ExplainPairing {
+        /// First directory path
+        #[clap(value_name = "DIR1")]
+        dir1: PathBuf,
+        
+        /// Second directory path
+        #[clap(value_name = "DIR2")]
+        dir2: PathBuf,
+        
+        /// Name of the file in DIR1
+        #[clap(value_name = "FILENAME")]
+        file_name: String,
+    },
+    /// Examine the file names of one or more directories (e.g. DIR1 and DIR2) and suggest a --key-regex
+    /// for names that don't follow the built-in pattern
+    SuggestPattern {
+        /// Directories whose file names are examined
+        #[clap(value_name = "DIR", required = true)]
+        dirs: Vec<PathBuf>,
+    },
+    /// Merge the --stream-ndjson results of several runs (e.g. one per report type) into one report,
+    /// written to --output or printed; a pair found in several streams is kept once, from the last one
+    MergeReports {
+        /// NDJSON result streams written by --stream-ndjson
+        #[clap(value_name = "STREAM", required = true)]
+        streams: Vec<PathBuf>,
+        
+        /// Format of the merged report
+        #[clap(long, value_enum, default_value_t = MergeFormat::Text)]
+        format: MergeFormat,
+    },
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() -> Result<ExitCode, Box<dyn std::error::Error>> {
     env_logger::init();
     
+    let started = Instant::now();
+    let run_started_at = SystemTime::now();
     let args = Args::parse();
+    CONSOLE_ON_STDERR.store(args.stream_ndjson.as_deref() == Some(Path::new("-")), AtomicOrdering::Relaxed);
+    if args.command.is_some() && args.previous_version {
+        Args::command()
+            .error(ErrorKind::ArgumentConflict, "--previous-version 不能与子命令同时使用")
+            .exit();
+    }
+    if args.merge_modifications.is_some_and(|threshold| !(0.0..=1.0).contains(&threshold)) {
+        Args::command()
+            .error(ErrorKind::ValueValidation, "--merge-modifications 的阈值必须在 0.0 到 1.0 之间")
+            .exit();
+    }
+    if let Some(range) = &args.similarity_range
+        && !(range.iter().all(|bound| (0.0..=1.0).contains(bound)) && range[0] <= range[1]) {
+        Args::command()
+            .error(ErrorKind::ValueValidation, "--similarity-range 的上下限必须在 0.0 到 1.0 之间，且下限不大于上限")
+            .exit();
+    }
+    let required_encoding = match &args.require_encoding {
+        Some(label) => match Encoding::for_label(label.as_bytes()) {
+            Some(encoding) => Some(encoding),
+            None => Args::command()
+                .error(ErrorKind::InvalidValue, format!("--require-encoding 的编码名称 {:?} 无效 (如 gbk、utf-8)", label))
+                .exit(),
+        },
+        None => None,
+    };
+    if !(args.truncation_size_ratio.is_finite() && args.truncation_size_ratio >= 1.0) {
+        Args::command()
+            .error(ErrorKind::ValueValidation, "--truncation-size-ratio 必须是大于或等于 1.0 的有限数")
+            .exit();
+    }
+    if args.sort_fields.as_deref() == Some("") {
+        Args::command()
+            .error(ErrorKind::ValueValidation, "--sort-fields 的分隔符不能为空")
+            .exit();
+    }
+    if args.drop_empty_fields.as_deref() == Some("") {
+        Args::command()
+            .error(ErrorKind::ValueValidation, "--drop-empty-fields 的分隔符不能为空")
+            .exit();
+    }
+    if cfg!(not(feature = "publish")) && args.publish_socket.is_some() {
+        Args::command()
+            .error(ErrorKind::InvalidValue, "--publish-socket 需要启用 publish 功能编译 (cargo build --features publish)")
+            .exit();
+    }
+    if cfg!(not(feature = "sqlite")) && args.sqlite_out.is_some() {
+        Args::command()
+            .error(ErrorKind::InvalidValue, "--sqlite-out 需要启用 sqlite 功能编译 (cargo build --features sqlite)")
+            .exit();
+    }
+    if args.command.is_some() && args.manifest.is_some() {
+        Args::command()
+            .error(ErrorKind::ArgumentConflict, "--manifest 不能与子命令同时使用")
+            .exit();
+    }
+    if args.command.is_some() && !args.candidate_dirs.is_empty() {
+        Args::command()
+            .error(ErrorKind::ArgumentConflict, "--candidate-dir 不能与子命令同时使用")
+            .exit();
+    }
+    
+    let messages = args.lang.messages();
+    
+    // Merging reports only reads the result streams
+    if let Some(Command::MergeReports { streams, format }) = &args.command {
+        let merged = merge_pair_streams(streams).context("无法合并结果流")?;
+        let content = match format {
+            MergeFormat::Text => merged_report(&merged, streams, messages),
+            MergeFormat::Json => merged_report_json(&merged, streams),
+        };
+        match &args.output {
+            Some(output_path) => {
+                std::fs::write(output_path, content)
+                    .with_context(|| format!("无法写入报告文件: {:?}", output_path))?;
+                println!("{}: {:?}", messages.report_saved, output_path);
+            }
+            None => print!("{}", content),
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+    
+    // Directories being compared; DIR2 is absent in --previous-version mode
+    let (dir1, dir2) = match &args.command {
+        Some(Command::Tree { dir1, dir2 } | Command::ExplainPairing { dir1, dir2, .. }) => (dir1.clone(), Some(dir2.clone())),
+        Some(Command::SuggestPattern { dirs }) => (dirs[0].clone(), dirs.get(1).cloned()),
+        Some(Command::MergeReports { .. }) => unreachable!("merge-reports returns before directories are used"),
+        None => (args.dir1.clone().context("缺少 DIR1 参数")?, args.dir2.clone()),
+    };
     
     // Set number of threads for rayon
     rayon::ThreadPoolBuilder::new().num_threads(args.threads).build_global()?;
     
-    info!("开始比较目录 {:?} 和 {:?}，使用 {} 个线程", 
-          args.dir1, args.dir2, args.threads);
+    // Description of what is being compared, used in logs and the report
+    let compared_dirs = match (&args.command, &dir2) {
+        (Some(Command::Tree { .. }), Some(dir2)) => (messages.dirs_tree)(&dir1, dir2),
+        (_, Some(dir2)) if let Some(manifest) = &args.manifest => (messages.dirs_manifest)(manifest, &dir1, dir2),
+        (_, Some(dir2)) if !args.candidate_dirs.is_empty() => {
+            let dir2s: Vec<_> = std::iter::once(dir2).chain(&args.candidate_dirs).map(|dir| format!("{:?}", dir)).collect();
+            (messages.dirs_candidates)(&dir1, &dir2s.join(messages.list_separator))
+        }
+        (_, Some(dir2)) => (messages.dirs_pair)(&dir1, dir2),
+        (_, None) => (messages.dirs_versions)(&dir1),
+    };
     
-    let file_pairs = generate_file_pairs(&args.dir1, &args.dir2)
-        .context("生成文件对失败")?;
+    info!("开始比较目录 {}，使用 {} 个线程", compared_dirs, args.threads);
     
-    if file_pairs.is_empty() {
-        println!("在目录间未找到匹配的文件对。");
-        return Ok(());
+    if let Some(format) = &args.strip_trailing_timestamp {
+        timestamp_format_width(format)?;
+    }
+    if let Some(locale) = &args.collation
+        && let Err(e) = check_collation(locale) {
+        Args::command()
+            .error(ErrorKind::ValueValidation, format!("--collation 的区域设置不可用: {:#}", e))
+            .exit();
+    }
+    
+    let exclude_lines = match &args.exclude_lines {
+        Some(path) => load_line_set(path)
+            .with_context(|| format!("无法读取排除行文件: {:?}", path))?,
+        None => HashSet::new(),
+    };
+    
+    let accepted_pairs = match &args.accept_pairs {
+        Some(path) => load_line_set(path)
+            .with_context(|| format!("无法读取接受列表文件: {:?}", path))?,
+        None => HashSet::new(),
+    };
+    
+    let equivalence_map = match &args.equivalence_map {
+        Some(path) => Some(Arc::new(EquivalenceMap::load(path)
+            .with_context(|| format!("无法读取等价映射文件: {:?}", path))?)),
+        None => None,
+    };
+    
+    let rule_usage = args.report_rule_usage.then(|| Arc::new(RuleUsage::new()));
+    
+    let line_weights = match &args.line_weights {
+        Some(path) => LineWeights::load(path)
+            .with_context(|| format!("无法读取行权重文件: {:?}", path))?,
+        None => LineWeights::default(),
+    };
+    
+    let encoding_map = match &args.encoding_map {
+        Some(path) => Some(Arc::new(EncodingMap::load(path)
+            .with_context(|| format!("无法读取编码映射文件: {:?}", path))?)),
+        None => None,
+    };
+    
+    let type_rules = match &args.type_rules {
+        Some(path) => Some(TypeRules::load(path)
+            .with_context(|| format!("无法读取类型规则文件: {:?}", path))?),
+        None => None,
+    };
+    
+    // A side without a count of its own skips the usual default when the other side has one
+    let default_header_lines = if args.json_lines { 0 } else { 1 };
+    let header_lines1 = args.header_lines1.or(args.header_lines);
+    let header_lines2 = args.header_lines2.or(args.header_lines);
+    let first_header_lines = header_lines1.or(header_lines2.map(|_| default_header_lines));
+    let second_header_lines = header_lines2.or(header_lines1.map(|_| default_header_lines))
+        .filter(|&header_lines| Some(header_lines) != first_header_lines);
+    
+    let compare_options = CompareOptions {
+        process: ProcessOptions {
+            preprocess_cmd: args.preprocess_cmd.clone(),
+            transform_cmd: args.transform1_cmd.clone(),
+            strip_trailing_timestamp: args.strip_trailing_timestamp.clone(),
+            numeric_sort: args.numeric_sort,
+            collation: args.collation.clone(),
+            exclude_lines,
+            head: args.head,
+            equivalence_map: equivalence_map.clone(),
+            expand_tabs: args.expand_tabs,
+            sort_command: args.sort_command.clone(),
+            section: args.section.clone(),
+            encoding_cache: args.uniform_encoding.then(|| Arc::new(EncodingCache::new())),
+            truncate_at: args.truncate_at.clone(),
+            encoding_map,
+            thousands_separator: args.strip_thousands_sep,
+            min_line_length: args.min_line_length,
+            sort_fields: args.sort_fields.clone(),
+            drop_empty_fields: args.drop_empty_fields.clone(),
+            json_lines: args.json_lines,
+            numeric_columns: args.numeric_columns.clone(),
+            track_trailing_whitespace: args.report_trailing_whitespace,
+            header_lines: first_header_lines,
+            auto_header: args.auto_header,
+            header_regex: args.header_regex.clone(),
+            require_header: args.require_header,
+            key_only: args.key_only.then(|| args.column_delimiter.clone()),
+            record_separator: args.record_separator.clone(),
+            record_length: args.record_length,
+            sample_rate: args.sample_rate,
+            rule_usage: rule_usage.clone(),
+            ..ProcessOptions::default()
+        },
+        external_diff_min_bytes: args.external_diff_min_bytes,
+        ordered: args.ordered,
+        sort_key: args.sort_key_field.map(|field| SortKey {
+            field: field.get(),
+            delimiter: args.column_delimiter.clone(),
+        }),
+        line_weights,
+        diff_command: args.diff_command.clone(),
+        byte_identity: args.byte_identity,
+        second_header_lines,
+        second_transform_cmd: args.transform2_cmd.clone(),
+    };
+    
+    // Lines shown in the report have their private fields masked
+    let redaction = ColumnRedaction {
+        columns: args.redact_columns.iter().flatten().map(|column| column.get()).collect(),
+        delimiter: args.column_delimiter.clone(),
+    };
+    
+    let keys = args.keys_from.as_deref()
+        .map(|path| load_match_keys(path, args.case_insensitive_names))
+        .transpose()?;
+    
+    // Pairs unchanged since the last successful incremental run are skipped
+    let modified_since = if args.incremental && !args.full {
+        read_last_run(&args.state_file)?
+    } else {
+        None
+    };
+    match modified_since {
+        Some(last_run) => console!("{}", (messages.incremental_since)(
+            &DateTime::<Local>::from(last_run).format("%Y-%m-%d %H:%M:%S").to_string())),
+        None if args.incremental && !args.full => console!("{}", (messages.incremental_no_state)(&args.state_file)),
+        None => {}
+    }
+    
+    // The anchored regex is valid, so it still is with the case-insensitive flag in front
+    let key_regex = match &args.key_regex {
+        Some(key_regex) if args.case_insensitive_names => Some(Regex::new(&format!("(?i){}", key_regex.as_str()))
+            .with_context(|| format!("无效的 --key-regex: {}", key_regex))?),
+        key_regex => key_regex.clone(),
+    };
+    let pairing_options = PairingOptions {
+        case_insensitive: args.case_insensitive_names,
+        key_regex,
+        keys,
+        min_file_size: args.min_file_size,
+        max_file_size: args.max_file_size,
+        match_by: args.match_by,
+        suffix_pattern: args.suffix_pattern.clone(),
+        modified_since,
+        key_fields: args.key_fields.clone(),
+        key_separator: args.key_separator.clone(),
+        key_normalizations: args.normalize_key_field.clone(),
+    };
+    
+    if let Some(Command::SuggestPattern { dirs }) = &args.command {
+        let suggestion = suggest_pattern(dirs).context("无法推断文件名模式")?;
+        print_pattern_suggestion(&suggestion, messages);
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Some(Command::ExplainPairing { dir2, file_name, .. }) = &args.command {
+        let explanation = explain_pairing(&dir1, dir2, file_name, &pairing_options)
+            .with_context(|| format!("无法解释 {} 的配对", file_name))?;
+        print_pairing_explanation(&explanation, args.match_by, messages);
+        return Ok(ExitCode::SUCCESS);
+    }
+    
+    // In tree mode, the files present in both trees are paired by relative path
+    let tree_diff = match (&args.command, &dir2) {
+        (Some(Command::Tree { .. }), Some(dir2)) => Some(diff_trees(&dir1, dir2).context("比较目录结构失败")?),
+        _ => None,
+    };
+    
+    // With candidate directories, each DIR1 file may have a counterpart in several directories
+    let candidate_pairs = match &dir2 {
+        Some(dir2) if !args.candidate_dirs.is_empty() => {
+            let dir2_paths: Vec<PathBuf> = std::iter::once(dir2.clone()).chain(args.candidate_dirs.iter().cloned()).collect();
+            generate_candidate_pairs(&dir1, &dir2_paths, &pairing_options).context("生成文件对失败")?
+        }
+        _ => Vec::new(),
+    };
+    
+    // Pairs listed in the manifest, with their header line overrides
+    let manifest_pairs = match (&args.manifest, &dir2) {
+        (Some(manifest), Some(dir2)) => load_manifest(manifest, &dir1, dir2)
+            .with_context(|| format!("无法读取文件对清单: {:?}", manifest))?,
+        (Some(_), None) => Args::command()
+            .error(ErrorKind::MissingRequiredArgument, "--manifest 需要同时指定 DIR1 和 DIR2")
+            .exit(),
+        (None, _) => Vec::new(),
+    };
+    let header_overrides: HashMap<(PathBuf, PathBuf), (usize, usize)> = manifest_pairs.iter()
+        .filter_map(|pair| Some(((pair.file1_path.clone(), pair.file2_path.clone()), pair.header_lines?)))
+        .collect();
+    
+    let mut file_pairs = match (&tree_diff, &dir2) {
+        (Some(tree_diff), Some(dir2)) => Ok(tree_diff.file_pairs(&dir1, dir2)),
+        // The first candidate stands in until the best match is picked below
+        (_, Some(_)) if !args.candidate_dirs.is_empty() => Ok(candidate_pairs.iter()
+            .map(|(file1_path, candidates)| (file1_path.clone(), candidates[0].clone()))
+            .collect()),
+        (_, Some(_)) if args.manifest.is_some() => Ok(manifest_pairs.into_iter()
+            .map(|pair| (pair.file1_path, pair.file2_path))
+            .collect()),
+        (_, Some(dir2)) => generate_file_pairs_with_options(&dir1, dir2, &pairing_options),
+        (_, None) => generate_version_pairs(&dir1, &pairing_options),
+    }.context("生成文件对失败")?;
+    
+    // Files in both directories whose names don't fit the pattern are never paired; list them so they're noticed
+    let unpaired_common_files = match (&args.command, &dir2) {
+        (None, Some(dir2)) if args.match_by == MatchBy::Pattern && args.manifest.is_none() =>
+            find_unpaired_common_files(&dir1, dir2, &pairing_options).context("查找同名但未配对的文件失败")?,
+        _ => Vec::new(),
+    };
+    if !unpaired_common_files.is_empty() {
+        console!("{}", (messages.unpaired_notice)(unpaired_common_files.len()));
+        for file in &unpaired_common_files {
+            console!("  {}", file.file_name);
+        }
+    }
+    
+    // Expected keys that didn't produce a pair tell whether every expected file was received
+    let key_coverage = match (&args.expected_keys, &args.command, &dir2) {
+        (Some(path), None, Some(dir2)) => {
+            let expected_keys = load_match_keys(path, args.case_insensitive_names)?;
+            let dir2_paths: Vec<&Path> = std::iter::once(dir2).chain(&args.candidate_dirs).map(PathBuf::as_path).collect();
+            Some(check_key_coverage(dir1.as_path(), &dir2_paths, &file_pairs, &expected_keys, &pairing_options)
+                .context("检查预期匹配键的覆盖情况失败")?)
+        }
+        (Some(_), None, None) => Args::command()
+            .error(ErrorKind::MissingRequiredArgument, "--expected-keys 需要同时指定 DIR1 和 DIR2")
+            .exit(),
+        _ => None,
+    };
+    if let Some(coverage) = &key_coverage {
+        print_key_coverage(coverage, messages);
+    }
+    
+    // In tree mode the structural differences are still reported when no file is in both trees
+    if file_pairs.is_empty() && tree_diff.is_none() {
+        if modified_since.is_some() {
+            console!("{}", messages.no_modified_pairs);
+            return Ok(ExitCode::SUCCESS);
+        }
+        console!("{}", messages.no_pairs_found);
+        return Ok(ExitCode::SUCCESS);
+    }
+    
+    // Comparing a directory with itself (or a copy) finds nothing; catch it before the long run
+    // (unless only one side is transformed, so identical bytes still compare differently)
+    if let Some(dir2) = &dir2
+        && !args.force
+        && args.transform1_cmd == args.transform2_cmd {
+        if is_same_file(&dir1, dir2).unwrap_or(false) {
+            eprintln!("{}", messages.same_dirs);
+            return Ok(ExitCode::FAILURE);
+        }
+        match looks_duplicated(&file_pairs, args.duplicate_check_samples) {
+            Ok(true) => {
+                eprintln!("{}", (messages.duplicate_dirs)(args.duplicate_check_samples.min(file_pairs.len())));
+                return Ok(ExitCode::FAILURE);
+            }
+            Ok(false) => {}
+            Err(e) => warn!("抽查文件对是否完全相同失败: {:#}", e),
+        }
+    }
+    
+    // Create the report file before comparing, so an unwritable destination fails fast
+    let timestamp_filename = Local::now().format("%Y%m%d_%H%M%S").to_string();
+    let report_path = match &args.output {
+        Some(output_path) if output_path.extension().is_none() => {
+            // Add timestamp to filename if no extension is provided
+            let stem = output_path.file_stem().unwrap_or_default().to_string_lossy();
+            let parent = output_path.parent().unwrap_or_else(|| std::path::Path::new("."));
+            parent.join(format!("{}_{}.txt", stem, timestamp_filename))
+        }
+        Some(output_path) => output_path.clone(),
+        None => match &args.output_template {
+            Some(template) => {
+                let dir_name = |dir: &Path| dir.file_name().unwrap_or_default().to_string_lossy().into_owned();
+                // DIR2 is absent in --previous-version mode
+                let dir2_name = dir2.as_deref().map(dir_name).unwrap_or_default();
+                PathBuf::from(render_output_template(template, &[
+                    ("dir1", &dir_name(&dir1)),
+                    ("dir2", &dir2_name),
+                    ("date", &timestamp_filename),
+                    ("ext", "txt"),
+                ])?)
+            }
+            // Default report name with timestamp
+            None => PathBuf::from(format!("comparison_report_{}.txt", timestamp_filename)),
+        },
+    };
+    let mut report_file = File::create(&report_path)
+        .with_context(|| format!("无法创建报告文件: {:?}", report_path))?;
+    
+    if let Some(patch_dir) = &args.patch_dir {
+        std::fs::create_dir_all(patch_dir)
+            .with_context(|| format!("无法创建补丁目录: {:?}", patch_dir))?;
+    }
+    
+    if let Some(conflict_dir) = &args.conflict_dir {
+        std::fs::create_dir_all(conflict_dir)
+            .with_context(|| format!("无法创建冲突标记文件目录: {:?}", conflict_dir))?;
+    }
+    
+    if let Some(split_dir) = &args.split_output_dir {
+        std::fs::create_dir_all(split_dir)
+            .with_context(|| format!("无法创建差异详情目录: {:?}", split_dir))?;
+    }
+    
+    if let Some(dump_dir) = &args.dump_processed {
+        std::fs::create_dir_all(dump_dir)
+            .with_context(|| format!("无法创建处理结果目录: {:?}", dump_dir))?;
+    }
+    
+    // Options refined by the type rules, for each report type among the pairs
+    let mut type_options: HashMap<String, CompareOptions> = HashMap::new();
+    if let Some(type_rules) = &type_rules {
+        for (file1_path, _) in &file_pairs {
+            if let Some(parsed) = parse_filename_with_options(file1_path, &pairing_options) {
+                type_options.entry(parsed.report_type)
+                    .or_insert_with_key(|report_type| type_rules.options_for(report_type, &compare_options));
+            }
+        }
+    }
+    
+    let options_for = |file1_path: &Path| parse_filename_with_options(file1_path, &pairing_options)
+        .and_then(|parsed| type_options.get(&parsed.report_type))
+        .unwrap_or(&compare_options);
+    
+    // Pick the most similar candidate for the DIR1 files that have several
+    let best_matches: HashMap<PathBuf, BestMatch> = candidate_pairs
+        .par_iter()
+        .filter(|(_, candidates)| candidates.len() > 1)
+        .map(|(file1_path, candidates)| {
+            let best = pick_best_match(file1_path, candidates, options_for(file1_path), args.similarity_metric);
+            (file1_path.clone(), best)
+        })
+        .collect();
+    for (file1_path, file2_path) in &mut file_pairs {
+        if let Some(best) = best_matches.get(file1_path) {
+            file2_path.clone_from(&best.file2_path);
+        }
     }
     
     let file_pairs_count = file_pairs.len();
-    println!("找到 {} 个文件对进行比较。", file_pairs_count);
+    console!("{}", (messages.pairs_found)(file_pairs_count));
+    if let Some(sample_rate) = args.sample_rate {
+        console!("{}", (messages.scope_sample)(sample_rate.get()));
+    }
+    
+    // Bucket counts replace the line-level comparison and its report
+    if let Some(bucket_field) = args.bucket_field {
+        let key = SortKey { field: bucket_field.get(), delimiter: args.column_delimiter.clone() };
+        let counts: Vec<_> = file_pairs.par_iter()
+            .map(|(file1_path, file2_path)| {
+                let counts = compare_bucket_counts(file1_path, file2_path, options_for(file1_path), &key);
+                (file1_path.clone(), file2_path.clone(), counts)
+            })
+            .collect();
+        let rule_width = args.report_width.map_or(DEFAULT_REPORT_WIDTH, NonZeroUsize::get);
+        report_file.write_all(bucket_report(&counts, bucket_field.get(), rule_width, messages).as_bytes())
+            .with_context(|| format!("无法写入报告文件: {:?}", report_path))?;
+        console!("{}: {:?}", messages.report_saved, report_path);
+        return Ok(ExitCode::SUCCESS);
+    }
+    
+    // Weight progress by file size so the ETA reflects the actual amount of work
+    let file_pairs: Vec<_> = file_pairs
+        .into_iter()
+        .map(|(file1_path, file2_path)| {
+            let pair_bytes = file_size(&file1_path).unwrap_or(0) + file_size(&file2_path).unwrap_or(0);
+            (file1_path, file2_path, pair_bytes)
+        })
+        .collect();
+    let total_bytes: u64 = file_pairs.iter().map(|(_, _, pair_bytes)| pair_bytes).sum();
     
-    // Create a progress bar
-    let pb = ProgressBar::new(file_pairs_count as u64);
+    // Create a progress bar; outside a terminal the bar is hidden and progress lines are logged instead
+    let progress_log = (args.progress_log || !console::Term::stderr().is_term())
+        .then(|| ProgressLog::new(file_pairs_count, Duration::from_secs(args.progress_interval), args.progress_every, messages));
+    let pb = if progress_log.is_some() { ProgressBar::hidden() } else { ProgressBar::new(total_bytes) };
     pb.set_style(
         ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta}) {msg}")
+            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({binary_bytes_per_sec}, {eta}) {msg}")
             .unwrap()
             .progress_chars(">=-"),
     );
     
-    // Process file pairs in parallel
-    let results: Vec<_> = file_pairs
-        .into_par_iter()
-        .map(|(file1_path, file2_path)| {
-            let result = compare_files(&file1_path, &file2_path);
-            pb.inc(1);
-            (file1_path, file2_path, result)
-        })
-        .collect();
+    // Differing pairs are serialized by the workers and written by a single thread,
+    // through a bounded channel so a slow writer applies back-pressure instead of buffering everything
+    let (ndjson_sender, ndjson_writer) = match &args.stream_ndjson {
+        Some(path) => {
+            let (sender, writer) = spawn_ndjson_writer(path)?;
+            (Some(sender), Some(writer))
+        }
+        None => (None, None),
+    };
+    
+    // Subscribers receive each pair's result as soon as it completes
+    #[cfg(feature = "publish")]
+    let publisher = match &args.publish_socket {
+        Some(address) => {
+            let publisher = Publisher::bind(address)
+                .with_context(|| format!("无法监听发布地址: {}", address))?;
+            info!("比较结果发布在 {}", publisher.local_addr().map_or_else(|| address.clone(), |addr| addr.to_string()));
+            Some(publisher)
+        }
+        None => None,
+    };
+    #[cfg(feature = "publish")]
+    let completed_pairs = std::sync::atomic::AtomicUsize::new(0);
+    
+    let deadline = args.deadline.map(|seconds| started + Duration::from_secs(seconds));
+    // Bytes of the pairs actually compared, for the throughput figures
+    let compared_bytes = AtomicU64::new(0);
     
-    pb.finish_with_message("比较完成");
+    // Compares one pair; called in parallel
+    let compare_pair = |(file1_path, file2_path, pair_bytes): (PathBuf, PathBuf, u64)| -> PairResult {
+        // Past the deadline no new pair is started; pairs already in flight finish normally
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            pb.inc(pair_bytes);
+            return PairResult { file1_path, file2_path, result: Ok(None), same_file: false, not_compared: true, reordered: false,
+                                content_hashes: None, encoding_mismatches: Vec::new(), memory_estimate: 0, line_counts: None,
+                                truncation: None };
+        }
+        
+        // Files that can't be read are reported by the comparison itself
+        let encoding_mismatches: Vec<_> = required_encoding.iter()
+            .flat_map(|&expected| [&file1_path, &file2_path].map(|file_path| {
+                check_encoding(file_path, expected)
+                    .inspect_err(|e| warn!("检查 {} 的编码失败: {:#}", file_path.display(), e))
+                    .ok()
+                    .flatten()
+                    .map(|detected| (file_path.clone(), detected))
+            }))
+            .flatten()
+            .collect();
+        
+        let same_file = args.skip_same_file && is_same_file(&file1_path, &file2_path).unwrap_or(false);
+        if same_file {
+            info!("{} 和 {} 指向同一文件，跳过比较", file1_path.display(), file2_path.display());
+            if let Some(sender) = &ndjson_sender {
+                let _ = sender.send(stream_record(&file1_path, &file2_path, "same_file").to_string());
+            }
+            pb.inc(pair_bytes);
+            return PairResult { file1_path, file2_path, result: Ok(None), same_file, not_compared: false, reordered: false,
+                                content_hashes: None, encoding_mismatches, memory_estimate: 0, line_counts: None,
+                                truncation: None };
+        }
+        
+        // Only metadata and the last bytes are read, before the content comparison
+        let truncation = args.check_truncation
+            .then(|| check_truncation(&file1_path, &file2_path, args.truncation_size_ratio))
+            .and_then(|signs| signs.inspect_err(|e| warn!("检查 {} 和 {} 是否被截断失败: {:#}", file1_path.display(), file2_path.display(), e)).ok())
+            .flatten();
+        
+        let mut options = options_for(&file1_path);
+        let overridden;
+        if let Some(&(header_lines1, header_lines2)) = header_overrides.get(&(file1_path.clone(), file2_path.clone())) {
+            overridden = CompareOptions {
+                process: ProcessOptions { header_lines: Some(header_lines1), ..options.process.clone() },
+                second_header_lines: Some(header_lines2),
+                ..options.clone()
+            };
+            options = &overridden;
+        }
+        if let Some(dump_dir) = &args.dump_processed {
+            for (side, file_path, side_options) in [(1, &file1_path, options), (2, &file2_path, &*options.for_second_file())] {
+                let dump_path = pair_output_path(&file1_path, &file2_path, dump_dir, &format!("{}.txt", side));
+                if let Err(e) = write_processed_lines(file_path, side_options, &dump_path) {
+                    error!("写入 {} 的处理结果失败: {:#}", file_path.display(), e);
+                }
+            }
+        }
+        let content_hashes = args.content_hash.and_then(|algorithm| {
+            let hashes = content_hash(&file1_path, algorithm)
+                .and_then(|hash1| Ok((hash1, content_hash(&file2_path, algorithm)?)));
+            hashes.inspect_err(|e| error!("计算 {} 和 {} 的哈希失败: {:#}", file1_path.display(), file2_path.display(), e)).ok()
+        });
+        // Lines are masked once, as soon as the pair is compared, so no output can show them unmasked
+        let (result, memory_estimate, line_counts) = match compare_files_with_stats(&file1_path, &file2_path, options) {
+            Ok((differences, stats)) => (Ok(differences.map(|diff| redaction.redact_differences(diff))), stats.memory_estimate, stats.line_counts),
+            Err(e) => (Err(e), 0, None),
+        };
+        compared_bytes.fetch_add(pair_bytes, AtomicOrdering::Relaxed);
+        if let (Some(patch_dir), Ok(Some(_))) = (&args.patch_dir, &result)
+            && let Err(e) = write_patch_file(&file1_path, &file2_path, patch_dir) {
+            error!("为 {} 和 {} 生成补丁失败: {:#}",
+                   file1_path.display(), file2_path.display(), e);
+        }
+        if let (Some(conflict_dir), Ok(Some(_))) = (&args.conflict_dir, &result)
+            && let Err(e) = write_conflict_file(&file1_path, &file2_path, conflict_dir) {
+            error!("为 {} 和 {} 生成冲突标记文件失败: {:#}",
+                   file1_path.display(), file2_path.display(), e);
+        }
+        if let Some(sender) = &ndjson_sender {
+            let mut record = match &result {
+                Ok(Some(diff)) => {
+                    let mut record = stream_record(&file1_path, &file2_path, "different");
+                    record["only_in_first"] = json!(diff.only_in_first);
+                    record["only_in_second"] = json!(diff.only_in_second);
+                    record["similarity"] = json!(diff.similarity(args.similarity_metric));
+                    record
+                }
+                Ok(None) => {
+                    let mut record = stream_record(&file1_path, &file2_path, "identical");
+                    record["similarity"] = json!(1.0);
+                    record
+                }
+                Err(e) => {
+                    let mut record = stream_record(&file1_path, &file2_path, "error");
+                    record["error"] = json!(format!("{:#}", e));
+                    record
+                }
+            };
+            if let (Some(algorithm), Some((hash1, hash2))) = (args.content_hash, &content_hashes) {
+                record["hash_algorithm"] = json!(algorithm.to_string());
+                record["file1_hash"] = json!(hash1);
+                record["file2_hash"] = json!(hash2);
+            }
+            // A send error means the writer thread failed; its error is reported when it is joined
+            let _ = sender.send(record.to_string());
+        }
+        // Identical line sets may still come from lines in a different order
+        let reordered = args.detect_reorder && matches!(result, Ok(None))
+            && is_reordered(&file1_path, &file2_path, options).unwrap_or_else(|e| {
+                warn!("检查 {} 和 {} 的行顺序失败: {:#}", file1_path.display(), file2_path.display(), e);
+                false
+            });
+        pb.inc(pair_bytes);
+        PairResult {
+            file1_path, file2_path, result, same_file, not_compared: false, reordered, content_hashes, encoding_mismatches,
+            memory_estimate, line_counts, truncation,
+        }
+    };
     
     // Generate report
     let mut report_content = String::new();
     let timestamp_display = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
-    let timestamp_filename = Local::now().format("%Y%m%d_%H%M%S").to_string();
+    
+    // Decorative rules follow the terminal width unless a width was given
+    let rule_width = args.report_width.map(NonZeroUsize::get)
+        .or_else(|| console::Term::stdout().size_checked().map(|(_, columns)| usize::from(columns)))
+        .unwrap_or(DEFAULT_REPORT_WIDTH);
+    let section_rule_width = (rule_width * 5 / 8).max(1);
     
     // Add header with decorative lines
-    report_content.push_str(&format!("{}\n", "=".repeat(80)));
-    report_content.push_str(&format!("{:^80}\n", "文件比较报告"));
-    report_content.push_str(&format!("{:^80}\n", format!("生成时间: {}", timestamp_display)));
-    report_content.push_str(&format!("{}\n\n", "=".repeat(80)));
+    report_content.push_str(&format!("{}\n", "=".repeat(rule_width)));
+    report_content.push_str(&format!("{:^rule_width$}\n", messages.report_title));
+    report_content.push_str(&format!("{:^rule_width$}\n", format!("{}: {}", messages.generated_at, timestamp_display)));
+    report_content.push_str(&format!("{}\n\n", "=".repeat(rule_width)));
     
     // Add comparison info section
-    report_content.push_str(&format!("{}\n", "-".repeat(50)));
-    report_content.push_str("比较信息\n");
-    report_content.push_str(&format!("{}\n", "-".repeat(50)));
-    report_content.push_str(&format!("比较目录: {:?} 和 {:?}\n", args.dir1, args.dir2));
-    report_content.push_str(&format!("文件对数量: {}\n\n", file_pairs_count));
+    report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+    report_content.push_str(&format!("{}\n", messages.comparison_info));
+    report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+    report_content.push_str(&format!("{}: {}\n", messages.compared_dirs, compared_dirs));
+    if args.match_by != MatchBy::Pattern && tree_diff.is_none() && dir2.is_some() {
+        report_content.push_str(&format!("{}: {}\n", messages.match_by, args.match_by));
+    }
+    if args.ordered {
+        match args.sort_key_field {
+            Some(field) => report_content.push_str(&format!("{}\n", (messages.ordered_by_field)(field.get()))),
+            None => report_content.push_str(&format!("{}\n", messages.ordered)),
+        }
+    }
+    if args.key_only {
+        report_content.push_str(&format!("{}\n", (messages.key_only)(&args.column_delimiter)));
+    }
+    if let Some(head) = args.head {
+        report_content.push_str(&format!("{}\n", (messages.scope_head)(head)));
+    }
+    if let Some(section) = &args.section {
+        report_content.push_str(&format!("{}\n", (messages.scope_section)(section)));
+    }
+    if let Some(truncate_at) = &args.truncate_at {
+        report_content.push_str(&format!("{}\n", (messages.scope_truncate_at)(truncate_at.as_str())));
+    }
+    if let Some(sample_rate) = args.sample_rate {
+        report_content.push_str(&format!("{}\n", (messages.scope_sample)(sample_rate.get())));
+    }
+    if let Some(last_run) = modified_since {
+        let last_run = DateTime::<Local>::from(last_run).format("%Y-%m-%d %H:%M:%S").to_string();
+        report_content.push_str(&format!("{}\n", (messages.scope_incremental)(&last_run)));
+    }
+    if let Some(range) = &args.similarity_range {
+        report_content.push_str(&format!("{}\n", (messages.report_range)(&args.similarity_metric.to_string(),
+                                                                           range[0] * 100.0, range[1] * 100.0)));
+    }
+    report_content.push_str(&format!("{}: {}\n", messages.pair_count, file_pairs_count));
+    
+    // The structural differences found in tree mode come before any content difference
+    let mut tree_section = String::new();
+    if let Some(tree_diff) = &tree_diff {
+        tree_section.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        tree_section.push_str(&format!("{}\n", messages.tree_section));
+        tree_section.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        tree_section.push_str(&format!("{}: {}\n", messages.in_both_trees, tree_diff.in_both.len()));
+        for (label, paths) in [(messages.only_in_first_dir, &tree_diff.only_in_first),
+                               (messages.only_in_second_dir, &tree_diff.only_in_second)] {
+            tree_section.push_str(&format!("{}: {}\n", label, paths.len()));
+            for path in paths {
+                tree_section.push_str(&format!("    • {}\n", path.display()));
+            }
+        }
+        tree_section.push('\n');
+    }
+    
+    // Differing pairs outside --similarity-range are counted but not reported
+    let only_in = if args.key_only { messages.keys_only_in } else { messages.only_in };
+    let in_similarity_range = |diff: &FileDifferences| args.similarity_range.as_ref()
+        .is_none_or(|range| (range[0]..=range[1]).contains(&diff.similarity(args.similarity_metric)));
+    
+    // A compact table of all reported differing pairs, numbered like the detailed sections
+    let overview = |results: &[PairResult]| -> String {
+        let mut overview = String::new();
+        let diff_summaries: Vec<_> = results.iter()
+            .filter_map(|pair| match &pair.result {
+                Ok(Some(diff)) if in_similarity_range(diff) => Some((pair, diff)),
+                _ => None,
+            })
+            .collect();
+        if !diff_summaries.is_empty() {
+            overview.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+            overview.push_str(&format!("{}\n", messages.overview_section));
+            overview.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+            for (index, (pair, diff)) in diff_summaries.iter().enumerate() {
+                overview.push_str(&format!("  #{:<4} [{}] {} ↔ {}  (-{} / +{})\n",
+                                                 index + 1, diff.pair_id,
+                                                 short_path(&pair.file1_path).display(),
+                                                 short_path(&pair.file2_path).display(),
+                                                 diff.only_in_first.len(), diff.only_in_second.len()));
+            }
+            overview.push('\n');
+        }
+        overview
+    };
     
     let mut diff_count = 0;
+    let mut reported_diff_count = 0;
+    let mut too_different_count = 0;
+    let mut near_identical_count = 0;
+    let mut whitespace_only_count = 0;
+    let mut diff_line_count = 0.0;
     let mut error_count = 0;
+    let mut errors_content = String::new();
+    let mut pivot_content = String::from("pair_id\tfile1\tfile2\tline\tin_first\tin_second\n");
+    let mut version_gap_warnings = Vec::new();
+    let mut same_file_pairs = Vec::new();
+    let mut accepted_diff_pairs = Vec::new();
+    let mut accepted_diff_line_count = 0.0;
+    let mut not_compared_pairs = Vec::new();
+    let mut reordered_pairs = Vec::new();
+    let mut count_mismatch_pairs = Vec::new();
+    let mut truncated_pairs = Vec::new();
+    let mut identical_pairs = Vec::new();
+    let mut encoding_errors = Vec::new();
+    let mut split_files = Vec::new();
+    let mut stat_entries = Vec::new();
     
-    // Process results
-    for (file1_path, file2_path, result) in results {
-        // 从第一个路径中提取父目录名和文件名
-        let file1_name = file1_path.file_name().unwrap_or_else(|| std::ffi::OsStr::new(""));
-        let parent1_name = file1_path.parent()
-            .and_then(|p| p.file_name())
-            .unwrap_or_else(|| std::ffi::OsStr::new(""));
-        let short_path1 = std::path::Path::new(parent1_name).join(file1_name);
-
-        // 对第二个路径执行同样的操作
-        let file2_name = file2_path.file_name().unwrap_or_else(|| std::ffi::OsStr::new(""));
-        let parent2_name = file2_path.parent()
-            .and_then(|p| p.file_name())
-            .unwrap_or_else(|| std::ffi::OsStr::new(""));
-        let short_path2 = std::path::Path::new(parent2_name).join(file2_name);
+    // Adds the section of one pair to the report, and its outcome to the counts
+    let mut add_pair_result = |pair: &PairResult, report_content: &mut String| {
+        let &PairResult { ref file1_path, ref file2_path, ref result, same_file, not_compared, reordered, ref content_hashes,
+                          ref encoding_mismatches, line_counts, truncation, .. } = pair;
+        let short_path1 = short_path(file1_path);
+        let short_path2 = short_path(file2_path);
+        
+        // Hash lines shown under the file names of the pair's section
+        let hash_lines = match (args.content_hash, &content_hashes) {
+            (Some(algorithm), Some((hash1, hash2))) =>
+                format!("{} {}: {}\n{} {}: {}\n", messages.file1, algorithm, hash1, messages.file2, algorithm, hash2),
+            _ => String::new(),
+        };
+        
+        if not_compared {
+            not_compared_pairs.push(format!("{} ↔ {}", short_path1.display(), short_path2.display()));
+            return;
+        }
+        
+        for (file_path, detected) in encoding_mismatches {
+            error!("{} 的编码为 {}，不符合要求的编码", file_path.display(), detected.name());
+            encoding_errors.push(format!("{} ({})", short_path(file_path).display(), (messages.detected_encoding)(detected.name())));
+        }
+        
+        let excessive_gap = args.warn_version_gap
+            .and_then(|max_gap| version_gap(file1_path, file2_path).filter(|gap| *gap > max_gap));
+        if let Some(gap) = excessive_gap {
+            warn!("{} 和 {} 的版本号相差 {}", file1_path.display(), file2_path.display(), gap);
+            version_gap_warnings.push(format!("{} ↔ {} ({})", short_path1.display(), short_path2.display(),
+                                              (messages.version_gap_entry)(&version_transition(file1_path, file2_path), gap)));
+        }
+        
+        if same_file {
+            same_file_pairs.push(format!("{} ↔ {}", short_path1.display(), short_path2.display()));
+        }
+        if reordered {
+            reordered_pairs.push(format!("{} ↔ {}", short_path1.display(), short_path2.display()));
+        }
+        if let Some(signs) = truncation {
+            let mut reasons = Vec::new();
+            if let Some(file) = signs.missing_final_newline {
+                let file_path = if file == 1 { &short_path1 } else { &short_path2 };
+                reasons.push((messages.missing_final_newline)(&file_path.display().to_string()));
+            }
+            if let Some(ratio) = signs.size_ratio {
+                reasons.push((messages.size_ratio)(ratio));
+            }
+            warn!("{} 和 {} 可能被截断: {}", file1_path.display(), file2_path.display(), reasons.join("; "));
+            truncated_pairs.push(format!("{} ↔ {} ({})", short_path1.display(), short_path2.display(), reasons.join("; ")));
+        }
+        let count_mismatch = args.flag_count_mismatch && line_counts.is_some_and(|(count1, count2)| count1 != count2);
+        if let Some((count1, count2)) = line_counts
+            && count_mismatch {
+            count_mismatch_pairs.push(format!("{} ↔ {} ({})", short_path1.display(), short_path2.display(),
+                                              (messages.line_count_values)(count1, count2)));
+        }
+        
         match result {
             Ok(Some(diff)) => {
                 diff_count += 1;
-                // Add section header for differences
-                report_content.push_str(&format!("{}\n", "-".repeat(50)));
-                report_content.push_str(&format!("发现差异的文件对 #{}\n", diff_count));
-                report_content.push_str(&format!("{}\n", "-".repeat(50)));
-                report_content.push_str(&format!("文件 1: {}\n", short_path1.display()));
-                report_content.push_str(&format!("文件 2: {}\n\n", short_path2.display()));
+                diff_line_count += diff.weights.differing();
+                let accepted = is_accepted_pair(&accepted_pairs, file1_path, file2_path, &pairing_options);
+                if accepted {
+                    accepted_diff_pairs.push(format!("{} ↔ {}", short_path1.display(), short_path2.display()));
+                    accepted_diff_line_count += diff.weights.differing();
+                }
+                if diff.only_in_first.is_empty() && diff.only_in_second.is_empty() {
+                    whitespace_only_count += 1;
+                }
+                if !in_similarity_range(diff) {
+                    if args.similarity_range.as_ref().is_some_and(|range| diff.similarity(args.similarity_metric) < range[0]) {
+                        too_different_count += 1;
+                    } else {
+                        near_identical_count += 1;
+                    }
+                    return;
+                }
+                reported_diff_count += 1;
+                stat_entries.push((short_path1.display().to_string(), diff.only_in_second.len(), diff.only_in_first.len()));
                 
-                if !diff.only_in_first.is_empty() {
-                    report_content.push_str(&format!("  ► 仅在 {} 中存在的行:\n", short_path1.display()));
+                // One row per distinct differing line, flagging the side(s) it's on
+                if args.pivot_out.is_some() {
+                    let mut presence: BTreeMap<&str, (bool, bool)> = BTreeMap::new();
                     for line in &diff.only_in_first {
-                        report_content.push_str(&format!("    • {}\n", line));
+                        presence.entry(line).or_default().0 = true;
                     }
-                    report_content.push_str("\n");
-                }
-                if !diff.only_in_second.is_empty() {
-                    report_content.push_str(&format!("  ► 仅在 {} 中存在的行:\n", short_path2.display()));
                     for line in &diff.only_in_second {
-                        report_content.push_str(&format!("    • {}\n", line));
+                        presence.entry(line).or_default().1 = true;
+                    }
+                    for (line, (in_first, in_second)) in presence {
+                        pivot_content.push_str(&format!("{}\t{}\t{}\t{}\t{}\t{}\n",
+                                                        diff.pair_id, file1_path.display(), file2_path.display(),
+                                                        line.replace(['\t', '\r', '\n'], " "), in_first, in_second));
+                    }
+                }
+                // Add section header for differences, into its own file with --split-output-dir
+                let mut section = String::new();
+                section.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+                section.push_str(&format!("{}\n", (messages.diff_section)(reported_diff_count)));
+                section.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+                section.push_str(&format!("ID: {}\n", diff.pair_id));
+                section.push_str(&format!("{}: {}\n", messages.file1, short_path1.display()));
+                section.push_str(&format!("{}: {}\n", messages.file2, short_path2.display()));
+                if accepted {
+                    section.push_str(&format!("{}\n", messages.accepted_pair));
+                }
+                section.push_str(&hash_lines);
+                section.push_str(&format!("{}: {}\n", messages.version, version_transition(file1_path, file2_path)));
+                if let Some(gap) = excessive_gap {
+                    section.push_str(&format!("{}\n", (messages.version_gap_exceeded)(gap, args.warn_version_gap.unwrap_or_default())));
+                }
+                let weighted = if args.line_weights.is_some() { messages.weighted } else { "" };
+                section.push_str(&format!("{} ({}{}): {:.2}%\n", messages.similarity,
+                                                 args.similarity_metric, weighted, diff.similarity(args.similarity_metric) * 100.0));
+                let (count1, count2) = diff.line_counts;
+                section.push_str(&format!("{}: {}\n", messages.line_counts, (messages.line_count_values)(count1, count2)));
+                if count_mismatch {
+                    section.push_str(&format!("{}\n", messages.count_mismatch));
+                }
+                if let Some((prefix, suffix)) = diff.common_edges {
+                    section.push_str(&format!("{}: {}\n", messages.common_edges, (messages.common_edge_values)(prefix, suffix)));
+                }
+                if args.truncate_at.is_some() {
+                    let (truncated1, truncated2) = diff.truncated_lines;
+                    section.push_str(&format!("{}\n", (messages.truncated_lines)(truncated1, truncated2)));
+                }
+                section.push('\n');
+                
+                // Records with the same JSON key on both sides are shown field by field when requested
+                let record_diff = args.json_key.as_deref()
+                    .map(|key_field| diff_json_records(&diff.only_in_first, &diff.only_in_second, key_field));
+                if let (Some(record_diff), Some(key_field)) = (&record_diff, &args.json_key)
+                    && !record_diff.changed.is_empty() {
+                    section.push_str(&format!("  ► {}:\n", messages.changed_records));
+                    for record in &record_diff.changed {
+                        section.push_str(&format!("    • {} = {}\n", key_field, record.key));
+                        for change in &record.fields {
+                            section.push_str(&format!("        {}: {} → {}\n", change.field,
+                                                      change.old.as_deref().unwrap_or(messages.none),
+                                                      change.new.as_deref().unwrap_or(messages.none)));
+                        }
                     }
-                    report_content.push_str("\n");
+                    section.push('\n');
+                }
+                
+                // Near-identical lines on both sides are shown as modifications when requested
+                let modifications = match (record_diff, args.merge_modifications) {
+                    (Some(record_diff), _) => LineModifications {
+                        removed: record_diff.removed,
+                        added: record_diff.added,
+                        ..LineModifications::default()
+                    },
+                    (None, Some(threshold)) => merge_modifications(&diff.only_in_first, &diff.only_in_second, threshold),
+                    (None, None) => LineModifications {
+                        removed: diff.only_in_first.clone(),
+                        added: diff.only_in_second.clone(),
+                        ..LineModifications::default()
+                    },
+                };
+                if !modifications.modified.is_empty() {
+                    section.push_str(&format!("  ► {}:\n", messages.modified_lines));
+                    for (old, new) in &modifications.modified {
+                        match args.inline_diff {
+                            Some(granularity) => section.push_str(&format!("    • {}\n", inline_diff(old, new, granularity))),
+                            None => section.push_str(&format!("    • {} → {}\n", old, new)),
+                        }
+                    }
+                    section.push('\n');
+                }
+                if !diff.whitespace_only_diffs.is_empty() {
+                    section.push_str(&format!("  ► {}:\n", messages.whitespace_only_lines));
+                    for whitespace_diff in &diff.whitespace_only_diffs {
+                        let trailing = (messages.trailing_whitespace)(&format!("{:?}", whitespace_diff.trailing1),
+                                                                      &format!("{:?}", whitespace_diff.trailing2));
+                        section.push_str(&format!("    • {} ({})\n", whitespace_diff.line, trailing));
+                    }
+                    section.push('\n');
+                }
+                if !modifications.removed.is_empty() {
+                    section.push_str(&format!("  ► {}:\n", only_in(&short_path1)));
+                    for line in &modifications.removed {
+                        section.push_str(&format!("    • {}\n", line));
+                    }
+                    section.push('\n');
+                }
+                if !modifications.added.is_empty() {
+                    section.push_str(&format!("  ► {}:\n", only_in(&short_path2)));
+                    for line in &modifications.added {
+                        section.push_str(&format!("    • {}\n", line));
+                    }
+                    section.push('\n');
+                }
+                
+                // An existing file is never overwritten: its pair's details go to the main report instead
+                let split_path = args.split_output_dir.as_ref()
+                    .map(|split_dir| pair_output_path(file1_path, file2_path, split_dir, "txt"));
+                match split_path.map(|split_path| write_new_file(&split_path, section.as_bytes()).map(|_| split_path)) {
+                    Some(Ok(split_path)) => {
+                        let file_name = split_path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+                        split_files.push(format!("#{:<4} [{}] {}", reported_diff_count, diff.pair_id, file_name));
+                    }
+                    Some(Err(e)) => {
+                        // Keep the details in the main report rather than losing them
+                        error!("写入 {} 和 {} 的差异详情文件失败: {}", file1_path.display(), file2_path.display(), e);
+                        report_content.push_str(&section);
+                    }
+                    None => report_content.push_str(&section),
                 }
             }
             Ok(None) => {
-                // No differences - don't add to report to keep it concise
+                // No differences - only listed by ID (and hashes) at the end to keep the report concise
+                let mut entry = format!("[{}] {} ↔ {}", pair_id(file1_path, file2_path), short_path1.display(), short_path2.display());
+                if let Some((hash1, hash2)) = &content_hashes {
+                    entry.push_str(&format!("\n      {}\n      {}", hash1, hash2));
+                }
+                identical_pairs.push(entry);
             }
             Err(e) => {
                 error_count += 1;
                 error!("比较 {} 和 {} 时出错: {}",
                        file1_path.display(), file2_path.display(), e);
                 // Add error section
-                report_content.push_str(&format!("{}\n", "-".repeat(50)));
-                report_content.push_str("比较错误\n");
-                report_content.push_str(&format!("{}\n", "-".repeat(50)));
-                report_content.push_str(&format!("文件 1: {}\n", short_path1.display()));
-                report_content.push_str(&format!("文件 2: {}\n", short_path2.display()));
-                report_content.push_str(&format!("错误信息: {}\n\n", e));
+                report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+                report_content.push_str(&format!("{}\n", messages.comparison_error));
+                report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+                report_content.push_str(&format!("ID: {}\n", pair_id(&file1_path, &file2_path)));
+                report_content.push_str(&format!("{}: {}\n", messages.file1, short_path1.display()));
+                report_content.push_str(&format!("{}: {}\n", messages.file2, short_path2.display()));
+                report_content.push_str(&hash_lines);
+                report_content.push_str(&format!("{}: {}\n", messages.version, version_transition(file1_path, file2_path)));
+                report_content.push_str(&format!("{}: {:#}\n\n", messages.error_message, e));
+                
+                // One entry per line: file1 <TAB> file2 <TAB> error (with its full cause chain)
+                let message = format!("{:#}", e).replace(['\t', '\r', '\n'], " ");
+                errors_content.push_str(&format!("{}\t{}\t{}\n",
+                                                 file1_path.display(), file2_path.display(), message));
+            }
+        }
+    };
+    
+    // Dispatch the largest pairs first (longest-processing-time-first): idle threads pull the next pair
+    // from a single queue, so a few huge pairs can't end up running alone at the end of the run.
+    // Results are put back in pairing order afterwards, unless the report is written as they complete.
+    if let Some(encoding_cache) = &compare_options.process.encoding_cache {
+        encoding_cache.seed(file_pairs.iter().flat_map(|(file1, file2, _)| [file1, file2]));
+    }
+    let mut dispatch_order: Vec<_> = file_pairs.into_iter().enumerate().collect();
+    dispatch_order.sort_by_key(|(_, (_, _, pair_bytes))| std::cmp::Reverse(*pair_bytes));
+    let run_pair = |(index, pair): (usize, (PathBuf, PathBuf, u64))| {
+        let result = compare_pair(pair);
+        if let Some(progress_log) = &progress_log {
+            progress_log.pair_done();
+        }
+        #[cfg(feature = "publish")]
+        if let Some(publisher) = &publisher {
+            let completed = completed_pairs.fetch_add(1, AtomicOrdering::Relaxed) + 1;
+            publisher.publish(&pair_message(&result, args.similarity_metric, completed, file_pairs_count));
+        }
+        (index, result)
+    };
+    let mut results: Vec<PairResult> = if args.tail_report {
+        // Each pair's section is appended as soon as the pair completes, so the report can be followed while
+        // the run goes on; sections come in completion order, which follows the size-sorted dispatch
+        report_content.push('\n');
+        report_content.push_str(&tree_section);
+        let mut append_to_report = |report_content: &mut String| -> Result<()> {
+            report_file.write_all(report_content.as_bytes())
+                .and_then(|_| report_file.flush())
+                .with_context(|| format!("无法写入报告文件: {:?}", report_path))?;
+            report_content.clear();
+            Ok(())
+        };
+        append_to_report(&mut report_content)?;
+        thread::scope(|scope| -> Result<Vec<PairResult>> {
+            let (sender, receiver) = mpsc::channel();
+            let run_pair = &run_pair;
+            scope.spawn(move || {
+                // A send error means writing the report failed; that error is returned below
+                let send_result = |sender: &mut mpsc::Sender<PairResult>, pair| {
+                    let _ = sender.send(run_pair(pair).1);
+                };
+                match args.par_chunk_size {
+                    Some(chunk_size) => dispatch_order.into_par_iter().with_min_len(chunk_size.get()).for_each_with(sender, send_result),
+                    None => dispatch_order.into_iter().par_bridge().for_each_with(sender, send_result),
+                }
+            });
+            let mut results = Vec::new();
+            for pair in receiver {
+                add_pair_result(&pair, &mut report_content);
+                append_to_report(&mut report_content)?;
+                results.push(pair);
+            }
+            Ok(results)
+        })?
+    } else {
+        let mut indexed_results: Vec<(usize, PairResult)> = match args.par_chunk_size {
+            Some(chunk_size) => dispatch_order.into_par_iter().with_min_len(chunk_size.get()).map(run_pair).collect(),
+            None => dispatch_order.into_iter().par_bridge().map(run_pair).collect(),
+        };
+        indexed_results.sort_by_key(|(index, _)| *index);
+        indexed_results.into_iter().map(|(_, pair)| pair).collect()
+    };
+    
+    pb.finish_with_message(messages.progress_finished);
+    if let Some(progress_log) = &progress_log {
+        progress_log.finish();
+    }
+    let elapsed = started.elapsed();
+    
+    #[cfg(feature = "publish")]
+    if let Some(publisher) = &publisher {
+        publisher.publish(&json!({ "event": "finished", "total": file_pairs_count, "elapsed_seconds": elapsed.as_secs_f64() }).to_string());
+    }
+    
+    // Closing the channel lets the writer thread finish
+    drop(ndjson_sender);
+    if let Some(writer) = ndjson_writer {
+        writer.join().map_err(|_| anyhow::anyhow!("NDJSON 写入线程异常退出"))??;
+    }
+    
+    order_results(&mut results, args.report_order);
+    
+    #[cfg(feature = "sqlite")]
+    let sqlite_pairs: Vec<PairRecord> = match &args.sqlite_out {
+        Some(_) => results.iter().map(|pair| pair_record(pair, args.similarity_metric)).collect(),
+        None => Vec::new(),
+    };
+    
+    // Pairs closest to the in-memory limits, by estimated peak memory
+    let memory_profile: Vec<(PathBuf, PathBuf, u64)> = if args.profile {
+        let mut pairs: Vec<_> = results.iter()
+            .filter(|pair| pair.memory_estimate > 0)
+            .map(|pair| (pair.file1_path.clone(), pair.file2_path.clone(), pair.memory_estimate))
+            .collect();
+        pairs.sort_by_key(|(_, _, memory_estimate)| std::cmp::Reverse(*memory_estimate));
+        pairs.truncate(PROFILE_TOP_PAIRS);
+        pairs
+    } else {
+        Vec::new()
+    };
+    
+    // Duplicates are looked up once per compared file, with the options of the pair it was compared in
+    let duplicate_lines: BTreeMap<PathBuf, Vec<(String, usize)>> = if args.report_intra_file_dups {
+        let files: BTreeMap<&Path, Cow<CompareOptions>> = results.iter()
+            .filter(|pair| !pair.not_compared && !pair.same_file)
+            .flat_map(|pair| {
+                let options = options_for(&pair.file1_path);
+                [(pair.file1_path.as_path(), Cow::Borrowed(options)), (pair.file2_path.as_path(), options.for_second_file())]
+            })
+            .collect();
+        files.into_par_iter()
+            .filter_map(|(file_path, options)| match find_duplicate_lines(file_path, &options.process) {
+                // Masked like the differences, before any output sees them
+                Ok(duplicates) if !duplicates.is_empty() => Some((file_path.to_path_buf(), duplicates.into_iter()
+                    .map(|(line, count)| (redaction.redact(&line).into_owned(), count))
+                    .collect::<Vec<_>>())),
+                Ok(_) => None,
+                Err(e) => {
+                    warn!("检查 {} 的重复行失败: {:#}", file_path.display(), e);
+                    None
+                }
+            })
+            .collect()
+    } else {
+        BTreeMap::new()
+    };
+    
+    let not_compared_count = results.iter().filter(|pair| pair.not_compared).count();
+    if not_compared_count > 0 {
+        warn!("已达到时限，{} 个文件对未比较", not_compared_count);
+    }
+    
+    if args.tail_report {
+        // The overview and the partial-run note need every result, so they follow the pair sections
+        report_content.push_str(&overview(&results));
+        if not_compared_count > 0 {
+            report_content.push_str(&format!("{}\n\n", (messages.partial_run)(args.deadline.unwrap_or_default(), not_compared_count)));
+        }
+    } else {
+        if not_compared_count > 0 {
+            report_content.push_str(&format!("{}\n", (messages.partial_run)(args.deadline.unwrap_or_default(), not_compared_count)));
+        }
+        report_content.push('\n');
+        report_content.push_str(&tree_section);
+        report_content.push_str(&overview(&results));
+        for pair in &results {
+            add_pair_result(pair, &mut report_content);
+        }
+    }
+    
+    // Index of the per-pair detail files written with --split-output-dir
+    if let Some(split_dir) = &args.split_output_dir
+        && !split_files.is_empty() {
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        report_content.push_str(&format!("{}\n", (messages.split_section)(split_dir)));
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        for entry in &split_files {
+            report_content.push_str(&format!("  {}\n", entry));
+        }
+        report_content.push('\n');
+    }
+    
+    // List pairs that the deadline left uncompared
+    if !not_compared_pairs.is_empty() {
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        report_content.push_str(&format!("{}\n", messages.not_compared_section));
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        for pair in &not_compared_pairs {
+            report_content.push_str(&format!("  • {}\n", pair));
+        }
+        report_content.push('\n');
+    }
+    
+    // List the pairs with the highest estimated memory
+    if !memory_profile.is_empty() {
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        report_content.push_str(&format!("{}\n", (messages.memory_section)(memory_profile.len())));
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        for (file1_path, file2_path, memory_estimate) in &memory_profile {
+            report_content.push_str(&format!("  {:>10.2} MiB  {} ↔ {}\n", *memory_estimate as f64 / (1024.0 * 1024.0),
+                                             short_path(file1_path).display(), short_path(file2_path).display()));
+        }
+        report_content.push('\n');
+    }
+    
+    // List the files both directories have that were left out because of their names
+    if !unpaired_common_files.is_empty() {
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        report_content.push_str(&format!("{}\n", messages.unpaired_section));
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        for file in &unpaired_common_files {
+            let reason = match &file.mismatch {
+                Some(mismatch) => name_mismatch_reason(mismatch, messages),
+                None => messages.key_regex_mismatch.to_string(),
+            };
+            report_content.push_str(&format!("  ✗ {}: {}\n", file.file_name, reason));
+        }
+        report_content.push('\n');
+    }
+    
+    // List the expected keys that didn't produce a pair, by where they are missing
+    if let Some(coverage) = &key_coverage
+        && !coverage.is_complete() {
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        report_content.push_str(&format!("{}\n", messages.key_coverage_section));
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        for (label, keys) in key_coverage_groups(coverage, messages) {
+            if keys.is_empty() {
+                continue;
+            }
+            report_content.push_str(&format!("  ► {} ({}):\n", label, keys.len()));
+            for key in keys {
+                report_content.push_str(&format!("    ✗ {}\n", key));
+            }
+        }
+        report_content.push('\n');
+    }
+    
+    // List files not in the required encoding, whatever their content comparison found
+    if let Some(expected) = required_encoding
+        && !encoding_errors.is_empty() {
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        report_content.push_str(&format!("{}\n", (messages.encoding_section)(expected.name())));
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        for entry in &encoding_errors {
+            report_content.push_str(&format!("  ✗ {}\n", entry));
+        }
+        report_content.push('\n');
+    }
+    
+    // List identical pairs whose lines are in a different order
+    if !reordered_pairs.is_empty() {
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        report_content.push_str(&format!("{}\n", messages.reordered_section));
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        for pair in &reordered_pairs {
+            report_content.push_str(&format!("  ⚠ {}\n", pair));
+        }
+        report_content.push('\n');
+    }
+    
+    // List pairs where a file may be cut off, apart from their content differences
+    if !truncated_pairs.is_empty() {
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        report_content.push_str(&format!("{}\n", messages.truncation_section));
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        for pair in &truncated_pairs {
+            report_content.push_str(&format!("  ⚠ {}\n", pair));
+        }
+        report_content.push('\n');
+    }
+    
+    // List pairs whose line counts differ, whatever their line sets
+    if !count_mismatch_pairs.is_empty() {
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        report_content.push_str(&format!("{}\n", messages.count_mismatch_section));
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        for pair in &count_mismatch_pairs {
+            report_content.push_str(&format!("  ⚠ {}\n", pair));
+        }
+        report_content.push('\n');
+    }
+    
+    // List pairs that were skipped because both sides are the same file
+    if !same_file_pairs.is_empty() {
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        report_content.push_str(&format!("{}\n", messages.same_file_section));
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        for pair in &same_file_pairs {
+            report_content.push_str(&format!("  • {}\n", pair));
+        }
+        report_content.push('\n');
+    }
+    
+    // List the differing pairs whose differences are accepted
+    if !accepted_diff_pairs.is_empty() {
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        report_content.push_str(&format!("{}\n", messages.accepted_section));
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        for pair in &accepted_diff_pairs {
+            report_content.push_str(&format!("  • {}\n", pair));
+        }
+        report_content.push('\n');
+    }
+    
+    // List the equivalence rules that were applied, so canonicalized lines can be audited
+    if let Some(equivalence_map) = &equivalence_map {
+        let applied: Vec<_> = equivalence_map.applied_counts().into_iter()
+            .filter(|(_, _, count)| *count > 0)
+            .collect();
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        report_content.push_str(&format!("{}\n", messages.equivalence_section));
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        if applied.is_empty() {
+            report_content.push_str(&format!("  {}\n", messages.none));
+        }
+        for (old, new, count) in applied {
+            report_content.push_str(&format!("  • {} → {}: {}\n", old, new, (messages.occurrences)(count)));
+        }
+        report_content.push('\n');
+    }
+    
+    // List how many lines each rule affected, flagging rules that never matched
+    let rule_usage_lines: Vec<String> = match &rule_usage {
+        Some(rule_usage) => rule_usage.counts().into_iter()
+            .map(|(rule, count)| match count {
+                0 => format!("{}: {} {}", rule, (messages.lines_affected)(count), messages.rule_unused),
+                _ => format!("{}: {}", rule, (messages.lines_affected)(count)),
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+    if rule_usage.is_some() {
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        report_content.push_str(&format!("{}\n", messages.rule_usage_section));
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        if rule_usage_lines.is_empty() {
+            report_content.push_str(&format!("  {}\n", messages.none));
+        }
+        for line in &rule_usage_lines {
+            report_content.push_str(&format!("  • {}\n", line));
+        }
+        report_content.push('\n');
+    }
+    
+    // Record identical pairs (and their hashes) too, so every compared pair is accounted for by its ID
+    if !identical_pairs.is_empty() {
+        let title = match args.content_hash {
+            Some(algorithm) => (messages.identical_hashes_section)(&algorithm.to_string()),
+            None => messages.identical_section.to_string(),
+        };
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        report_content.push_str(&format!("{}\n", title));
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        for entry in &identical_pairs {
+            report_content.push_str(&format!("  • {}\n", entry));
+        }
+        report_content.push('\n');
+    }
+    
+    // List the lines repeated within a single file
+    if args.report_intra_file_dups {
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        report_content.push_str(&format!("{}\n", messages.duplicates_section));
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        if duplicate_lines.is_empty() {
+            report_content.push_str(&format!("  {}\n", messages.none));
+        }
+        for (file_path, duplicates) in &duplicate_lines {
+            report_content.push_str(&format!("  • {}: {}\n", short_path(file_path).display(), (messages.repeated_lines)(duplicates.len())));
+            for (line, count) in duplicates {
+                report_content.push_str(&format!("      {} (×{})\n", line, count));
             }
         }
+        report_content.push('\n');
+    }
+    
+    // List the candidates considered for each DIR1 file that had several
+    if !best_matches.is_empty() {
+        let similarity = |similarity: Option<f64>| match similarity {
+            Some(similarity) => (messages.similarity_value)(similarity * 100.0),
+            None => messages.comparison_failed.to_string(),
+        };
+        let mut best_matches: Vec<_> = best_matches.iter().collect();
+        best_matches.sort_by_key(|(file1_path, _)| *file1_path);
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        report_content.push_str(&format!("{}\n", (messages.candidates_section)(&args.similarity_metric.to_string())));
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        for (file1_path, best) in best_matches {
+            report_content.push_str(&format!("  • {} → {} ({})\n", short_path(file1_path).display(),
+                                             short_path(&best.file2_path).display(), similarity(best.similarity)));
+            for (alternate, alternate_similarity) in &best.alternates {
+                report_content.push_str(&format!("      {}: {} ({})\n", messages.alternate,
+                                                 short_path(alternate).display(), similarity(*alternate_similarity)));
+            }
+        }
+        report_content.push('\n');
+    }
+    
+    // Add version gap warnings, covering identical pairs that have no section of their own
+    if !version_gap_warnings.is_empty() {
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        report_content.push_str(&format!("{}\n", (messages.version_gap_section)(args.warn_version_gap.unwrap_or_default())));
+        report_content.push_str(&format!("{}\n", "-".repeat(section_rule_width)));
+        for warning in &version_gap_warnings {
+            report_content.push_str(&format!("  ⚠ {}\n", warning));
+        }
+        report_content.push('\n');
     }
     
     // Add summary section
-    report_content.push_str(&format!("{}\n", "=".repeat(80)));
-    report_content.push_str("统计摘要\n");
-    report_content.push_str(&format!("{}\n", "=".repeat(80)));
-    report_content.push_str(&format!("  • 发现差异的文件对: {}\n", diff_count));
-    report_content.push_str(&format!("  • 比较出错的文件对: {}\n", error_count));
-    report_content.push_str(&format!("  • 完全相同的文件对: {}\n", file_pairs_count - diff_count - error_count));
-    report_content.push_str(&format!("{}\n", "=".repeat(80)));
+    report_content.push_str(&format!("{}\n", "=".repeat(rule_width)));
+    report_content.push_str(&format!("{}\n", messages.summary));
+    report_content.push_str(&format!("{}\n", "=".repeat(rule_width)));
+    report_content.push_str(&format!("  • {}: {}\n", messages.differing_pairs, diff_count));
+    if args.report_trailing_whitespace {
+        report_content.push_str(&format!("    {}: {}\n", messages.whitespace_only_pairs, whitespace_only_count));
+    }
+    if args.accept_pairs.is_some() {
+        report_content.push_str(&format!("    {}: {}\n", messages.accepted_pairs, accepted_diff_pairs.len()));
+    }
+    if let Some(range) = &args.similarity_range {
+        report_content.push_str(&format!("    {}: {}\n", (messages.in_range_pairs)(range[0] * 100.0, range[1] * 100.0), reported_diff_count));
+        report_content.push_str(&format!("    {}: {}\n", (messages.too_different_pairs)(range[0] * 100.0), too_different_count));
+        report_content.push_str(&format!("    {}: {}\n", (messages.near_identical_pairs)(range[1] * 100.0), near_identical_count));
+    }
+    report_content.push_str(&format!("  • {}: {}\n", messages.error_pairs, error_count));
+    if required_encoding.is_some() {
+        report_content.push_str(&format!("  • {}: {}\n", messages.encoding_error_files, encoding_errors.len()));
+    }
+    report_content.push_str(&format!("  • {}: {}\n", messages.identical_pairs, file_pairs_count - diff_count - error_count - not_compared_count));
+    if !same_file_pairs.is_empty() {
+        report_content.push_str(&format!("    {}: {}\n", messages.same_file_pairs, same_file_pairs.len()));
+    }
+    if args.detect_reorder {
+        report_content.push_str(&format!("    {}: {}\n", messages.reordered_pairs, reordered_pairs.len()));
+    }
+    if args.check_truncation {
+        report_content.push_str(&format!("  • {}: {}\n", messages.truncated_pairs, truncated_pairs.len()));
+    }
+    if args.flag_count_mismatch {
+        report_content.push_str(&format!("  • {}: {}\n", messages.count_mismatch_pairs, count_mismatch_pairs.len()));
+    }
+    if not_compared_count > 0 {
+        report_content.push_str(&format!("  • {}: {}\n", messages.not_compared_pairs, not_compared_count));
+    }
+    if !unpaired_common_files.is_empty() {
+        report_content.push_str(&format!("  • {}: {}\n", messages.unpaired_files, unpaired_common_files.len()));
+    }
+    if let Some(coverage) = &key_coverage {
+        report_content.push_str(&format!("  • {}: {}\n", messages.expected_keys_paired, coverage.paired.len()));
+        report_content.push_str(&format!("  • {}: {}\n", messages.expected_keys_not_paired, coverage.not_paired_count()));
+    }
+    if let Some(tree_diff) = &tree_diff {
+        report_content.push_str(&format!("  • {}: {}\n", messages.only_in_first_dir, tree_diff.only_in_first.len()));
+        report_content.push_str(&format!("  • {}: {}\n", messages.only_in_second_dir, tree_diff.only_in_second.len()));
+    }
+    if args.report_intra_file_dups {
+        report_content.push_str(&format!("  • {}: {}\n", messages.files_with_duplicates, duplicate_lines.len()));
+    }
+    let compared_pairs = file_pairs_count - not_compared_count - same_file_pairs.len();
+    let throughput = throughput(compared_bytes.into_inner(), compared_pairs, elapsed, messages);
+    report_content.push_str(&format!("  • {}: {}\n", messages.processed, throughput));
+    report_content.push_str(&format!("{}\n", "=".repeat(rule_width)));
     
     // Output to console
-    println!("\n比较完成！");
-    println!("发现差异的文件对: {}", diff_count);
-    println!("比较出错的文件对: {}", error_count);
-    println!("完全相同的文件对: {}", file_pairs_count - diff_count - error_count);
-    
-    // Save report to file if requested
-    if let Some(output_path) = &args.output {
-        let report_path = if output_path.extension().is_none() {
-            // Add timestamp to filename if no extension is provided
-            let stem = output_path.file_stem().unwrap_or_default().to_string_lossy();
-            let parent = output_path.parent().unwrap_or_else(|| std::path::Path::new("."));
-            parent.join(format!("{}_{}.txt", stem, timestamp_filename))
+    if args.stat {
+        console!();
+        console!("{}", diffstat(&stat_entries, messages).trim_end_matches('\n'));
+    }
+    console!("\n{}", messages.finished);
+    console!("{}: {}", messages.differing_pairs, diff_count);
+    if args.similarity_range.is_some() {
+        console!("  {}", (messages.in_range_console)(reported_diff_count, too_different_count, near_identical_count));
+    }
+    console!("{}: {}", messages.error_pairs, error_count);
+    if required_encoding.is_some() {
+        console!("{}: {}", messages.encoding_error_files, encoding_errors.len());
+    }
+    console!("{}: {}", messages.identical_pairs, file_pairs_count - diff_count - error_count - not_compared_count);
+    if args.check_truncation {
+        console!("{}: {}", messages.truncated_pairs, truncated_pairs.len());
+    }
+    if args.flag_count_mismatch {
+        console!("{}: {}", messages.count_mismatch_pairs, count_mismatch_pairs.len());
+    }
+    if not_compared_count > 0 {
+        console!("{}: {}", messages.not_compared_console, not_compared_count);
+    }
+    if let Some(tree_diff) = &tree_diff {
+        console!("{}: {}", messages.only_in_first_dir, tree_diff.only_in_first.len());
+        console!("{}: {}", messages.only_in_second_dir, tree_diff.only_in_second.len());
+    }
+    console!("{}: {}", messages.processed, throughput);
+    if rule_usage.is_some() {
+        console!("{}:", messages.rule_usage_section);
+        if rule_usage_lines.is_empty() {
+            console!("  {}", messages.none);
+        }
+        for line in &rule_usage_lines {
+            console!("  {}", line);
+        }
+    }
+    
+    // Save report; if writing fails now, print it instead so the comparison work isn't lost
+    let write_result = report_file.write_all(report_content.as_bytes())
+        .and_then(|_| report_file.flush());
+    match write_result {
+        Ok(()) => console!("{}: {:?}", messages.report_saved, report_path),
+        Err(e) => {
+            warn!("无法写入报告文件 {:?}: {}", report_path, e);
+            eprintln!("{}", (messages.report_write_failed)(&report_path, &e.to_string()));
+            console!("\n{}", report_content);
+        }
+    }
+    
+    if let Some(pivot_path) = &args.pivot_out {
+        std::fs::write(pivot_path, pivot_content.as_bytes())
+            .with_context(|| format!("无法写入差异行透视文件: {:?}", pivot_path))?;
+        console!("{}: {:?}", messages.pivot_saved, pivot_path);
+    }
+    
+    if let Some(errors_path) = &args.errors_out
+        && (error_count > 0 || !args.errors_out_skip_empty) {
+        std::fs::write(errors_path, errors_content.as_bytes())
+            .with_context(|| format!("无法写入错误日志文件: {:?}", errors_path))?;
+        console!("{}: {:?}", messages.errors_saved, errors_path);
+    }
+    
+    if let Some(patch_dir) = &args.patch_dir {
+        console!("{}: {:?}", messages.patches_saved, patch_dir);
+    }
+    
+    if let Some(conflict_dir) = &args.conflict_dir {
+        console!("{}: {:?}", messages.conflicts_saved, conflict_dir);
+    }
+    
+    if let Some(dump_dir) = &args.dump_processed {
+        console!("{}: {:?}", messages.processed_saved, dump_dir);
+    }
+    
+    if let Some(split_dir) = &args.split_output_dir {
+        console!("{}: {:?}", messages.split_saved, split_dir);
+    }
+    
+    #[cfg(feature = "sqlite")]
+    if let Some(db_path) = &args.sqlite_out {
+        let run = RunRecord {
+            started_at: timestamp_display.clone(),
+            dir1: dir1.display().to_string(),
+            dir2: dir2.as_ref().map(|dir2| dir2.display().to_string()),
+            pair_count: file_pairs_count,
+            diff_count,
+            error_count,
+            identical_count: file_pairs_count - diff_count - error_count - not_compared_count,
+        };
+        write_run(db_path, &run, &sqlite_pairs)
+            .with_context(|| format!("无法写入 SQLite 数据库: {:?}", db_path))?;
+        console!("{}: {:?}", messages.sqlite_saved, db_path);
+    }
+    
+    // The next incremental run picks up the files modified since this one started
+    if args.incremental {
+        if error_count == 0 && not_compared_count == 0 {
+            write_last_run(&args.state_file, run_started_at)?;
+            console!("{}: {:?}", messages.state_updated, args.state_file);
+        } else {
+            console!("{}", messages.state_not_updated);
+        }
+    }
+    
+    info!("文件比较完成");
+    
+    // Gate on the amount of drift rather than on any difference at all; accepted pairs never count
+    let unaccepted_diff_count = diff_count - accepted_diff_pairs.len();
+    let unaccepted_line_count = diff_line_count - accepted_diff_line_count;
+    
+    if let Some(minor_threshold) = args.minor_threshold {
+        let severity = if error_count > 0 || not_compared_count > 0 {
+            Severity::Error
+        } else if unaccepted_line_count >= minor_threshold
+            || required_encoding.is_some() && !encoding_errors.is_empty() {
+            Severity::Major
+        } else if unaccepted_diff_count > 0 {
+            Severity::Minor
         } else {
-            output_path.clone()
+            Severity::Identical
+        };
+        let band = match severity {
+            Severity::Identical => messages.severity_identical,
+            Severity::Minor => messages.severity_minor,
+            Severity::Major => messages.severity_major,
+            Severity::Error => messages.severity_error,
         };
+        eprintln!("{}", (messages.severity_gate)(band, severity.exit_code()));
+        return Ok(ExitCode::from(severity.exit_code()));
+    }
+    
+    // Encoding drift always fails the run
+    let mut exceeded = false;
+    if let Some(expected) = required_encoding
+        && !encoding_errors.is_empty() {
+        eprintln!("{}", (messages.encoding_gate)(encoding_errors.len(), expected.name()));
+        exceeded = true;
+    }
+    if let Some(threshold) = args.fail_threshold
+        && unaccepted_diff_count > threshold {
+        eprintln!("{}", (messages.diff_threshold_gate)(unaccepted_diff_count, threshold));
+        exceeded = true;
+    }
+    if let Some(threshold) = args.fail_line_threshold
+        && unaccepted_line_count > threshold {
+        eprintln!("{}", (messages.line_threshold_gate)(unaccepted_line_count, threshold));
+        exceeded = true;
+    }
+    // Without a threshold, an accept list fails the run on any other differing pair
+    if args.accept_pairs.is_some() && args.fail_threshold.is_none() && args.fail_line_threshold.is_none()
+        && unaccepted_diff_count > 0 {
+        eprintln!("{}", (messages.unaccepted_gate)(unaccepted_diff_count));
+        exceeded = true;
+    }
+    
+    Ok(if exceeded { ExitCode::FAILURE } else { ExitCode::SUCCESS })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diffstat() {
+        let messages = Lang::En.messages();
+        let entries = vec![("a.txt".to_string(), 3, 1), ("long_name.txt".to_string(), 100, 0)];
         
-        let mut file = File::create(&report_path)
-            .with_context(|| format!("无法创建报告文件: {:?}", report_path))?;
-        file.write_all(report_content.as_bytes())
-            .with_context(|| format!("无法写入报告文件: {:?}", report_path))?;
+        // Names are padded to one column and the bars scaled to the fixed width, keeping at least one mark
+        assert_eq!(diffstat(&entries, messages), format!(
+            " a.txt         |   4 ++-\n long_name.txt | 100 {}\n 2 file pairs differ, 103 lines added (+), 1 lines removed (-)\n",
+            "+".repeat(STAT_BAR_WIDTH)));
         
-        println!("详细报告已保存到: {:?}", report_path);
-    } else {
-        // Default report name with timestamp
-        let report_filename = format!("comparison_report_{}.txt", timestamp_filename);
-        let mut file = File::create(&report_filename)
-            .with_context(|| format!("无法创建报告文件: {}", report_filename))?;
-        file.write_all(report_content.as_bytes())
-            .with_context(|| format!("无法写入报告文件: {}", report_filename))?;
+        // Small counts aren't scaled up
+        let entries = vec![("b.txt".to_string(), 2, 2)];
+        assert!(diffstat(&entries, messages).starts_with(" b.txt | 4 ++--\n"));
+    }
+
+    #[test]
+    fn test_render_output_template() -> Result<()> {
+        let values = [("dir1", "old"), ("dir2", "new:A/B"), ("ext", "txt")];
         
-        println!("详细报告已保存到: {}", report_filename);
+        // Values are sanitized, the template's own text is kept
+        assert_eq!(render_output_template("reports/{dir1}_{dir2}.{ext}", &values)?, "reports/old_new_A_B.txt");
+        assert_eq!(render_output_template("plain.txt", &values)?, "plain.txt");
+        
+        // Unknown placeholders and unclosed braces are rejected
+        let error = render_output_template("{dir1}_{count}.txt", &values).unwrap_err().to_string();
+        assert!(error.contains("{count}") && error.contains("{dir1}, {dir2}, {ext}"), "{}", error);
+        assert!(render_output_template("{dir1", &values).is_err());
+        
+        Ok(())
     }
-    
-    info!("文件比较完成");
-    Ok(())
 }
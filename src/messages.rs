@@ -0,0 +1,599 @@
+//! Report and console message catalogs for the tbcompare binary, one per output language.
+//! Log lines and error messages are not translated.
+
+use std::path::Path;
+use clap::ValueEnum;
+
+/// Language of the report and console output
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Lang {
+    /// Chinese
+    #[default]
+    Zh,
+    /// English
+    En,
+}
+
+impl Lang {
+    /// Returns the message catalog of the language
+    pub fn messages(self) -> &'static Messages {
+        match self {
+            Lang::Zh => &ZH,
+            Lang::En => &EN,
+        }
+    }
+}
+
+/// Messages of one output language
+/// Plain labels are followed by `: <value>` where they're used; messages with values inside are functions.
+pub struct Messages {
+    // Console progress and summary
+    pub progress: fn(usize, usize, f64, f64) -> String,
+    pub stat_total: fn(usize, usize, usize) -> String,
+    pub throughput: fn(f64, usize, f64, f64, f64) -> String,
+    pub progress_finished: &'static str,
+    pub finished: &'static str,
+    pub in_range_console: fn(usize, usize, usize) -> String,
+    pub not_compared_console: &'static str,
+    pub report_saved: &'static str,
+    pub report_write_failed: fn(&Path, &str) -> String,
+    pub pivot_saved: &'static str,
+    pub errors_saved: &'static str,
+    pub patches_saved: &'static str,
+    pub conflicts_saved: &'static str,
+    pub processed_saved: &'static str,
+    pub split_saved: &'static str,
+    #[cfg(feature = "sqlite")]
+    pub sqlite_saved: &'static str,
+    pub state_updated: &'static str,
+    pub state_not_updated: &'static str,
+    pub encoding_gate: fn(usize, &str) -> String,
+    pub diff_threshold_gate: fn(usize, usize) -> String,
+    pub line_threshold_gate: fn(f64, f64) -> String,
+    pub unaccepted_gate: fn(usize) -> String,
+    pub severity_gate: fn(&str, u8) -> String,
+    pub severity_identical: &'static str,
+    pub severity_minor: &'static str,
+    pub severity_major: &'static str,
+    pub severity_error: &'static str,
+    pub incremental_since: fn(&str) -> String,
+    pub incremental_no_state: fn(&Path) -> String,
+    pub unpaired_notice: fn(usize) -> String,
+    pub key_coverage_notice: fn(usize, usize) -> String,
+    pub keys_missing_from_both: &'static str,
+    pub keys_missing_from_first: &'static str,
+    pub keys_missing_from_second: &'static str,
+    pub keys_not_paired: &'static str,
+    pub no_modified_pairs: &'static str,
+    pub no_pairs_found: &'static str,
+    pub same_dirs: &'static str,
+    pub duplicate_dirs: fn(usize) -> String,
+    pub pairs_found: fn(usize) -> String,
+    
+    // File naming and pairing (explain-pairing, suggest-pattern)
+    pub name_not_utf8: &'static str,
+    pub name_too_few_fields: fn(usize) -> String,
+    pub name_wrong_prefix: fn(&str) -> String,
+    pub name_wrong_suffix: fn(&str) -> String,
+    pub file: &'static str,
+    pub name_fields: &'static str,
+    pub name_pattern_mismatch: &'static str,
+    pub match_by: &'static str,
+    pub match_key: &'static str,
+    pub match_key_by_position: &'static str,
+    pub match_key_unavailable: &'static str,
+    pub result: &'static str,
+    pub matched: fn(&Path) -> String,
+    pub unmatched: fn(&str) -> String,
+    pub key_regex_mismatch: &'static str,
+    pub key_not_selected: &'static str,
+    pub no_counterpart: &'static str,
+    pub no_file_at_position: fn(usize) -> String,
+    pub size_out_of_range: fn(&Path) -> String,
+    pub unchanged_since_last_run: fn(&Path) -> String,
+    pub suggestion_sampled: fn(usize, usize, usize) -> String,
+    pub role_fixed: &'static str,
+    pub role_key: &'static str,
+    pub role_version: &'static str,
+    pub suggestion_field: fn(usize, &str, &str, &str) -> String,
+    pub suggestion_built_in: &'static str,
+    pub suggestion: &'static str,
+    pub suggestion_hint: &'static str,
+    
+    // Compared directories
+    pub dirs_tree: fn(&Path, &Path) -> String,
+    pub dirs_manifest: fn(&Path, &Path, &Path) -> String,
+    pub dirs_candidates: fn(&Path, &str) -> String,
+    pub dirs_pair: fn(&Path, &Path) -> String,
+    pub dirs_versions: fn(&Path) -> String,
+    pub list_separator: &'static str,
+    
+    // Report header and comparison info
+    pub report_title: &'static str,
+    pub generated_at: &'static str,
+    pub comparison_info: &'static str,
+    pub compared_dirs: &'static str,
+    pub ordered_by_field: fn(usize) -> String,
+    pub ordered: &'static str,
+    pub key_only: fn(&str) -> String,
+    pub scope_head: fn(usize) -> String,
+    pub scope_section: fn(&str) -> String,
+    pub scope_truncate_at: fn(&str) -> String,
+    pub scope_sample: fn(usize) -> String,
+    pub scope_incremental: fn(&str) -> String,
+    pub report_range: fn(&str, f64, f64) -> String,
+    pub pair_count: &'static str,
+    pub partial_run: fn(u64, usize) -> String,
+    
+    // Report sections
+    pub tree_section: &'static str,
+    pub in_both_trees: &'static str,
+    pub only_in_first_dir: &'static str,
+    pub only_in_second_dir: &'static str,
+    pub overview_section: &'static str,
+    pub diff_section: fn(usize) -> String,
+    pub file1: &'static str,
+    pub file2: &'static str,
+    pub version: &'static str,
+    pub version_gap_exceeded: fn(u64, u64) -> String,
+    pub version_gap_entry: fn(&str, u64) -> String,
+    pub similarity: &'static str,
+    pub weighted: &'static str,
+    pub truncated_lines: fn(usize, usize) -> String,
+    pub line_counts: &'static str,
+    pub line_count_values: fn(usize, usize) -> String,
+    pub common_edges: &'static str,
+    pub common_edge_values: fn(usize, usize) -> String,
+    pub count_mismatch: &'static str,
+    pub accepted_pair: &'static str,
+    pub count_mismatch_section: &'static str,
+    pub truncation_section: &'static str,
+    pub missing_final_newline: fn(&str) -> String,
+    pub size_ratio: fn(f64) -> String,
+    pub changed_records: &'static str,
+    pub none: &'static str,
+    pub modified_lines: &'static str,
+    pub whitespace_only_lines: &'static str,
+    pub trailing_whitespace: fn(&str, &str) -> String,
+    pub only_in: fn(&Path) -> String,
+    pub keys_only_in: fn(&Path) -> String,
+    pub comparison_error: &'static str,
+    pub error_message: &'static str,
+    pub detected_encoding: fn(&str) -> String,
+    pub split_section: fn(&Path) -> String,
+    pub not_compared_section: &'static str,
+    pub memory_section: fn(usize) -> String,
+    pub unpaired_section: &'static str,
+    pub key_coverage_section: &'static str,
+    pub encoding_section: fn(&str) -> String,
+    pub reordered_section: &'static str,
+    pub same_file_section: &'static str,
+    pub accepted_section: &'static str,
+    pub equivalence_section: &'static str,
+    pub occurrences: fn(usize) -> String,
+    pub rule_usage_section: &'static str,
+    pub lines_affected: fn(usize) -> String,
+    pub rule_unused: &'static str,
+    pub identical_section: &'static str,
+    pub identical_hashes_section: fn(&str) -> String,
+    pub duplicates_section: &'static str,
+    pub repeated_lines: fn(usize) -> String,
+    pub similarity_value: fn(f64) -> String,
+    pub comparison_failed: &'static str,
+    pub candidates_section: fn(&str) -> String,
+    pub alternate: &'static str,
+    pub version_gap_section: fn(u64) -> String,
+    
+    // Merged report (merge-reports)
+    pub merged_report_title: &'static str,
+    pub merged_sources: &'static str,
+    pub merged_source: &'static str,
+    pub merged_duplicates: &'static str,
+    
+    pub bucket_report_title: &'static str,
+    pub bucket_field: fn(usize) -> String,
+    pub bucket: &'static str,
+    pub delta: &'static str,
+    pub bucket_counts_differ: &'static str,
+    pub bucket_changed_pairs: &'static str,
+    
+    // Report summary
+    pub summary: &'static str,
+    pub differing_pairs: &'static str,
+    pub whitespace_only_pairs: &'static str,
+    pub accepted_pairs: &'static str,
+    pub in_range_pairs: fn(f64, f64) -> String,
+    pub too_different_pairs: fn(f64) -> String,
+    pub near_identical_pairs: fn(f64) -> String,
+    pub error_pairs: &'static str,
+    pub encoding_error_files: &'static str,
+    pub identical_pairs: &'static str,
+    pub same_file_pairs: &'static str,
+    pub reordered_pairs: &'static str,
+    pub count_mismatch_pairs: &'static str,
+    pub truncated_pairs: &'static str,
+    pub not_compared_pairs: &'static str,
+    pub unpaired_files: &'static str,
+    pub expected_keys_paired: &'static str,
+    pub expected_keys_not_paired: &'static str,
+    pub files_with_duplicates: &'static str,
+    pub processed: &'static str,
+}
+
+static ZH: Messages = Messages {
+    progress: |completed, total, percent, seconds| format!("进度: {}/{} 个文件对 ({:.1}%)，已用时 {:.0} 秒", completed, total, percent, seconds),
+    stat_total: |pairs, added, removed| format!(" {} 个文件对有差异，新增 {} 行 (+)，删除 {} 行 (-)", pairs, added, removed),
+    throughput: |mebibytes, pairs, seconds, mebibytes_per_second, pairs_per_second|
+        format!("{:.2} MiB / {} 个文件对，耗时 {:.3} 秒 ({:.2} MiB/s，{:.2} 对/s)", mebibytes, pairs, seconds, mebibytes_per_second, pairs_per_second),
+    progress_finished: "比较完成",
+    finished: "比较完成！",
+    in_range_console: |in_range, too_different, near_identical|
+        format!("相似度在范围内: {}，差异过大: {}，几乎相同: {}", in_range, too_different, near_identical),
+    not_compared_console: "未比较的文件对 (已达到时限)",
+    report_saved: "详细报告已保存到",
+    report_write_failed: |path, error| format!("无法写入报告文件 {:?}: {}，报告内容将输出到标准输出", path, error),
+    pivot_saved: "差异行透视文件已保存到",
+    errors_saved: "错误日志已保存到",
+    patches_saved: "补丁文件已保存到目录",
+    conflicts_saved: "冲突标记文件已保存到目录",
+    processed_saved: "处理后的行已保存到目录",
+    split_saved: "各差异文件对的详情已保存到目录",
+    #[cfg(feature = "sqlite")]
+    sqlite_saved: "结果已写入 SQLite 数据库",
+    state_updated: "状态文件已更新",
+    state_not_updated: "有出错或未比较的文件对，状态文件未更新，下次增量运行将重新比较这些文件",
+    encoding_gate: |count, encoding| format!("{} 个文件的编码不是 {}", count, encoding),
+    diff_threshold_gate: |count, threshold| format!("差异文件对数量 {} 超过阈值 {}", count, threshold),
+    line_threshold_gate: |count, threshold| format!("差异行总数 {} 超过阈值 {}", count, threshold),
+    unaccepted_gate: |count| format!("{} 个不在接受列表中的文件对存在差异", count),
+    severity_gate: |band, code| format!("差异程度: {} (退出码 {})", band, code),
+    severity_identical: "无差异",
+    severity_minor: "轻微差异",
+    severity_major: "严重差异",
+    severity_error: "比较出错",
+    incremental_since: |last_run| format!("增量比较: 只比较 {} 之后修改过的文件对", last_run),
+    incremental_no_state: |state_path| format!("增量比较: 状态文件 {:?} 尚不存在，将比较全部文件对", state_path),
+    unpaired_notice: |count| format!("注意: {} 个文件在两个目录中同名，但文件名不符合命名规则，未参与比较:", count),
+    key_coverage_notice: |paired, expected| format!("预期匹配键: {} 个中 {} 个已配对", expected, paired),
+    keys_missing_from_both: "两个目录中都缺少",
+    keys_missing_from_first: "第一个目录中缺少",
+    keys_missing_from_second: "第二个目录中缺少",
+    keys_not_paired: "两个目录中都有但未配对 (被文件大小或修改时间条件排除)",
+    no_modified_pairs: "自上次增量运行以来没有修改过的文件对。",
+    no_pairs_found: "在目录间未找到匹配的文件对。",
+    same_dirs: "⚠ DIR1 和 DIR2 是同一目录，比较不会发现任何差异；确认要比较请加上 --force",
+    duplicate_dirs: |samples| format!("⚠ 抽查的 {} 个文件对全部完全相同，两个目录可能是彼此的副本；确认要比较请加上 --force", samples),
+    pairs_found: |count| format!("找到 {} 个文件对进行比较。", count),
+    
+    name_not_utf8: "文件名不是有效的 UTF-8",
+    name_too_few_fields: |count| format!("文件名只有 {} 个以 _ 分隔的字段，至少需要 6 个", count),
+    name_wrong_prefix: |prefix| format!("文件名前缀为 {}，应为 SC（--case-insensitive-names 可忽略大小写）", prefix),
+    name_wrong_suffix: |suffix| format!("文件名后缀为 {}，应为 Z 或匹配 --suffix-pattern（--case-insensitive-names 可忽略大小写）", suffix),
+    file: "文件",
+    name_fields: "文件名字段",
+    name_pattern_mismatch: "不符合 SC_aaaaaaaa_yyyymmdd_tttN_AXX_Z 命名规则",
+    match_by: "配对方式",
+    match_key: "匹配键",
+    match_key_by_position: "无（按排序后的位置配对）",
+    match_key_unavailable: "无法生成",
+    result: "结果",
+    matched: |file2_path| format!("匹配到 {}", file2_path.display()),
+    unmatched: |reason| format!("未匹配：{}", reason),
+    key_regex_mismatch: "--key-regex 与文件名不匹配",
+    key_not_selected: "匹配键不在 --keys-from 列表中",
+    no_counterpart: "DIR2 中没有匹配键相同的文件",
+    no_file_at_position: |position| format!("DIR2 中没有第 {} 个文件", position),
+    size_out_of_range: |file2_path| format!("对应文件 {} 存在，但文件对超出 --min-file-size/--max-file-size 范围", file2_path.display()),
+    unchanged_since_last_run: |file2_path| format!("对应文件 {} 存在，但两个文件自上次增量运行以来都未修改", file2_path.display()),
+    suggestion_sampled: |sampled, matching, fields| format!("采样文件名: {} 个，其中 {} 个有 {} 个以 _ 分隔的字段，据此推断模式", sampled, matching, fields),
+    role_fixed: "固定",
+    role_key: "匹配键",
+    role_version: "版本（各目录内不变，目录之间不同）",
+    suggestion_field: |position, pattern, example, role| format!("  字段 {}: {:<16} 示例 {:<16} {}", position, pattern, example, role),
+    suggestion_built_in: "所有文件名都符合内置的 SC_aaaaaaaa_yyyymmdd_tttN_AXX_Z 命名规则，无需 --key-regex",
+    suggestion: "建议",
+    suggestion_hint: "可先用 explain-pairing 子命令检查个别文件的配对结果",
+    
+    dirs_tree: |dir1, dir2| format!("{:?} 和 {:?} 的目录树", dir1, dir2),
+    dirs_manifest: |manifest, dir1, dir2| format!("{:?} 中列出的文件对 ({:?} 和 {:?})", manifest, dir1, dir2),
+    dirs_candidates: |dir1, dir2s| format!("{:?} 和 {} (每个文件选择最相似的候选)", dir1, dir2s),
+    dirs_pair: |dir1, dir2| format!("{:?} 和 {:?}", dir1, dir2),
+    dirs_versions: |dir1| format!("{:?} 中相邻版本的文件", dir1),
+    list_separator: "、",
+    
+    report_title: "文件比较报告",
+    generated_at: "生成时间",
+    comparison_info: "比较信息",
+    compared_dirs: "比较目录",
+    ordered_by_field: |field| format!("比较方式: 按第 {} 个字段排序后逐行按顺序比较", field),
+    ordered: "比较方式: 逐行按顺序比较",
+    key_only: |delimiter| format!("比较方式: 仅比较每行的第一个字段 (以 {:?} 分隔) 组成的键", delimiter),
+    scope_head: |head| format!("比较范围: 每个文件仅比较首行之后的前 {} 行", head),
+    scope_section: |section| format!("比较范围: 仅比较 [{}] 节中的行", section),
+    scope_truncate_at: |truncate_at| format!("比较范围: 每个文件从第一个匹配 {} 的行起截断", truncate_at),
+    scope_sample: |rate| format!("⚠ 抽样比较: 按行内容哈希仅比较约 1/{} 的行，结果并不完整", rate),
+    scope_incremental: |last_run| format!("比较范围: 仅比较 {} (上次增量运行) 之后修改过的文件对", last_run),
+    report_range: |metric, low, high| format!("报告范围: 仅列出相似度 ({}) 在 {:.2}%–{:.2}% 之间的差异文件对", metric, low, high),
+    pair_count: "文件对数量",
+    partial_run: |deadline, count| format!("⚠ 部分运行 (已达到 {} 秒时限)：报告仅包含已完成比较的文件对，{} 个文件对未比较", deadline, count),
+    
+    tree_section: "目录结构差异",
+    in_both_trees: "两侧共有的文件",
+    only_in_first_dir: "仅在第一个目录中存在的文件",
+    only_in_second_dir: "仅在第二个目录中存在的文件",
+    overview_section: "差异概览",
+    diff_section: |number| format!("发现差异的文件对 #{}", number),
+    file1: "文件 1",
+    file2: "文件 2",
+    version: "版本",
+    version_gap_exceeded: |gap, threshold| format!("⚠ 版本号相差 {}，超过阈值 {}，可能在比较相距较远的版本", gap, threshold),
+    version_gap_entry: |transition, gap| format!("版本 {}，相差 {}", transition, gap),
+    similarity: "相似度",
+    weighted: ", 加权",
+    truncated_lines: |truncated1, truncated2| format!("截断的行: 文件 1 {} 行，文件 2 {} 行 (未参与比较)", truncated1, truncated2),
+    line_counts: "行数",
+    line_count_values: |count1, count2| format!("文件 1 {} 行，文件 2 {} 行", count1, count2),
+    common_edges: "相同的首尾行",
+    common_edge_values: |prefix, suffix| format!("开头 {} 行，结尾 {} 行", prefix, suffix),
+    count_mismatch: "⚠ 两个文件的行数不一致",
+    accepted_pair: "差异已接受 (--accept-pairs)，不计入失败判定",
+    count_mismatch_section: "行数不一致的文件对",
+    truncation_section: "可能被截断的文件",
+    missing_final_newline: |file| format!("{} 缺少结尾换行符", file),
+    size_ratio: |ratio| format!("文件大小相差 {:.1} 倍", ratio),
+    changed_records: "修改的记录",
+    none: "(无)",
+    modified_lines: "修改的行",
+    whitespace_only_lines: "仅行尾空白不同的行 (低严重性)",
+    trailing_whitespace: |trailing1, trailing2| format!("文件 1 行尾 {}，文件 2 行尾 {}", trailing1, trailing2),
+    only_in: |file_path| format!("仅在 {} 中存在的行", file_path.display()),
+    keys_only_in: |file_path| format!("仅在 {} 中存在的键", file_path.display()),
+    comparison_error: "比较错误",
+    error_message: "错误信息",
+    detected_encoding: |encoding| format!("检测到 {}", encoding),
+    split_section: |split_dir| format!("差异详情文件 (目录 {:?})", split_dir),
+    not_compared_section: "未比较 (已达到时限)",
+    memory_section: |count| format!("内存估算 (估计峰值最高的 {} 个文件对，不是实际 RSS)", count),
+    unpaired_section: "同名但未配对的文件 (文件名不符合命名规则，未比较)",
+    key_coverage_section: "未配对的预期匹配键",
+    encoding_section: |encoding| format!("编码错误 (要求 {})", encoding),
+    reordered_section: "内容相同但行顺序不同",
+    same_file_section: "同一文件 (硬链接)，已跳过比较",
+    accepted_section: "已接受差异的文件对 (--accept-pairs)",
+    equivalence_section: "已应用的等价映射规则",
+    occurrences: |count| format!("{} 处", count),
+    rule_usage_section: "规则命中统计",
+    lines_affected: |count| format!("{} 行", count),
+    rule_unused: "⚠ 未命中任何行",
+    identical_section: "完全相同的文件对",
+    identical_hashes_section: |algorithm| format!("完全相同的文件对及其哈希 ({})", algorithm),
+    duplicates_section: "文件内重复行",
+    repeated_lines: |count| format!("{} 行重复", count),
+    similarity_value: |percent| format!("相似度 {:.2}%", percent),
+    comparison_failed: "比较出错",
+    candidates_section: |metric| format!("候选匹配 ({})", metric),
+    alternate: "备选",
+    version_gap_section: |threshold| format!("版本差距警告 (版本号相差超过 {})", threshold),
+    
+    merged_report_title: "合并的文件比较报告",
+    merged_sources: "合并的结果流",
+    merged_source: "来源",
+    merged_duplicates: "在多个结果流中重复的文件对 (已去重)",
+    
+    bucket_report_title: "分桶记录数报告",
+    bucket_field: |field| format!("分桶字段: 第 {} 列", field),
+    bucket: "分桶",
+    delta: "差值",
+    bucket_counts_differ: "← 记录数不同",
+    bucket_changed_pairs: "分桶记录数不同的文件对",
+    
+    summary: "统计摘要",
+    differing_pairs: "发现差异的文件对",
+    whitespace_only_pairs: "其中仅行尾空白不同",
+    accepted_pairs: "其中差异已接受",
+    in_range_pairs: |low, high| format!("其中相似度在 {:.2}%–{:.2}% 之间 (已列出)", low, high),
+    too_different_pairs: |low| format!("其中差异过大 (低于 {:.2}%，未列出)", low),
+    near_identical_pairs: |high| format!("其中几乎相同 (高于 {:.2}%，未列出)", high),
+    error_pairs: "比较出错的文件对",
+    encoding_error_files: "编码不符合要求的文件",
+    identical_pairs: "完全相同的文件对",
+    same_file_pairs: "其中同一文件 (硬链接)",
+    reordered_pairs: "其中行顺序不同",
+    count_mismatch_pairs: "行数不一致的文件对",
+    truncated_pairs: "可能被截断的文件对",
+    not_compared_pairs: "未比较的文件对",
+    unpaired_files: "同名但未配对的文件",
+    expected_keys_paired: "已配对的预期匹配键",
+    expected_keys_not_paired: "未配对的预期匹配键",
+    files_with_duplicates: "包含重复行的文件",
+    processed: "处理量",
+};
+
+static EN: Messages = Messages {
+    progress: |completed, total, percent, seconds| format!("Progress: {}/{} file pairs ({:.1}%), {:.0} s elapsed", completed, total, percent, seconds),
+    stat_total: |pairs, added, removed| format!(" {} file pairs differ, {} lines added (+), {} lines removed (-)", pairs, added, removed),
+    throughput: |mebibytes, pairs, seconds, mebibytes_per_second, pairs_per_second|
+        format!("{:.2} MiB / {} file pairs in {:.3} s ({:.2} MiB/s, {:.2} pairs/s)", mebibytes, pairs, seconds, mebibytes_per_second, pairs_per_second),
+    progress_finished: "Comparison finished",
+    finished: "Comparison finished!",
+    in_range_console: |in_range, too_different, near_identical|
+        format!("Similarity in range: {}, too different: {}, near identical: {}", in_range, too_different, near_identical),
+    not_compared_console: "Pairs not compared (deadline reached)",
+    report_saved: "Detailed report saved to",
+    report_write_failed: |path, error| format!("Failed to write report file {:?}: {}, printing the report to standard output", path, error),
+    pivot_saved: "Differing line pivot saved to",
+    errors_saved: "Error log saved to",
+    patches_saved: "Patch files saved to directory",
+    conflicts_saved: "Conflict-marked files saved to directory",
+    processed_saved: "Processed lines saved to directory",
+    split_saved: "Per-pair difference details saved to directory",
+    #[cfg(feature = "sqlite")]
+    sqlite_saved: "Results written to SQLite database",
+    state_updated: "State file updated",
+    state_not_updated: "Some pairs failed or weren't compared, so the state file wasn't updated; the next incremental run compares them again",
+    encoding_gate: |count, encoding| format!("{} files are not encoded in {}", count, encoding),
+    diff_threshold_gate: |count, threshold| format!("{} differing pairs exceed the threshold of {}", count, threshold),
+    line_threshold_gate: |count, threshold| format!("{} differing lines exceed the threshold of {}", count, threshold),
+    unaccepted_gate: |count| format!("{} pairs not on the accept list differ", count),
+    severity_gate: |band, code| format!("Severity: {} (exit code {})", band, code),
+    severity_identical: "no differences",
+    severity_minor: "minor drift",
+    severity_major: "major drift",
+    severity_error: "comparison errors",
+    incremental_since: |last_run| format!("Incremental run: only comparing the pairs modified since {}", last_run),
+    incremental_no_state: |state_path| format!("Incremental run: state file {:?} doesn't exist yet, comparing all pairs", state_path),
+    unpaired_notice: |count| format!("Note: {} files have the same name in both directories but don't follow the naming pattern, so they weren't compared:", count),
+    key_coverage_notice: |paired, expected| format!("Expected match keys: {} of {} paired", paired, expected),
+    keys_missing_from_both: "Missing from both directories",
+    keys_missing_from_first: "Missing from the first directory",
+    keys_missing_from_second: "Missing from the second directory",
+    keys_not_paired: "In both directories but not paired (excluded by the file size or modification time filters)",
+    no_modified_pairs: "No file pairs were modified since the last incremental run.",
+    no_pairs_found: "No matching file pairs found between the directories.",
+    same_dirs: "⚠ DIR1 and DIR2 are the same directory, so the comparison can't find any difference; add --force to compare anyway",
+    duplicate_dirs: |samples| format!("⚠ All {} sampled file pairs are identical, the directories may be copies of each other; add --force to compare them anyway", samples),
+    pairs_found: |count| format!("Found {} file pairs to compare.", count),
+    
+    name_not_utf8: "the file name isn't valid UTF-8",
+    name_too_few_fields: |count| format!("the file name has only {} fields separated by _, at least 6 are needed", count),
+    name_wrong_prefix: |prefix| format!("the file name prefix is {}, expected SC (--case-insensitive-names ignores case)", prefix),
+    name_wrong_suffix: |suffix| format!("the file name suffix is {}, expected Z or a --suffix-pattern match (--case-insensitive-names ignores case)", suffix),
+    file: "File",
+    name_fields: "File name fields",
+    name_pattern_mismatch: "doesn't follow the SC_aaaaaaaa_yyyymmdd_tttN_AXX_Z naming pattern",
+    match_by: "Pairing",
+    match_key: "Match key",
+    match_key_by_position: "none (paired by position after sorting)",
+    match_key_unavailable: "can't be built",
+    result: "Result",
+    matched: |file2_path| format!("matched with {}", file2_path.display()),
+    unmatched: |reason| format!("not matched: {}", reason),
+    key_regex_mismatch: "--key-regex doesn't match the file name",
+    key_not_selected: "the match key isn't in the --keys-from list",
+    no_counterpart: "DIR2 has no file with the same match key",
+    no_file_at_position: |position| format!("DIR2 has no file #{}", position),
+    size_out_of_range: |file2_path| format!("counterpart {} exists, but the pair is outside the --min-file-size/--max-file-size range", file2_path.display()),
+    unchanged_since_last_run: |file2_path| format!("counterpart {} exists, but neither file was modified since the last incremental run", file2_path.display()),
+    suggestion_sampled: |sampled, matching, fields| format!("Sampled file names: {}, of which {} have {} fields separated by _, used to infer the pattern", sampled, matching, fields),
+    role_fixed: "fixed",
+    role_key: "match key",
+    role_version: "version (constant within each directory, different across directories)",
+    suggestion_field: |position, pattern, example, role| format!("  Field {}: {:<16} e.g. {:<16} {}", position, pattern, example, role),
+    suggestion_built_in: "All file names follow the built-in SC_aaaaaaaa_yyyymmdd_tttN_AXX_Z naming pattern, no --key-regex is needed",
+    suggestion: "Suggestion",
+    suggestion_hint: "Use the explain-pairing subcommand to check how individual files are paired",
+    
+    dirs_tree: |dir1, dir2| format!("directory trees {:?} and {:?}", dir1, dir2),
+    dirs_manifest: |manifest, dir1, dir2| format!("file pairs listed in {:?} ({:?} and {:?})", manifest, dir1, dir2),
+    dirs_candidates: |dir1, dir2s| format!("{:?} and {} (the most similar candidate is picked for each file)", dir1, dir2s),
+    dirs_pair: |dir1, dir2| format!("{:?} and {:?}", dir1, dir2),
+    dirs_versions: |dir1| format!("adjacent versions of the files in {:?}", dir1),
+    list_separator: ", ",
+    
+    report_title: "File Comparison Report",
+    generated_at: "Generated at",
+    comparison_info: "Comparison Info",
+    compared_dirs: "Compared",
+    ordered_by_field: |field| format!("Comparison: line by line in order, after sorting by field {}", field),
+    ordered: "Comparison: line by line in order",
+    key_only: |delimiter| format!("Comparison: only the keys in the first field of each line (delimited by {:?})", delimiter),
+    scope_head: |head| format!("Scope: only the first {} lines after the first line of each file", head),
+    scope_section: |section| format!("Scope: only the lines in section [{}]", section),
+    scope_truncate_at: |truncate_at| format!("Scope: each file is truncated from the first line matching {}", truncate_at),
+    scope_sample: |rate| format!("⚠ Sampled comparison: only about 1 in {} lines (by content hash) compared, results are not exhaustive", rate),
+    scope_incremental: |last_run| format!("Scope: only the pairs modified since {} (last incremental run)", last_run),
+    report_range: |metric, low, high| format!("Reported: only the differing pairs with a similarity ({}) between {:.2}% and {:.2}%", metric, low, high),
+    pair_count: "File pairs",
+    partial_run: |deadline, count| format!("⚠ Partial run ({} s deadline reached): the report only covers the compared pairs, {} pairs weren't compared", deadline, count),
+    
+    tree_section: "Directory Structure Differences",
+    in_both_trees: "Files in both trees",
+    only_in_first_dir: "Files only in the first directory",
+    only_in_second_dir: "Files only in the second directory",
+    overview_section: "Differences Overview",
+    diff_section: |number| format!("Differing Pair #{}", number),
+    file1: "File 1",
+    file2: "File 2",
+    version: "Version",
+    version_gap_exceeded: |gap, threshold| format!("⚠ Versions are {} apart, more than the threshold of {}; distant versions may be compared", gap, threshold),
+    version_gap_entry: |transition, gap| format!("versions {}, {} apart", transition, gap),
+    similarity: "Similarity",
+    weighted: ", weighted",
+    truncated_lines: |truncated1, truncated2| format!("Truncated lines: {} in file 1, {} in file 2 (not compared)", truncated1, truncated2),
+    line_counts: "Line counts",
+    line_count_values: |count1, count2| format!("{} in file 1, {} in file 2", count1, count2),
+    common_edges: "Identical leading/trailing lines",
+    common_edge_values: |prefix, suffix| format!("{} leading, {} trailing", prefix, suffix),
+    count_mismatch: "⚠ The files have different line counts",
+    accepted_pair: "Differences accepted (--accept-pairs), not counted toward failure",
+    count_mismatch_section: "Pairs with Different Line Counts",
+    truncation_section: "Possibly Truncated Files",
+    missing_final_newline: |file| format!("{} lacks the final newline", file),
+    size_ratio: |ratio| format!("sizes differ by a factor of {:.1}", ratio),
+    changed_records: "Changed records",
+    none: "(none)",
+    modified_lines: "Modified lines",
+    whitespace_only_lines: "Lines differing only in trailing whitespace (low severity)",
+    trailing_whitespace: |trailing1, trailing2| format!("file 1 ends with {}, file 2 ends with {}", trailing1, trailing2),
+    only_in: |file_path| format!("Lines only in {}", file_path.display()),
+    keys_only_in: |file_path| format!("Keys only in {}", file_path.display()),
+    comparison_error: "Comparison Error",
+    error_message: "Error",
+    detected_encoding: |encoding| format!("detected {}", encoding),
+    split_section: |split_dir| format!("Difference Detail Files (directory {:?})", split_dir),
+    not_compared_section: "Not Compared (deadline reached)",
+    memory_section: |count| format!("Memory Estimates (the {} pairs with the highest estimated peak, not actual RSS)", count),
+    unpaired_section: "Same-Named Files Not Paired (names don't follow the naming pattern, not compared)",
+    key_coverage_section: "Expected Match Keys Not Paired",
+    encoding_section: |encoding| format!("Encoding Errors ({} required)", encoding),
+    reordered_section: "Same Content in a Different Line Order",
+    same_file_section: "Same File (hardlinks), Comparison Skipped",
+    accepted_section: "Accepted Differing Pairs (--accept-pairs)",
+    equivalence_section: "Applied Equivalence Rules",
+    occurrences: |count| format!("{} occurrences", count),
+    rule_usage_section: "Rule Usage",
+    lines_affected: |count| format!("{} lines", count),
+    rule_unused: "⚠ never matched",
+    identical_section: "Identical Pairs",
+    identical_hashes_section: |algorithm| format!("Identical Pairs and Their Hashes ({})", algorithm),
+    duplicates_section: "Lines Repeated Within a File",
+    repeated_lines: |count| format!("{} repeated lines", count),
+    similarity_value: |percent| format!("similarity {:.2}%", percent),
+    comparison_failed: "comparison failed",
+    candidates_section: |metric| format!("Candidate Matches ({})", metric),
+    alternate: "Alternate",
+    version_gap_section: |threshold| format!("Version Gap Warnings (versions more than {} apart)", threshold),
+    
+    merged_report_title: "Merged File Comparison Report",
+    merged_sources: "Merged result streams",
+    merged_source: "Source",
+    merged_duplicates: "Pairs repeated across streams (de-duplicated)",
+    
+    bucket_report_title: "Record Counts per Bucket",
+    bucket_field: |field| format!("Bucket field: column {}", field),
+    bucket: "Bucket",
+    delta: "Delta",
+    bucket_counts_differ: "← counts differ",
+    bucket_changed_pairs: "Pairs with differing bucket counts",
+    
+    summary: "Summary",
+    differing_pairs: "Differing pairs",
+    whitespace_only_pairs: "of which only trailing whitespace differs",
+    accepted_pairs: "of which differences are accepted",
+    in_range_pairs: |low, high| format!("of which with a similarity between {:.2}% and {:.2}% (listed)", low, high),
+    too_different_pairs: |low| format!("of which too different (below {:.2}%, not listed)", low),
+    near_identical_pairs: |high| format!("of which near identical (above {:.2}%, not listed)", high),
+    error_pairs: "Pairs with comparison errors",
+    encoding_error_files: "Files not in the required encoding",
+    identical_pairs: "Identical pairs",
+    same_file_pairs: "of which the same file (hardlinks)",
+    reordered_pairs: "of which in a different line order",
+    count_mismatch_pairs: "Pairs with different line counts",
+    truncated_pairs: "Possibly truncated pairs",
+    not_compared_pairs: "Pairs not compared",
+    unpaired_files: "Same-named files not paired",
+    expected_keys_paired: "Expected match keys paired",
+    expected_keys_not_paired: "Expected match keys not paired",
+    files_with_duplicates: "Files with repeated lines",
+    processed: "Processed",
+};
@@ -0,0 +1,295 @@
+//! Ordered (line-by-line) diff functions for the tbcompare tool.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use similar::{capture_diff_slices, Algorithm, DiffOp, TextDiff};
+use crate::comparison::pair_output_path;
+use crate::file_utils::read_input;
+
+/// Number of unchanged context lines around each hunk in a unified diff
+const UNIFIED_CONTEXT_LINES: usize = 3;
+
+/// Line-level changes between two sequences of lines, in order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OrderedDiff {
+    /// Lines of the first sequence that were removed, in order
+    pub removed: Vec<String>,
+    /// Lines of the second sequence that were added, in order
+    pub added: Vec<String>,
+    /// Number of lines left unchanged
+    pub unchanged: usize,
+    /// Number of identical lines before the first change
+    pub common_prefix: usize,
+    /// Number of identical lines after the last change (not overlapping the prefix)
+    pub common_suffix: usize,
+}
+
+impl OrderedDiff {
+    /// Returns true if the two sequences are identical
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty() && self.added.is_empty()
+    }
+}
+
+/// Computes the ordered (positional) diff between two sequences of lines
+/// 
+/// Unlike the set-based comparison, moved and duplicated lines count as changes. The identical leading
+/// and trailing lines tell whether the changes are a localized edit or spread over the whole file.
+/// 
+/// # Arguments
+/// 
+/// * `lines1` - Lines of the first file
+/// * `lines2` - Lines of the second file
+/// 
+/// # Returns
+/// 
+/// The removed, added and unchanged lines, and the lengths of the common prefix and suffix
+pub fn diff_ordered(lines1: &[String], lines2: &[String]) -> OrderedDiff {
+    let common_prefix = lines1.iter().zip(lines2).take_while(|(line1, line2)| line1 == line2).count();
+    let common_suffix = lines1[common_prefix..].iter().rev()
+        .zip(lines2[common_prefix..].iter().rev())
+        .take_while(|(line1, line2)| line1 == line2)
+        .count();
+    let mut diff = OrderedDiff { common_prefix, common_suffix, ..OrderedDiff::default() };
+    for op in capture_diff_slices(Algorithm::Myers, lines1, lines2) {
+        match op {
+            DiffOp::Equal { len, .. } => diff.unchanged += len,
+            DiffOp::Delete { old_index, old_len, .. } => {
+                diff.removed.extend_from_slice(&lines1[old_index..old_index + old_len]);
+            }
+            DiffOp::Insert { new_index, new_len, .. } => {
+                diff.added.extend_from_slice(&lines2[new_index..new_index + new_len]);
+            }
+            DiffOp::Replace { old_index, old_len, new_index, new_len } => {
+                diff.removed.extend_from_slice(&lines1[old_index..old_index + old_len]);
+                diff.added.extend_from_slice(&lines2[new_index..new_index + new_len]);
+            }
+        }
+    }
+    diff
+}
+
+/// Builds a unified diff that transforms the first file into the second
+///
+/// The diff is computed on the raw bytes of both files (header line included, original order kept),
+/// so applying it with `patch file1 < patch` reproduces the second file exactly, whatever the encoding.
+/// Gzip-compressed files are diffed on their decompressed content.
+///
+/// # Arguments
+///
+/// * `file1_path` - Path to the first (original) file
+/// * `file2_path` - Path to the second (target) file
+///
+/// # Returns
+///
+/// A Result containing the patch contents, or `None` if the files are byte-identical
+pub fn unified_patch<P: AsRef<Path>>(file1_path: P, file2_path: P) -> Result<Option<Vec<u8>>> {
+    let file1_path = file1_path.as_ref();
+    let file2_path = file2_path.as_ref();
+    
+    let content1 = read_input(file1_path)?;
+    let content2 = read_input(file2_path)?;
+    
+    if content1 == content2 {
+        return Ok(None);
+    }
+    
+    let diff = TextDiff::from_lines(content1.as_slice(), content2.as_slice());
+    let mut patch = Vec::new();
+    diff.unified_diff()
+        .context_radius(UNIFIED_CONTEXT_LINES)
+        .header(&file1_path.display().to_string(), &file2_path.display().to_string())
+        .to_writer(&mut patch)
+        .context("Failed to serialize unified diff")?;
+    
+    Ok(Some(patch))
+}
+
+/// Writes the unified diff of a file pair into a patch file in `output_dir`
+///
+/// The patch file is named after the pair, see [`pair_output_path`]: `<file1 stem>__<file2 stem>.<pair id>.patch`.
+///
+/// # Arguments
+///
+/// * `file1_path` - Path to the first (original) file
+/// * `file2_path` - Path to the second (target) file
+/// * `output_dir` - Directory the patch file is written to
+///
+/// # Returns
+///
+/// A Result containing the path of the written patch, or `None` if the files are byte-identical
+pub fn write_patch_file<P: AsRef<Path>>(file1_path: P, file2_path: P, output_dir: &Path) -> Result<Option<PathBuf>> {
+    let file1_path = file1_path.as_ref();
+    let file2_path = file2_path.as_ref();
+    
+    let Some(patch) = unified_patch(file1_path, file2_path)? else {
+        return Ok(None);
+    };
+    
+    let patch_path = pair_output_path(file1_path, file2_path, output_dir, "patch");
+    fs::write(&patch_path, patch)
+        .with_context(|| format!("Failed to write patch file: {}", patch_path.display()))?;
+    
+    Ok(Some(patch_path))
+}
+
+/// Builds a copy of the files with each differing region between Git-style conflict markers
+///
+/// Like [`unified_patch`], the regions come from a positional diff of the raw content of both files
+/// (header line included, original order kept). Unchanged lines are copied once; each run of changed
+/// lines becomes `<<<<<<< file1`, the first file's lines, `=======`, the second file's lines and
+/// `>>>>>>> file2`, so the result can be resolved in any editor that understands merge conflicts.
+///
+/// # Arguments
+///
+/// * `file1_path` - Path to the first file
+/// * `file2_path` - Path to the second file
+///
+/// # Returns
+///
+/// A Result containing the conflict-marked contents, or `None` if the files are byte-identical
+pub fn conflict_merge<P: AsRef<Path>>(file1_path: P, file2_path: P) -> Result<Option<Vec<u8>>> {
+    let file1_path = file1_path.as_ref();
+    let file2_path = file2_path.as_ref();
+    
+    let content1 = read_input(file1_path)?;
+    let content2 = read_input(file2_path)?;
+    
+    if content1 == content2 {
+        return Ok(None);
+    }
+    
+    let diff = TextDiff::from_lines(content1.as_slice(), content2.as_slice());
+    let (lines1, lines2) = (diff.old_slices(), diff.new_slices());
+    let mut merged = Vec::new();
+    // Consecutive changes (e.g. a deletion followed by an insertion) make up a single conflict,
+    // starting at these line indices of both files
+    let mut conflict_start: Option<(usize, usize)> = None;
+    for op in diff.ops() {
+        match *op {
+            DiffOp::Equal { old_index, new_index, len } => {
+                if let Some((start1, start2)) = conflict_start.take() {
+                    write_conflict(&mut merged, file1_path, &lines1[start1..old_index], file2_path, &lines2[start2..new_index]);
+                }
+                merged.extend(lines1[old_index..old_index + len].concat());
+            }
+            _ => {
+                conflict_start.get_or_insert((op.old_range().start, op.new_range().start));
+            }
+        }
+    }
+    if let Some((start1, start2)) = conflict_start {
+        write_conflict(&mut merged, file1_path, &lines1[start1..], file2_path, &lines2[start2..]);
+    }
+    
+    Ok(Some(merged))
+}
+
+/// Appends one conflict region, ending lines without a terminator so each marker starts its own line
+fn write_conflict(merged: &mut Vec<u8>, file1_path: &Path, ours: &[&[u8]], file2_path: &Path, theirs: &[&[u8]]) {
+    let push_lines = |merged: &mut Vec<u8>, lines: &[&[u8]]| {
+        for line in lines {
+            merged.extend_from_slice(line);
+            if !line.ends_with(b"\n") {
+                merged.push(b'\n');
+            }
+        }
+    };
+    merged.extend_from_slice(format!("<<<<<<< {}\n", file1_path.display()).as_bytes());
+    push_lines(merged, ours);
+    merged.extend_from_slice(b"=======\n");
+    push_lines(merged, theirs);
+    merged.extend_from_slice(format!(">>>>>>> {}\n", file2_path.display()).as_bytes());
+}
+
+/// Writes the conflict-marked copy of a file pair into `output_dir`
+///
+/// The file is named after the pair, see [`pair_output_path`]: `<file1 stem>__<file2 stem>.<pair id>.conflict.txt`.
+///
+/// # Arguments
+///
+/// * `file1_path` - Path to the first file
+/// * `file2_path` - Path to the second file
+/// * `output_dir` - Directory the file is written to
+///
+/// # Returns
+///
+/// A Result containing the path of the written file, or `None` if the files are byte-identical
+pub fn write_conflict_file<P: AsRef<Path>>(file1_path: P, file2_path: P, output_dir: &Path) -> Result<Option<PathBuf>> {
+    let file1_path = file1_path.as_ref();
+    let file2_path = file2_path.as_ref();
+    
+    let Some(merged) = conflict_merge(file1_path, file2_path)? else {
+        return Ok(None);
+    };
+    
+    let merged_path = pair_output_path(file1_path, file2_path, output_dir, "conflict.txt");
+    fs::write(&merged_path, merged)
+        .with_context(|| format!("Failed to write conflict file: {}", merged_path.display()))?;
+    
+    Ok(Some(merged_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::Builder;
+    use anyhow::Result;
+
+    #[test]
+    fn test_diff_ordered() {
+        let lines = |text: &str| text.split(' ').map(String::from).collect::<Vec<_>>();
+        
+        // "b" moved to the end and "e" was inserted before it
+        let diff = diff_ordered(&lines("a b c d"), &lines("a c d e b"));
+        assert_eq!(diff.removed, lines("b"));
+        assert_eq!(diff.added, lines("e b"));
+        assert_eq!(diff.unchanged, 3);
+        assert_eq!((diff.common_prefix, diff.common_suffix), (1, 0));
+        
+        // The suffix doesn't count the lines already in the prefix
+        let diff = diff_ordered(&lines("a b c"), &lines("a b x b c"));
+        assert_eq!((diff.common_prefix, diff.common_suffix), (2, 1));
+        
+        let diff = diff_ordered(&lines("a b"), &lines("a b"));
+        assert!(diff.is_empty());
+        assert_eq!((diff.common_prefix, diff.common_suffix), (2, 0));
+    }
+
+    #[test]
+    fn test_unified_patch() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file1_path = dir.path().join("file1.txt");
+        let file2_path = dir.path().join("file2.txt");
+        fs::write(&file1_path, "Header line\nLine 1\nLine 2\nLine 3\n")?;
+        fs::write(&file2_path, "Header line\nLine 4\nLine 2\nLine 3\n")?;
+        
+        let patch = String::from_utf8(unified_patch(&file1_path, &file2_path)?.unwrap())?;
+        assert!(patch.contains("@@ -1,4 +1,4 @@"));
+        assert!(patch.contains("\n-Line 1\n+Line 4\n"));
+        
+        // Identical files produce no patch
+        assert!(unified_patch(&file1_path, &file1_path)?.is_none());
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_conflict_merge() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let file1_path = dir.path().join("file1.txt");
+        let file2_path = dir.path().join("file2.txt");
+        fs::write(&file1_path, "Header line\nLine 1\nLine 2\nLine 3")?;
+        fs::write(&file2_path, "Header line\nLine 4\nLine 2\nLine 5\nLine 6\n")?;
+        
+        let merged = String::from_utf8(conflict_merge(&file1_path, &file2_path)?.unwrap())?;
+        assert_eq!(merged, format!(
+            "Header line\n<<<<<<< {0}\nLine 1\n=======\nLine 4\n>>>>>>> {1}\nLine 2\n<<<<<<< {0}\nLine 3\n=======\nLine 5\nLine 6\n>>>>>>> {1}\n",
+            file1_path.display(), file2_path.display()));
+        
+        assert!(conflict_merge(&file1_path, &file1_path)?.is_none());
+        
+        Ok(())
+    }
+}
@@ -0,0 +1,222 @@
+//! Live publishing of comparison results over a socket for the tbcompare tool (`publish` feature).
+
+use std::io::Write;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use anyhow::{Context, Result};
+use log::{info, warn};
+
+/// Number of frames queued for a subscriber before it is dropped as too slow
+const SUBSCRIBER_QUEUE_FRAMES: usize = 64;
+
+/// Time a write to a subscriber may block before the subscriber is dropped
+const SUBSCRIBER_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Queues of the connected subscribers, each drained by the subscriber's own writer thread
+type Subscribers = Arc<Mutex<Vec<SyncSender<Arc<[u8]>>>>>;
+
+/// Publishes messages to every client connected to a TCP port or Unix socket
+///
+/// Each message is sent as a frame: its length in bytes as a 4-byte big-endian integer, then the
+/// UTF-8 message itself. Clients may connect at any time and receive the messages published after
+/// they connect. Each client is written to by its own thread, so publishing never waits on a client;
+/// a client that disconnects, can't be written to, or falls too far behind is dropped.
+pub struct Publisher {
+    subscribers: Subscribers,
+    /// Bound TCP address (`None` for a Unix socket)
+    local_addr: Option<SocketAddr>,
+    /// Path of the Unix socket, removed when the publisher is dropped
+    socket_path: Option<std::path::PathBuf>,
+}
+
+impl Publisher {
+    /// Starts listening for subscribers in a background thread
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - `unix:<path>` for a Unix socket, otherwise a TCP address such as `127.0.0.1:7878`
+    ///   (optionally prefixed with `tcp:`)
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either the publisher or an error if the address can't be bound
+    pub fn bind(address: &str) -> Result<Self> {
+        let subscribers: Subscribers = Arc::default();
+        if let Some(socket_path) = address.strip_prefix("unix:") {
+            return Self::bind_unix(socket_path, subscribers);
+        }
+        
+        let address = address.strip_prefix("tcp:").unwrap_or(address);
+        let listener = TcpListener::bind(address)
+            .with_context(|| format!("Failed to listen on TCP address: {}", address))?;
+        let local_addr = listener.local_addr().context("Failed to get the bound TCP address")?;
+        let accepted = Arc::clone(&subscribers);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        info!("Subscriber connected from {}", stream.peer_addr().map_or_else(|_| "?".to_string(), |addr| addr.to_string()));
+                        // Small frames are sent as soon as they are written
+                        let _ = stream.set_nodelay(true);
+                        let _ = stream.set_write_timeout(Some(SUBSCRIBER_WRITE_TIMEOUT));
+                        lock(&accepted).push(spawn_writer(stream));
+                    }
+                    Err(e) => warn!("Failed to accept subscriber: {}", e),
+                }
+            }
+        });
+        
+        Ok(Publisher { subscribers, local_addr: Some(local_addr), socket_path: None })
+    }
+    
+    #[cfg(unix)]
+    fn bind_unix(socket_path: &str, subscribers: Subscribers) -> Result<Self> {
+        use std::os::unix::net::UnixListener;
+        
+        let listener = UnixListener::bind(socket_path)
+            .with_context(|| format!("Failed to listen on Unix socket: {}", socket_path))?;
+        let accepted = Arc::clone(&subscribers);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        info!("Subscriber connected");
+                        let _ = stream.set_write_timeout(Some(SUBSCRIBER_WRITE_TIMEOUT));
+                        lock(&accepted).push(spawn_writer(stream));
+                    }
+                    Err(e) => warn!("Failed to accept subscriber: {}", e),
+                }
+            }
+        });
+        
+        Ok(Publisher { subscribers, local_addr: None, socket_path: Some(socket_path.into()) })
+    }
+    
+    #[cfg(not(unix))]
+    fn bind_unix(socket_path: &str, _subscribers: Subscribers) -> Result<Self> {
+        anyhow::bail!("Unix sockets are not supported on this platform: {}", socket_path)
+    }
+    
+    /// Returns the bound TCP address, e.g. to find the port picked for `127.0.0.1:0`
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+    
+    /// Returns the number of connected subscribers
+    pub fn subscriber_count(&self) -> usize {
+        lock(&self.subscribers).len()
+    }
+    
+    /// Queues a message for every connected subscriber, dropping those whose queue is full or closed
+    pub fn publish(&self, message: &str) {
+        let Ok(length) = u32::try_from(message.len()) else {
+            warn!("Message of {} bytes is too large to publish", message.len());
+            return;
+        };
+        let mut frame = Vec::with_capacity(4 + message.len());
+        frame.extend_from_slice(&length.to_be_bytes());
+        frame.extend_from_slice(message.as_bytes());
+        let frame: Arc<[u8]> = frame.into();
+        
+        lock(&self.subscribers).retain(|subscriber| match subscriber.try_send(Arc::clone(&frame)) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                info!("Subscriber dropped: too far behind");
+                false
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+}
+
+impl Drop for Publisher {
+    fn drop(&mut self) {
+        if let Some(socket_path) = &self.socket_path {
+            let _ = std::fs::remove_file(socket_path);
+        }
+    }
+}
+
+/// Starts the thread writing a subscriber's queued frames, returning the queue
+/// The thread ends, closing the queue, when a write fails or times out, or when the subscriber is dropped.
+fn spawn_writer<W: Write + Send + 'static>(mut stream: W) -> SyncSender<Arc<[u8]>> {
+    let (sender, receiver) = mpsc::sync_channel::<Arc<[u8]>>(SUBSCRIBER_QUEUE_FRAMES);
+    thread::spawn(move || {
+        for frame in receiver {
+            if let Err(e) = stream.write_all(&frame).and_then(|()| stream.flush()) {
+                info!("Subscriber dropped: {}", e);
+                break;
+            }
+        }
+    });
+    sender
+}
+
+/// Locks the subscriber list, recovering it if a writer panicked while holding the lock
+fn lock(subscribers: &Subscribers) -> std::sync::MutexGuard<'_, Vec<SyncSender<Arc<[u8]>>>> {
+    subscribers.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpStream;
+    use std::time::Instant;
+    use anyhow::Result;
+
+    /// Waits until the accept thread has registered the given number of subscribers
+    fn wait_for_subscribers(publisher: &Publisher, count: usize) -> Result<()> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while publisher.subscriber_count() != count {
+            anyhow::ensure!(Instant::now() < deadline, "subscriber count stayed at {}", publisher.subscriber_count());
+            thread::sleep(Duration::from_millis(10));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_publish_frames() -> Result<()> {
+        let publisher = Publisher::bind("tcp:127.0.0.1:0")?;
+        let address = publisher.local_addr().context("no TCP address")?;
+        let mut client = TcpStream::connect(address)?;
+        client.set_read_timeout(Some(Duration::from_secs(5)))?;
+        
+        wait_for_subscribers(&publisher, 1)?;
+        publisher.publish(r#"{"id":"1"}"#);
+        publisher.publish("中文");
+        
+        for expected in [r#"{"id":"1"}"#, "中文"] {
+            let mut length = [0; 4];
+            client.read_exact(&mut length)?;
+            let mut message = vec![0; u32::from_be_bytes(length) as usize];
+            client.read_exact(&mut message)?;
+            assert_eq!(String::from_utf8(message)?, expected);
+        }
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_publish_drops_stalled_subscriber() -> Result<()> {
+        let publisher = Publisher::bind("tcp:127.0.0.1:0")?;
+        let address = publisher.local_addr().context("no TCP address")?;
+        // Connected, but never reading
+        let _client = TcpStream::connect(address)?;
+        wait_for_subscribers(&publisher, 1)?;
+        
+        // Publishing doesn't wait on the client; once its queue is full it is dropped
+        let message = "x".repeat(256 * 1024);
+        let started = Instant::now();
+        for _ in 0..2 * SUBSCRIBER_QUEUE_FRAMES {
+            publisher.publish(&message);
+        }
+        assert!(started.elapsed() < SUBSCRIBER_WRITE_TIMEOUT);
+        assert_eq!(publisher.subscriber_count(), 0);
+        
+        Ok(())
+    }
+}
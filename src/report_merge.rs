@@ -0,0 +1,162 @@
+//! Merging of the pair results streamed by separate runs (`--stream-ndjson`) for the tbcompare tool.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use serde_json::Value;
+
+/// A pair as recorded in a `--stream-ndjson` stream
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamedPair {
+    /// Stable identifier of the file pair, see [`crate::pair_id`]
+    pub id: String,
+    /// Path of the first file, as written by the run
+    pub file1: String,
+    /// Path of the second file, as written by the run
+    pub file2: String,
+    /// `different`, `identical`, `same_file` or `error` (records without a status are differing pairs)
+    pub status: String,
+    /// Lines only in the first file, empty unless the pair is different
+    pub only_in_first: Vec<String>,
+    /// Lines only in the second file, empty unless the pair is different
+    pub only_in_second: Vec<String>,
+    /// Similarity of the pair, absent for errors
+    pub similarity: Option<f64>,
+    /// Error message of a pair that couldn't be compared
+    pub error: Option<String>,
+    /// Stream the record was read from
+    pub source: PathBuf,
+}
+
+impl StreamedPair {
+    /// Whether the pair had differences
+    pub fn is_different(&self) -> bool {
+        self.status == "different"
+    }
+}
+
+/// The pairs of several streams, each pair once
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MergedPairs {
+    /// Pairs in the order they first appear in the streams
+    pub pairs: Vec<StreamedPair>,
+    /// Number of records dropped because a later stream had the same pair
+    pub duplicates: usize,
+}
+
+impl MergedPairs {
+    /// Number of pairs with the given status
+    pub fn count(&self, status: &str) -> usize {
+        self.pairs.iter().filter(|pair| pair.status == status).count()
+    }
+}
+
+/// Reads the `--stream-ndjson` streams of several runs and merges their pairs
+/// 
+/// A pair recorded by more than one stream (same `id`) is kept once, with the record of the last stream
+/// listed, as that is usually the most recent run.
+/// 
+/// # Arguments
+/// 
+/// * `paths` - Paths of the NDJSON streams, in order
+/// 
+/// # Returns
+/// 
+/// A Result containing the merged pairs, or an error if a stream can't be read or holds an invalid record
+pub fn merge_pair_streams<P: AsRef<Path>>(paths: &[P]) -> Result<MergedPairs> {
+    let mut merged = MergedPairs::default();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    for path in paths {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read result stream: {}", path.display()))?;
+        for (index, line) in content.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+            let pair = parse_streamed_pair(line, path)
+                .with_context(|| format!("Invalid record on line {} of {}", index + 1, path.display()))?;
+            match positions.get(&pair.id) {
+                Some(&position) => {
+                    merged.pairs[position] = pair;
+                    merged.duplicates += 1;
+                }
+                None => {
+                    positions.insert(pair.id.clone(), merged.pairs.len());
+                    merged.pairs.push(pair);
+                }
+            }
+        }
+    }
+    Ok(merged)
+}
+
+/// Parses one NDJSON record of a result stream
+fn parse_streamed_pair(line: &str, source: &Path) -> Result<StreamedPair> {
+    let record: Value = serde_json::from_str(line)?;
+    let string = |field: &str| record[field].as_str()
+        .map(str::to_string)
+        .with_context(|| format!("Missing string field {:?}", field));
+    let lines = |field: &str| -> Result<Vec<String>> {
+        record[field].as_array()
+            .with_context(|| format!("Missing array field {:?}", field))?
+            .iter()
+            .map(|line| line.as_str().map(str::to_string).with_context(|| format!("Non-string line in {:?}", field)))
+            .collect()
+    };
+    // Streams written before records carried a status only held differing pairs
+    let status = record["status"].as_str().unwrap_or("different").to_string();
+    let different = status == "different";
+    Ok(StreamedPair {
+        id: string("id")?,
+        file1: string("file1")?,
+        file2: string("file2")?,
+        only_in_first: if different { lines("only_in_first")? } else { Vec::new() },
+        only_in_second: if different { lines("only_in_second")? } else { Vec::new() },
+        similarity: match record["similarity"].as_f64() {
+            None if different => anyhow::bail!("Missing number field \"similarity\""),
+            similarity => similarity,
+        },
+        error: record["error"].as_str().map(str::to_string),
+        status,
+        source: source.to_path_buf(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::Builder;
+
+    #[test]
+    fn test_merge_pair_streams() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let stream1_path = dir.path().join("a01.ndjson");
+        let stream2_path = dir.path().join("a02.ndjson");
+        fs::write(&stream1_path, concat!(
+            r#"{"id":"p1","file1":"a/x","file2":"b/x","only_in_first":["1"],"only_in_second":[],"similarity":0.5}"#, "\n",
+            r#"{"id":"p2","file1":"a/y","file2":"b/y","only_in_first":[],"only_in_second":["2"],"similarity":0.25}"#, "\n",
+        ))?;
+        fs::write(&stream2_path, concat!(
+            "\n",
+            r#"{"id":"p1","file1":"a/x","file2":"b/x","status":"different","only_in_first":["3"],"only_in_second":[],"similarity":0.75}"#, "\n",
+            r#"{"id":"p4","file1":"a/z","file2":"b/z","status":"identical","similarity":1.0}"#, "\n",
+            r#"{"id":"p5","file1":"a/w","file2":"b/w","status":"error","error":"unreadable"}"#, "\n",
+        ))?;
+        
+        // The overlapping pair keeps its position, with the later stream's record
+        let merged = merge_pair_streams(&[&stream1_path, &stream2_path])?;
+        assert_eq!(merged.duplicates, 1);
+        let ids: Vec<_> = merged.pairs.iter().map(|pair| pair.id.as_str()).collect();
+        assert_eq!(ids, vec!["p1", "p2", "p4", "p5"]);
+        assert_eq!(merged.pairs[0].only_in_first, vec!["3"]);
+        assert_eq!(merged.pairs[0].source, stream2_path);
+        
+        // Records without a status are differing pairs; the others carry their status
+        assert_eq!((merged.count("different"), merged.count("identical"), merged.count("error")), (2, 1, 1));
+        assert_eq!(merged.pairs[3].error.as_deref(), Some("unreadable"));
+        
+        fs::write(&stream2_path, r#"{"id":"p3"}"#)?;
+        assert!(merge_pair_streams(&[&stream2_path]).is_err());
+        
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+//! SQLite output of comparison results for the tbcompare tool (`sqlite` feature).
+
+use std::path::Path;
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection};
+
+/// Tables created on first use; existing tables are left untouched
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    started_at TEXT NOT NULL,
+    dir1 TEXT NOT NULL,
+    dir2 TEXT,
+    pair_count INTEGER NOT NULL,
+    diff_count INTEGER NOT NULL,
+    error_count INTEGER NOT NULL,
+    identical_count INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS pairs (
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    pair_id TEXT NOT NULL,
+    file1 TEXT NOT NULL,
+    file2 TEXT NOT NULL,
+    status TEXT NOT NULL,
+    only_in_first INTEGER NOT NULL,
+    only_in_second INTEGER NOT NULL,
+    similarity REAL
+);
+CREATE INDEX IF NOT EXISTS pairs_pair_id ON pairs(pair_id);
+";
+
+/// Summary of a comparison run, stored as one row of the `runs` table
+#[derive(Debug, Clone, Default)]
+pub struct RunRecord {
+    /// Local start time of the run, `YYYY-MM-DD HH:MM:SS`
+    pub started_at: String,
+    /// First directory compared
+    pub dir1: String,
+    /// Second directory compared (`None` when comparing versions within the first directory)
+    pub dir2: Option<String>,
+    /// Number of file pairs found
+    pub pair_count: usize,
+    /// Number of pairs with differences
+    pub diff_count: usize,
+    /// Number of pairs that failed to compare
+    pub error_count: usize,
+    /// Number of identical pairs
+    pub identical_count: usize,
+}
+
+/// Result of one file pair, stored as one row of the `pairs` table
+#[derive(Debug, Clone, Default)]
+pub struct PairRecord {
+    /// Stable identifier of the pair, see [`crate::pair_id`]
+    pub pair_id: String,
+    /// Path of the first file
+    pub file1: String,
+    /// Path of the second file
+    pub file2: String,
+    /// `different`, `identical`, `error`, `same_file` or `not_compared`
+    pub status: &'static str,
+    /// Number of lines only in the first file
+    pub only_in_first: usize,
+    /// Number of lines only in the second file
+    pub only_in_second: usize,
+    /// Similarity of the pair (1.0 when identical, `None` when not compared or on error)
+    pub similarity: Option<f64>,
+}
+
+/// Appends a run and its pairs to a SQLite database, creating the database and tables if needed
+/// All rows are inserted in a single transaction, so a failed write leaves no partial run.
+///
+/// # Arguments
+///
+/// * `db_path` - Path to the database file
+/// * `run` - Summary of the run
+/// * `pairs` - Results of the run's file pairs
+///
+/// # Returns
+///
+/// A Result containing either the id of the inserted run or an error
+pub fn write_run<P: AsRef<Path>>(db_path: P, run: &RunRecord, pairs: &[PairRecord]) -> Result<i64> {
+    let db_path = db_path.as_ref();
+    let mut connection = Connection::open(db_path)
+        .with_context(|| format!("Failed to open SQLite database: {}", db_path.display()))?;
+    connection.execute_batch(SCHEMA)
+        .with_context(|| format!("Failed to create tables in SQLite database: {}", db_path.display()))?;
+    
+    let transaction = connection.transaction().context("Failed to start SQLite transaction")?;
+    transaction.execute(
+        "INSERT INTO runs (started_at, dir1, dir2, pair_count, diff_count, error_count, identical_count)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![run.started_at, run.dir1, run.dir2, run.pair_count as i64, run.diff_count as i64,
+                run.error_count as i64, run.identical_count as i64],
+    ).context("Failed to insert run")?;
+    let run_id = transaction.last_insert_rowid();
+    {
+        let mut statement = transaction.prepare(
+            "INSERT INTO pairs (run_id, pair_id, file1, file2, status, only_in_first, only_in_second, similarity)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        ).context("Failed to prepare pair insertion")?;
+        for pair in pairs {
+            statement.execute(params![run_id, pair.pair_id, pair.file1, pair.file2, pair.status,
+                                      pair.only_in_first as i64, pair.only_in_second as i64, pair.similarity])
+                .with_context(|| format!("Failed to insert pair: {} / {}", pair.file1, pair.file2))?;
+        }
+    }
+    transaction.commit().context("Failed to commit SQLite transaction")?;
+    
+    Ok(run_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::Builder;
+    use anyhow::Result;
+
+    #[test]
+    fn test_write_run() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let db_path = dir.path().join("results.db");
+        let run = RunRecord { dir1: "a".to_string(), pair_count: 2, diff_count: 1, identical_count: 1, ..RunRecord::default() };
+        let pairs = vec![
+            PairRecord { pair_id: "1".to_string(), status: "different", only_in_first: 2, similarity: Some(0.5), ..PairRecord::default() },
+            PairRecord { pair_id: "2".to_string(), status: "identical", similarity: Some(1.0), ..PairRecord::default() },
+        ];
+        
+        // Runs accumulate in an existing database
+        assert_eq!(write_run(&db_path, &run, &pairs)?, 1);
+        assert_eq!(write_run(&db_path, &run, &pairs)?, 2);
+        
+        let connection = Connection::open(&db_path)?;
+        let (count, removed): (i64, i64) = connection.query_row(
+            "SELECT COUNT(*), SUM(only_in_first) FROM pairs WHERE run_id = 2", [], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        assert_eq!((count, removed), (2, 2));
+        
+        Ok(())
+    }
+}
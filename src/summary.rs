@@ -0,0 +1,129 @@
+//! Aggregate counts of a whole directory comparison for the tbcompare tool.
+
+use std::collections::HashSet;
+use std::path::Path;
+use anyhow::Result;
+use log::warn;
+use rayon::prelude::*;
+use crate::comparison::{compare_files_with_options, generate_file_pairs_with_options, list_files, CompareOptions, PairingOptions};
+use crate::file_utils::is_same_file;
+
+/// Options of a directory comparison: how files are paired, then how each pair is compared
+#[derive(Debug, Clone, Default)]
+pub struct SummaryOptions {
+    /// How the files of the two directories are matched into pairs
+    pub pairing: PairingOptions,
+    /// How the files of each pair are read, normalized and compared
+    pub compare: CompareOptions,
+}
+
+/// Counts of a directory comparison, without any per-pair detail
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Summary {
+    /// Number of file pairs compared
+    pub pairs: usize,
+    /// Pairs whose files differ
+    pub differing: usize,
+    /// Pairs that couldn't be compared
+    pub errors: usize,
+    /// Pairs whose files are identical (after normalization)
+    pub identical: usize,
+    /// Files of the first directory that aren't part of any pair
+    pub unmatched_first: usize,
+    /// Files of the second directory that aren't part of any pair
+    pub unmatched_second: usize,
+}
+
+/// Outcome of the comparison of one pair, all that is kept of it
+enum PairOutcome {
+    Identical,
+    Differing,
+    Error,
+}
+
+/// Pairs the files of two directories and compares every pair, returning only the counts
+///
+/// Each pair's differences are dropped as soon as the pair is compared, so memory doesn't grow with the
+/// number of differing pairs. Pairs of the same file (hardlinks) are identical without being read, and
+/// the external identity check of [`compare_files_with_options`] applies as configured in `options.compare`.
+/// Pairs that fail to compare are counted as errors rather than failing the whole run.
+///
+/// # Arguments
+///
+/// * `dir1_path` - Path to the first directory
+/// * `dir2_path` - Path to the second directory
+/// * `options` - Pairing and comparison options
+///
+/// # Returns
+///
+/// A Result containing the counts, or an error if a directory can't be read
+pub fn summarize_directories<P: AsRef<Path>>(dir1_path: P, dir2_path: P, options: &SummaryOptions) -> Result<Summary> {
+    let dir1_path = dir1_path.as_ref();
+    let dir2_path = dir2_path.as_ref();
+    
+    let pairs = generate_file_pairs_with_options(dir1_path, dir2_path, &options.pairing)?;
+    let paired1: HashSet<&Path> = pairs.iter().map(|(file1_path, _)| file1_path.as_path()).collect();
+    let paired2: HashSet<&Path> = pairs.iter().map(|(_, file2_path)| file2_path.as_path()).collect();
+    let mut summary = Summary {
+        pairs: pairs.len(),
+        unmatched_first: list_files(dir1_path)?.iter().filter(|path| !paired1.contains(path.as_path())).count(),
+        unmatched_second: list_files(dir2_path)?.iter().filter(|path| !paired2.contains(path.as_path())).count(),
+        ..Summary::default()
+    };
+    
+    let outcomes: Vec<PairOutcome> = pairs.par_iter()
+        .map(|(file1_path, file2_path)| {
+            if is_same_file(file1_path, file2_path).unwrap_or(false) {
+                return PairOutcome::Identical;
+            }
+            match compare_files_with_options(file1_path, file2_path, &options.compare) {
+                Ok(None) => PairOutcome::Identical,
+                Ok(Some(_)) => PairOutcome::Differing,
+                Err(e) => {
+                    warn!("Failed to compare {} and {}: {:#}", file1_path.display(), file2_path.display(), e);
+                    PairOutcome::Error
+                }
+            }
+        })
+        .collect();
+    for outcome in outcomes {
+        match outcome {
+            PairOutcome::Identical => summary.identical += 1,
+            PairOutcome::Differing => summary.differing += 1,
+            PairOutcome::Error => summary.errors += 1,
+        }
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::Builder;
+
+    #[test]
+    fn test_summarize_directories() -> Result<()> {
+        let dir1 = Builder::new().prefix("tbcompare_test1").tempdir()?;
+        let dir2 = Builder::new().prefix("tbcompare_test2").tempdir()?;
+        fs::write(dir1.path().join("SC_11111111_20240101_001N_A01_Z.txt"), "Header\nline 1\n")?;
+        fs::write(dir2.path().join("SC_11111111_20240101_002N_A01_Z.txt"), "Header\nline 1\n")?;
+        fs::write(dir1.path().join("SC_22222222_20240101_001N_A02_Z.txt"), "Header\nline 1\n")?;
+        fs::write(dir2.path().join("SC_22222222_20240101_001N_A02_Z.txt"), "Header\nline 2\n")?;
+        fs::write(dir1.path().join("SC_33333333_20240101_001N_A03_Z.txt"), "Header\n")?;
+        fs::write(dir2.path().join("notes.txt"), "")?;
+        fs::write(dir2.path().join("SC_44444444_20240101_001N_A04_Z.txt"), "Header\n")?;
+        
+        let summary = summarize_directories(dir1.path(), dir2.path(), &SummaryOptions::default())?;
+        assert_eq!(summary, Summary {
+            pairs: 2,
+            differing: 1,
+            errors: 0,
+            identical: 1,
+            unmatched_first: 1,
+            unmatched_second: 2,
+        });
+        
+        Ok(())
+    }
+}
@@ -0,0 +1,217 @@
+//! Per-report-type comparison option overrides for the tbcompare tool.
+
+use std::num::NonZeroUsize;
+use std::path::Path;
+use anyhow::{Context, Result};
+use regex::Regex;
+use crate::comparison::CompareOptions;
+use crate::file_utils::{read_list_lines, timestamp_format_width};
+
+/// Option overrides of one `[PATTERN]` section of a rules file
+#[derive(Debug, Clone, Default)]
+struct Overrides {
+    header_lines: Option<usize>,
+    ignore_case: Option<bool>,
+    ignore_patterns: Vec<Regex>,
+    strip_trailing_timestamp: Option<String>,
+    numeric_sort: Option<bool>,
+    expand_tabs: Option<NonZeroUsize>,
+    ordered: Option<bool>,
+}
+
+impl Overrides {
+    /// Parses one `key = value` setting into the overrides
+    fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let parse_bool = |value: &str| match value {
+            "true" => Ok(true),
+            "false" => Ok(false),
+            _ => anyhow::bail!("expected true or false, got {:?}", value),
+        };
+        match key {
+            "header_lines" => self.header_lines = Some(value.parse()
+                .with_context(|| format!("expected a number of lines, got {:?}", value))?),
+            "ignore_case" => self.ignore_case = Some(parse_bool(value)?),
+            "ignore_pattern" => self.ignore_patterns.push(Regex::new(value)
+                .with_context(|| format!("invalid regex {:?}", value))?),
+            "strip_trailing_timestamp" => {
+                timestamp_format_width(value)?;
+                self.strip_trailing_timestamp = Some(value.to_string());
+            }
+            "numeric_sort" => self.numeric_sort = Some(parse_bool(value)?),
+            "expand_tabs" => self.expand_tabs = Some(value.parse()
+                .with_context(|| format!("expected a positive tab width, got {:?}", value))?),
+            "ordered" => self.ordered = Some(parse_bool(value)?),
+            _ => anyhow::bail!("unknown setting {:?}", key),
+        }
+        Ok(())
+    }
+
+    /// Applies the overrides on top of `options`
+    fn apply(&self, options: &mut CompareOptions) {
+        // A per-type header count applies to both sides, replacing --header-lines1/--header-lines2
+        if let Some(header_lines) = self.header_lines {
+            options.process.header_lines = Some(header_lines);
+            options.second_header_lines = None;
+        }
+        if let Some(ignore_case) = self.ignore_case {
+            options.process.ignore_case = ignore_case;
+        }
+        options.process.ignore_patterns.extend(self.ignore_patterns.iter().cloned());
+        if let Some(format) = &self.strip_trailing_timestamp {
+            options.process.strip_trailing_timestamp = Some(format.clone());
+        }
+        if let Some(numeric_sort) = self.numeric_sort {
+            options.process.numeric_sort = numeric_sort;
+        }
+        if let Some(tab_width) = self.expand_tabs {
+            options.process.expand_tabs = Some(tab_width);
+        }
+        if let Some(ordered) = self.ordered {
+            options.ordered = ordered;
+        }
+    }
+}
+
+/// Comparison option overrides selected by the report type (AXX) of each pair
+/// 
+/// Rules files are made of `[PATTERN]` sections of `key = value` settings. The pattern is matched
+/// case-insensitively against the whole report type, with `*` matching any run of characters and
+/// `?` any single character. Every matching section applies, in file order, on top of the options
+/// given on the command line. Supported settings: `header_lines`, `ignore_case`, `ignore_pattern`
+/// (repeatable), `strip_trailing_timestamp`, `numeric_sort`, `expand_tabs` and `ordered`.
+/// 
+/// ```text
+/// [A05]
+/// strip_trailing_timestamp = %Y-%m-%d %H:%M:%S
+/// 
+/// [A09]
+/// header_lines = 3
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TypeRules {
+    sections: Vec<(String, Overrides)>,
+}
+
+impl TypeRules {
+    /// Loads a rules file
+    /// Empty lines and lines starting with `#` are ignored; the file's encoding is detected like any input file
+    /// 
+    /// # Arguments
+    /// 
+    /// * `file_path` - Path to the rules file
+    /// 
+    /// # Returns
+    /// 
+    /// A Result containing either the rules or an error naming the first invalid line
+    pub fn load<P: AsRef<Path>>(file_path: P) -> Result<Self> {
+        let file_path = file_path.as_ref();
+        let mut rules = TypeRules::default();
+        for line in read_list_lines(file_path)? {
+            if line.starts_with('#') {
+                continue;
+            }
+            if let Some(pattern) = line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+                rules.sections.push((pattern.trim().to_string(), Overrides::default()));
+                continue;
+            }
+            
+            let Some((_, overrides)) = rules.sections.last_mut() else {
+                anyhow::bail!("Setting outside of a [PATTERN] section in {}: {:?}", file_path.display(), line);
+            };
+            let (key, value) = line.split_once('=')
+                .with_context(|| format!("Invalid setting in {}: expected \"key = value\", got {:?}",
+                                         file_path.display(), line))?;
+            overrides.set(key.trim(), value.trim())
+                .with_context(|| format!("Invalid setting in {}: {:?}", file_path.display(), line))?;
+        }
+        Ok(rules)
+    }
+
+    /// Returns the options to compare a pair of the given report type with
+    /// 
+    /// # Arguments
+    /// 
+    /// * `report_type` - Report type (AXX) of the pair
+    /// * `defaults` - Options the matching sections are applied on top of
+    /// 
+    /// # Returns
+    /// 
+    /// The refined options
+    pub fn options_for(&self, report_type: &str, defaults: &CompareOptions) -> CompareOptions {
+        let mut options = defaults.clone();
+        for (pattern, overrides) in &self.sections {
+            if glob_matches(pattern, report_type) {
+                overrides.apply(&mut options);
+            }
+        }
+        options
+    }
+}
+
+/// Matches `text` against a glob `pattern` (`*` and `?` wildcards), ignoring ASCII case
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().map(|c| c.to_ascii_lowercase()).collect();
+    let text: Vec<char> = text.chars().map(|c| c.to_ascii_lowercase()).collect();
+    
+    // Backtrack to the most recent `*` on mismatch
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::Builder;
+    use anyhow::Result;
+
+    #[test]
+    fn test_type_rules() -> Result<()> {
+        let dir = Builder::new().prefix("tbcompare_test").tempdir()?;
+        let rules_path = dir.path().join("rules.txt");
+        fs::write(&rules_path, "# per-type settings\n[A0*]\nignore_case = true\n\n[a09]\nheader_lines = 3\nignore_pattern = ^DEBUG\n")?;
+        
+        let rules = TypeRules::load(&rules_path)?;
+        let defaults = CompareOptions { ordered: true, ..CompareOptions::default() };
+        
+        let options = rules.options_for("A09", &defaults);
+        assert_eq!(options.process.header_lines, Some(3));
+        assert!(options.process.ignore_case);
+        assert_eq!(options.process.ignore_patterns.len(), 1);
+        assert!(options.ordered);
+        
+        let options = rules.options_for("A01", &defaults);
+        assert_eq!(options.process.header_lines, None);
+        assert!(options.process.ignore_case);
+        
+        assert!(!rules.options_for("B01", &defaults).process.ignore_case);
+        
+        // A per-type header count replaces different header counts given for the two sides
+        let per_side = CompareOptions { second_header_lines: Some(2), ..defaults.clone() };
+        let options = rules.options_for("A09", &per_side);
+        assert_eq!(options.process.header_lines, Some(3));
+        assert_eq!(options.second_header_lines, None);
+        assert_eq!(rules.options_for("A01", &per_side).second_header_lines, Some(2));
+        
+        fs::write(&rules_path, "[A01]\nheader_lines = many\n")?;
+        assert!(TypeRules::load(&rules_path).is_err());
+        
+        Ok(())
+    }
+}